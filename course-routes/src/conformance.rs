@@ -0,0 +1,256 @@
+//! # Error-Handling Conformance Suite
+//!
+//! An API's error contract is only as good as its worst corner. Handler
+//! errors go through `AppError`, but the failures Axum produces on its own
+//! (an unknown route, the wrong method, a body that isn't JSON, a body
+//! that's too big) are plain text unless the app does something about it,
+//! and a handler that panics gets no response at all. Clients that parse
+//! every error as JSON break exactly there.
+//!
+//! `assert_conforms` takes any `Router` and fires each of those at it,
+//! asserting that every answer:
+//! - has the expected status
+//! - is one of the two standard shapes, told apart by content type:
+//!   `application/problem+json` with `type`, `title` (strings) and `status`
+//!   (RFC 7807), or `application/json` with `error` (string) and `code`
+//!   (the status) - the envelope
+//! - either way, has `error_code` (string) and `retryable` (bool)
+//!
+//! It knows nothing about the app beyond a few routes to aim at
+//! (`Probes`). Behind the `conformance` feature, so only tests pull it in:
+//!
+//! ```ignore
+//! course_routes::conformance::assert_conforms(app(), &Probes {
+//!     get_route: "/users/1",
+//!     json_route: Some("/users/bulk"),
+//!     body_limit: MAX_BODY_BYTES,
+//!     panic_route: Some("/buggy"),
+//! }).await;
+//! ```
+//!
+//! Every module with tests runs it. Module 07 passes; the others keep
+//! Axum's plain-text rejections on purpose - each teaches something else -
+//! so their run is `#[ignore]`d with the reason, and `cargo test --
+//! --ignored` shows how far each one is from the contract.
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+
+/// Where to aim the probes
+pub struct Probes {
+    /// A route that answers GET and nothing else
+    pub get_route: &'static str,
+    /// A POST route that reads a JSON body, if the app has one; without
+    /// it only the unknown route and the wrong method are sent
+    pub json_route: Option<&'static str>,
+    /// The app's body limit; the oversized probe sends one byte more
+    pub body_limit: usize,
+    /// A GET route whose handler panics, if the app has one to offer
+    pub panic_route: Option<&'static str>,
+}
+
+struct Probe {
+    name: &'static str,
+    request: Request<Body>,
+    expected: StatusCode,
+}
+
+fn probes(p: &Probes) -> Vec<Probe> {
+    let mut probes = vec![
+        Probe {
+            name: "unknown route",
+            request: Request::get("/conformance/no-such-route")
+                .body(Body::empty())
+                .unwrap(),
+            expected: StatusCode::NOT_FOUND,
+        },
+        Probe {
+            name: "wrong method",
+            request: Request::builder()
+                .method(Method::DELETE)
+                .uri(p.get_route)
+                .body(Body::empty())
+                .unwrap(),
+            expected: StatusCode::METHOD_NOT_ALLOWED,
+        },
+    ];
+    if let Some(route) = p.json_route {
+        let json = |body: Vec<u8>| {
+            Request::post(route)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        probes.extend([
+            Probe {
+                name: "malformed JSON",
+                // Broken from the first byte, whatever shape the route expects
+                request: json(b"undefined".to_vec()),
+                expected: StatusCode::BAD_REQUEST,
+            },
+            Probe {
+                name: "JSON of the wrong shape",
+                request: json(br#"{"unexpected": true}"#.to_vec()),
+                expected: StatusCode::UNPROCESSABLE_ENTITY,
+            },
+            Probe {
+                name: "not JSON at all",
+                request: Request::post(route)
+                    .header(header::CONTENT_TYPE, "text/plain")
+                    .body(Body::from("hello"))
+                    .unwrap(),
+                expected: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            },
+            Probe {
+                name: "oversized body",
+                // Valid JSON, so only the size can be the problem
+                request: json(format!(r#"["{}"]"#, "x".repeat(p.body_limit)).into_bytes()),
+                expected: StatusCode::PAYLOAD_TOO_LARGE,
+            },
+        ]);
+    }
+    if let Some(route) = p.panic_route {
+        probes.push(Probe {
+            name: "handler panic",
+            request: Request::get(route).body(Body::empty()).unwrap(),
+            expected: StatusCode::INTERNAL_SERVER_ERROR,
+        });
+    }
+    probes
+}
+
+/// Everything wrong with one answer, if anything is
+fn violations(
+    status: StatusCode,
+    content_type: &str,
+    body: &[u8],
+    expected: StatusCode,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    if status != expected {
+        problems.push(format!("status {} instead of {}", status, expected));
+    }
+    let is_problem = content_type.starts_with("application/problem+json");
+    if !is_problem && !content_type.starts_with("application/json") {
+        problems.push(format!("content type {:?}", content_type));
+    }
+    let Ok(json) = serde_json::from_slice::<serde_json::Value>(body) else {
+        problems.push(format!("not JSON: {:?}", String::from_utf8_lossy(body)));
+        return problems;
+    };
+    if is_problem {
+        for member in ["type", "title"] {
+            if !json[member].is_string() {
+                problems.push(format!("no `{}` string", member));
+            }
+        }
+        if json["status"] != status.as_u16() {
+            problems.push(format!("`status` is {} on a {}", json["status"], status));
+        }
+    } else {
+        if !json["error"].is_string() {
+            problems.push("no `error` string".to_string());
+        }
+        if json["code"] != status.as_u16() {
+            problems.push(format!("`code` is {} on a {}", json["code"], status));
+        }
+    }
+    if !json["error_code"].is_string() {
+        problems.push("no `error_code` string".to_string());
+    }
+    if !json["retryable"].is_boolean() {
+        problems.push("no `retryable` bool".to_string());
+    }
+    problems
+}
+
+/// Panics listing every probe whose answer broke the contract
+pub async fn assert_conforms(app: Router, p: &Probes) {
+    let mut failures = Vec::new();
+    for probe in probes(p) {
+        let response = app.clone().oneshot(probe.request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        for problem in violations(status, &content_type, &body, probe.expected) {
+            failures.push(format!("{}: {}", probe.name, problem));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "error responses off the contract:\n  {}",
+        failures.join("\n  ")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::{get, post};
+
+    #[tokio::test]
+    #[should_panic(expected = "unknown route: not JSON")]
+    async fn test_axum_defaults_fail_the_suite() {
+        // Plain-text rejections and empty 404/405s, as Axum ships them
+        let app = Router::new()
+            .route("/get", get(|| async { "ok" }))
+            .route("/json", post(|_: axum::Json<Vec<String>>| async { "ok" }));
+        let probes = Probes {
+            get_route: "/get",
+            json_route: Some("/json"),
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        assert_conforms(app, &probes).await;
+    }
+
+    #[test]
+    fn test_without_a_json_route_only_routing_is_probed() {
+        let probes = probes(&Probes {
+            get_route: "/get",
+            json_route: None,
+            body_limit: 0,
+            panic_route: None,
+        });
+        let names: Vec<&str> = probes.iter().map(|p| p.name).collect();
+        assert_eq!(names, ["unknown route", "wrong method"]);
+    }
+
+    #[test]
+    fn test_every_field_of_the_envelope_is_checked() {
+        let body = br#"{"error": "Nope", "code": 404, "error_code": "NOPE", "retryable": false}"#;
+        let json = "application/json";
+        assert!(violations(StatusCode::NOT_FOUND, json, body, StatusCode::NOT_FOUND).is_empty());
+
+        let problems = violations(
+            StatusCode::GONE,
+            "text/plain",
+            br#"{"code": 404}"#,
+            StatusCode::NOT_FOUND,
+        );
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+    }
+
+    #[test]
+    fn test_problem_details_are_checked_as_rfc_7807() {
+        let problem = "application/problem+json";
+        let body = br#"{"type": "https://example.com/problems/nope", "title": "Nope",
+            "status": 404, "error_code": "NOPE", "retryable": false}"#;
+        assert!(violations(StatusCode::NOT_FOUND, problem, body, StatusCode::NOT_FOUND).is_empty());
+
+        // The envelope's fields don't make up for missing members
+        let body = br#"{"error": "Nope", "code": 404, "error_code": "NOPE", "retryable": false}"#;
+        let problems = violations(StatusCode::NOT_FOUND, problem, body, StatusCode::NOT_FOUND);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+    }
+}
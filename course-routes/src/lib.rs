@@ -0,0 +1,429 @@
+//! # A Route Registry That Prints Its Own Endpoint Table
+//!
+//! A hand-written list of endpoints in `main` drifts: a route is renamed or
+//! removed, and the startup banner keeps advertising it. `Routes` registers
+//! each route with its description in the same call that adds it to the
+//! router, so the table printed at startup is generated from what is
+//! actually served. Module 02 introduces it; every module builds its
+//! router through it and prints the table in its banner.
+//!
+//! ```ignore
+//! Routes::new()
+//!     .get("/users", list_users, "List users")
+//!     .post("/users", create_user, "Create a user")
+//!     .nest("/api/v1", api_v1_routes())
+//! ```
+//!
+//! The method comes from the call (`get`, `post`, ...), not from a string
+//! next to it, and nested registries carry their entries up with the
+//! prefix added. `guard` applies a `route_layer` to the routes registered
+//! so far and labels them in the table's auth column - which is exactly
+//! what `route_layer` protects.
+//!
+//! `with_state`, `merge` and `layer` work as they do on `Router`, so a
+//! router built in pieces - per-feature registries with their own state,
+//! merged and layered - keeps every row. `into_router()` hands the
+//! finished `Router` to `axum::serve`.
+//!
+//! The `conformance` feature adds the error-contract suite the modules'
+//! tests run against their routers (see `conformance.rs`).
+
+#[cfg(feature = "conformance")]
+pub mod conformance;
+
+use axum::{
+    handler::Handler,
+    http::Request,
+    response::IntoResponse,
+    routing::{any, on, on_service, MethodFilter, MethodRouter, Route},
+    Router,
+};
+use std::{convert::Infallible, fmt};
+use tower::{Layer, Service};
+
+/// One row of the endpoint table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub method: &'static str,
+    pub path: String,
+    pub auth: &'static str,
+    pub description: &'static str,
+}
+
+/// A `Router` that records every route added through it
+pub struct Routes<S = ()> {
+    router: Router<S>,
+    endpoints: Vec<Endpoint>,
+}
+
+/// `get`, `post`, ... for handlers; the filter and the table's label
+/// come from the same line
+macro_rules! method {
+    ($($name:ident => $filter:ident),* $(,)?) => {
+        $(
+            pub fn $name<H, T>(self, path: &str, handler: H, description: &'static str) -> Self
+            where
+                H: Handler<T, S>,
+                T: 'static,
+            {
+                let filter = MethodFilter::$filter;
+                self.add(stringify!($filter), path, on(filter, handler), description)
+            }
+        )*
+    };
+}
+
+impl<S> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            router: Router::new(),
+            endpoints: Vec::new(),
+        }
+    }
+
+    method! {
+        get => GET,
+        post => POST,
+        put => PUT,
+        patch => PATCH,
+        delete => DELETE,
+    }
+
+    /// Every method, e.g. a WebSocket upgrade or a proxy
+    pub fn any<H, T>(self, path: &str, handler: H, description: &'static str) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.add("ANY", path, any(handler), description)
+    }
+
+    /// A GET route served by a service, e.g. a handler `with_state`
+    pub fn get_service<T>(self, path: &str, service: T, description: &'static str) -> Self
+    where
+        T: Service<Request<axum::body::Body>, Error = Infallible> + Clone + Send + Sync + 'static,
+        T::Response: IntoResponse + 'static,
+        T::Future: Send + 'static,
+    {
+        let route = on_service(MethodFilter::GET, service);
+        self.add("GET", path, route, description)
+    }
+
+    /// A POST route served by a service
+    pub fn post_service<T>(self, path: &str, service: T, description: &'static str) -> Self
+    where
+        T: Service<Request<axum::body::Body>, Error = Infallible> + Clone + Send + Sync + 'static,
+        T::Response: IntoResponse + 'static,
+        T::Future: Send + 'static,
+    {
+        let route = on_service(MethodFilter::POST, service);
+        self.add("POST", path, route, description)
+    }
+
+    fn add(
+        mut self,
+        method: &'static str,
+        path: &str,
+        route: MethodRouter<S>,
+        description: &'static str,
+    ) -> Self {
+        self.router = self.router.route(path, route);
+        self.endpoints.push(Endpoint {
+            method,
+            path: path.to_string(),
+            auth: "public",
+            description,
+        });
+        self
+    }
+
+    /// Mount another registry under `prefix`, entries included
+    pub fn nest(mut self, prefix: &str, routes: Routes<S>) -> Self {
+        self.router = self.router.nest(prefix, routes.router);
+        self.endpoints
+            .extend(routes.endpoints.into_iter().map(|endpoint| Endpoint {
+                path: join(prefix, &endpoint.path),
+                ..endpoint
+            }));
+        self
+    }
+
+    /// Another registry's routes at the same level, entries included
+    pub fn merge(mut self, routes: Routes<S>) -> Self {
+        self.router = self.router.merge(routes.router);
+        self.endpoints.extend(routes.endpoints);
+        self
+    }
+
+    /// A service answering everything under `prefix`, e.g. `ServeDir`;
+    /// listed as one catch-all row
+    pub fn nest_service<T>(mut self, prefix: &str, service: T, description: &'static str) -> Self
+    where
+        T: Service<Request<axum::body::Body>, Error = Infallible> + Clone + Send + Sync + 'static,
+        T::Response: IntoResponse,
+        T::Future: Send + 'static,
+    {
+        self.router = self.router.nest_service(prefix, service);
+        self.endpoints.push(Endpoint {
+            method: "GET",
+            path: join(prefix, "/{*path}"),
+            auth: "public",
+            description,
+        });
+        self
+    }
+
+    /// Mount a plain `Router` whose routes aren't known up front (e.g. ones
+    /// loaded at runtime); it's listed as one catch-all row
+    pub fn nest_router(
+        mut self,
+        prefix: &str,
+        router: Router<S>,
+        description: &'static str,
+    ) -> Self {
+        self.router = self.router.nest(prefix, router);
+        self.endpoints.push(Endpoint {
+            method: "ANY",
+            path: join(prefix, "/{*path}"),
+            auth: "public",
+            description,
+        });
+        self
+    }
+
+    /// `route_layer` over every route registered so far, shown as `auth`
+    pub fn guard<L>(mut self, auth: &'static str, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request<axum::body::Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<axum::body::Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<axum::body::Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<axum::body::Body>>>::Future: Send + 'static,
+    {
+        self.router = self.router.route_layer(layer);
+        for endpoint in &mut self.endpoints {
+            if endpoint.auth == "public" {
+                endpoint.auth = auth;
+            }
+        }
+        self
+    }
+
+    /// `Router::layer`: wraps every route registered so far, and the
+    /// fallback; the table doesn't change
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + Sync + 'static,
+        L::Service: Service<Request<axum::body::Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<axum::body::Body>>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request<axum::body::Body>>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request<axum::body::Body>>>::Future: Send + 'static,
+    {
+        self.router = self.router.layer(layer);
+        self
+    }
+
+    /// Provide the state, e.g. to nest this registry under one with
+    /// another state type
+    pub fn with_state<S2>(self, state: S) -> Routes<S2> {
+        Routes {
+            router: self.router.with_state(state),
+            endpoints: self.endpoints,
+        }
+    }
+
+    pub fn fallback<H, T>(mut self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.router = self.router.fallback(handler);
+        self
+    }
+
+    /// What a known path answers to a method it doesn't serve
+    pub fn method_not_allowed_fallback<H, T>(mut self, handler: H) -> Self
+    where
+        H: Handler<T, S>,
+        T: 'static,
+    {
+        self.router = self.router.method_not_allowed_fallback(handler);
+        self
+    }
+
+    /// What the table lists, in registration order
+    pub fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    pub fn into_router(self) -> Router<S> {
+        self.router
+    }
+}
+
+impl<S> Default for Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `/api` + `/` is `/api`, the way `Router::nest` serves it
+fn join(prefix: &str, path: &str) -> String {
+    match path {
+        "/" => prefix.to_string(),
+        path => format!("{}{}", prefix.trim_end_matches('/'), path),
+    }
+}
+
+/// The startup table: one aligned row per endpoint
+impl<S> fmt::Display for Routes<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = Endpoint {
+            method: "METHOD",
+            path: "PATH".to_string(),
+            auth: "AUTH",
+            description: "DESCRIPTION",
+        };
+        let rows: Vec<&Endpoint> = std::iter::once(&header).chain(&self.endpoints).collect();
+        let method_width = rows.iter().map(|e| e.method.len()).max().unwrap_or(0);
+        let path_width = rows.iter().map(|e| e.path.len()).max().unwrap_or(0);
+        let auth_width = rows.iter().map(|e| e.auth.len()).max().unwrap_or(0);
+        for row in rows {
+            writeln!(
+                f,
+                "   {:<mw$}  {:<pw$}  {:<aw$}  {}",
+                row.method,
+                row.path,
+                row.auth,
+                row.description,
+                mw = method_width,
+                pw = path_width,
+                aw = auth_width,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::StatusCode,
+        middleware::{self, Next},
+        response::Response,
+    };
+    use tower::ServiceExt;
+
+    async fn require_key(request: Request<Body>, next: Next) -> Response {
+        if request.headers().contains_key("x-api-key") {
+            next.run(request).await
+        } else {
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+
+    fn routes() -> Routes {
+        let users = Routes::new()
+            .get("/", || async { "list" }, "List users")
+            .post("/", || async { "create" }, "Create a user")
+            .delete("/{id}", || async { "delete" }, "Delete a user");
+        Routes::new()
+            .get("/admin", || async { "admin" }, "Admin page")
+            .guard("api key", middleware::from_fn(require_key))
+            .get("/", || async { "home" }, "Home")
+            .nest("/api/users", users)
+    }
+
+    #[test]
+    fn test_entries_follow_registration_and_nesting() {
+        let routes = routes();
+        let rows: Vec<_> = routes
+            .endpoints
+            .iter()
+            .map(|e| (e.method, e.path.as_str(), e.auth))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("GET", "/admin", "api key"),
+                ("GET", "/", "public"),
+                ("GET", "/api/users", "public"),
+                ("POST", "/api/users", "public"),
+                ("DELETE", "/api/users/{id}", "public"),
+            ]
+        );
+
+        let table = routes.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].trim_start().starts_with("METHOD"));
+        // Columns line up
+        let column = lines[0].find("AUTH").unwrap();
+        assert_eq!(lines[5].find("public"), Some(column));
+    }
+
+    #[tokio::test]
+    async fn test_every_listed_endpoint_is_served() {
+        let routes = routes();
+        let endpoints = routes.endpoints.clone();
+        let app = routes.into_router();
+        for endpoint in endpoints {
+            let request = Request::builder()
+                .method(endpoint.method)
+                .uri(endpoint.path.replace("{id}", "7"))
+                .header("x-api-key", "k")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{:?}", endpoint);
+        }
+
+        // And the guard is the real thing
+        let request = Request::get("/admin").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_stateful_merged_and_layered_routes_keep_their_rows() {
+        use axum::extract::State;
+
+        let counter = Routes::new()
+            .get(
+                "/count",
+                |State(n): State<u32>| async move { n.to_string() },
+                "Count",
+            )
+            .with_state(7);
+        let routes: Routes = Routes::new()
+            .any("/echo", || async { "echo" }, "Any method")
+            .merge(counter)
+            .layer(middleware::from_fn(require_key));
+        let rows: Vec<_> = routes
+            .endpoints()
+            .iter()
+            .map(|e| (e.method, e.path.as_str()))
+            .collect();
+        assert_eq!(rows, [("ANY", "/echo"), ("GET", "/count")]);
+
+        let app = routes.into_router();
+        let request = Request::get("/count")
+            .header("x-api-key", "k")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // `layer` covers the merged routes too
+        let request = Request::delete("/echo").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -7,10 +7,7 @@
 //! - Understanding the Router type
 //! - Basic request/response flow
 
-use axum::{
-    routing::{get, post},
-    Router,
-};
+use course_routes::Routes;
 
 // ============================================================================
 // LESSON 1: Your First Handler
@@ -86,19 +83,24 @@ async fn main() {
     // Build our application router
     // 
     // The Router is the core of Axum - it maps paths to handlers
-    // You can chain multiple routes together using the builder pattern
-    let app = Router::new()
+    // You can chain multiple routes together using the builder pattern.
+    // `Routes` (from the shared `course-routes` crate) wraps a Router and
+    // records a description per route, so the startup table below is
+    // generated rather than typed out by hand - module 02 explains it.
+    let routes = Routes::new()
         // Basic GET routes
-        .route("/", get(hello_world))
-        .route("/hello", get(hello_axum))
-        .route("/health", get(health_check))
+        .get("/", hello_world, "Hello World")
+        .get("/hello", hello_axum, "Welcome message")
+        .get("/health", health_check, "Health check")
         
         // Routes with different status codes
-        .route("/created", get(with_status))
-        .route("/status", get(conditional_response))
+        .get("/created", with_status, "Status code example")
+        .get("/status", conditional_response, "Status chosen at runtime")
         
         // POST route (we'll explore this more later)
-        .route("/echo", post(echo));
+        .post("/echo", echo, "Echo back your message");
+    let table = routes.to_string();
+    let app = routes.into_router();
 
     // Create a TCP listener
     // 
@@ -112,11 +114,7 @@ async fn main() {
     println!("   Server running on http://localhost:3000");
     println!();
     println!("📝 Try these endpoints:");
-    println!("   GET  /        - Hello World");
-    println!("   GET  /hello   - Welcome message");
-    println!("   GET  /health  - Health check");
-    println!("   GET  /created - Status code example");
-    println!("   POST /echo    - Echo back your message");
+    print!("{}", table);
     println!();
     println!("💡 Example: curl http://localhost:3000/hello");
     println!("💡 Example: curl -X POST -d 'Hello!' http://localhost:3000/echo");
@@ -0,0 +1,375 @@
+//! # Dynamic Routes from a Manifest
+//!
+//! Routes are usually fixed at compile time. A CMS or an API gateway needs
+//! them to come from configuration instead: this sub-router is built from a
+//! JSON manifest at startup and rebuilt when the process gets `SIGHUP`.
+//!
+//! Each manifest entry maps a path to one of:
+//! - `static`: a fixed status, content type and body
+//! - `template`: a body with `{param}` placeholders filled from the path
+//! - `upstream`: a reverse proxy to another HTTP service
+//!
+//! A reload builds the new router completely before swapping it in. If the
+//! manifest is unreadable or invalid, the routes already being served stay.
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{any, get},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tower::ServiceExt;
+
+// ============================================================================
+// MANIFEST FORMAT
+// ============================================================================
+
+/// `{"routes": [{"path": "/about", "static": {...}}, ...]}`
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    routes: Vec<RouteEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RouteEntry {
+    path: String,
+    #[serde(flatten)]
+    target: Target,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Target {
+    Static {
+        #[serde(default = "default_status")]
+        status: u16,
+        #[serde(default = "default_content_type")]
+        content_type: String,
+        body: String,
+    },
+    Template {
+        #[serde(default = "default_content_type")]
+        content_type: String,
+        body: String,
+    },
+    Upstream {
+        /// Base URL; a `{*rest}` wildcard in the path is appended to it
+        url: String,
+    },
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+fn default_content_type() -> String {
+    "text/plain; charset=utf-8".to_string()
+}
+
+impl Target {
+    fn kind(&self) -> &'static str {
+        match self {
+            Target::Static { .. } => "static",
+            Target::Template { .. } => "template",
+            Target::Upstream { .. } => "upstream",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Read(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManifestError::Read(e) => write!(f, "cannot read manifest: {}", e),
+            ManifestError::Parse(e) => write!(f, "invalid manifest JSON: {}", e),
+            ManifestError::Invalid(e) => write!(f, "invalid route: {}", e),
+        }
+    }
+}
+
+// ============================================================================
+// BUILDING THE SUB-ROUTER
+// ============================================================================
+
+fn build_router(manifest: &Manifest, client: &reqwest::Client) -> Result<Router, ManifestError> {
+    let mut seen = HashSet::new();
+    for entry in &manifest.routes {
+        if !entry.path.starts_with('/') {
+            return Err(ManifestError::Invalid(format!(
+                "{}: path must start with '/'",
+                entry.path
+            )));
+        }
+        if !seen.insert(entry.path.as_str()) {
+            return Err(ManifestError::Invalid(format!(
+                "{}: listed twice",
+                entry.path
+            )));
+        }
+        if let Target::Static { status, .. } = &entry.target {
+            StatusCode::from_u16(*status)
+                .map_err(|_| ManifestError::Invalid(format!("{}: bad status", entry.path)))?;
+        }
+    }
+
+    // `Router::route` panics on paths it can't accept (e.g. `/{a}` next to
+    // `/{b}`). A bad manifest must not take the server down, so the panic
+    // becomes an error and the old routes stay in place.
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        manifest
+            .routes
+            .iter()
+            .fold(Router::new(), |router, entry| {
+                let target = entry.target.clone();
+                match target {
+                    Target::Upstream { url } => {
+                        let client = client.clone();
+                        router.route(
+                            &entry.path,
+                            any(move |params, request| proxy(client, url, params, request)),
+                        )
+                    }
+                    target => router.route(&entry.path, get(move |params| respond(target, params))),
+                }
+            })
+            .fallback(|| async { (StatusCode::NOT_FOUND, "No dynamic route for this path") })
+    }))
+    .map_err(|panic| {
+        let message = panic
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "rejected by the router".to_string());
+        ManifestError::Invalid(message)
+    })
+}
+
+/// Routes without parameters have no `Path` to extract, hence the `Option`
+async fn respond(target: Target, params: Option<Path<HashMap<String, String>>>) -> Response {
+    let params = params.map(|Path(params)| params).unwrap_or_default();
+    match target {
+        Target::Static {
+            status,
+            content_type,
+            body,
+        } => (
+            StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
+            [(header::CONTENT_TYPE, content_type)],
+            body,
+        )
+            .into_response(),
+        Target::Template { content_type, body } => {
+            let is_html = content_type.contains("html");
+            let rendered = params.iter().fold(body, |body, (name, value)| {
+                let value = if is_html {
+                    html_escape(value)
+                } else {
+                    value.clone()
+                };
+                body.replace(&format!("{{{}}}", name), &value)
+            });
+            ([(header::CONTENT_TYPE, content_type)], rendered).into_response()
+        }
+        Target::Upstream { .. } => unreachable!("upstream routes are proxied"),
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Forward method, remaining path, query and body; relay status, content
+/// type and body back
+async fn proxy(
+    client: reqwest::Client,
+    base: String,
+    params: Option<Path<HashMap<String, String>>>,
+    request: Request,
+) -> Response {
+    let params = params.map(|Path(params)| params).unwrap_or_default();
+    let mut url = base.trim_end_matches('/').to_string();
+    if let Some(rest) = params.get("rest") {
+        url.push('/');
+        url.push_str(rest);
+    }
+    if let Some(query) = request.uri().query() {
+        url.push('?');
+        url.push_str(query);
+    }
+
+    let method = reqwest::Method::from_bytes(request.method().as_str().as_bytes())
+        .unwrap_or(reqwest::Method::GET);
+    let content_type = request.headers().get(header::CONTENT_TYPE).cloned();
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 1024).await {
+        Ok(body) => body,
+        Err(_) => {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Body too large to proxy").into_response()
+        }
+    };
+
+    let mut outbound = client.request(method, &url).body(body);
+    if let Some(content_type) = content_type.as_ref().and_then(|v| v.to_str().ok()) {
+        outbound = outbound.header("content-type", content_type);
+    }
+
+    match outbound.send().await {
+        Ok(upstream) => {
+            let status =
+                StatusCode::from_u16(upstream.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+            let mut headers = HeaderMap::new();
+            if let Some(value) = upstream
+                .headers()
+                .get("content-type")
+                .and_then(|v| HeaderValue::from_bytes(v.as_bytes()).ok())
+            {
+                headers.insert(header::CONTENT_TYPE, value);
+            }
+            let body = upstream.bytes().await.unwrap_or_else(|_| Bytes::new());
+            (status, headers, body).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            format!("Upstream {} failed: {}", url, e),
+        )
+            .into_response(),
+    }
+}
+
+// ============================================================================
+// HOT-SWAPPABLE HANDLE
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize)]
+struct RouteSummary {
+    path: String,
+    kind: &'static str,
+}
+
+struct Loaded {
+    router: Router,
+    routes: Vec<RouteSummary>,
+    /// Bumped on every successful (re)load
+    version: u64,
+    loaded_at: u64,
+}
+
+/// The current dynamic sub-router, shared by every request and the reloader
+#[derive(Clone)]
+pub struct DynamicRoutes {
+    source: Arc<PathBuf>,
+    client: reqwest::Client,
+    loaded: Arc<RwLock<Loaded>>,
+}
+
+impl DynamicRoutes {
+    /// No routes until `reload` succeeds
+    pub fn new(source: impl Into<PathBuf>) -> Self {
+        Self {
+            source: Arc::new(source.into()),
+            client: reqwest::Client::new(),
+            loaded: Arc::new(RwLock::new(Loaded {
+                router: Router::new(),
+                routes: Vec::new(),
+                version: 0,
+                loaded_at: 0,
+            })),
+        }
+    }
+
+    /// Read the manifest, build a new router and swap it in. Returns the
+    /// number of routes; on error nothing changes.
+    pub fn reload(&self) -> Result<usize, ManifestError> {
+        let text = std::fs::read_to_string(self.source.as_ref()).map_err(ManifestError::Read)?;
+        let manifest: Manifest = serde_json::from_str(&text).map_err(ManifestError::Parse)?;
+        let router = build_router(&manifest, &self.client)?;
+        let routes: Vec<RouteSummary> = manifest
+            .routes
+            .iter()
+            .map(|entry| RouteSummary {
+                path: entry.path.clone(),
+                kind: entry.target.kind(),
+            })
+            .collect();
+
+        let count = routes.len();
+        let mut loaded = self.loaded.write().unwrap();
+        *loaded = Loaded {
+            router,
+            routes,
+            version: loaded.version + 1,
+            loaded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        Ok(count)
+    }
+
+    /// A router that forwards everything to the current dynamic router.
+    /// Mount it with `nest`; the manifest paths are relative to the prefix.
+    pub fn router(&self) -> Router {
+        Router::new().fallback(dispatch).with_state(self.clone())
+    }
+
+    /// Re-read the manifest every time the process receives `SIGHUP`
+    /// (`kill -HUP <pid>`), the usual "reload your config" signal
+    #[cfg(unix)]
+    pub async fn reload_on_sighup(self) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                eprintln!("⚠️  Cannot listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match self.reload() {
+                Ok(count) => println!("🔄 SIGHUP: reloaded {} dynamic route(s)", count),
+                Err(e) => eprintln!("⚠️  SIGHUP: kept the previous routes, {}", e),
+            }
+        }
+    }
+}
+
+/// Each request runs against the router that is current when it arrives;
+/// in-flight requests finish on the one they started with
+async fn dispatch(State(routes): State<DynamicRoutes>, request: Request) -> Response {
+    let router = routes.loaded.read().unwrap().router.clone();
+    match router.oneshot(request).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
+}
+
+/// GET /admin/routes - what is being served right now
+pub async fn describe(State(routes): State<DynamicRoutes>) -> Json<serde_json::Value> {
+    let loaded = routes.loaded.read().unwrap();
+    Json(serde_json::json!({
+        "source": routes.source.display().to_string(),
+        "version": loaded.version,
+        "loaded_at": loaded.loaded_at,
+        "routes": loaded.routes,
+    }))
+}
@@ -6,24 +6,38 @@
 //! - Query parameters
 //! - Router nesting and merging
 //! - Method routing (GET, POST, PUT, DELETE, etc.)
+//! - Automatic HEAD and OPTIONS handling
+//! - Routes loaded from a manifest and reloaded on SIGHUP (see `dynamic_routes.rs`)
+//! - Shadowing a route to a rewritten handler and diffing the answers (see `shadow.rs`)
+//! - A route registry that generates the startup endpoint table (`course-routes`)
+
+mod dynamic_routes;
+mod shadow;
 
 use axum::{
-    extract::{Path, Query},
-    routing::{delete, get, patch, post, put},
-    Router,
+    body::{Body, HttpBody},
+    extract::{Path, Query, Request},
+    handler::Handler,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json, Router,
 };
+use course_routes::Routes;
+use dynamic_routes::DynamicRoutes;
 use serde::Deserialize;
+use shadow::shadow;
 
 // ============================================================================
 // LESSON 1: Path Parameters - NEW SYNTAX IN AXUM 0.8!
 // ============================================================================
 
-/// IMPORTANT: Axum 0.8 introduced new path syntax!
-///
-/// OLD (pre-0.8): `/:id` and `/*rest`
-/// NEW (0.8+):    `/{id}` and `/{*rest}`
-///
-/// This change allows routes like `/api/:colon` that start with `:` or `*`
+// IMPORTANT: Axum 0.8 introduced new path syntax!
+//
+// OLD (pre-0.8): `/:id` and `/*rest`
+// NEW (0.8+):    `/{id}` and `/{*rest}`
+//
+// This change allows routes like `/api/:colon` that start with `:` or `*`
 
 /// Single path parameter
 async fn get_user(Path(id): Path<u64>) -> String {
@@ -119,16 +133,14 @@ async fn delete_user(Path(id): Path<u64>) -> String {
 // ============================================================================
 
 /// Create a sub-router for user-related routes
-fn user_routes() -> Router {
-    Router::new()
-        .route("/", get(list_users).post(create_user))
-        .route(
-            "/{id}",
-            get(get_user)
-                .put(update_user)
-                .patch(patch_user)
-                .delete(delete_user),
-        )
+fn user_routes() -> Routes {
+    Routes::new()
+        .get("/", list_users, "List users")
+        .post("/", create_user, "Create a user")
+        .get("/{id}", get_user, "Read one user")
+        .put("/{id}", update_user, "Replace a user")
+        .patch("/{id}", patch_user, "Update part of a user")
+        .delete("/{id}", delete_user, "Delete a user")
 }
 
 async fn list_users() -> &'static str {
@@ -136,10 +148,10 @@ async fn list_users() -> &'static str {
 }
 
 /// Create a sub-router for post-related routes
-fn post_routes() -> Router {
-    Router::new()
-        .route("/", get(list_posts))
-        .route("/{id}", get(get_post))
+fn post_routes() -> Routes {
+    Routes::new()
+        .get("/", list_posts, "List posts")
+        .get("/{id}", get_post, "Read one post")
 }
 
 async fn list_posts() -> &'static str {
@@ -155,17 +167,17 @@ async fn get_post(Path(id): Path<u64>) -> String {
 // ============================================================================
 
 /// Create an API v1 router by merging multiple routers
-fn api_v1_routes() -> Router {
-    Router::new()
+fn api_v1_routes() -> Routes {
+    Routes::new()
         .nest("/users", user_routes())
         .nest("/posts", post_routes())
 }
 
 /// You can also have multiple API versions
-fn api_v2_routes() -> Router {
-    Router::new()
-        .route("/users", get(|| async { "API v2 - Users endpoint" }))
-        .route("/posts", get(|| async { "API v2 - Posts endpoint" }))
+fn api_v2_routes() -> Routes {
+    Routes::new()
+        .get("/users", || async { "API v2 - Users endpoint" }, "v2 users")
+        .get("/posts", || async { "API v2 - Posts endpoint" }, "v2 posts")
 }
 
 // ============================================================================
@@ -176,53 +188,219 @@ async fn not_found() -> (axum::http::StatusCode, &'static str) {
     (axum::http::StatusCode::NOT_FOUND, "404 - Route not found")
 }
 
+// ============================================================================
+// LESSON 8: Automatic HEAD and OPTIONS
+// ============================================================================
+
+// HEAD is GET without the body; OPTIONS asks "which methods does this path
+// accept?". Writing both by hand for every route is tedious and drifts out of
+// sync, so one layer answers them for the whole app - nested routers included.
+//
+// The router is already the registry of methods per path: when a path matches
+// but the method doesn't, it answers 405 with an `Allow` header. The layer
+// turns that answer into a proper OPTIONS response, so it can never disagree
+// with the routes that are actually registered.
+
+async fn auto_head_options(request: Request, next: Next) -> Response {
+    match *request.method() {
+        Method::HEAD => {
+            // Run the GET handler, keep status and headers, drop the body
+            let (mut parts, body) = request.into_parts();
+            parts.method = Method::GET;
+            let response = next.run(Request::from_parts(parts, body)).await;
+
+            let (mut parts, body) = response.into_parts();
+            if let Some(length) = body.size_hint().exact() {
+                parts
+                    .headers
+                    .entry(header::CONTENT_LENGTH)
+                    .or_insert_with(|| HeaderValue::from(length));
+            }
+            Response::from_parts(parts, Body::empty())
+        }
+        Method::OPTIONS => {
+            let response = next.run(request).await;
+            // A route with its own OPTIONS handler, or an unknown path (404)
+            if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                return response;
+            }
+
+            let mut allow: Vec<String> = response
+                .headers()
+                .get(header::ALLOW)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .split(',')
+                .map(|m| m.trim().to_string())
+                .filter(|m| !m.is_empty())
+                .collect();
+            if !allow.iter().any(|m| m == "OPTIONS") {
+                allow.push("OPTIONS".to_string());
+            }
+
+            (StatusCode::NO_CONTENT, [(header::ALLOW, allow.join(", "))]).into_response()
+        }
+        _ => next.run(request).await,
+    }
+}
+
+/// Apply the layer around a finished router.
+///
+/// `Router::layer` wraps each route *inside* the router, and the 405 `Allow`
+/// header is added outside of that - the layer would never see it. Mounting
+/// the finished router as the fallback of an outer one puts the layer around
+/// the router's complete answer.
+fn with_auto_head_options(router: Router) -> Router {
+    Router::new()
+        .fallback_service(router)
+        .layer(middleware::from_fn(auto_head_options))
+}
+
+// ============================================================================
+// LESSON 9: Dynamic Routes from a Config File
+// ============================================================================
+
+// Everything above is fixed at compile time. `DynamicRoutes` builds a
+// sub-router from `routes.json` instead, and swaps in a fresh one when the
+// manifest is reloaded. It is mounted with `nest` like any other router, so
+// the manifest's `/about` is served at `/dynamic/about`.
+
+/// Where the manifest is read from; `ROUTES_MANIFEST` overrides it
+fn manifest_path() -> String {
+    std::env::var("ROUTES_MANIFEST")
+        .unwrap_or_else(|_| concat!(env!("CARGO_MANIFEST_DIR"), "/routes.json").to_string())
+}
+
+// ============================================================================
+// LESSON 10: Shadowing a Route to a Rewritten Handler
+// ============================================================================
+
+// `shipping_quote_v2` is a rewrite of `shipping_quote` with a rate table
+// instead of a formula. Before switching over, `shadow` serves every request
+// from v1, replays it against v2 in the background, and reports where the
+// two disagree at `GET /admin/shadow`. (v2 has a bug above 20 kg.)
+
+/// The handler in production: 5.00 base plus 1.20 per kg. A weight whose
+/// price doesn't fit a `u32` is refused rather than wrapped.
+async fn shipping_quote(
+    Path(kg): Path<u32>,
+) -> Result<Json<serde_json::Value>, (StatusCode, &'static str)> {
+    let cents = kg
+        .checked_mul(120)
+        .and_then(|per_kg| per_kg.checked_add(500))
+        .ok_or((StatusCode::BAD_REQUEST, "Too heavy to quote"))?;
+    Ok(Json(serde_json::json!({ "kg": kg, "cents": cents })))
+}
+
+/// The rewrite: the same prices from a table, which stops at 20 kg
+async fn shipping_quote_v2(Path(kg): Path<u32>) -> Json<serde_json::Value> {
+    let table: Vec<u32> = (0..=20).map(|kg| 500 + kg * 120).collect();
+    let cents = table[kg.min(20) as usize];
+    Json(serde_json::json!({ "cents": cents, "kg": kg }))
+}
+
+// ============================================================================
+// LESSON 11: An Endpoint Table Generated From the Routes
+// ============================================================================
+
+// Every route below goes through `Routes` (the `course-routes` crate) with a
+// one-line description. The table printed at startup is built from those
+// registrations, so it lists exactly what the router serves - add, rename
+// or remove a route and the banner follows.
+
 // ============================================================================
 // MAIN: Putting It All Together
 // ============================================================================
 
-#[tokio::main]
-async fn main() {
-    let app = Router::new()
+fn routes(dynamic: DynamicRoutes) -> Routes {
+    let quote = shadow(shipping_quote, shipping_quote_v2);
+    let shadow_stats = quote.stats();
+
+    Routes::new()
         // Basic routes
-        .route("/", get(|| async { "Welcome to the Routing Module!" }))
+        .get(
+            "/",
+            || async { "Welcome to the Routing Module!" },
+            "Welcome",
+        )
         // ===== HTTP METHODS DEMO =====
         // Each method demonstrated with a standalone route
-        .route("/resource", get(|| async { "GET - Read resource" }))
-        .route("/resource", post(|| async { "POST - Create resource" }))
-        .route(
+        .get("/resource", || async { "GET - Read resource" }, "Read all")
+        .post(
+            "/resource",
+            || async { "POST - Create resource" },
+            "Create new",
+        )
+        .get(
             "/resource/{id}",
-            get(|Path(id): Path<u64>| async move { format!("GET - Read resource {}", id) }),
+            |Path(id): Path<u64>| async move { format!("GET - Read resource {}", id) },
+            "Read one",
         )
-        .route(
+        .put(
             "/resource/{id}",
-            put(|Path(id): Path<u64>| async move { format!("PUT - Full update resource {}", id) }),
+            |Path(id): Path<u64>| async move { format!("PUT - Full update resource {}", id) },
+            "Full update",
         )
-        .route(
+        .patch(
             "/resource/{id}",
-            patch(|Path(id): Path<u64>| async move {
-                format!("PATCH - Partial update resource {}", id)
-            }),
+            |Path(id): Path<u64>| async move { format!("PATCH - Partial update resource {}", id) },
+            "Partial update",
         )
-        .route(
+        .delete(
             "/resource/{id}",
-            delete(|Path(id): Path<u64>| async move { format!("DELETE - Remove resource {}", id) }),
+            |Path(id): Path<u64>| async move { format!("DELETE - Remove resource {}", id) },
+            "Remove",
         )
         // Path parameters (new syntax!)
-        .route("/users/{id}/posts/{post_id}", get(get_user_post))
-        .route(
+        .get(
+            "/users/{id}/posts/{post_id}",
+            get_user_post,
+            "Two path params",
+        )
+        .get(
             "/users/{user_id}/posts/{post_id}/comments/{comment_id}",
-            get(get_comment),
+            get_comment,
+            "Path params into a struct",
         )
         // Wildcard route (must come after specific routes)
-        .route("/files/{*path}", get(files))
+        .get("/files/{*path}", files, "Wildcard: the rest of the path")
         // Query parameters
-        .route("/items", get(list_items))
-        .route("/search", get(search))
+        .get("/items", list_items, "?page=&limit=")
+        .get("/search", search, "?q=&category=&sort=")
         // Nested routers - creates /api/v1/users, /api/v1/posts, etc.
         .nest("/api/v1", api_v1_routes())
         .nest("/api/v2", api_v2_routes())
+        // Routes from the manifest, replaceable at runtime
+        .nest_router("/dynamic", dynamic.router(), "Routes from the manifest")
+        .get_service(
+            "/admin/routes",
+            dynamic_routes::describe.with_state(dynamic),
+            "The manifest routes now loaded",
+        )
+        // v1 answers, v2 is compared in the background
+        .get("/shipping/{kg}", quote, "v1 answers, v2 is diffed")
+        .get_service(
+            "/admin/shadow",
+            shadow::report.with_state(shadow_stats),
+            "Shadow matches and mismatches",
+        )
         // Fallback for unmatched routes
-        .fallback(not_found);
+        .fallback(not_found)
+}
+
+fn app(routes: Routes) -> Router {
+    with_auto_head_options(routes.into_router())
+}
+
+#[tokio::main]
+async fn main() {
+    let dynamic = DynamicRoutes::new(manifest_path());
+    let loaded = dynamic.reload();
+    #[cfg(unix)]
+    tokio::spawn(dynamic.clone().reload_on_sighup());
+    let routes = routes(dynamic);
+    let table = routes.to_string();
+    let app = app(routes);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
@@ -231,22 +409,280 @@ async fn main() {
     println!("🚀 Module 02: Routing Deep Dive");
     println!("   Server running on http://localhost:3000");
     println!();
-    println!("📝 HTTP Methods Demo:");
-    println!("   GET    /resource      - Read all");
-    println!("   POST   /resource      - Create new");
-    println!("   GET    /resource/123  - Read one");
-    println!("   PUT    /resource/123  - Full update");
-    println!("   PATCH  /resource/123  - Partial update");
-    println!("   DELETE /resource/123  - Remove");
-    println!();
-    println!("📝 Path Parameters (NEW SYNTAX!):");
-    println!("   GET /users/123/posts/456");
-    println!("   GET /files/docs/readme.md");
+    println!("📝 Endpoints (HEAD and OPTIONS are answered for every route):");
+    print!("{}", table);
     println!();
-    println!("📝 Nested Routes:");
-    println!("   GET  /api/v1/users");
-    println!("   POST /api/v1/users");
-    println!("   PUT  /api/v1/users/123");
+    println!("📝 Dynamic Routes ({}):", manifest_path());
+    match loaded {
+        Ok(count) => println!(
+            "   {} route(s) under /dynamic - GET /admin/routes to list them",
+            count
+        ),
+        Err(e) => println!("   ⚠️  none loaded: {}", e),
+    }
+    println!("   kill -HUP {}  - reload the manifest", std::process::id());
 
     axum::serve(listener, app).await.expect("Server failed");
 }
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use course_routes::conformance::{self, Probes};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt; // for `oneshot`
+
+    async fn send(method: Method, uri: &str) -> Response {
+        app(routes(DynamicRoutes::new("routes.json")))
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    fn allowed(response: &Response) -> Vec<&str> {
+        let mut methods: Vec<&str> = response.headers()[header::ALLOW]
+            .to_str()
+            .unwrap()
+            .split(", ")
+            .collect();
+        methods.sort_unstable();
+        methods
+    }
+
+    #[tokio::test]
+    async fn test_options_top_level_route() {
+        let response = send(Method::OPTIONS, "/resource").await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(allowed(&response), ["GET", "HEAD", "OPTIONS", "POST"]);
+    }
+
+    #[tokio::test]
+    async fn test_options_nested_route_with_params() {
+        let response = send(Method::OPTIONS, "/api/v1/users/7").await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            allowed(&response),
+            ["DELETE", "GET", "HEAD", "OPTIONS", "PATCH", "PUT"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_options_second_nested_router() {
+        let response = send(Method::OPTIONS, "/api/v2/posts").await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(allowed(&response), ["GET", "HEAD", "OPTIONS"]);
+    }
+
+    #[tokio::test]
+    async fn test_options_unknown_path_is_404() {
+        let response = send(Method::OPTIONS, "/api/v1/nope").await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_head_nested_route_matches_get_without_body() {
+        let get = send(Method::GET, "/api/v1/posts/1").await;
+        let head = send(Method::HEAD, "/api/v1/posts/1").await;
+
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(
+            head.headers()[header::CONTENT_TYPE],
+            get.headers()[header::CONTENT_TYPE]
+        );
+
+        let get_body = get.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            head.headers()[header::CONTENT_LENGTH],
+            get_body.len().to_string().as_str()
+        );
+        let head_body = head.into_body().collect().await.unwrap().to_bytes();
+        assert!(head_body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shadow_serves_primary_and_counts_mismatches() {
+        let app = app(routes(DynamicRoutes::new("routes.json")));
+
+        let same = app
+            .clone()
+            .oneshot(get_request("/shipping/10"))
+            .await
+            .unwrap();
+        let differs = app
+            .clone()
+            .oneshot(get_request("/shipping/30"))
+            .await
+            .unwrap();
+
+        // Always the primary's answer, key order included
+        assert_eq!(body_text(same).await, r#"{"cents":1700,"kg":10}"#);
+        assert_eq!(body_text(differs).await, r#"{"cents":4100,"kg":30}"#);
+
+        // The comparisons finish in the background
+        let mut report = serde_json::Value::Null;
+        for _ in 0..50 {
+            let response = app
+                .clone()
+                .oneshot(get_request("/admin/shadow"))
+                .await
+                .unwrap();
+            report = serde_json::from_str(&body_text(response).await).unwrap();
+            if report["compared"] == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(report["matched"], 1);
+        assert_eq!(report["body_mismatch"], 1);
+        assert_eq!(report["recent_mismatches"][0]["uri"], "/shipping/30");
+    }
+
+    #[tokio::test]
+    async fn test_shipping_quote_refuses_a_weight_it_cant_price() {
+        let app = app(routes(DynamicRoutes::new("routes.json")));
+        let uri = format!("/shipping/{}", u32::MAX);
+
+        let response = app.oneshot(get_request(&uri)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    fn get_request(uri: &str) -> Request {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_head_unknown_path_is_404() {
+        let response = send(Method::HEAD, "/api/v2/nope").await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// A manifest in the temp dir, unique per test
+    fn write_manifest(name: &str, json: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("module-02-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    async fn body_text(response: Response) -> String {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    async fn send_to(app: &Router, uri: &str) -> Response {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_static_and_template_routes() {
+        let path = write_manifest(
+            "serve",
+            r#"{"routes": [
+                {"path": "/about", "static": {"status": 202, "body": "About"}},
+                {"path": "/hello/{name}", "template": {
+                    "content_type": "text/html", "body": "<p>Hi {name}</p>"}}
+            ]}"#,
+        );
+        let dynamic = DynamicRoutes::new(&path);
+        assert_eq!(dynamic.reload().unwrap(), 2);
+        let app = app(routes(dynamic));
+
+        let about = send_to(&app, "/dynamic/about").await;
+        assert_eq!(about.status(), StatusCode::ACCEPTED);
+        assert_eq!(body_text(about).await, "About");
+
+        let hello = send_to(&app, "/dynamic/hello/%3Cb%3E").await;
+        assert_eq!(hello.headers()[header::CONTENT_TYPE], "text/html");
+        assert_eq!(body_text(hello).await, "<p>Hi &lt;b&gt;</p>");
+
+        let missing = send_to(&app, "/dynamic/nope").await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_reload_swaps_routes() {
+        let path = write_manifest(
+            "reload",
+            r#"{"routes": [{"path": "/old", "static": {"body": "old"}}]}"#,
+        );
+        let dynamic = DynamicRoutes::new(&path);
+        dynamic.reload().unwrap();
+        let app = app(routes(dynamic.clone()));
+        assert_eq!(send_to(&app, "/dynamic/old").await.status(), StatusCode::OK);
+
+        std::fs::write(
+            &path,
+            r#"{"routes": [{"path": "/new", "static": {"body": "new"}}]}"#,
+        )
+        .unwrap();
+        dynamic.reload().unwrap();
+
+        // Same `app`, new routes
+        assert_eq!(
+            send_to(&app, "/dynamic/old").await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(body_text(send_to(&app, "/dynamic/new").await).await, "new");
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_invalid_manifest_keeps_previous_routes() {
+        let path = write_manifest(
+            "invalid",
+            r#"{"routes": [{"path": "/keep", "static": {"body": "kept"}}]}"#,
+        );
+        let dynamic = DynamicRoutes::new(&path);
+        dynamic.reload().unwrap();
+        let app = app(routes(dynamic.clone()));
+
+        // Conflicting routes make `Router::route` panic; the reload must not
+        std::fs::write(
+            &path,
+            r#"{"routes": [
+                {"path": "/{a}", "static": {"body": "a"}},
+                {"path": "/{b}", "static": {"body": "b"}}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(dynamic.reload().is_err());
+        std::fs::write(&path, "not json").unwrap();
+        assert!(dynamic.reload().is_err());
+
+        assert_eq!(
+            body_text(send_to(&app, "/dynamic/keep").await).await,
+            "kept"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "404 and 405 are plain text here; module 07 turns them into JSON"]
+    async fn test_error_conformance() {
+        let probes = Probes {
+            get_route: "/api/v1/posts/1",
+            // No route here reads JSON
+            json_route: None,
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        conformance::assert_conforms(app(routes(DynamicRoutes::new("routes.json"))), &probes).await;
+    }
+}
@@ -0,0 +1,340 @@
+//! # Request Shadowing
+//!
+//! A rewritten handler passes its tests, but does it answer *real* traffic
+//! the way the old one did? `shadow(primary, candidate)` finds out without
+//! risking anything:
+//! 1. The request is buffered, so it can be replayed
+//! 2. `primary` handles it, and its response goes back to the client
+//! 3. In a background task, `candidate` handles a copy of the same request
+//! 4. The two responses are compared - status, then body, as JSON when both
+//!    parse - and the result is logged and counted in `ShadowStats`
+//!
+//! A primary response over `MAX_BODY` isn't compared: what was read of it
+//! goes out followed by the rest of the stream, and the candidate isn't run.
+//!
+//! The client never waits for the candidate, and never sees its answer or
+//! its errors. What shadowing can't protect against is side effects: the
+//! candidate really runs, so only shadow handlers that don't write, or
+//! whose writes go somewhere harmless.
+//!
+//! `shadow` returns a `Handler`, so it goes wherever a handler does:
+//! `get(shadow(quote_v1, quote_v2))`.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{MatchedPath, Request, State},
+    handler::Handler,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Largest request or primary response body that is buffered for a comparison
+const MAX_BODY: usize = 2 * 1024 * 1024;
+/// A candidate slower than this counts as failed
+const CANDIDATE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Mismatches kept for `GET /admin/shadow`
+const RECENT_MISMATCHES: usize = 10;
+
+// ============================================================================
+// STATS
+// ============================================================================
+
+#[derive(Default)]
+pub struct ShadowStats {
+    compared: AtomicU64,
+    matched: AtomicU64,
+    status_mismatch: AtomicU64,
+    body_mismatch: AtomicU64,
+    /// Timed out, or its body couldn't be read
+    candidate_failed: AtomicU64,
+    /// The primary's body was too large to buffer
+    skipped: AtomicU64,
+    recent: Mutex<VecDeque<Mismatch>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Mismatch {
+    pub route: String,
+    pub uri: String,
+    pub primary_status: u16,
+    pub candidate_status: u16,
+    pub primary_body: String,
+    pub candidate_body: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShadowReport {
+    pub compared: u64,
+    pub matched: u64,
+    pub status_mismatch: u64,
+    pub body_mismatch: u64,
+    pub candidate_failed: u64,
+    pub skipped: u64,
+    /// Newest first
+    pub recent_mismatches: Vec<Mismatch>,
+}
+
+impl ShadowStats {
+    pub fn report(&self) -> ShadowReport {
+        ShadowReport {
+            compared: self.compared.load(Ordering::Relaxed),
+            matched: self.matched.load(Ordering::Relaxed),
+            status_mismatch: self.status_mismatch.load(Ordering::Relaxed),
+            body_mismatch: self.body_mismatch.load(Ordering::Relaxed),
+            candidate_failed: self.candidate_failed.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            recent_mismatches: self.recent.lock().unwrap().iter().rev().cloned().collect(),
+        }
+    }
+
+    fn record_mismatch(&self, mismatch: Mismatch, status_differs: bool) {
+        let counter = if status_differs {
+            &self.status_mismatch
+        } else {
+            &self.body_mismatch
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        eprintln!(
+            "🔀 Shadow mismatch on {} ({}): primary {} {:?}, candidate {} {:?}",
+            mismatch.route,
+            mismatch.uri,
+            mismatch.primary_status,
+            mismatch.primary_body,
+            mismatch.candidate_status,
+            mismatch.candidate_body
+        );
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_MISMATCHES {
+            recent.pop_front();
+        }
+        recent.push_back(mismatch);
+    }
+}
+
+/// GET /admin/shadow
+pub async fn report(State(stats): State<Arc<ShadowStats>>) -> Json<ShadowReport> {
+    Json(stats.report())
+}
+
+// ============================================================================
+// THE COMBINATOR
+// ============================================================================
+
+/// `primary` answers; `candidate` runs on a copy and is only compared
+pub struct Shadow<P, C, TP, TC> {
+    primary: P,
+    candidate: C,
+    stats: Arc<ShadowStats>,
+    _extractors: PhantomData<fn() -> (TP, TC)>,
+}
+
+pub fn shadow<P, C, TP, TC>(primary: P, candidate: C) -> Shadow<P, C, TP, TC> {
+    Shadow {
+        primary,
+        candidate,
+        stats: Arc::default(),
+        _extractors: PhantomData,
+    }
+}
+
+impl<P, C, TP, TC> Shadow<P, C, TP, TC> {
+    /// The comparison counters; grab them before mounting the handler
+    pub fn stats(&self) -> Arc<ShadowStats> {
+        self.stats.clone()
+    }
+}
+
+impl<P: Clone, C: Clone, TP, TC> Clone for Shadow<P, C, TP, TC> {
+    fn clone(&self) -> Self {
+        Self {
+            primary: self.primary.clone(),
+            candidate: self.candidate.clone(),
+            stats: self.stats.clone(),
+            _extractors: PhantomData,
+        }
+    }
+}
+
+/// `Handler`'s first parameter tells impls apart; this one is ours alone
+pub struct ShadowHandler<TP, TC>(PhantomData<fn() -> (TP, TC)>);
+
+impl<P, C, TP, TC, S> Handler<ShadowHandler<TP, TC>, S> for Shadow<P, C, TP, TC>
+where
+    P: Handler<TP, S>,
+    C: Handler<TC, S>,
+    TP: 'static,
+    TC: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+    fn call(self, req: Request, state: S) -> Self::Future {
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let Ok(body) = to_bytes(body, MAX_BODY).await else {
+                return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+            };
+            let copy = Request::from_parts(parts.clone(), Body::from(body.clone()));
+            let route = parts
+                .extensions
+                .get::<MatchedPath>()
+                .map_or_else(|| parts.uri.path().to_string(), |p| p.as_str().to_string());
+            let uri = parts.uri.to_string();
+
+            let response = self
+                .primary
+                .call(Request::from_parts(parts, Body::from(body)), state.clone())
+                .await;
+            // Keep a copy of the primary's body to compare against
+            let (head, body) = response.into_parts();
+            let body = match buffer(body).await {
+                Buffered::Whole(body) => body,
+                Buffered::Streamed(body) => {
+                    self.stats.skipped.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("🔀 Shadow skipped {} ({}): response too large", route, uri);
+                    return Response::from_parts(head, body);
+                }
+            };
+
+            let primary = (head.status, body.clone());
+            let (candidate, stats) = (self.candidate, self.stats);
+            tokio::spawn(async move {
+                let answer = tokio::time::timeout(CANDIDATE_TIMEOUT, async {
+                    let response = candidate.call(copy, state).await;
+                    let status = response.status();
+                    to_bytes(response.into_body(), MAX_BODY)
+                        .await
+                        .map(|body| (status, body))
+                })
+                .await;
+                stats.compared.fetch_add(1, Ordering::Relaxed);
+                match answer {
+                    Ok(Ok(candidate)) => compare(&stats, route, uri, primary, candidate),
+                    _ => {
+                        stats.candidate_failed.fetch_add(1, Ordering::Relaxed);
+                        eprintln!("🔀 Shadow candidate failed on {} ({})", route, uri);
+                    }
+                }
+            });
+
+            Response::from_parts(head, Body::from(body))
+        })
+    }
+}
+
+enum Buffered {
+    Whole(Bytes),
+    /// Over `MAX_BODY`, or it failed: the chunks read so far, then the rest
+    Streamed(Body),
+}
+
+/// Reads `body` into memory unless it is larger than `MAX_BODY`
+async fn buffer(body: Body) -> Buffered {
+    let mut rest = body.into_data_stream();
+    let mut chunks = Vec::new();
+    let mut len = 0;
+    while let Some(chunk) = rest.next().await {
+        let Ok(chunk) = chunk else {
+            let read = stream::iter(chunks.into_iter().map(Ok));
+            return Buffered::Streamed(Body::from_stream(read.chain(stream::iter([chunk]))));
+        };
+        len += chunk.len();
+        chunks.push(chunk);
+        if len > MAX_BODY {
+            let read = stream::iter(chunks.into_iter().map(Ok));
+            return Buffered::Streamed(Body::from_stream(read.chain(rest)));
+        }
+    }
+    Buffered::Whole(chunks.concat().into())
+}
+
+fn compare(
+    stats: &ShadowStats,
+    route: String,
+    uri: String,
+    (primary_status, primary_body): (StatusCode, Bytes),
+    (candidate_status, candidate_body): (StatusCode, Bytes),
+) {
+    let status_differs = primary_status != candidate_status;
+    if !status_differs && same_body(&primary_body, &candidate_body) {
+        stats.matched.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    stats.record_mismatch(
+        Mismatch {
+            route,
+            uri,
+            primary_status: primary_status.as_u16(),
+            candidate_status: candidate_status.as_u16(),
+            primary_body: preview(&primary_body),
+            candidate_body: preview(&candidate_body),
+        },
+        status_differs,
+    );
+}
+
+/// JSON bodies are compared as values, so key order and whitespace don't count
+fn same_body(a: &[u8], b: &[u8]) -> bool {
+    match (
+        serde_json::from_slice::<serde_json::Value>(a),
+        serde_json::from_slice::<serde_json::Value>(b),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn preview(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+    match text.char_indices().nth(200) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn large() -> String {
+        "x".repeat(MAX_BODY + 1)
+    }
+
+    async fn small() -> &'static str {
+        "small"
+    }
+
+    #[tokio::test]
+    async fn test_a_large_primary_response_streams_through_uncompared() {
+        let quote = shadow(large, small);
+        let stats = quote.stats();
+        let app = Router::new().route("/", get(quote));
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body.len(), MAX_BODY + 1);
+        let report = stats.report();
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.compared, 0);
+    }
+}
@@ -0,0 +1,14 @@
+//! Generates Rust types from `proto/*.proto` at build time.
+//!
+//! prost-build needs a `protoc` binary. We use the vendored one unless the
+//! `PROTOC` environment variable already points at a local install.
+
+fn main() -> std::io::Result<()> {
+    if std::env::var_os("PROTOC").is_none() {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc not available");
+        std::env::set_var("PROTOC", protoc);
+    }
+
+    println!("cargo:rerun-if-changed=proto/contact.proto");
+    prost_build::compile_protos(&["proto/contact.proto"], &["proto/"])
+}
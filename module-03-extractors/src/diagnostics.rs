@@ -0,0 +1,514 @@
+//! # Extraction Diagnostics
+//!
+//! The built-in `Json`/`Query` rejections say *that* something is wrong.
+//! These drop-in extractors say exactly *what*: the offending field, its
+//! JSON pointer or query key, the expected type, the received value, and a
+//! snippet of the raw input around the error.
+//!
+//! The trick is `serde_path_to_error`, which wraps any serde `Deserializer`
+//! and records the path to the field that failed.
+//!
+//! `DiagnosticPath<T>` does the same for path parameters, checking them
+//! against the shapes in the `RouteRegistry` and suggesting a route that
+//! fits when the value doesn't.
+
+use axum::{
+    body::Bytes,
+    extract::{
+        path::ErrorKind, rejection::PathRejection, FromRef, FromRequest, FromRequestParts,
+        MatchedPath, RawPathParams, Request,
+    },
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_path_to_error::{Path, Segment};
+
+use crate::route_registry::RouteRegistry;
+
+// ============================================================================
+// DIAGNOSTIC REPORT
+// ============================================================================
+
+/// A learner-friendly description of why extraction failed
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    #[serde(skip)]
+    status: StatusCode,
+    error: &'static str,
+    /// "body", "query" or "path"
+    location: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field: Option<String>,
+    /// RFC 6901 pointer for body errors, e.g. `/address/zip`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pointer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    received: Option<String>,
+    /// Raw input around the error position
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
+    /// Routes that would accept the input
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    suggestions: Vec<String>,
+    /// Correlation id, for handlers that want it in their rejections too
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(status: StatusCode, error: &'static str, location: &'static str, message: String) -> Self {
+        Self {
+            status,
+            error,
+            location,
+            message,
+            field: None,
+            pointer: None,
+            expected: None,
+            received: None,
+            snippet: None,
+            suggestions: Vec::new(),
+            request_id: None,
+        }
+    }
+
+    /// Tag the report with the request's correlation id
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+}
+
+impl IntoResponse for Diagnostic {
+    fn into_response(self) -> Response {
+        (self.status, Json(self)).into_response()
+    }
+}
+
+// ============================================================================
+// SERDE MESSAGE PARSING
+// ============================================================================
+
+/// serde_json appends " at line 1 column 12" - drop exactly that suffix,
+/// rebuilt from `line()`/`column()` rather than searched for in the text
+fn strip_position(err: &serde_json::Error) -> String {
+    let message = err.to_string();
+    if err.line() == 0 {
+        return message;
+    }
+    let suffix = format!(" at line {} column {}", err.line(), err.column());
+    match message.strip_suffix(&suffix) {
+        Some(stripped) => stripped.to_string(),
+        None => message,
+    }
+}
+
+/// "invalid type: string \"abc\", expected u32" -> ("string \"abc\"", "u32")
+fn split_expected(message: &str) -> Option<(String, String)> {
+    let rest = message
+        .strip_prefix("invalid type: ")
+        .or_else(|| message.strip_prefix("invalid value: "))?;
+    let (received, expected) = rest.split_once(", expected ")?;
+    Some((received.to_string(), expected.to_string()))
+}
+
+/// "missing field `email`" -> "email"
+fn backticked(message: &str) -> Option<String> {
+    let start = message.find('`')? + 1;
+    let end = start + message[start..].find('`')?;
+    Some(message[start..end].to_string())
+}
+
+/// Segments of the path, e.g. ["address", "zip"] or ["tags", "0"]
+fn segments(path: &Path) -> Vec<String> {
+    path.iter()
+        .filter_map(|segment| match segment {
+            Segment::Seq { index } => Some(index.to_string()),
+            Segment::Map { key } => Some(key.clone()),
+            Segment::Enum { variant } => Some(variant.clone()),
+            Segment::Unknown => None,
+        })
+        .collect()
+}
+
+fn json_pointer(segments: &[String]) -> String {
+    segments
+        .iter()
+        .map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// A window of the raw body around (line, column), both 1-based as
+/// serde_json reports them; column 0 means "before the first character"
+fn snippet(body: &[u8], line: usize, column: usize) -> Option<String> {
+    let text = std::str::from_utf8(body).ok()?;
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let offset = (line_start + column.saturating_sub(1)).min(text.len());
+
+    let mut start = offset.saturating_sub(24);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (offset + 8).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    Some(text[start..end].replace('\n', " "))
+}
+
+// ============================================================================
+// DiagnosticJson<T>
+// ============================================================================
+
+/// `Json<T>` with detailed diagnostics on failure
+pub struct DiagnosticJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for DiagnosticJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Diagnostic;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+        if !is_json {
+            return Err(Diagnostic::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "body",
+                "Expected Content-Type: application/json".to_string(),
+            ));
+        }
+
+        let body = Bytes::from_request(req, state).await.map_err(|e| {
+            Diagnostic::new(StatusCode::BAD_REQUEST, "body_read", "body", e.body_text())
+        })?;
+
+        let deserializer = &mut serde_json::Deserializer::from_slice(&body);
+        serde_path_to_error::deserialize(deserializer)
+            .map(DiagnosticJson)
+            .map_err(|err| json_diagnostic(&body, err))
+    }
+}
+
+fn json_diagnostic(body: &[u8], err: serde_path_to_error::Error<serde_json::Error>) -> Diagnostic {
+    let mut path = segments(err.path());
+    let inner = err.inner();
+    let message = strip_position(inner);
+
+    let (status, error) = if inner.is_data() {
+        (StatusCode::UNPROCESSABLE_ENTITY, "invalid_field")
+    } else {
+        (StatusCode::BAD_REQUEST, "invalid_json")
+    };
+    let error = if message.starts_with("duplicate field") {
+        "duplicate_key"
+    } else {
+        error
+    };
+
+    // For missing/duplicate/unknown fields the path points at the parent object
+    if message.starts_with("missing field")
+        || message.starts_with("duplicate field")
+        || message.starts_with("unknown field")
+    {
+        path.extend(backticked(&message));
+    }
+
+    let mut diagnostic = Diagnostic::new(status, error, "body", message);
+    if let Some((received, expected)) = split_expected(&diagnostic.message) {
+        diagnostic.received = Some(received);
+        diagnostic.expected = Some(expected);
+    }
+    if !path.is_empty() {
+        diagnostic.field = Some(path.join("."));
+        diagnostic.pointer = Some(json_pointer(&path));
+    }
+    if inner.line() > 0 {
+        diagnostic.snippet = snippet(body, inner.line(), inner.column());
+    }
+    diagnostic
+}
+
+// ============================================================================
+// DiagnosticQuery<T>
+// ============================================================================
+
+/// `Query<T>` with detailed diagnostics on failure, and duplicate-key detection
+pub struct DiagnosticQuery<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for DiagnosticQuery<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Diagnostic;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let pairs: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+
+        // `?page=1&page=2` - which one did the client mean? Refuse to guess.
+        for (i, (key, _)) in pairs.iter().enumerate() {
+            if pairs[..i].iter().any(|(k, _)| k == key) {
+                let values: Vec<&str> = pairs
+                    .iter()
+                    .filter(|(k, _)| k == key)
+                    .map(|(_, v)| v.as_str())
+                    .collect();
+                let mut diagnostic = Diagnostic::new(
+                    StatusCode::BAD_REQUEST,
+                    "duplicate_key",
+                    "query",
+                    format!("Query parameter `{}` was given more than once", key),
+                );
+                diagnostic.field = Some(key.clone());
+                diagnostic.received = Some(values.join(", "));
+                return Err(diagnostic);
+            }
+        }
+
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer)
+            .map(DiagnosticQuery)
+            .map_err(|err| query_diagnostic(&pairs, err))
+    }
+}
+
+fn query_diagnostic(
+    pairs: &[(String, String)],
+    err: serde_path_to_error::Error<serde_urlencoded::de::Error>,
+) -> Diagnostic {
+    let mut path = segments(err.path());
+    let message = err.inner().to_string();
+    if message.starts_with("missing field") || message.starts_with("unknown field") {
+        path.extend(backticked(&message));
+    }
+
+    let mut diagnostic = Diagnostic::new(StatusCode::BAD_REQUEST, "invalid_query", "query", message);
+    let key = path.join(".");
+
+    // Query values are parsed with `FromStr`, so the message rarely names
+    // the type - translate the common ones
+    diagnostic.expected = split_expected(&diagnostic.message)
+        .map(|(_, expected)| expected)
+        .or_else(|| {
+            let hint = match diagnostic.message.as_str() {
+                "invalid digit found in string" => "an integer",
+                "number too large to fit in target type" => "a smaller integer",
+                "number too small to fit in target type" => "a non-negative integer",
+                "invalid float literal" => "a number",
+                "provided string was not `true` or `false`" => "`true` or `false`",
+                _ => return None,
+            };
+            Some(hint.to_string())
+        });
+    diagnostic.received = pairs
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v.clone());
+    if !key.is_empty() {
+        diagnostic.snippet = diagnostic
+            .received
+            .as_ref()
+            .map(|v| format!("{}={}", key, v));
+        diagnostic.field = Some(key);
+    }
+    diagnostic
+}
+
+// ============================================================================
+// DiagnosticPath<T>
+// ============================================================================
+
+/// `Path<T>` with strict parameter checks and route suggestions on failure
+pub struct DiagnosticPath<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for DiagnosticPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+    RouteRegistry: FromRef<S>,
+{
+    type Rejection = Diagnostic;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let registry = RouteRegistry::from_ref(state);
+        let matched = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(|m| m.as_str().to_string())
+            .unwrap_or_default();
+        let raw = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(|e| {
+                Diagnostic::new(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_path",
+                    "path",
+                    e.body_text(),
+                )
+            })?;
+        let params: Vec<(&str, &str)> = raw.iter().collect();
+        let values: Vec<&str> = params.iter().map(|(_, value)| *value).collect();
+        let request_path = parts.uri.path().to_string();
+
+        let fail = |key: &str, received: &str, expected: String| {
+            let mut diagnostic = Diagnostic::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_path_param",
+                "path",
+                format!("Path parameter `{}` must be {}", key, expected),
+            );
+            diagnostic.field = Some(key.to_string());
+            diagnostic.expected = Some(expected);
+            diagnostic.received = Some(received.to_string());
+            diagnostic.snippet = Some(request_path.clone());
+            diagnostic.suggestions = registry.suggest(&matched, &values);
+            diagnostic
+        };
+
+        // Strict check against the registered shape first: `FromStr` would
+        // let `/users/+7` and `/users/007` through
+        if let Some(route) = registry.lookup(&matched) {
+            for ((key, value), (_, kind)) in params.iter().zip(route.params()) {
+                if !kind.accepts(value) {
+                    return Err(fail(key, value, kind.describe().to_string()));
+                }
+            }
+        }
+
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(DiagnosticPath(value)),
+            Err(PathRejection::FailedToDeserializePathParams(err)) => {
+                // A single-value `Path<u64>` reports no key; use the route's
+                let first_key = params.first().map_or("", |(key, _)| *key);
+                Err(match err.kind() {
+                    ErrorKind::ParseErrorAtKey {
+                        key,
+                        value,
+                        expected_type,
+                    } => fail(key, value, expected_type.to_string()),
+                    ErrorKind::ParseErrorAtIndex {
+                        index,
+                        value,
+                        expected_type,
+                    } => fail(
+                        params.get(*index).map_or(first_key, |(key, _)| *key),
+                        value,
+                        expected_type.to_string(),
+                    ),
+                    ErrorKind::ParseError {
+                        value,
+                        expected_type,
+                    } => fail(first_key, value, expected_type.to_string()),
+                    _ => Diagnostic::new(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_path",
+                        "path",
+                        err.body_text(),
+                    ),
+                })
+            }
+            Err(rejection) => Err(Diagnostic::new(
+                rejection.status(),
+                "invalid_path",
+                "path",
+                rejection.body_text(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Signup {
+        name: String,
+        age: u32,
+    }
+
+    fn diagnose(body: &str) -> Diagnostic {
+        let deserializer = &mut serde_json::Deserializer::from_str(body);
+        let err = serde_path_to_error::deserialize::<_, Signup>(deserializer).unwrap_err();
+        json_diagnostic(body.as_bytes(), err)
+    }
+
+    #[test]
+    fn strips_only_the_reported_position() {
+        let err = serde_json::from_str::<Signup>(r#"{"name": 1}"#).unwrap_err();
+        assert!(err.to_string().ends_with(" at line 1 column 10"));
+        assert_eq!(
+            strip_position(&err),
+            "invalid type: integer `1`, expected a string"
+        );
+    }
+
+    #[test]
+    fn snippet_is_centred_on_the_offending_byte() {
+        let body = r#"{"name": "a fairly long name here", "age": x, "extra": true}"#;
+        let diagnostic = diagnose(body);
+        assert_eq!(diagnostic.error, "invalid_json");
+        assert_eq!(diagnostic.message, "expected value");
+
+        // 24 bytes of context before the `x`, then the `x` and 7 more
+        let at = body.find('x').unwrap();
+        assert_eq!(diagnostic.snippet.as_deref(), Some(&body[at - 24..at + 8]));
+    }
+
+    #[test]
+    fn snippet_finds_the_column_on_later_lines() {
+        let body = "{\n  \"name\": \"a fairly long name here\",\n  \"age\": ?\n}";
+        let diagnostic = diagnose(body);
+        let at = body.find('?').unwrap();
+        let expected = body[at - 24..].replace('\n', " ");
+        assert_eq!(diagnostic.snippet, Some(expected));
+    }
+
+    #[test]
+    fn type_errors_name_the_field() {
+        let diagnostic = diagnose(r#"{"name": "Ada", "age": "old"}"#);
+        assert_eq!(diagnostic.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(diagnostic.field.as_deref(), Some("age"));
+        assert_eq!(diagnostic.pointer.as_deref(), Some("/age"));
+        assert_eq!(diagnostic.expected.as_deref(), Some("u32"));
+        assert_eq!(diagnostic.received.as_deref(), Some("string \"old\""));
+    }
+
+    #[test]
+    fn missing_fields_point_at_the_field_itself() {
+        let diagnostic = diagnose(r#"{"name": "Ada"}"#);
+        assert_eq!(diagnostic.message, "missing field `age`");
+        assert_eq!(diagnostic.pointer.as_deref(), Some("/age"));
+    }
+
+    #[test]
+    fn request_id_is_only_serialized_when_set() {
+        let plain = serde_json::to_value(diagnose("{}")).unwrap();
+        assert!(plain.get("request_id").is_none());
+
+        let tagged = serde_json::to_value(diagnose("{}").with_request_id("req-1")).unwrap();
+        assert_eq!(tagged["request_id"], "req-1");
+    }
+}
@@ -9,17 +9,42 @@
 //! - NEW: No more #[async_trait] needed!
 //! - Custom extractors
 //! - Extractor ordering (important!)
+//! - Request IDs: an extractor backed by a middleware
+//! - Locale negotiation from `Accept-Language`
+//! - Streaming request bodies with an enforced size limit
+//! - Binary wire formats: a `Protobuf<T>` extractor/response (prost + build.rs)
+//! - Precise Query/Json error diagnostics (see `diagnostics.rs`)
+//! - Strict Path parameters with route suggestions (see `route_registry.rs`)
+//! - Multipart uploads with a fixed field order and CSRF check (see `strict_multipart.rs`)
+//! - Form posts checked for spam: honeypot field and signed fill time (see `spam_check.rs`)
+
+mod diagnostics;
+mod route_registry;
+mod spam_check;
+mod strict_multipart;
 
 use axum::{
-    body::Bytes,
-    extract::{FromRequest, FromRequestParts, Path, Query, Request, State},
-    http::{header::HeaderMap, request::Parts, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{get, post},
-    Json, Router,
+    body::{BodyDataStream, Bytes},
+    extract::{
+        DefaultBodyLimit, FromRef, FromRequest, FromRequestParts, OptionalFromRequestParts, Path,
+        Query, Request, State,
+    },
+    handler::Handler,
+    http::{header, header::HeaderMap, request::Parts, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    Json,
 };
+use course_routes::Routes;
+use diagnostics::{Diagnostic, DiagnosticJson, DiagnosticPath, DiagnosticQuery};
+use futures::StreamExt;
+use route_registry::{ParamKind, RouteRegistry};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use sha2::{Digest, Sha256};
+use spam_check::{SpamCheckedForm, SpamGuard};
+use std::{convert::Infallible, sync::Arc};
+use strict_multipart::{StrictMultipart, UploadRejection, CSRF_FIELD};
+use uuid::Uuid;
 
 // ============================================================================
 // LESSON 1: Built-in Extractors
@@ -27,7 +52,10 @@ use std::sync::Arc;
 
 /// Path extractor - extracts path parameters
 /// Route: GET /users/{id}
-async fn get_user(Path(id): Path<u64>) -> String {
+///
+/// `DiagnosticPath` is a stricter `Path` (see LESSON 12); `/users/abc`
+/// gets a 400 that suggests `/users/by-name/abc`
+async fn get_user(DiagnosticPath(id): DiagnosticPath<u64>) -> String {
     format!("User ID: {}", id)
 }
 
@@ -138,8 +166,8 @@ async fn optional_query(Query(params): Query<ListParams>) -> String {
 // LESSON 4: Custom Extractor - NO MORE #[async_trait]!
 // ============================================================================
 
-/// In Axum 0.8, you don't need #[async_trait] anymore!
-/// Rust now supports `impl Future<Output = _>` in traits natively.
+// In Axum 0.8, you don't need #[async_trait] anymore!
+// Rust now supports `impl Future<Output = _>` in traits natively.
 
 /// A custom extractor for API keys
 struct ApiKey(String);
@@ -190,6 +218,47 @@ async fn protected_endpoint(ApiKey(key): ApiKey) -> String {
     format!("Access granted! Your API key: {}", key)
 }
 
+// NEW IN AXUM 0.8: `Option<ApiKey>` only works if we say what "optional" means.
+//
+// Here: no header at all -> `None` (anonymous caller), but a header that is
+// present and malformed is still rejected. A typo'd key should produce an
+// error, not silently downgrade the caller to anonymous.
+impl<S> OptionalFromRequestParts<S> for ApiKey
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiKeyError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        match parts.headers.get("x-api-key") {
+            None => Ok(None),
+            Some(value) => match value.to_str() {
+                Ok(key) if !key.is_empty() => Ok(Some(ApiKey(key.to_string()))),
+                _ => Err(ApiKeyError),
+            },
+        }
+    }
+}
+
+/// Same endpoint, different behaviour for anonymous vs keyed callers
+async fn rate_limits(api_key: Option<ApiKey>) -> Json<serde_json::Value> {
+    match api_key {
+        Some(ApiKey(key)) => Json(serde_json::json!({
+            "caller": "api_key",
+            "key_prefix": key.chars().take(4).collect::<String>(),
+            "requests_per_hour": 5000
+        })),
+        None => Json(serde_json::json!({
+            "caller": "anonymous",
+            "requests_per_hour": 60,
+            "hint": "Send X-API-Key for a higher limit"
+        })),
+    }
+}
+
 // ============================================================================
 // LESSON 5: Custom Extractor with Body
 // ============================================================================
@@ -205,7 +274,7 @@ struct ValidatedJson<T>(T);
 
 #[derive(Debug)]
 enum ValidationError {
-    InvalidJson(String),
+    InvalidJson(Box<Diagnostic>),
     InvalidEmail,
     NameTooShort,
 }
@@ -213,9 +282,8 @@ enum ValidationError {
 impl IntoResponse for ValidationError {
     fn into_response(self) -> Response {
         let (status, message) = match self {
-            ValidationError::InvalidJson(e) => {
-                (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e))
-            }
+            // Field-level diagnostics are already a complete response
+            ValidationError::InvalidJson(diagnostic) => return (*diagnostic).into_response(),
             ValidationError::InvalidEmail => {
                 (StatusCode::BAD_REQUEST, "Invalid email format".to_string())
             }
@@ -236,28 +304,25 @@ where
 {
     type Rejection = ValidationError;
 
-    fn from_request(
-        req: Request,
-        state: &S,
-    ) -> impl std::future::Future<Output = Result<Self, Self::Rejection>> + Send {
-        async move {
-            // Extract JSON first
-            let Json(user): Json<ValidatedUser> = Json::from_request(req, state)
+    // Plain `async fn` works too - it desugars to the same `impl Future`
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        // Extract JSON first (with precise diagnostics on failure)
+        let DiagnosticJson(user): DiagnosticJson<ValidatedUser> =
+            DiagnosticJson::from_request(req, state)
                 .await
-                .map_err(|e| ValidationError::InvalidJson(e.to_string()))?;
-
-            // Validate name length
-            if user.name.len() < 2 {
-                return Err(ValidationError::NameTooShort);
-            }
+                .map_err(|d| ValidationError::InvalidJson(Box::new(d)))?;
 
-            // Validate email (simple check)
-            if !user.email.contains('@') {
-                return Err(ValidationError::InvalidEmail);
-            }
+        // Validate name length
+        if user.name.len() < 2 {
+            return Err(ValidationError::NameTooShort);
+        }
 
-            Ok(ValidatedJson(user))
+        // Validate email (simple check)
+        if !user.email.contains('@') {
+            return Err(ValidationError::InvalidEmail);
         }
+
+        Ok(ValidatedJson(user))
     }
 }
 
@@ -273,40 +338,737 @@ async fn create_validated_user(ValidatedJson(user): ValidatedJson<ValidatedUser>
 struct AppState {
     db_pool: String, // In real app, this would be a database pool
     api_version: String,
+    locales: LocaleConfig,
+    routes: RouteRegistry,
+    spam: SpamGuard,
 }
 
 async fn with_state(State(state): State<Arc<AppState>>) -> String {
     format!("API Version: {}, DB: {}", state.api_version, state.db_pool)
 }
 
+// ============================================================================
+// LESSON 7: Request ID Extractor + Middleware
+// ============================================================================
+
+/// Header used to carry the correlation id in and out
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The correlation id of the current request
+///
+/// The middleware below stores it in the request extensions; the extractor
+/// just reads it back. If the middleware isn't installed we generate one,
+/// so handlers can always rely on having an id.
+#[derive(Debug, Clone)]
+struct RequestId(String);
+
+impl RequestId {
+    fn generate() -> Self {
+        RequestId(Uuid::new_v4().to_string())
+    }
+
+    /// Accept a client-supplied id only if it looks sane (no header injection,
+    /// no megabyte-long log lines)
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= 64
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| RequestId(value.to_string()))
+    }
+}
+
+/// Middleware: reuse the incoming `x-request-id` or generate one,
+/// make it available to handlers and echo it in the response
+async fn request_id_middleware(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::generate);
+
+    request.extensions_mut().insert(request_id.clone());
+
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// This extractor can never fail, so the rejection type is `Infallible`
+impl<S> FromRequestParts<S> for RequestId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(request_id) = parts.extensions.get::<RequestId>() {
+            return Ok(request_id.clone());
+        }
+
+        // No middleware in front of us - generate one and remember it so
+        // every extractor in this request sees the same id
+        let request_id = RequestId::generate();
+        parts.extensions.insert(request_id.clone());
+        Ok(request_id)
+    }
+}
+
+/// JSON error body that always carries the correlation id
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    request_id: String,
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+    request_id: RequestId,
+}
+
+impl ApiError {
+    fn new(request_id: RequestId, status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            request_id,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: self.message,
+            request_id: self.request_id.0,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Handlers take `RequestId` like any other extractor and use it in
+/// logs and error payloads
+///
+/// The path is taken as a `Result` so its rejection can carry the id too:
+/// an extractor that fails never reaches the handler otherwise.
+async fn get_order(
+    request_id: RequestId,
+    path: Result<DiagnosticPath<u64>, Diagnostic>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let DiagnosticPath(id) = path.map_err(|diagnostic| {
+        diagnostic
+            .with_request_id(request_id.0.clone())
+            .into_response()
+    })?;
+    println!("[{}] looking up order {}", request_id.0, id);
+
+    if id != 42 {
+        return Err(ApiError::new(
+            request_id,
+            StatusCode::NOT_FOUND,
+            format!("Order {} not found", id),
+        )
+        .into_response());
+    }
+
+    Ok(Json(serde_json::json!({
+        "id": id,
+        "item": "Rust book",
+        "request_id": request_id.0
+    })))
+}
+
+// ============================================================================
+// LESSON 8: Locale Extractor (Accept-Language + State)
+// ============================================================================
+
+/// Which locales the app can serve, configured in state
+#[derive(Debug, Clone)]
+struct LocaleConfig {
+    supported: Vec<String>,
+    default: String,
+}
+
+// `FromRef` lets the extractor pull just this piece out of the app state
+impl FromRef<Arc<AppState>> for LocaleConfig {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        state.locales.clone()
+    }
+}
+
+/// The best supported locale for this request, e.g. "fr"
+#[derive(Debug, Clone)]
+struct Locale(String);
+
+/// Parse `Accept-Language` into tags ordered by preference
+///
+/// `fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5` -> ["fr-ch", "fr", "en", "*"]
+/// Entries with `q=0` mean "not acceptable" and are dropped, and so are
+/// entries whose parameters don't parse: a broken `q` isn't a `q=1`.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.trim().split(';');
+            let tag = pieces.next()?.trim().to_lowercase();
+            let mut q = 1.0;
+            for param in pieces.filter(|p| !p.trim().is_empty()) {
+                let (name, value) = param.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = parse_qvalue(value.trim())?;
+                }
+            }
+            (!tag.is_empty() && q > 0.0).then_some((tag, q))
+        })
+        .collect();
+
+    // Stable sort keeps header order for equal q-values
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// A weight is `0` to `1` with at most three decimals (RFC 9110 12.4.2);
+/// anything else - `q=abc`, `q=2`, `q=inf` - is `None`
+fn parse_qvalue(value: &str) -> Option<f32> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let well_formed = matches!(whole, "0" | "1")
+        && fraction.len() <= 3
+        && fraction.bytes().all(|b| b.is_ascii_digit());
+    let q = value.parse::<f32>().ok()?;
+    (well_formed && q <= 1.0).then_some(q)
+}
+
+impl LocaleConfig {
+    /// Pick the first requested tag we support: exact match first,
+    /// then the primary language ("en-US" -> "en"), then the default
+    fn negotiate(&self, requested: &[String]) -> String {
+        for tag in requested {
+            if tag == "*" {
+                return self.default.clone();
+            }
+            let primary = tag.split('-').next().unwrap_or(tag);
+            let found = self
+                .supported
+                .iter()
+                .find(|s| s.eq_ignore_ascii_case(tag))
+                .or_else(|| {
+                    self.supported
+                        .iter()
+                        .find(|s| s.eq_ignore_ascii_case(primary))
+                });
+            if let Some(locale) = found {
+                return locale.clone();
+            }
+        }
+        self.default.clone()
+    }
+}
+
+impl<S> FromRequestParts<S> for Locale
+where
+    S: Send + Sync,
+    LocaleConfig: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = LocaleConfig::from_ref(state);
+        let requested = parts
+            .headers
+            .get("accept-language")
+            .and_then(|v| v.to_str().ok())
+            .map(parse_accept_language)
+            .unwrap_or_default();
+
+        Ok(Locale(config.negotiate(&requested)))
+    }
+}
+
+async fn greeting(Locale(locale): Locale) -> Json<serde_json::Value> {
+    let message = match locale.as_str() {
+        "fr" => "Bonjour !",
+        "es" => "¡Hola!",
+        "de" => "Hallo!",
+        _ => "Hello!",
+    };
+    Json(serde_json::json!({ "locale": locale, "message": message }))
+}
+
+// ============================================================================
+// LESSON 9: Streaming Body Extractor with a Size Limit
+// ============================================================================
+
+/// `Bytes`/`String`/`Json` buffer the whole body in memory before your
+/// handler runs. For large uploads, consume the body as a stream instead and
+/// process it chunk by chunk - memory use stays at one chunk.
+///
+/// `LIMIT` is a const generic so each route can pick its own maximum.
+struct LimitedStream<const LIMIT: usize> {
+    stream: BodyDataStream,
+    received: usize,
+}
+
+#[derive(Debug)]
+enum StreamError {
+    TooLarge(usize),
+    Body(axum::Error),
+}
+
+impl IntoResponse for StreamError {
+    fn into_response(self) -> Response {
+        match self {
+            StreamError::TooLarge(limit) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Body exceeds the limit of {} bytes", limit),
+            )
+                .into_response(),
+            StreamError::Body(e) => (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to read body: {}", e),
+            )
+                .into_response(),
+        }
+    }
+}
+
+impl<S, const LIMIT: usize> FromRequest<S> for LimitedStream<LIMIT>
+where
+    S: Send + Sync,
+{
+    type Rejection = StreamError;
+
+    async fn from_request(req: Request, _state: &S) -> Result<Self, Self::Rejection> {
+        // Fail fast if the client already told us the body is too big...
+        let declared = req
+            .headers()
+            .get("content-length")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if declared.is_some_and(|len| len > LIMIT) {
+            return Err(StreamError::TooLarge(LIMIT));
+        }
+
+        // ...but never trust it: `next_chunk` counts the real bytes too
+        Ok(LimitedStream {
+            stream: req.into_body().into_data_stream(),
+            received: 0,
+        })
+    }
+}
+
+impl<const LIMIT: usize> LimitedStream<LIMIT> {
+    /// Next chunk of the body, or `None` at the end
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, StreamError> {
+        match self.stream.next().await {
+            None => Ok(None),
+            Some(Err(e)) => Err(StreamError::Body(e)),
+            Some(Ok(chunk)) => {
+                self.received += chunk.len();
+                if self.received > LIMIT {
+                    return Err(StreamError::TooLarge(LIMIT));
+                }
+                Ok(Some(chunk))
+            }
+        }
+    }
+}
+
+/// Hash an upload of up to 10 MB without ever holding it in memory
+async fn hash_upload(
+    mut body: LimitedStream<{ 10 * 1024 * 1024 }>,
+) -> Result<Json<serde_json::Value>, StreamError> {
+    let mut hasher = Sha256::new();
+    let mut chunks = 0;
+
+    while let Some(chunk) = body.next_chunk().await? {
+        hasher.update(&chunk);
+        chunks += 1;
+    }
+
+    Ok(Json(serde_json::json!({
+        "bytes": body.received,
+        "chunks": chunks,
+        "sha256": format!("{:x}", hasher.finalize())
+    })))
+}
+
+// ============================================================================
+// LESSON 10: Protobuf Extractor & Response
+// ============================================================================
+
+/// Types generated by `build.rs` from `proto/contact.proto`
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/course.extractors.rs"));
+}
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Like `Json<T>`, but for protobuf messages - works as an extractor AND a response
+struct Protobuf<T>(T);
+
+#[derive(Debug)]
+enum ProtobufRejection {
+    UnsupportedMediaType,
+    Body(String),
+    Decode(prost::DecodeError),
+}
+
+impl IntoResponse for ProtobufRejection {
+    fn into_response(self) -> Response {
+        match self {
+            ProtobufRejection::UnsupportedMediaType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("Expected Content-Type: {}", PROTOBUF_CONTENT_TYPE),
+            )
+                .into_response(),
+            ProtobufRejection::Body(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+            ProtobufRejection::Decode(e) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Invalid protobuf message: {}", e),
+            )
+                .into_response(),
+        }
+    }
+}
+
+impl<T, S> FromRequest<S> for Protobuf<T>
+where
+    T: prost::Message + Default,
+    S: Send + Sync,
+{
+    type Rejection = ProtobufRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        // Content-type enforcement, same as `Json` does for application/json
+        let content_type = req
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(str::trim);
+        if !matches!(
+            content_type,
+            Some("application/x-protobuf") | Some("application/protobuf")
+        ) {
+            return Err(ProtobufRejection::UnsupportedMediaType);
+        }
+
+        // Reuse `Bytes` so the default body limit still applies
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| ProtobufRejection::Body(e.body_text()))?;
+
+        T::decode(bytes)
+            .map(Protobuf)
+            .map_err(ProtobufRejection::Decode)
+    }
+}
+
+impl<T: prost::Message> IntoResponse for Protobuf<T> {
+    fn into_response(self) -> Response {
+        (
+            [("content-type", PROTOBUF_CONTENT_TYPE)],
+            self.0.encode_to_vec(),
+        )
+            .into_response()
+    }
+}
+
+/// GET a sample message, e.g. to feed back into the POST endpoint
+async fn sample_contact() -> Protobuf<proto::Contact> {
+    Protobuf(proto::Contact {
+        id: 1,
+        name: "Ferris".to_string(),
+        email: "ferris@example.com".to_string(),
+        tags: vec!["rust".to_string(), "crab".to_string()],
+    })
+}
+
+/// Round trip: decode, normalize, encode
+async fn echo_contact(Protobuf(mut contact): Protobuf<proto::Contact>) -> Protobuf<proto::Contact> {
+    if contact.id == 0 {
+        contact.id = 42;
+    }
+    contact.email = contact.email.to_lowercase();
+    contact.tags.sort();
+    contact.tags.dedup();
+    Protobuf(contact)
+}
+
+// ============================================================================
+// LESSON 11: Precise Error Diagnostics for Query & Json
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)] // Fields shown for demonstration
+struct Address {
+    city: String,
+    zip: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)] // Fields shown for demonstration
+struct Profile {
+    name: String,
+    age: u8,
+    address: Address,
+    tags: Vec<String>,
+}
+
+/// Try `?page=abc` or `?page=1&page=2`
+async fn diagnostic_query(DiagnosticQuery(params): DiagnosticQuery<ListParams>) -> String {
+    format!("Parsed: {:?}", params)
+}
+
+/// Try `{"name":"Ann","age":"old",...}` or a wrong `address.zip`
+async fn diagnostic_json(DiagnosticJson(profile): DiagnosticJson<Profile>) -> String {
+    format!("Parsed: {:?}", profile)
+}
+
+// ============================================================================
+// LESSON 12: Strict Path Parameters with Route Suggestions
+// ============================================================================
+
+// `Path<u64>` on `/users/abc` answers with a plain-text 400. `DiagnosticPath`
+// answers with the parameter, the expected type, and - looked up in the
+// route registry - routes that would accept the value.
+
+impl FromRef<Arc<AppState>> for RouteRegistry {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        state.routes.clone()
+    }
+}
+
+/// Every route that extracts `DiagnosticPath`, with its parameter kinds
+fn route_registry() -> RouteRegistry {
+    RouteRegistry::default()
+        .register("/users/{id}", &[ParamKind::Id])
+        .register("/users/by-name/{name}", &[ParamKind::Text])
+        .register("/orders/{id}", &[ParamKind::Id])
+        .register("/orders/by-ref/{reference}", &[ParamKind::Uuid])
+}
+
+/// The route `/users/abc` is pointed to
+async fn get_user_by_name(DiagnosticPath(name): DiagnosticPath<String>) -> String {
+    format!("User named: {}", name)
+}
+
+/// Try `/orders/42` with a UUID instead: you're sent here
+async fn get_order_by_ref(DiagnosticPath(reference): DiagnosticPath<Uuid>) -> String {
+    format!("Order with reference: {}", reference)
+}
+
+// ============================================================================
+// LESSON 13: Strict Multipart Uploads with CSRF Protection
+// ============================================================================
+
+// `StrictMultipart` reads `csrf_token`, then `metadata`, then `file`, and
+// rejects the upload at the first field that's wrong - before the file's
+// bytes are buffered. It enforces its own per-field limits, so the route
+// lifts the default 2 MB body limit.
+
+#[derive(Debug, Deserialize, Serialize)]
+struct UploadMeta {
+    title: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Largest accepted `file` field
+const MAX_UPLOAD_BYTES: usize = 5 * 1024 * 1024;
+
+/// Issues a CSRF token as a cookie and embeds the same token in the form
+async fn upload_form() -> impl IntoResponse {
+    let token = Uuid::new_v4().simple().to_string();
+    let cookie = format!(
+        "{}={}; Path=/uploads; HttpOnly; SameSite=Strict",
+        CSRF_FIELD, token
+    );
+    // Field order in the form is the order the browser sends them in
+    let page = format!(
+        r#"<!doctype html>
+<form method="post" action="/uploads" enctype="multipart/form-data">
+  <input type="hidden" name="{CSRF_FIELD}" value="{token}">
+  <textarea name="metadata">{{"title": "My upload", "tags": ["demo"]}}</textarea>
+  <input type="file" name="file">
+  <button>Upload</button>
+</form>"#
+    );
+    ([(header::SET_COOKIE, cookie)], Html(page))
+}
+
+async fn strict_upload(
+    upload: StrictMultipart<UploadMeta, MAX_UPLOAD_BYTES>,
+) -> Result<Json<serde_json::Value>, UploadRejection> {
+    let file = upload.file;
+    Ok(Json(serde_json::json!({
+        "metadata": upload.metadata,
+        "file_name": file.file_name,
+        "content_type": file.content_type,
+        "bytes": file.bytes.len(),
+        "sha256": format!("{:x}", Sha256::digest(&file.bytes)),
+    })))
+}
+
+// ============================================================================
+// LESSON 14: Spam-Checked Form Posts
+// ============================================================================
+
+// `SpamCheckedForm<T>` is `Form<T>` plus a honeypot field and a minimum
+// time between rendering the form and posting it. The form page embeds
+// both with `SpamGuard::hidden_fields()`.
+
+impl FromRef<Arc<AppState>> for SpamGuard {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        state.spam.clone()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ContactMessage {
+    name: String,
+    message: String,
+}
+
+/// A contact form, stamped with the time it was rendered
+async fn contact_form(State(state): State<Arc<AppState>>) -> Html<String> {
+    Html(format!(
+        r#"<!doctype html>
+<form method="post" action="/contact">
+  {}
+  <input name="name" placeholder="Your name">
+  <textarea name="message" placeholder="Message"></textarea>
+  <button>Send</button>
+</form>"#,
+        state.spam.hidden_fields()
+    ))
+}
+
+async fn send_contact(
+    SpamCheckedForm(message): SpamCheckedForm<ContactMessage>,
+) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "received": message }))
+}
+
 // ============================================================================
 // MAIN: Putting It All Together
 // ============================================================================
 
-#[tokio::main]
-async fn main() {
+fn routes() -> Routes {
     // Create shared state
     let state = Arc::new(AppState {
         db_pool: "postgres://localhost/mydb".to_string(),
         api_version: "v1.0.0".to_string(),
+        locales: LocaleConfig {
+            supported: vec!["en".into(), "fr".into(), "es".into(), "de".into()],
+            default: "en".to_string(),
+        },
+        routes: route_registry(),
+        spam: SpamGuard::from_env(),
     });
 
-    let app = Router::new()
+    Routes::new()
         // Built-in extractors
-        .route("/users/{id}", get(get_user))
-        .route("/users", get(list_users).post(create_user))
-        .route("/headers", get(show_headers))
-        .route("/raw", post(raw_body))
+        .get(
+            "/users/{id}",
+            get_user,
+            "Path extractor (strict: /users/abc suggests routes)",
+        )
+        .get(
+            "/users/by-name/{name}",
+            get_user_by_name,
+            "Path extractor for a name",
+        )
+        .get("/users", list_users, "Query extractor (?page=2&limit=5)")
+        .post("/users", create_user, "Json extractor")
+        .get("/headers", show_headers, "Headers extractor")
+        .post("/raw", raw_body, "Raw body as a String")
+        .post("/stream/hash", hash_upload, "Streaming body (max 10 MB)")
+        .get("/protobuf/contact", sample_contact, "Protobuf response")
+        .post("/protobuf/contact", echo_contact, "Protobuf round trip")
+        // Extraction diagnostics
+        .get(
+            "/diagnostics/query",
+            diagnostic_query,
+            "Query errors with field-level detail",
+        )
+        .post(
+            "/diagnostics/json",
+            diagnostic_json,
+            "Json errors with JSON pointer + snippet",
+        )
         // Multiple extractors
-        .route("/users/{id}/update", post(combined_extractors))
+        .post(
+            "/users/{id}/update",
+            combined_extractors,
+            "Path, query and body together",
+        )
         // Optional extractors
-        .route("/optional", get(optional_query))
+        .get("/optional", optional_query, "Optional query parameters")
         // Custom extractors
-        .route("/protected", get(protected_endpoint))
-        .route("/validated", post(create_validated_user))
+        .get(
+            "/protected",
+            protected_endpoint,
+            "API key (Header: X-API-Key)",
+        )
+        .get(
+            "/rate-limits",
+            rate_limits,
+            "Optional API key (anonymous or keyed)",
+        )
+        .post("/validated", create_validated_user, "Validated JSON body")
         // State extractor
-        .route("/state", get(with_state))
-        .with_state(state);
+        .get("/state", with_state, "State extractor")
+        // Request ID extractor
+        .get(
+            "/orders/{id}",
+            get_order,
+            "Request ID in responses and errors",
+        )
+        .get(
+            "/orders/by-ref/{reference}",
+            get_order_by_ref,
+            "Request ID with a string key",
+        )
+        // Locale extractor
+        .get("/greeting", greeting, "Locale from Accept-Language")
+        // Strict multipart upload
+        .get(
+            "/uploads/form",
+            upload_form,
+            "Upload form with a CSRF token",
+        )
+        .post(
+            "/uploads",
+            strict_upload.layer(DefaultBodyLimit::disable()),
+            "Strict multipart: token, metadata, then file",
+        )
+        // Spam-checked form
+        .get(
+            "/contact",
+            contact_form,
+            "Contact form with a honeypot and a signed stamp",
+        )
+        .post(
+            "/contact",
+            send_contact,
+            "422 if the honeypot is filled or it came too fast",
+        )
+        .with_state(state)
+        .layer(middleware::from_fn(request_id_middleware))
+}
+
+#[tokio::main]
+async fn main() {
+    let routes = routes();
+    let table = routes.to_string();
+    let app = routes.into_router();
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
@@ -315,15 +1077,8 @@ async fn main() {
     println!("🚀 Module 03: Extractors Deep Dive");
     println!("   Server running on http://localhost:3000");
     println!();
-    println!("📝 Built-in Extractors:");
-    println!("   GET  /users/123          - Path extractor");
-    println!("   GET  /users?page=2       - Query extractor");
-    println!("   POST /users              - Json extractor");
-    println!("   GET  /headers            - Headers extractor");
-    println!();
-    println!("📝 Custom Extractors:");
-    println!("   GET  /protected          - API key (Header: X-API-Key)");
-    println!("   POST /validated          - Validated JSON body");
+    println!("📝 Endpoints:");
+    print!("{}", table);
     println!();
     println!("💡 Examples:");
     println!("   curl http://localhost:3000/users?page=2&limit=5");
@@ -334,3 +1089,49 @@ async fn main() {
 
     axum::serve(listener, app).await.expect("Server failed");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use course_routes::conformance::{self, Probes};
+
+    #[test]
+    fn test_parse_accept_language_drops_unacceptable_and_malformed_entries() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5"),
+            ["fr-ch", "fr", "en", "*"]
+        );
+        // q=0 is "not acceptable", in any spelling
+        assert_eq!(parse_accept_language("fr;q=0, de;q=0.000, en"), ["en"]);
+        // A broken weight drops its entry instead of promoting it to q=1
+        for broken in [
+            "q=abc", "q=", "q=2", "q=1.5", "q=inf", "q=NaN", "q=-1", "q=0.1234",
+        ] {
+            assert_eq!(
+                parse_accept_language(&format!("fr;{}, en;q=0.5", broken)),
+                ["en"],
+                "{}",
+                broken
+            );
+        }
+        // Parameter names are case-insensitive, and spaces are allowed
+        assert_eq!(
+            parse_accept_language("fr;Q=0.2, en ; q = 0.7"),
+            ["en", "fr"]
+        );
+        assert_eq!(parse_accept_language("fr;, en;q=1.000"), ["fr", "en"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "extractor rejections stay Axum's plain text, the subject of this module"]
+    async fn test_error_conformance() {
+        let probes = Probes {
+            get_route: "/users/1",
+            json_route: Some("/users"),
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        conformance::assert_conforms(routes().into_router(), &probes).await;
+    }
+}
@@ -0,0 +1,161 @@
+//! # Route Registry
+//!
+//! The router knows which routes exist but not what their parameters are
+//! supposed to look like. This registry records each route's shape, e.g.
+//! `/users/{id}` takes an unsigned integer, so `DiagnosticPath` can:
+//! - check parameters strictly (`/users/007` and `/users/+7` are rejected)
+//! - name the expected type in its error
+//! - suggest a route that *would* accept the value, e.g. `/users/abc` ->
+//!   `/users/by-name/abc`
+
+use std::sync::Arc;
+
+/// What a path parameter must look like
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    /// `u64` in canonical form: digits only, no sign, no leading zeros
+    Id,
+    /// A hyphenated UUID, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    Uuid,
+    /// Any non-empty segment
+    Text,
+}
+
+impl ParamKind {
+    pub fn describe(self) -> &'static str {
+        match self {
+            ParamKind::Id => "an unsigned integer (u64) without sign or leading zeros",
+            ParamKind::Uuid => "a hyphenated UUID",
+            ParamKind::Text => "a non-empty string",
+        }
+    }
+
+    /// Stricter than `FromStr`, which happily takes `+7` and `007`
+    pub fn accepts(self, value: &str) -> bool {
+        match self {
+            ParamKind::Id => {
+                value.bytes().all(|b| b.is_ascii_digit())
+                    && (value == "0" || !value.starts_with('0'))
+                    && value.parse::<u64>().is_ok()
+            }
+            ParamKind::Uuid => value.len() == 36 && uuid::Uuid::try_parse(value).is_ok(),
+            ParamKind::Text => !value.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(&'static str),
+    Param(&'static str, ParamKind),
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteSpec {
+    pattern: &'static str,
+    segments: Vec<Segment>,
+}
+
+impl RouteSpec {
+    /// The kind of each `{param}`, in order
+    pub fn params(&self) -> impl Iterator<Item = (&'static str, ParamKind)> + '_ {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Param(name, kind) => Some((*name, *kind)),
+            Segment::Literal(_) => None,
+        })
+    }
+
+    fn literals(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Literal(literal) => Some(*literal),
+            Segment::Param(..) => None,
+        })
+    }
+
+    /// The route with `values` substituted for its parameters
+    fn fill(&self, values: &[&str]) -> String {
+        let mut values = values.iter();
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => format!("/{}", literal),
+                Segment::Param(..) => format!("/{}", values.next().unwrap_or(&"")),
+            })
+            .collect()
+    }
+}
+
+/// The shape of every route that uses `DiagnosticPath`
+#[derive(Debug, Clone, Default)]
+pub struct RouteRegistry {
+    routes: Arc<Vec<RouteSpec>>,
+}
+
+impl RouteRegistry {
+    /// `kinds` gives the type of each `{param}` in `pattern`, in order
+    pub fn register(mut self, pattern: &'static str, kinds: &[ParamKind]) -> Self {
+        let mut kinds = kinds.iter();
+        let segments = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(
+                |s| match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                    Some(name) => Segment::Param(
+                        name,
+                        *kinds.next().unwrap_or_else(|| {
+                            panic!("{}: no kind given for {{{}}}", pattern, name)
+                        }),
+                    ),
+                    None => Segment::Literal(s),
+                },
+            )
+            .collect();
+        assert!(
+            kinds.next().is_none(),
+            "{}: more kinds than params",
+            pattern
+        );
+
+        Arc::make_mut(&mut self.routes).push(RouteSpec { pattern, segments });
+        self
+    }
+
+    pub fn lookup(&self, pattern: &str) -> Option<&RouteSpec> {
+        self.routes.iter().find(|route| route.pattern == pattern)
+    }
+
+    /// Other routes that would accept these values. "Nearby" means one
+    /// route's literal segments are the other's plus some extra ones, so
+    /// `/users/{id}` and `/users/by-name/{name}` point at each other.
+    pub fn suggest(&self, matched: &str, values: &[&str]) -> Vec<String> {
+        let matched_literals: Vec<&str> = self
+            .lookup(matched)
+            .map(|route| route.literals().collect())
+            .unwrap_or_default();
+
+        self.routes
+            .iter()
+            .filter(|route| route.pattern != matched)
+            .filter(|route| {
+                let literals: Vec<&str> = route.literals().collect();
+                is_subsequence(&matched_literals, &literals)
+                    || is_subsequence(&literals, &matched_literals)
+            })
+            .filter(|route| {
+                let kinds: Vec<ParamKind> = route.params().map(|(_, kind)| kind).collect();
+                kinds.len() == values.len()
+                    && kinds
+                        .iter()
+                        .zip(values)
+                        .all(|(kind, value)| kind.accepts(value))
+            })
+            .map(|route| route.fill(values))
+            .collect()
+    }
+}
+
+/// Every item of `short` appears in `long`, in the same order
+fn is_subsequence(short: &[&str], long: &[&str]) -> bool {
+    let mut long = long.iter();
+    short.iter().all(|wanted| long.any(|item| item == wanted))
+}
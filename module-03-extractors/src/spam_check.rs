@@ -0,0 +1,220 @@
+//! # Spam-Checked Form Posts
+//!
+//! A public form without a login gets posted to by bots. Two cheap checks
+//! catch most of them without bothering people with a CAPTCHA:
+//! - **Honeypot**: the form has a `website` field hidden from people with
+//!   CSS. Humans leave it empty; bots fill in every field they find.
+//! - **Fill time**: the form carries the time it was rendered. A human takes
+//!   a few seconds to type; a bot posts within milliseconds. The time is
+//!   signed (HMAC-SHA256), so a bot can't just send an older one.
+//!
+//! `SpamCheckedForm<T>` is a `Form<T>` that runs both checks first. The
+//! rendering side is `SpamGuard::hidden_fields()`, which goes in the form.
+//!
+//! Every spam rejection is the same `422` with a generic message - telling
+//! a bot *which* check it failed teaches it to pass. The reason is logged.
+//!
+//! Stamps are accepted for `max_age` (an hour), so one can't be harvested
+//! once and reused forever. `T` must not `deny_unknown_fields`: the check's
+//! own fields are in the same body.
+
+use axum::{
+    body::Bytes,
+    extract::{FromRef, FromRequest, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::Sha256;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Hidden from people, irresistible to bots
+pub const HONEYPOT_FIELD: &str = "website";
+/// `<unix millis>.<hex HMAC of them>`
+pub const RENDERED_AT_FIELD: &str = "rendered_at";
+
+const DEV_SECRET: &str = "form-secret-change-in-production";
+
+/// Signing key and thresholds, in the app state
+#[derive(Clone)]
+pub struct SpamGuard {
+    secret: Arc<[u8]>,
+    /// Faster than this from render to post is a bot
+    pub min_fill_time: Duration,
+    /// Older stamps are refused, so they can't be reused indefinitely
+    pub max_age: Duration,
+}
+
+impl SpamGuard {
+    pub fn new(secret: &str, min_fill_time: Duration) -> Self {
+        Self {
+            secret: secret.as_bytes().into(),
+            min_fill_time,
+            max_age: Duration::from_secs(60 * 60),
+        }
+    }
+
+    /// `FORM_SECRET` and `SPAM_MIN_FILL_SECS` (default 3)
+    pub fn from_env() -> Self {
+        let secret = std::env::var("FORM_SECRET").unwrap_or_else(|_| {
+            println!("⚠️  FORM_SECRET not set - using the development secret");
+            DEV_SECRET.to_string()
+        });
+        let min_fill_secs = std::env::var("SPAM_MIN_FILL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3);
+        Self::new(&secret, Duration::from_secs(min_fill_secs))
+    }
+
+    fn mac(&self, millis: u64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("any key length works");
+        mac.update(millis.to_string().as_bytes());
+        mac
+    }
+
+    /// A signed stamp for "rendered now"
+    pub fn stamp(&self) -> String {
+        let millis = unix_millis();
+        let signature = self.mac(millis).finalize().into_bytes();
+        format!("{}.{:x}", millis, signature)
+    }
+
+    /// The honeypot and the stamp, to put inside a `<form>`
+    pub fn hidden_fields(&self) -> String {
+        format!(
+            r#"<div style="position: absolute; left: -10000px" aria-hidden="true">
+    <label>Leave this empty <input name="{HONEYPOT_FIELD}" tabindex="-1" autocomplete="off"></label>
+  </div>
+  <input type="hidden" name="{RENDERED_AT_FIELD}" value="{}">"#,
+            self.stamp()
+        )
+    }
+
+    /// When the form was rendered, if the stamp is ours
+    fn verify(&self, stamp: &str) -> Option<u64> {
+        let (millis, signature) = stamp.split_once('.')?;
+        let millis: u64 = millis.parse().ok()?;
+        let signature = decode_hex(signature)?;
+        // `verify_slice` compares in constant time
+        self.mac(millis).verify_slice(&signature).ok()?;
+        Some(millis)
+    }
+
+    fn check(&self, fields: &SpamFields) -> Result<(), &'static str> {
+        if fields.website.as_deref().is_some_and(|v| !v.is_empty()) {
+            return Err("honeypot filled in");
+        }
+        let stamp = fields.rendered_at.as_deref().ok_or("no render stamp")?;
+        let rendered = self.verify(stamp).ok_or("bad render stamp")?;
+        let elapsed = Duration::from_millis(unix_millis().saturating_sub(rendered));
+        if elapsed < self.min_fill_time {
+            return Err("submitted too fast");
+        }
+        if elapsed > self.max_age {
+            return Err("render stamp expired");
+        }
+        Ok(())
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// ============================================================================
+// THE EXTRACTOR
+// ============================================================================
+
+/// `Form<T>`, from a post that passed the honeypot and fill-time checks
+pub struct SpamCheckedForm<T>(pub T);
+
+/// The check's own fields, read from the same body as `T`
+#[derive(Deserialize)]
+struct SpamFields {
+    website: Option<String>,
+    rendered_at: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SpamRejection {
+    NotAForm,
+    Body(String),
+    /// Real validation errors are fine to explain
+    Invalid(String),
+    /// Why stays in the log
+    Spam(&'static str),
+}
+
+impl IntoResponse for SpamRejection {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            SpamRejection::NotAForm => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected an application/x-www-form-urlencoded body".to_string(),
+            ),
+            SpamRejection::Body(e) => (StatusCode::BAD_REQUEST, e),
+            SpamRejection::Invalid(e) => (StatusCode::UNPROCESSABLE_ENTITY, e),
+            SpamRejection::Spam(reason) => {
+                println!("🚫 Rejected a form post as spam: {}", reason);
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Your submission could not be accepted".to_string(),
+                )
+            }
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+impl<S, T> FromRequest<S> for SpamCheckedForm<T>
+where
+    S: Send + Sync,
+    SpamGuard: FromRef<S>,
+    T: DeserializeOwned,
+{
+    type Rejection = SpamRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+        if !is_form {
+            return Err(SpamRejection::NotAForm);
+        }
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| SpamRejection::Body(e.body_text()))?;
+
+        // Spam first: a bot's post isn't worth validating
+        let fields: SpamFields = serde_urlencoded::from_bytes(&body)
+            .map_err(|e| SpamRejection::Invalid(e.to_string()))?;
+        SpamGuard::from_ref(state)
+            .check(&fields)
+            .map_err(SpamRejection::Spam)?;
+
+        serde_urlencoded::from_bytes(&body)
+            .map(SpamCheckedForm)
+            .map_err(|e| SpamRejection::Invalid(e.to_string()))
+    }
+}
@@ -0,0 +1,224 @@
+//! # Strict Multipart Uploads
+//!
+//! `Multipart` hands you fields in whatever order the client sent them. For
+//! an upload form that's a problem: by the time you find out the CSRF token
+//! is wrong or the metadata doesn't parse, you may have received megabytes
+//! of file you're about to throw away.
+//!
+//! `StrictMultipart<M, LIMIT>` declares the form's schema and checks it as
+//! the body streams in:
+//! 1. `csrf_token` - must match the `csrf_token` cookie (double-submit)
+//! 2. `metadata` - JSON that deserializes into `M`
+//! 3. `file` - at most `LIMIT` bytes, buffered only after 1 and 2 passed
+//!
+//! Anything else - a missing field, a field out of order, an extra field
+//! after `file` - is rejected the moment it's seen, with `Connection: close`
+//! so the server stops reading the rest of the body. Browsers send fields in
+//! the order they appear in the `<form>`, so a real form always passes;
+//! `GET /uploads/form` serves one.
+
+use axum::{
+    body::Bytes,
+    extract::{multipart::Field, FromRequest, Multipart, Request},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+
+/// Name of both the cookie and the form field carrying the token
+pub const CSRF_FIELD: &str = "csrf_token";
+const METADATA_FIELD: &str = "metadata";
+const FILE_FIELD: &str = "file";
+/// The text fields are small; a huge "token" is an attack, not a typo
+const MAX_TOKEN_BYTES: usize = 256;
+const MAX_METADATA_BYTES: usize = 16 * 1024;
+
+/// A validated upload: the token matched, `metadata` parsed, `file` fit
+pub struct StrictMultipart<M, const LIMIT: usize> {
+    pub metadata: M,
+    pub file: UploadedFile,
+}
+
+pub struct UploadedFile {
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub bytes: Bytes,
+}
+
+// ============================================================================
+// REJECTIONS
+// ============================================================================
+
+#[derive(Debug)]
+pub enum UploadRejection {
+    NotMultipart(String),
+    /// `expected` was due, `found` arrived (`None`: the body ended)
+    OutOfOrder {
+        expected: &'static str,
+        found: Option<String>,
+    },
+    UnexpectedField(String),
+    MissingCsrfCookie,
+    CsrfMismatch,
+    InvalidMetadata(String),
+    TooLarge {
+        field: &'static str,
+        limit: usize,
+    },
+    Body(String),
+}
+
+impl IntoResponse for UploadRejection {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            UploadRejection::NotMultipart(e) => (StatusCode::BAD_REQUEST, e),
+            UploadRejection::OutOfOrder { expected, found } => (
+                StatusCode::BAD_REQUEST,
+                match found {
+                    Some(found) => format!("Expected field `{}`, got `{}`", expected, found),
+                    None => format!("Expected field `{}`, but the form ended", expected),
+                },
+            ),
+            UploadRejection::UnexpectedField(name) => (
+                StatusCode::BAD_REQUEST,
+                format!("Unexpected field `{}` after `{}`", name, FILE_FIELD),
+            ),
+            UploadRejection::MissingCsrfCookie => (
+                StatusCode::FORBIDDEN,
+                "No CSRF cookie: load GET /uploads/form first".to_string(),
+            ),
+            UploadRejection::CsrfMismatch => (
+                StatusCode::FORBIDDEN,
+                "CSRF token doesn't match the cookie".to_string(),
+            ),
+            UploadRejection::InvalidMetadata(e) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Invalid `{}`: {}", METADATA_FIELD, e),
+            ),
+            UploadRejection::TooLarge { field, limit } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Field `{}` exceeds {} bytes", field, limit),
+            ),
+            UploadRejection::Body(e) => (StatusCode::BAD_REQUEST, e),
+        };
+        // Tell hyper not to keep the connection (and drain the unread body)
+        (
+            status,
+            [(header::CONNECTION, "close")],
+            Json(serde_json::json!({ "error": message })),
+        )
+            .into_response()
+    }
+}
+
+// ============================================================================
+// THE EXTRACTOR
+// ============================================================================
+
+impl<S, M, const LIMIT: usize> FromRequest<S> for StrictMultipart<M, LIMIT>
+where
+    S: Send + Sync,
+    M: DeserializeOwned + Send,
+{
+    type Rejection = UploadRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        // Read the cookie before the body: no cookie, no reason to read on
+        let cookie = csrf_cookie(req.headers()).ok_or(UploadRejection::MissingCsrfCookie)?;
+        let mut multipart = Multipart::from_request(req, state)
+            .await
+            .map_err(|e| UploadRejection::NotMultipart(e.body_text()))?;
+
+        let token = expect_field(&mut multipart, CSRF_FIELD).await?;
+        let token = read_limited(token, CSRF_FIELD, MAX_TOKEN_BYTES).await?;
+        if !constant_time_eq(&token, cookie.as_bytes()) {
+            return Err(UploadRejection::CsrfMismatch);
+        }
+
+        let metadata = expect_field(&mut multipart, METADATA_FIELD).await?;
+        let metadata = read_limited(metadata, METADATA_FIELD, MAX_METADATA_BYTES).await?;
+        let metadata: M = serde_json::from_slice(&metadata)
+            .map_err(|e| UploadRejection::InvalidMetadata(e.to_string()))?;
+
+        // Only now is the file worth receiving
+        let file = expect_field(&mut multipart, FILE_FIELD).await?;
+        let file_name = file.file_name().map(str::to_string);
+        let content_type = file.content_type().map(str::to_string);
+        let bytes = read_limited(file, FILE_FIELD, LIMIT).await?;
+
+        if let Some(extra) = next_field(&mut multipart).await? {
+            return Err(UploadRejection::UnexpectedField(
+                extra.name().unwrap_or_default().to_string(),
+            ));
+        }
+
+        Ok(StrictMultipart {
+            metadata,
+            file: UploadedFile {
+                file_name,
+                content_type,
+                bytes,
+            },
+        })
+    }
+}
+
+async fn next_field(multipart: &mut Multipart) -> Result<Option<Field<'_>>, UploadRejection> {
+    multipart
+        .next_field()
+        .await
+        .map_err(|e| UploadRejection::Body(e.body_text()))
+}
+
+/// The next field, which must be called `name`
+async fn expect_field<'a>(
+    multipart: &'a mut Multipart,
+    name: &'static str,
+) -> Result<Field<'a>, UploadRejection> {
+    let field = next_field(multipart).await?;
+    match field {
+        Some(field) if field.name() == Some(name) => Ok(field),
+        other => Err(UploadRejection::OutOfOrder {
+            expected: name,
+            found: other.map(|field| field.name().unwrap_or_default().to_string()),
+        }),
+    }
+}
+
+/// Buffer a field chunk by chunk, stopping as soon as it passes `limit`
+async fn read_limited(
+    mut field: Field<'_>,
+    name: &'static str,
+    limit: usize,
+) -> Result<Bytes, UploadRejection> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| UploadRejection::Body(e.body_text()))?
+    {
+        if buffer.len() + chunk.len() > limit {
+            return Err(UploadRejection::TooLarge { field: name, limit });
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+    Ok(buffer.into())
+}
+
+fn csrf_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == CSRF_FIELD)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Compare without returning early, so timing doesn't reveal the prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,213 @@
+//! # API Response Envelope
+//!
+//! Every `/api/*` response has the same shape, success or not:
+//!
+//! ```json
+//! {
+//!   "success": true,
+//!   "data": [...],
+//!   "error": null,
+//!   "meta": { "page": 1, "per_page": 10, "total": 25, "next_cursor": "10" },
+//!   "request_id": "6f1c..."
+//! }
+//! ```
+//!
+//! - `error` carries a typed, machine-readable `code` next to the message;
+//!   the HTTP status is derived from the code
+//! - `meta` is only present on list endpoints
+//! - `request_id` is echoed from `X-Request-Id` (or generated) so a client
+//!   can quote it in a bug report
+//!
+//! Built with methods rather than struct literals:
+//! `ApiResponse::ok(users).with_meta(meta).with_request_id(id)`.
+
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::convert::Infallible;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// ============================================================================
+// ERRORS
+// ============================================================================
+
+/// Stable error codes clients can match on, independent of the message text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    BadRequest,
+    ValidationFailed,
+    NotFound,
+    Conflict,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::ValidationFailed => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict => StatusCode::CONFLICT,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+// ============================================================================
+// META
+// ============================================================================
+
+/// Pagination metadata for list endpoints; unset fields are omitted
+#[derive(Debug, Default, Serialize)]
+pub struct Meta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    /// Opaque; pass back as `?cursor=` to get the next page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl Meta {
+    /// Offset pagination: page N of `total` items
+    pub fn paged(page: u32, per_page: u32, total: u64) -> Self {
+        Self {
+            page: Some(page),
+            per_page: Some(per_page),
+            total: Some(total),
+            next_cursor: None,
+        }
+    }
+
+    /// Cursor pagination: `None` means this was the last page
+    pub fn cursor(per_page: u32, next_cursor: Option<String>) -> Self {
+        Self {
+            per_page: Some(per_page),
+            next_cursor,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+}
+
+// ============================================================================
+// ENVELOPE
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T: Serialize> {
+    success: bool,
+    data: Option<T>,
+    error: Option<ApiError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    /// Overrides the default 200 for successes (e.g. 201 Created)
+    #[serde(skip)]
+    status: Option<StatusCode>,
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    pub fn ok(data: T) -> Self {
+        Self {
+            success: true,
+            data: Some(data),
+            error: None,
+            meta: None,
+            request_id: None,
+            status: None,
+        }
+    }
+
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(ApiError {
+                code,
+                message: message.into(),
+            }),
+            meta: None,
+            request_id: None,
+            status: None,
+        }
+    }
+
+    pub fn with_meta(mut self, meta: Meta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: RequestId) -> Self {
+        self.request_id = Some(request_id.0);
+        self
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
+}
+
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> Response {
+        let status = match &self.error {
+            Some(error) => error.code.status(),
+            None => self.status.unwrap_or(StatusCode::OK),
+        };
+        let request_id = self
+            .request_id
+            .as_deref()
+            .and_then(|id| HeaderValue::from_str(id).ok());
+
+        let mut response = (status, Json(self)).into_response();
+        if let Some(request_id) = request_id {
+            response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+        }
+        response
+    }
+}
+
+// ============================================================================
+// REQUEST ID
+// ============================================================================
+
+/// The caller's `X-Request-Id`, or a fresh UUID when absent or unusable
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl<S> FromRequestParts<S> for RequestId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let id = parts
+            .headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty() && v.len() <= 128)
+            .map(str::to_string)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        Ok(RequestId(id))
+    }
+}
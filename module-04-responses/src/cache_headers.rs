@@ -0,0 +1,192 @@
+//! # Cache Headers with `Cached<T>`
+//!
+//! `Cached<T>` wraps any response and adds caching headers, so handlers
+//! state a policy instead of hand-building a `HeaderMap`:
+//! - `Cache-Control`, built from a preset plus optional directives
+//! - `Expires`, derived from `max-age` (for HTTP/1.0 caches) unless set explicitly
+//! - `Vary`, appended to whatever the inner response already varies on
+//!
+//! Error responses (4xx/5xx) from the inner type are always sent with
+//! `no-store`: a cached 500 would outlive the outage that caused it.
+
+use axum::{
+    http::{header, HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use axum_extra::headers::{Expires, HeaderMapExt};
+
+use crate::vary::merge_vary;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Visibility {
+    Public,
+    Private,
+}
+
+/// The directives that end up in `Cache-Control`
+#[derive(Debug, Clone, Default)]
+struct CacheControl {
+    visibility: Option<Visibility>,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    no_cache: bool,
+    no_store: bool,
+    immutable: bool,
+}
+
+impl CacheControl {
+    fn render(&self) -> String {
+        let mut directives = Vec::new();
+        match self.visibility {
+            Some(Visibility::Public) => directives.push("public".to_string()),
+            Some(Visibility::Private) => directives.push("private".to_string()),
+            None => {}
+        }
+        if self.no_store {
+            directives.push("no-store".to_string());
+        }
+        if self.no_cache {
+            directives.push("no-cache".to_string());
+        }
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("max-age={}", max_age.as_secs()));
+        }
+        if let Some(s_maxage) = self.s_maxage {
+            directives.push(format!("s-maxage={}", s_maxage.as_secs()));
+        }
+        if let Some(swr) = self.stale_while_revalidate {
+            directives.push(format!("stale-while-revalidate={}", swr.as_secs()));
+        }
+        if self.immutable {
+            directives.push("immutable".to_string());
+        }
+        directives.join(", ")
+    }
+}
+
+/// Any response plus a caching policy
+pub struct Cached<T> {
+    inner: T,
+    control: CacheControl,
+    expires: Option<SystemTime>,
+    vary: Vec<HeaderName>,
+}
+
+impl<T> Cached<T> {
+    fn with_control(inner: T, control: CacheControl) -> Self {
+        Self {
+            inner,
+            control,
+            expires: None,
+            vary: Vec::new(),
+        }
+    }
+
+    /// Cacheable by browsers and shared caches (CDNs, proxies)
+    pub fn public(max_age: Duration, inner: T) -> Self {
+        Self::with_control(
+            inner,
+            CacheControl {
+                visibility: Some(Visibility::Public),
+                max_age: Some(max_age),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Per-user data: only the browser may cache it
+    pub fn private(max_age: Duration, inner: T) -> Self {
+        Self::with_control(
+            inner,
+            CacheControl {
+                visibility: Some(Visibility::Private),
+                max_age: Some(max_age),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// May be stored, but must be revalidated (e.g. with an ETag) before reuse
+    pub fn revalidate(inner: T) -> Self {
+        Self::with_control(
+            inner,
+            CacheControl {
+                no_cache: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Never written to any cache - secrets, one-time tokens
+    pub fn no_store(inner: T) -> Self {
+        Self::with_control(
+            inner,
+            CacheControl {
+                no_store: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// A different lifetime for shared caches than for browsers
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.control.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// Serve stale content this long while refreshing in the background
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.control.stale_while_revalidate = Some(window);
+        self
+    }
+
+    /// The content at this URL never changes (fingerprinted assets)
+    pub fn immutable(mut self) -> Self {
+        self.control.immutable = true;
+        self
+    }
+
+    /// Override the `Expires` derived from `max-age`
+    pub fn expires_at(mut self, at: SystemTime) -> Self {
+        self.expires = Some(at);
+        self
+    }
+
+    /// Caches must key on this request header as well as the URL
+    pub fn vary(mut self, header: HeaderName) -> Self {
+        self.vary.push(header);
+        self
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Cached<T> {
+    fn into_response(self) -> Response {
+        let mut response = self.inner.into_response();
+        let status = response.status();
+        let headers = response.headers_mut();
+
+        if status.is_client_error() || status.is_server_error() {
+            headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            return response;
+        }
+
+        if let Ok(value) = HeaderValue::from_str(&self.control.render()) {
+            headers.insert(header::CACHE_CONTROL, value);
+        }
+
+        let expires = self.expires.or_else(|| {
+            (!self.control.no_store && !self.control.no_cache)
+                .then_some(self.control.max_age)
+                .flatten()
+                .map(|max_age| SystemTime::now() + max_age)
+        });
+        if let Some(at) = expires {
+            headers.typed_insert(Expires::from(at));
+        }
+
+        merge_vary(headers, self.vary);
+        response
+    }
+}
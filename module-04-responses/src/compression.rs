@@ -0,0 +1,142 @@
+//! # Compression Opt-Out: `NoCompress<T>` and `no-transform`
+//!
+//! A `CompressionLayer` around the whole app gzips every response the client
+//! accepts. Some bodies must reach the client exactly as the handler built
+//! them:
+//! - already-compressed downloads (`.gz`, `.zip`): gzipping again wastes CPU
+//!   and can make them bigger
+//! - signed bodies: the signature covers these exact bytes
+//!
+//! Two ways to opt out, both honoured by `compression_layer`:
+//! - `NoCompress<T>` wraps any response and marks it with a response
+//!   extension - nothing extra goes over the wire
+//! - `Cache-Control: no-transform` (RFC 9111 §5.2.2.6) tells every
+//!   intermediary, this server's own layer included, to leave the body alone
+
+use axum::{
+    body::HttpBody,
+    http::{header, HeaderMap, Response as HttpResponse},
+    response::{IntoResponse, Response},
+};
+use tower_http::compression::{
+    predicate::{DefaultPredicate, Predicate},
+    CompressionLayer,
+};
+
+/// Serve `T` uncompressed, whatever the client's `Accept-Encoding`
+pub struct NoCompress<T>(pub T);
+
+/// Extension left on the response for `RespectOptOut` to find
+#[derive(Debug, Clone, Copy)]
+struct SkipCompression;
+
+impl<T: IntoResponse> IntoResponse for NoCompress<T> {
+    fn into_response(self) -> Response {
+        let mut response = self.0.into_response();
+        response.extensions_mut().insert(SkipCompression);
+        response
+    }
+}
+
+/// Compress unless the response opted out with `NoCompress` or
+/// `Cache-Control: no-transform`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RespectOptOut;
+
+impl Predicate for RespectOptOut {
+    fn should_compress<B>(&self, response: &HttpResponse<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        response.extensions().get::<SkipCompression>().is_none()
+            && !has_no_transform(response.headers())
+    }
+}
+
+/// `Cache-Control: no-transform` in any of the response's `Cache-Control`
+/// headers. Every layer that rewrites bodies should check it.
+pub fn has_no_transform(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+}
+
+/// tower-http's defaults (skip tiny bodies, images, gRPC and SSE) plus the
+/// opt-outs above
+pub fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(DefaultPredicate::new().and(RespectOptOut))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    /// Long and repetitive, so gzip would certainly shrink it
+    fn body() -> String {
+        "id,name,email\n1,Alice,alice@example.com\n".repeat(100)
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/plain", get(|| async { body() }))
+            .route("/opt-out", get(|| async { NoCompress(body()) }))
+            .route(
+                "/no-transform",
+                get(|| async {
+                    (
+                        [(header::CACHE_CONTROL, "public, max-age=60, No-Transform")],
+                        body(),
+                    )
+                }),
+            )
+            .layer(compression_layer())
+    }
+
+    async fn get_gzip(uri: &str) -> (Option<String>, Vec<u8>) {
+        let response = app()
+            .oneshot(
+                Request::get(uri)
+                    .header(header::ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let encoding = response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string());
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        (encoding, bytes.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_other_responses_are_still_compressed() {
+        let (encoding, bytes) = get_gzip("/plain").await;
+
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(bytes.len() < body().len());
+    }
+
+    #[tokio::test]
+    async fn test_no_compress_passes_through_byte_identical() {
+        let (encoding, bytes) = get_gzip("/opt-out").await;
+
+        assert_eq!(encoding, None);
+        assert_eq!(bytes, body().into_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_no_transform_passes_through_byte_identical() {
+        let (encoding, bytes) = get_gzip("/no-transform").await;
+
+        assert_eq!(encoding, None);
+        assert_eq!(bytes, body().into_bytes());
+    }
+}
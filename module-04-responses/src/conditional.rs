@@ -0,0 +1,173 @@
+//! # Conditional Responses (ETag / Last-Modified)
+//!
+//! A client that already has a response can ask "has it changed?":
+//! - `ETag` is a fingerprint of the body; the client sends it back in
+//!   `If-None-Match`
+//! - `Last-Modified` is a timestamp; the client sends it back in
+//!   `If-Modified-Since`
+//!
+//! If nothing changed, the server answers `304 Not Modified` with no body.
+//! `If-None-Match` wins when both are present (RFC 9110 §13.2.2).
+//!
+//! Two ways to use it:
+//! - `Conditional` - an extractor for handlers that know their data's
+//!   modification time and want to skip work
+//! - `conditional_get` - a layer that fingerprints any buffered GET response
+
+use axum::{
+    body::{to_bytes, Body, Bytes, HttpBody},
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{convert::Infallible, time::SystemTime};
+
+/// The layer only fingerprints bodies up to this size
+const MAX_ETAG_BODY: usize = 1024 * 1024;
+
+/// Strong ETag: a hash of the exact bytes sent
+pub fn strong_etag(body: &[u8]) -> ETag {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex).parse().expect("hex is a valid ETag")
+}
+
+// ============================================================================
+// HANDLER-LEVEL HELPER
+// ============================================================================
+
+/// The request's validators, extracted once per request
+pub struct Conditional {
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<IfModifiedSince>,
+}
+
+impl<S> FromRequestParts<S> for Conditional
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+impl Conditional {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            if_none_match: headers.typed_get(),
+            if_modified_since: headers.typed_get(),
+        }
+    }
+
+    /// Does the client's copy still match?
+    pub fn is_fresh(&self, etag: &ETag, last_modified: Option<SystemTime>) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            return !if_none_match.precondition_passes(etag);
+        }
+        match (&self.if_modified_since, last_modified) {
+            (Some(since), Some(modified)) => !since.is_modified(modified),
+            _ => false,
+        }
+    }
+
+    /// Cheap check before doing any work: is the client's copy at least as
+    /// new as `last_modified`? Only meaningful without `If-None-Match`.
+    pub fn not_modified_since(&self, last_modified: SystemTime) -> bool {
+        self.if_none_match.is_none()
+            && self
+                .if_modified_since
+                .as_ref()
+                .is_some_and(|since| !since.is_modified(last_modified))
+    }
+
+    /// Serialize `data` as JSON with validators, or answer 304
+    pub fn json<T: Serialize>(&self, data: &T, last_modified: Option<SystemTime>) -> Response {
+        let body = match serde_json::to_vec(data) {
+            Ok(body) => body,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+        let etag = strong_etag(&body);
+
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(etag.clone());
+        if let Some(modified) = last_modified {
+            headers.typed_insert(LastModified::from(modified));
+        }
+
+        if self.is_fresh(&etag, last_modified) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/json"),
+        );
+        (headers, body).into_response()
+    }
+
+    /// A bare 304 carrying the validators
+    pub fn not_modified(last_modified: SystemTime) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(LastModified::from(last_modified));
+        (StatusCode::NOT_MODIFIED, headers).into_response()
+    }
+}
+
+// ============================================================================
+// LAYER
+// ============================================================================
+
+/// Add a strong ETag to successful GET/HEAD responses and answer 304 when
+/// the client's `If-None-Match` (or `If-Modified-Since`, when the handler
+/// set `Last-Modified`) says it is up to date.
+///
+/// The handler still runs - the layer saves bandwidth, not work.
+pub async fn conditional_get(request: Request, next: Next) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let conditional = Conditional::from_headers(request.headers());
+    let response = next.run(request).await;
+
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    // Only buffer bodies of known, bounded size - leave streams alone
+    let buffered = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|n| n <= MAX_ETAG_BODY as u64);
+    if !buffered {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes: Bytes = match to_bytes(body, MAX_ETAG_BODY).await {
+        Ok(bytes) => bytes,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let etag = parts
+        .headers
+        .typed_get::<ETag>()
+        .unwrap_or_else(|| strong_etag(&bytes));
+    parts.headers.typed_insert(etag.clone());
+    let last_modified = parts
+        .headers
+        .typed_get::<LastModified>()
+        .map(SystemTime::from);
+
+    if conditional.is_fresh(&etag, last_modified) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_TYPE);
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
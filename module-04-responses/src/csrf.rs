@@ -0,0 +1,297 @@
+//! # CSRF Protection for Form Posts
+//!
+//! A browser attaches cookies to every request for a site, including a form
+//! another site auto-submits to it. Once a form flow trusts a cookie, it
+//! needs proof that the post came from one of its own pages. `csrf_protect`
+//! uses the double-submit pattern:
+//! - every response to a visitor without one sets a random `csrf_token`
+//!   cookie, and handlers get the same value as a `CsrfToken` to render
+//! - templates put it in each form with `{{ csrf.hidden_field()|safe }}`
+//! - `POST`/`PUT`/`PATCH`/`DELETE` must send it back, in the `csrf_token`
+//!   form field or an `X-CSRF-Token` header, and it must match the cookie
+//!
+//! Another site can make the browser send the cookie, but it can't read it,
+//! so it can't put the same value in the form. A mismatch is a `403`: an
+//! HTML page for browsers (`Accept: text/html`), JSON for everyone else.
+//!
+//! Only `application/x-www-form-urlencoded` bodies are searched for the
+//! field (module 03's `strict_multipart.rs` checks it in multipart uploads);
+//! API clients send the header. The cookie isn't signed, so a sibling
+//! subdomain that can set cookies for this one could plant a value it
+//! knows; sign it (or bind it to a session) when that's a concern.
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Form, FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use std::fmt;
+use uuid::Uuid;
+
+/// Cookie and form field name
+pub const CSRF_FIELD: &str = "csrf_token";
+/// For htmx (`hx-headers`) and API clients
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Form bodies are read whole to find the field; forms here are small
+const MAX_FORM_BYTES: usize = 64 * 1024;
+
+/// This visitor's token, inserted by `csrf_protect` for handlers to render
+#[derive(Debug, Clone)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// `<input type="hidden" ...>` for a form. The token is hex, so it
+    /// needs no escaping and the template can mark it `|safe`.
+    pub fn hidden_field(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            CSRF_FIELD, self.0
+        )
+    }
+}
+
+impl fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<CsrfToken>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "CsrfToken needs the csrf_protect layer",
+        ))
+    }
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// The layer: `middleware::from_fn(csrf_protect)`
+pub async fn csrf_protect(request: Request, next: Next) -> Response {
+    let cookie = cookie_token(request.headers());
+    let (token, issued) = match cookie {
+        Some(token) => (token, false),
+        None => (Uuid::new_v4().simple().to_string(), true),
+    };
+
+    let mut request = if is_safe(request.method()) {
+        request
+    } else {
+        // A post without the cookie can't prove anything
+        if issued {
+            return CsrfRejection.into_response_for(request.headers());
+        }
+        match verify(request, &token).await {
+            Ok(request) => request,
+            Err(headers) => return CsrfRejection.into_response_for(&headers),
+        }
+    };
+
+    request.extensions_mut().insert(CsrfToken(token.clone()));
+    let mut response = next.run(request).await;
+    if issued {
+        let cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Lax", CSRF_FIELD, token);
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == CSRF_FIELD)
+        .map(|(_, value)| value.to_string())
+        // Ours look like this; anything else is replaced, never rendered
+        .filter(|value| value.len() == 32 && value.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+#[derive(Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+/// The request back, with its body intact for the handler, if the submitted
+/// token matches; otherwise its headers, to pick the rejection format
+async fn verify(request: Request, expected: &str) -> Result<Request, HeaderMap> {
+    if let Some(sent) = request.headers().get(CSRF_HEADER) {
+        let matches = tokens_match(sent.as_bytes(), expected.as_bytes());
+        return if matches {
+            Ok(request)
+        } else {
+            Err(request.headers().clone())
+        };
+    }
+
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Err(request.headers().clone());
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_FORM_BYTES).await else {
+        return Err(parts.headers);
+    };
+    // Parse a copy; the handler gets the same bytes to parse its own way
+    let copy = Request::from_parts(parts.clone(), Body::from(bytes.clone()));
+    let sent = Form::<CsrfForm>::from_request(copy, &())
+        .await
+        .ok()
+        .and_then(|Form(form)| form.csrf_token);
+    match sent {
+        Some(sent) if tokens_match(sent.as_bytes(), expected.as_bytes()) => {
+            Ok(Request::from_parts(parts, Body::from(bytes)))
+        }
+        _ => Err(parts.headers),
+    }
+}
+
+/// Compares every byte, so the time taken doesn't say how much matched
+fn tokens_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// ============================================================================
+// REJECTION
+// ============================================================================
+
+struct CsrfRejection;
+
+impl CsrfRejection {
+    const MESSAGE: &'static str = "CSRF token missing or invalid";
+
+    /// HTML for a browser's form post, JSON otherwise
+    fn into_response_for(self, headers: &HeaderMap) -> Response {
+        let wants_html = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("text/html"));
+        if wants_html {
+            let page = format!(
+                "<!doctype html>\n<h1>403 Forbidden</h1>\n<p>{}. Reload the page and try again.</p>",
+                Self::MESSAGE
+            );
+            return (StatusCode::FORBIDDEN, Html(page)).into_response();
+        }
+        (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": Self::MESSAGE })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, pretty_json::JsonFormat};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    async fn send(request: Request) -> (StatusCode, HeaderMap, String) {
+        let response = app(JsonFormat::Compact).oneshot(request).await.unwrap();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, headers, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn post_form(cookie: Option<&str>, body: &str) -> axum::http::request::Builder {
+        let mut request = Request::post("/htmx/todos")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::CONTENT_LENGTH, body.len());
+        if let Some(token) = cookie {
+            request = request.header(header::COOKIE, format!("{}={}", CSRF_FIELD, token));
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_the_page_issues_a_cookie_and_embeds_the_same_token() {
+        let request = Request::get("/htmx/todos").body(Body::empty()).unwrap();
+        let (status, headers, page) = send(request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let token = cookie_token(&HeaderMap::from_iter([(
+            header::COOKIE,
+            headers[header::SET_COOKIE].clone(),
+        )]))
+        .unwrap();
+        assert!(page.contains(&CsrfToken(token).hidden_field()));
+    }
+
+    #[tokio::test]
+    async fn test_posts_need_a_token_matching_the_cookie() {
+        let body = "title=Forged";
+        // No cookie, wrong field, no field at all
+        for (cookie, body) in [
+            (None, format!("{}&csrf_token={}", body, TOKEN)),
+            (Some(TOKEN), format!("{}&csrf_token=abc", body)),
+            (Some(TOKEN), body.to_string()),
+        ] {
+            let request = post_form(cookie, &body)
+                .body(Body::from(body.clone()))
+                .unwrap();
+            let (status, _, error) = send(request).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+            assert_eq!(error, r#"{"error":"CSRF token missing or invalid"}"#);
+        }
+
+        // A browser gets a page
+        let request = post_form(Some(TOKEN), body)
+            .header(header::ACCEPT, "text/html,*/*")
+            .body(Body::from(body))
+            .unwrap();
+        let (status, headers, _) = send(request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(headers[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+    }
+
+    #[tokio::test]
+    async fn test_a_matching_field_or_header_lets_the_post_through() {
+        // The handler still parses `title` from the body the check read
+        let body = format!("title=Legit&csrf_token={}", TOKEN);
+        let request = post_form(Some(TOKEN), &body)
+            .header("hx-request", "true")
+            .body(Body::from(body))
+            .unwrap();
+        let (status, _, row) = send(request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(row.contains("Legit"));
+
+        let request = Request::post("/htmx/todos/clear-done")
+            .header(header::COOKIE, format!("theme=dark; csrf_token={}", TOKEN))
+            .header(CSRF_HEADER, TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, _) = send(request).await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+    }
+}
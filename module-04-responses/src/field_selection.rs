@@ -0,0 +1,187 @@
+//! # Partial Responses with `?fields=`
+//!
+//! Clients often need a handful of fields from a large object. `PartialJson`
+//! serializes the data as usual, then prunes the resulting `serde_json::Value`
+//! down to the requested fields:
+//! - `?fields=id,name` keeps only those keys
+//! - Dot paths select nested fields: `?fields=name,address.city`
+//! - Arrays are transparent: a path applies to every element
+//! - Unknown fields are a `400` listing what's available, not a silent no-op
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Query string for any endpoint supporting field selection
+#[derive(Deserialize)]
+pub struct FieldsQuery {
+    pub fields: Option<String>,
+}
+
+/// Responder that serializes `data` and keeps only the selected fields
+pub struct PartialJson {
+    body: PartialBody,
+    /// Apply the selection to this top-level key instead of the whole body
+    within: Option<&'static str>,
+}
+
+enum PartialBody {
+    /// No `?fields=`: serialized directly, keeping the struct's field order
+    Full(Response),
+    Select {
+        value: Result<Value, serde_json::Error>,
+        fields: String,
+    },
+}
+
+impl PartialJson {
+    pub fn new<T: Serialize>(data: &T, fields: Option<String>) -> Self {
+        let body = match fields.filter(|f| !f.trim().is_empty()) {
+            Some(fields) => PartialBody::Select {
+                value: serde_json::to_value(data),
+                fields,
+            },
+            None => PartialBody::Full(Json(data).into_response()),
+        };
+        Self { body, within: None }
+    }
+
+    /// Select fields inside an envelope, e.g. the items of `{"users": [...]}`,
+    /// leaving the rest of the envelope (totals, paging) untouched
+    pub fn within(mut self, key: &'static str) -> Self {
+        self.within = Some(key);
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct UnknownFields {
+    error: &'static str,
+    unknown_fields: Vec<String>,
+    available_fields: Vec<String>,
+}
+
+impl IntoResponse for PartialJson {
+    fn into_response(self) -> Response {
+        let (value, fields) = match self.body {
+            PartialBody::Full(response) => return response,
+            PartialBody::Select { value, fields } => (value, fields),
+        };
+        let mut value = match value {
+            Ok(value) => value,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+
+        let paths: Vec<Vec<&str>> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .map(|f| f.split('.').collect())
+            .collect();
+        if paths.is_empty() {
+            return Json(value).into_response();
+        }
+
+        let target = match self.within {
+            Some(key) => match value.get_mut(key) {
+                Some(target) => target,
+                None => return Json(value).into_response(),
+            },
+            None => &mut value,
+        };
+
+        let unknown: Vec<String> = paths
+            .iter()
+            .filter(|path| !path_exists(target, path))
+            .map(|path| path.join("."))
+            .collect();
+        if !unknown.is_empty() {
+            let mut available = Vec::new();
+            collect_paths(target, String::new(), &mut available);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(UnknownFields {
+                    error: "Unknown field in ?fields=",
+                    unknown_fields: unknown,
+                    available_fields: available,
+                }),
+            )
+                .into_response();
+        }
+
+        *target = prune(target.take(), &paths);
+        Json(value).into_response()
+    }
+}
+
+// ============================================================================
+// VALUE WALKING
+// ============================================================================
+
+fn path_exists(value: &Value, path: &[&str]) -> bool {
+    let Some((first, rest)) = path.split_first() else {
+        return true;
+    };
+    match value {
+        // An empty list can't prove a field wrong, so accept it
+        Value::Array(items) => items.is_empty() || items.iter().any(|v| path_exists(v, path)),
+        Value::Object(map) => map.get(*first).is_some_and(|v| path_exists(v, rest)),
+        _ => false,
+    }
+}
+
+/// Keep only the keys named by `paths`; a path ending at a key keeps its
+/// whole value
+fn prune(value: Value, paths: &[Vec<&str>]) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.into_iter().map(|v| prune(v, paths)).collect()),
+        Value::Object(map) => {
+            let mut kept = Map::new();
+            for (key, child) in map {
+                let tails: Vec<Vec<&str>> = paths
+                    .iter()
+                    .filter(|p| p.first() == Some(&key.as_str()))
+                    .map(|p| p[1..].to_vec())
+                    .collect();
+                if tails.is_empty() {
+                    continue;
+                }
+                let child = if tails.iter().any(Vec::is_empty) {
+                    child
+                } else {
+                    prune(child, &tails)
+                };
+                kept.insert(key, child);
+            }
+            Value::Object(kept)
+        }
+        other => other,
+    }
+}
+
+/// Every selectable dot path, looking at the first element of arrays
+fn collect_paths(value: &Value, prefix: String, out: &mut Vec<String>) {
+    match value {
+        Value::Array(items) => {
+            if let Some(first) = items.first() {
+                collect_paths(first, prefix, out);
+            }
+        }
+        Value::Object(map) => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                out.push(path.clone());
+                collect_paths(child, path, out);
+            }
+        }
+        _ => {}
+    }
+}
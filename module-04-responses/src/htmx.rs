@@ -0,0 +1,301 @@
+//! # HTMX-Aware Responses
+//!
+//! htmx sends `HX-Request: true` on every request it makes and swaps the
+//! returned HTML into the page. The same URL can therefore serve two
+//! audiences:
+//! - htmx: just the fragment being swapped (a row, a list)
+//! - a browser navigation or a no-JS form post: the full templated page
+//!
+//! `HxRequest` extracts the header, `HxTemplate` picks fragment or page, and
+//! `HxRedirect` / `HxTrigger` set the response headers htmx acts on.
+
+use askama::Template;
+use axum::{
+    extract::{Form, FromRequestParts, Path},
+    http::{request::Parts, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, IntoResponseParts, Redirect, Response, ResponseParts},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    sync::{LazyLock, RwLock},
+};
+
+use crate::{csrf::CsrfToken, vary::merge_vary, HtmlTemplate};
+
+const HX_REQUEST: HeaderName = HeaderName::from_static("hx-request");
+const HX_BOOSTED: HeaderName = HeaderName::from_static("hx-boosted");
+const HX_REDIRECT: HeaderName = HeaderName::from_static("hx-redirect");
+const HX_TRIGGER: HeaderName = HeaderName::from_static("hx-trigger");
+
+// ============================================================================
+// REQUEST: WAS THIS SENT BY HTMX?
+// ============================================================================
+
+/// `true` when htmx made the request and wants a fragment back
+#[derive(Debug, Clone, Copy)]
+pub struct HxRequest(pub bool);
+
+impl<S: Send + Sync> FromRequestParts<S> for HxRequest {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let is_set = |name: &HeaderName| {
+            parts
+                .headers
+                .get(name)
+                .is_some_and(|v| v.as_bytes() == b"true")
+        };
+        // `hx-boost` turns links into htmx requests that replace the whole
+        // <body>, so they still need the full page
+        Ok(Self(is_set(&HX_REQUEST) && !is_set(&HX_BOOSTED)))
+    }
+}
+
+impl HxRequest {
+    /// Render `fragment` for htmx and `page` for everyone else. Both are
+    /// closures so only the one that is sent gets built.
+    pub fn render<F, P>(
+        self,
+        fragment: impl FnOnce() -> F,
+        page: impl FnOnce() -> P,
+    ) -> HxTemplate<F, P> {
+        if self.0 {
+            HxTemplate::Fragment(fragment())
+        } else {
+            HxTemplate::Page(page())
+        }
+    }
+}
+
+// ============================================================================
+// RESPONSE: FRAGMENT OR FULL PAGE
+// ============================================================================
+
+pub enum HxTemplate<F, P> {
+    Fragment(F),
+    Page(P),
+}
+
+impl<F: Template, P: Template> IntoResponse for HxTemplate<F, P> {
+    fn into_response(self) -> Response {
+        let mut response = match self {
+            HxTemplate::Fragment(fragment) => HtmlTemplate(fragment).into_response(),
+            HxTemplate::Page(page) => HtmlTemplate(page).into_response(),
+        };
+        // One URL, two bodies: caches must key on the header too
+        merge_vary(response.headers_mut(), [HX_REQUEST]);
+        response
+    }
+}
+
+// ============================================================================
+// RESPONSE HEADERS
+// ============================================================================
+
+/// `HX-Redirect`: htmx does a full-page navigation to this URL instead of
+/// swapping the body (a plain 3xx would be followed by the XHR and swapped in)
+pub struct HxRedirect(pub String);
+
+impl IntoResponseParts for HxRedirect {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            res.headers_mut().insert(HX_REDIRECT, value);
+        }
+        Ok(res)
+    }
+}
+
+/// `HX-Trigger`: client-side events htmx fires once the response arrives
+#[derive(Default)]
+pub struct HxTrigger {
+    events: Vec<(String, Option<Value>)>,
+}
+
+impl HxTrigger {
+    pub fn event(name: impl Into<String>) -> Self {
+        Self::default().and(name)
+    }
+
+    pub fn and(mut self, name: impl Into<String>) -> Self {
+        self.events.push((name.into(), None));
+        self
+    }
+
+    /// An event whose listeners receive `detail` as `event.detail`
+    pub fn and_with_detail(mut self, name: impl Into<String>, detail: Value) -> Self {
+        self.events.push((name.into(), Some(detail)));
+        self
+    }
+
+    fn header_value(&self) -> String {
+        // Plain names are a comma-separated list; details need the JSON form
+        if self.events.iter().all(|(_, detail)| detail.is_none()) {
+            let names: Vec<&str> = self.events.iter().map(|(name, _)| name.as_str()).collect();
+            return names.join(", ");
+        }
+        let object: serde_json::Map<String, Value> = self
+            .events
+            .iter()
+            .map(|(name, detail)| (name.clone(), detail.clone().unwrap_or(Value::Null)))
+            .collect();
+        Value::Object(object).to_string()
+    }
+}
+
+impl IntoResponseParts for HxTrigger {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Ok(value) = HeaderValue::from_str(&self.header_value()) {
+            res.headers_mut().insert(HX_TRIGGER, value);
+        }
+        Ok(res)
+    }
+}
+
+// ============================================================================
+// DEMO: A TODO LIST THAT SWAPS IN PLACE
+// ============================================================================
+
+#[derive(Clone)]
+pub struct HxTodo {
+    id: u32,
+    title: String,
+    done: bool,
+}
+
+static TODOS: LazyLock<RwLock<Vec<HxTodo>>> = LazyLock::new(|| {
+    RwLock::new(
+        ["Read the htmx docs", "Swap a row in place", "Ship it"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, title)| HxTodo {
+                id: i as u32 + 1,
+                title: title.to_string(),
+                done: i == 0,
+            })
+            .collect(),
+    )
+});
+
+#[derive(Template)]
+#[template(path = "todos.html")]
+pub struct TodosPage {
+    todos: Vec<HxTodo>,
+    remaining: usize,
+    csrf: CsrfToken,
+}
+
+#[derive(Template)]
+#[template(path = "partials/todo_list.html")]
+pub struct TodoListFragment {
+    todos: Vec<HxTodo>,
+    csrf: CsrfToken,
+}
+
+#[derive(Template)]
+#[template(path = "partials/todo_row.html")]
+pub struct TodoRowFragment {
+    todo: HxTodo,
+    /// Every row has its own toggle form
+    csrf: CsrfToken,
+}
+
+fn todos_page(csrf: &CsrfToken) -> TodosPage {
+    let todos = TODOS.read().unwrap().clone();
+    let remaining = todos.iter().filter(|t| !t.done).count();
+    TodosPage {
+        todos,
+        remaining,
+        csrf: csrf.clone(),
+    }
+}
+
+/// Adds the event that tells the page's counter to update itself
+fn todos_changed(trigger: HxTrigger) -> HxTrigger {
+    let remaining = TODOS.read().unwrap().iter().filter(|t| !t.done).count();
+    trigger.and_with_detail(
+        "todos-changed",
+        serde_json::json!({ "remaining": remaining }),
+    )
+}
+
+/// GET /htmx/todos - the page, or just the `<ul>` for htmx's refresh button
+pub async fn list_todos(hx: HxRequest, csrf: CsrfToken) -> HxTemplate<TodoListFragment, TodosPage> {
+    hx.render(
+        || TodoListFragment {
+            todos: TODOS.read().unwrap().clone(),
+            csrf: csrf.clone(),
+        },
+        || todos_page(&csrf),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct NewTodo {
+    title: String,
+}
+
+/// POST /htmx/todos - htmx appends the returned row; a plain form post is
+/// redirected back to the page (Post/Redirect/Get)
+pub async fn add_todo(hx: HxRequest, csrf: CsrfToken, Form(input): Form<NewTodo>) -> Response {
+    let title = input.title.trim();
+    if title.is_empty() {
+        return (StatusCode::UNPROCESSABLE_ENTITY, "Title is required").into_response();
+    }
+
+    let todo = {
+        let mut todos = TODOS.write().unwrap();
+        let todo = HxTodo {
+            id: todos.iter().map(|t| t.id).max().unwrap_or(0) + 1,
+            title: title.to_string(),
+            done: false,
+        };
+        todos.push(todo.clone());
+        todo
+    };
+
+    if hx.0 {
+        // `todo-added` fires on the form, which resets itself
+        let trigger = todos_changed(HxTrigger::event("todo-added"));
+        (trigger, HtmlTemplate(TodoRowFragment { todo, csrf })).into_response()
+    } else {
+        Redirect::to("/htmx/todos").into_response()
+    }
+}
+
+/// POST /htmx/todos/{id}/toggle - the row swaps itself (`hx-swap="outerHTML"`)
+pub async fn toggle_todo(hx: HxRequest, csrf: CsrfToken, Path(id): Path<u32>) -> Response {
+    let toggled = {
+        let mut todos = TODOS.write().unwrap();
+        todos.iter_mut().find(|t| t.id == id).map(|todo| {
+            todo.done = !todo.done;
+            todo.clone()
+        })
+    };
+    let Some(todo) = toggled else {
+        return (StatusCode::NOT_FOUND, "Todo not found").into_response();
+    };
+
+    if hx.0 {
+        let trigger = todos_changed(HxTrigger::default());
+        (trigger, HtmlTemplate(TodoRowFragment { todo, csrf })).into_response()
+    } else {
+        Redirect::to("/htmx/todos").into_response()
+    }
+}
+
+/// POST /htmx/todos/clear-done - too many rows change to swap one, so htmx
+/// is told to reload the whole page instead
+pub async fn clear_done(hx: HxRequest) -> Response {
+    TODOS.write().unwrap().retain(|t| !t.done);
+    if hx.0 {
+        (HxRedirect("/htmx/todos".to_string()), ()).into_response()
+    } else {
+        Redirect::to("/htmx/todos").into_response()
+    }
+}
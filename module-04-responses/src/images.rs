@@ -0,0 +1,89 @@
+//! # Generated Binary Responses (PNG)
+//!
+//! Not every response is text. `Png` returns raw image bytes with the
+//! headers a browser needs to treat them as an image:
+//! - `Content-Type: image/png`
+//! - `Content-Length` from the encoded size, so clients can show progress
+//!   and connections can be reused without chunked encoding
+//!
+//! `qr_png` draws a QR code from the `qrcode` crate's module grid and encodes
+//! it with the `png` crate - no image library or files on disk needed.
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use qrcode::{types::QrError, Color, QrCode};
+
+/// Encoded PNG bytes
+pub struct Png(pub Bytes);
+
+impl IntoResponse for Png {
+    fn into_response(self) -> Response {
+        (
+            [
+                (header::CONTENT_TYPE, HeaderValue::from_static("image/png")),
+                (header::CONTENT_LENGTH, HeaderValue::from(self.0.len())),
+            ],
+            self.0,
+        )
+            .into_response()
+    }
+}
+
+/// Why a QR code couldn't be produced
+#[derive(Debug)]
+pub enum ImageError {
+    /// More data than the largest QR code holds (~2.9 KB)
+    TooLong,
+    Qr(QrError),
+    Encode(png::EncodingError),
+}
+
+impl std::fmt::Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::TooLong => write!(f, "Text is too long for a QR code"),
+            ImageError::Qr(err) => write!(f, "QR encoding failed: {}", err),
+            ImageError::Encode(err) => write!(f, "PNG encoding failed: {}", err),
+        }
+    }
+}
+
+/// A QR code for `text`: each module is `scale` x `scale` pixels, with a
+/// quiet zone of `margin` modules around it (scanners need at least 4)
+pub fn qr_png(text: &str, scale: u32, margin: u32) -> Result<Bytes, ImageError> {
+    let code = QrCode::new(text.as_bytes()).map_err(|err| match err {
+        QrError::DataTooLong => ImageError::TooLong,
+        other => ImageError::Qr(other),
+    })?;
+    let modules = code.width() as u32;
+    let colors = code.to_colors();
+    let size = (modules + 2 * margin) * scale;
+
+    // 8-bit grayscale, one byte per pixel, row by row
+    let mut pixels = vec![255u8; (size * size) as usize];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+        let (mx, my) = (i as u32 % modules, i as u32 / modules);
+        for y in 0..scale {
+            let row = (my + margin) * scale + y;
+            let start = (row * size + (mx + margin) * scale) as usize;
+            pixels[start..start + scale as usize].fill(0);
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, size, size);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(ImageError::Encode)?;
+    writer
+        .write_image_data(&pixels)
+        .map_err(ImageError::Encode)?;
+    writer.finish().map_err(ImageError::Encode)?;
+    Ok(Bytes::from(out))
+}
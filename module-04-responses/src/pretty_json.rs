@@ -0,0 +1,166 @@
+//! # Pretty JSON in Development
+//!
+//! Compact JSON is what you want on the wire and what nobody wants to read
+//! in a terminal. `pretty_json` is a layer that re-indents JSON response
+//! bodies when:
+//! - the server runs with `APP_ENV=dev` (the default is compact), or
+//! - the request asks for it with `?pretty=1` (`?pretty=0` forces compact)
+//!
+//! Handlers keep returning `Json<T>`, `ApiResponse<T>` or anything else with
+//! a JSON content type. Only whitespace is added, so key order and number
+//! formatting stay exactly as the handler serialized them. Responses marked
+//! `Cache-Control: no-transform` are never touched.
+
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::compression::has_no_transform;
+
+/// Bodies larger than this (or of unknown size, like streams) pass through
+const MAX_PRETTY_BODY: usize = 1024 * 1024;
+
+/// How JSON bodies are formatted unless the request says otherwise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonFormat {
+    Pretty,
+    Compact,
+}
+
+impl JsonFormat {
+    /// `APP_ENV=dev` (or `development`) means pretty; anything else compact
+    pub fn from_env() -> Self {
+        match std::env::var("APP_ENV").as_deref() {
+            Ok("dev") | Ok("development") => JsonFormat::Pretty,
+            _ => JsonFormat::Compact,
+        }
+    }
+
+    /// `?pretty=1|true` and `?pretty=0|false` override the default
+    fn for_query(self, query: Option<&str>) -> Self {
+        let requested = query
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .find_map(|pair| match pair.split_once('=').unwrap_or((pair, "1")) {
+                ("pretty", "" | "1" | "true") => Some(JsonFormat::Pretty),
+                ("pretty", "0" | "false") => Some(JsonFormat::Compact),
+                _ => None,
+            });
+        requested.unwrap_or(self)
+    }
+}
+
+/// The layer: `middleware::from_fn_with_state(JsonFormat::from_env(), pretty_json)`
+pub async fn pretty_json(
+    State(default): State<JsonFormat>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let format = default.for_query(request.uri().query());
+    let response = next.run(request).await;
+    // `no-transform` bodies (e.g. signed ones) must leave byte for byte
+    let untouchable = !is_json(&response) || has_no_transform(response.headers());
+    if format == JsonFormat::Compact || untouchable {
+        return response;
+    }
+    let buffered = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|n| n <= MAX_PRETTY_BODY as u64);
+    if !buffered {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_PRETTY_BODY).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+    // Never "fix up" something that isn't valid JSON
+    if serde_json::from_slice::<serde::de::IgnoredAny>(&bytes).is_err() {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+
+    // Same content, different bytes: a strong ETag would now be a lie
+    let strong_etag = parts
+        .headers
+        .get(header::ETAG)
+        .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+        .and_then(|etag| HeaderValue::from_bytes(&[b"W/", etag.as_bytes()].concat()).ok());
+    if let Some(weak) = strong_etag {
+        parts.headers.insert(header::ETAG, weak);
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(indent(&bytes)))
+}
+
+fn is_json(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|mime| mime == "application/json" || mime.ends_with("+json"))
+}
+
+/// Two-space indentation of already-valid JSON, walking it byte by byte so
+/// nothing but whitespace changes. Empty `{}` / `[]` stay on one line.
+fn indent(json: &[u8]) -> Vec<u8> {
+    fn newline(out: &mut Vec<u8>, depth: usize) {
+        out.push(b'\n');
+        out.extend(std::iter::repeat_n(b' ', depth * 2));
+    }
+
+    let mut out = Vec::with_capacity(json.len() * 2);
+    let mut depth = 0;
+    let (mut in_string, mut escaped) = (false, false);
+    let mut bytes = json.iter().copied().peekable();
+
+    while let Some(byte) = bytes.next() {
+        if in_string {
+            out.push(byte);
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => {
+                in_string = true;
+                out.push(byte);
+            }
+            b'{' | b'[' => {
+                out.push(byte);
+                while bytes.next_if(|b| b.is_ascii_whitespace()).is_some() {}
+                if let Some(close) = bytes.next_if(|b| matches!(b, b'}' | b']')) {
+                    out.push(close);
+                    continue;
+                }
+                depth += 1;
+                newline(&mut out, depth);
+            }
+            b'}' | b']' => {
+                depth -= 1;
+                newline(&mut out, depth);
+                out.push(byte);
+            }
+            b',' => {
+                out.push(byte);
+                newline(&mut out, depth);
+            }
+            b':' => out.extend_from_slice(b": "),
+            b if b.is_ascii_whitespace() => {}
+            _ => out.push(byte),
+        }
+    }
+    // curl doesn't add one, and the shell prompt ends up after the `}`
+    out.push(b'\n');
+    out
+}
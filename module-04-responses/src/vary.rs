@@ -0,0 +1,210 @@
+//! # `Vary` and Canonical URLs
+//!
+//! A shared cache keys what it stores on the URL. When a handler also reads
+//! a request header - `Accept` to pick JSON or text, `Accept-Language`,
+//! `Authorization`, `HX-Request` - one URL has several bodies, and unless
+//! the response lists those headers in `Vary` the cache serves whichever it
+//! stored first to everyone: French to an English reader, a fragment as a
+//! page, one user's data to the next. That's cache poisoning by omission.
+//!
+//! - `Vary::on([..])` is a response part for handlers that negotiate
+//! - `merge_vary` does the same for code that holds a `HeaderMap`
+//!   (`Cached<T>`, `HxTemplate`)
+//! - `vary_on` is a route layer for groups of routes that all depend on
+//!   the same headers, e.g. everything that reads the session
+//! - `normalize_vary` goes outermost: handlers and layers each add their
+//!   own `Vary` (compression adds `Accept-Encoding`), and it collapses them
+//!   into one header without duplicates. `*` absorbs everything else
+//!
+//! The opposite problem - one resource under two URLs - splits the cache
+//! and the search index. `canonical_link` marks an alias route with
+//! `Link: </real/path>; rel="canonical"`.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponseParts, Response, ResponseParts},
+};
+use std::convert::Infallible;
+
+/// The headers a response depends on when it's personalized
+pub const AUTH_HEADERS: &[HeaderName] = &[header::AUTHORIZATION, header::COOKIE];
+
+/// Add `names` to the response's `Vary`, keeping a single header with each
+/// name once (compared case-insensitively, as header names are)
+pub fn merge_vary(headers: &mut HeaderMap, names: impl IntoIterator<Item = HeaderName>) {
+    let mut merged: Vec<String> = Vec::new();
+    let existing = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty());
+    for name in existing.chain(names.into_iter().map(|name| name.as_str().to_string())) {
+        if !merged.contains(&name) {
+            merged.push(name);
+        }
+    }
+    if merged.is_empty() {
+        return;
+    }
+    // "Varies on things no header describes": no cache may reuse it
+    let value = if merged.iter().any(|name| name == "*") {
+        "*".to_string()
+    } else {
+        merged.join(", ")
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(header::VARY, value);
+    }
+}
+
+/// `(Vary::on([header::ACCEPT]), body)` from a handler that negotiates
+pub struct Vary(Vec<HeaderName>);
+
+impl Vary {
+    pub fn on(names: impl IntoIterator<Item = HeaderName>) -> Self {
+        Self(names.into_iter().collect())
+    }
+}
+
+impl IntoResponseParts for Vary {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        merge_vary(res.headers_mut(), self.0);
+        Ok(res)
+    }
+}
+
+/// Route layer: `route_layer(middleware::from_fn_with_state(AUTH_HEADERS, vary_on))`
+pub async fn vary_on(
+    State(names): State<&'static [HeaderName]>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    merge_vary(response.headers_mut(), names.iter().cloned());
+    response
+}
+
+/// Outermost layer: one `Vary` header, no duplicates
+pub async fn normalize_vary(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    merge_vary(response.headers_mut(), []);
+    response
+}
+
+/// Route layer for an alias: `from_fn_with_state("/new-location", canonical_link)`
+pub async fn canonical_link(
+    State(canonical): State<&'static str>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    if let Ok(link) = HeaderValue::from_str(&format!("<{}>; rel=\"canonical\"", canonical)) {
+        // Other `Link`s (pagination, preload) stay
+        response.headers_mut().append(header::LINK, link);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, pretty_json::JsonFormat};
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn get(uri: &str, headers: &[(&str, &str)]) -> (HeaderMap, String) {
+        let mut request = Request::get(uri).header(header::ACCEPT_ENCODING, "identity");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let response = app(JsonFormat::Compact)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let headers = response.headers().clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (headers, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Every `Vary` value, and how many `Vary` headers carried them
+    fn vary(headers: &HeaderMap) -> (usize, String) {
+        let values: Vec<_> = headers.get_all(header::VARY).iter().collect();
+        let joined = values
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect::<Vec<_>>()
+            .join(", ");
+        (values.len(), joined)
+    }
+
+    #[test]
+    fn test_merge_vary_dedupes_and_respects_star() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+        headers.append(header::VARY, HeaderValue::from_static("accept, HX-Request"));
+
+        merge_vary(&mut headers, [header::ACCEPT, header::ACCEPT_LANGUAGE]);
+        assert_eq!(
+            vary(&headers),
+            (
+                1,
+                "accept-encoding, accept, hx-request, accept-language".to_string()
+            )
+        );
+
+        headers.append(header::VARY, HeaderValue::from_static("*"));
+        merge_vary(&mut headers, [header::COOKIE]);
+        assert_eq!(vary(&headers), (1, "*".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_negotiated_greeting_varies_on_what_it_reads() {
+        let (headers, body) = get(
+            "/greeting",
+            &[
+                ("accept", "application/json"),
+                ("accept-language", "de, fr;q=0.9, en;q=0.5"),
+            ],
+        )
+        .await;
+        assert_eq!(body, r#"{"greeting":"Bonjour","lang":"fr"}"#);
+        assert_eq!(headers[header::CONTENT_LANGUAGE], "fr");
+
+        let (headers, body) = get("/greeting", &[]).await;
+        assert_eq!(body, "Hello");
+        // Too small for compression to add `accept-encoding`; `/cached/public` covers that
+        assert_eq!(vary(&headers), (1, "accept, accept-language".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_every_negotiated_endpoint_declares_its_vary() {
+        for (uri, depends_on) in [
+            ("/htmx/todos", "hx-request"),
+            ("/cached/private", "authorization"),
+            ("/cached/public", "accept-encoding"),
+            ("/greeting", "accept-language"),
+        ] {
+            let (headers, _) = get(uri, &[]).await;
+            let (count, names) = vary(&headers);
+            assert_eq!(count, 1, "{}: {}", uri, names);
+            assert_eq!(names.matches(depends_on).count(), 1, "{}: {}", uri, names);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_alias_routes_point_to_the_canonical_url() {
+        let (alias, alias_body) = get("/temp-location", &[]).await;
+        let (canonical, canonical_body) = get("/new-location", &[]).await;
+
+        assert_eq!(alias[header::LINK], r#"</new-location>; rel="canonical""#);
+        assert_eq!(alias_body, canonical_body);
+        assert!(canonical.get(header::LINK).is_none());
+    }
+}
@@ -0,0 +1,242 @@
+//! # Actor-Owned State: Message Passing Instead of Locks
+//!
+//! Every other store in this module is shared memory plus a lock. An actor
+//! turns that around: one spawned task *owns* the `HashMap` outright, and
+//! everyone else sends it messages.
+//! - Handlers hold a `StoreHandle`, which is only an `mpsc::Sender`
+//! - Each message carries a `oneshot::Sender` for the reply
+//! - The actor handles one message at a time, so it needs no lock at all
+//!
+//! The tradeoffs, compared to `RwLock<HashMap>`:
+//! - Every operation is serialized, reads included - like a `Mutex`, except
+//!   that waiting callers `.await` instead of blocking a thread
+//! - Each call costs two channel hops and a task switch
+//! - The bounded mailbox is natural backpressure: when the actor falls
+//!   behind, `send` waits instead of memory growing
+//! - Multi-step updates are atomic for free: add a message that does them all
+//! - If the actor task dies, calls fail with `ActorGone` instead of
+//!   panicking on a poisoned lock
+//!
+//! The same CRUD surface is served from `/todos-actor`.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use std::collections::HashMap;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{ids::SharedIds, CreateTodo, Todo, UpdateTodo};
+
+/// Messages waiting beyond this make senders wait
+const MAILBOX: usize = 64;
+
+/// Everything the actor can be asked to do
+enum Command {
+    List {
+        reply: oneshot::Sender<Vec<Todo>>,
+    },
+    Create {
+        title: String,
+        reply: oneshot::Sender<Todo>,
+    },
+    Get {
+        id: String,
+        reply: oneshot::Sender<Option<Todo>>,
+    },
+    Update {
+        id: String,
+        input: UpdateTodo,
+        reply: oneshot::Sender<Option<Todo>>,
+    },
+    Delete {
+        id: String,
+        reply: oneshot::Sender<bool>,
+    },
+}
+
+/// The actor task stopped, so nobody will ever answer
+#[derive(Debug)]
+pub struct ActorGone;
+
+/// A cheap, cloneable way to talk to the store's task
+#[derive(Clone)]
+pub struct StoreHandle {
+    sender: mpsc::Sender<Command>,
+}
+
+impl StoreHandle {
+    /// Start the actor, giving new todos ids from `ids`. It runs until the
+    /// last handle is dropped.
+    pub fn spawn(ids: SharedIds) -> Self {
+        let (sender, receiver) = mpsc::channel(MAILBOX);
+        tokio::spawn(run(receiver, ids));
+        Self { sender }
+    }
+
+    /// Send a command built around a fresh reply channel and wait for the answer
+    async fn ask<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> Command,
+    ) -> Result<T, ActorGone> {
+        let (reply, answer) = oneshot::channel();
+        self.sender
+            .send(command(reply))
+            .await
+            .map_err(|_| ActorGone)?;
+        answer.await.map_err(|_| ActorGone)
+    }
+
+    pub async fn list(&self) -> Result<Vec<Todo>, ActorGone> {
+        self.ask(|reply| Command::List { reply }).await
+    }
+
+    pub async fn create(&self, title: String) -> Result<Todo, ActorGone> {
+        self.ask(|reply| Command::Create { title, reply }).await
+    }
+
+    pub async fn get(&self, id: String) -> Result<Option<Todo>, ActorGone> {
+        self.ask(|reply| Command::Get { id, reply }).await
+    }
+
+    pub async fn update(&self, id: String, input: UpdateTodo) -> Result<Option<Todo>, ActorGone> {
+        self.ask(|reply| Command::Update { id, input, reply }).await
+    }
+
+    pub async fn delete(&self, id: String) -> Result<bool, ActorGone> {
+        self.ask(|reply| Command::Delete { id, reply }).await
+    }
+}
+
+/// The actor: the only code that ever touches `todos`
+async fn run(mut receiver: mpsc::Receiver<Command>, ids: SharedIds) {
+    let mut todos: HashMap<String, Todo> = HashMap::new();
+
+    // `None` once every `StoreHandle` is gone
+    while let Some(command) = receiver.recv().await {
+        // A failed `send` means the caller stopped waiting (e.g. the client
+        // disconnected); the work is done either way
+        match command {
+            Command::List { reply } => {
+                let _ = reply.send(todos.values().cloned().collect());
+            }
+            Command::Create { title, reply } => {
+                let todo = Todo::new(ids.next_id(), title);
+                todos.insert(todo.id.clone(), todo.clone());
+                let _ = reply.send(todo);
+            }
+            Command::Get { id, reply } => {
+                let _ = reply.send(todos.get(&id).cloned());
+            }
+            Command::Update { id, input, reply } => {
+                let updated = todos.get_mut(&id).map(|todo| {
+                    input.apply(todo);
+                    todo.clone()
+                });
+                let _ = reply.send(updated);
+            }
+            Command::Delete { id, reply } => {
+                let _ = reply.send(todos.remove(&id).is_some());
+            }
+        }
+    }
+}
+
+impl From<ActorGone> for StatusCode {
+    fn from(_: ActorGone) -> Self {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+// ============================================================================
+// CRUD THROUGH THE HANDLE
+// ============================================================================
+
+pub async fn list_todos(State(store): State<StoreHandle>) -> Result<Json<Vec<Todo>>, StatusCode> {
+    Ok(Json(store.list().await?))
+}
+
+pub async fn create_todo(
+    State(store): State<StoreHandle>,
+    Json(input): Json<CreateTodo>,
+) -> Result<(StatusCode, Json<Todo>), StatusCode> {
+    let todo = store.create(input.title).await?;
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+pub async fn get_todo(
+    State(store): State<StoreHandle>,
+    Path(id): Path<String>,
+) -> Result<Json<Todo>, StatusCode> {
+    store.get(id).await?.map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn update_todo(
+    State(store): State<StoreHandle>,
+    Path(id): Path<String>,
+    Json(input): Json<UpdateTodo>,
+) -> Result<Json<Todo>, StatusCode> {
+    store
+        .update(id, input)
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn delete_todo(
+    State(store): State<StoreHandle>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    match store.delete(id).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::SequentialIds;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_concurrent_callers_are_serialized_by_the_actor() {
+        let store = StoreHandle::spawn(Arc::new(SequentialIds::default()));
+
+        let tasks: Vec<_> = (0..200)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let todo = store.create(format!("todo {}", i)).await.unwrap();
+                    let done = UpdateTodo {
+                        title: None,
+                        completed: Some(true),
+                    };
+                    store.update(todo.id.clone(), done).await.unwrap();
+                    if i % 2 == 1 {
+                        assert!(store.delete(todo.id).await.unwrap());
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let todos = store.list().await.unwrap();
+        assert_eq!(todos.len(), 100);
+        assert!(todos.iter().all(|todo| todo.completed));
+    }
+
+    #[tokio::test]
+    async fn test_calls_fail_cleanly_once_the_actor_is_gone() {
+        // A handle whose actor never ran: the receiver is dropped at once
+        let (sender, receiver) = mpsc::channel(1);
+        drop(receiver);
+        let store = StoreHandle { sender };
+
+        assert!(matches!(store.list().await, Err(ActorGone)));
+        assert!(matches!(store.get("missing".into()).await, Err(ActorGone)));
+    }
+}
@@ -0,0 +1,238 @@
+//! # Async-Aware Locking: `tokio::sync::RwLock` vs `std::sync::RwLock`
+//!
+//! `TodoStore` uses `std::sync::RwLock`. That is fine while a guard is held
+//! for a few nanoseconds, but in an async server it has two sharp edges:
+//! - Waiting for a std lock *blocks the worker thread*, so every other task
+//!   scheduled on that thread stalls too
+//! - A panic while holding the guard poisons the lock, and every later
+//!   `.unwrap()` panics as well
+//!
+//! `tokio::sync::RwLock` waits with `.await` instead (the task yields, the
+//! thread keeps running other tasks), never poisons, and its guard may be
+//! held across an `.await` - which a std guard can't in a spawned task,
+//! because it isn't `Send`.
+//!
+//! The same CRUD surface is served from `/todos-async`, and
+//! `GET /admin/lock-bench` runs both locks under contention while a
+//! heartbeat task measures how late the runtime wakes it up.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+
+use crate::{ids::SharedIds, CreateTodo, Todo, UpdateTodo};
+
+/// Same data as `TodoStore`, behind an async lock
+pub type AsyncTodoStore = Arc<RwLock<HashMap<String, Todo>>>;
+
+// ============================================================================
+// CRUD WITH `.read().await` / `.write().await`
+// ============================================================================
+
+pub async fn list_todos(State(store): State<AsyncTodoStore>) -> Json<Vec<Todo>> {
+    Json(store.read().await.values().cloned().collect())
+}
+
+pub async fn create_todo(
+    State(store): State<AsyncTodoStore>,
+    State(ids): State<SharedIds>,
+    Json(input): Json<CreateTodo>,
+) -> (StatusCode, Json<Todo>) {
+    let todo = Todo::new(ids.next_id(), input.title);
+    store.write().await.insert(todo.id.clone(), todo.clone());
+    (StatusCode::CREATED, Json(todo))
+}
+
+pub async fn get_todo(
+    State(store): State<AsyncTodoStore>,
+    Path(id): Path<String>,
+) -> Result<Json<Todo>, StatusCode> {
+    store
+        .read()
+        .await
+        .get(&id)
+        .cloned()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub async fn update_todo(
+    State(store): State<AsyncTodoStore>,
+    Path(id): Path<String>,
+    Json(input): Json<UpdateTodo>,
+) -> Result<Json<Todo>, StatusCode> {
+    let mut todos = store.write().await;
+    let todo = todos.get_mut(&id).ok_or(StatusCode::NOT_FOUND)?;
+    input.apply(todo);
+    Ok(Json(todo.clone()))
+}
+
+pub async fn delete_todo(
+    State(store): State<AsyncTodoStore>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    match store.write().await.remove(&id) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+// ============================================================================
+// BENCHMARK
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct BenchQuery {
+    /// Concurrent tasks hitting the lock
+    tasks: Option<usize>,
+    /// Operations per task; every fourth is a write
+    ops: Option<usize>,
+    /// How long each operation holds the guard
+    hold_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    elapsed_ms: u128,
+    operations: usize,
+    /// The heartbeat asks to sleep 1ms; this is how much later it woke up.
+    /// It measures how long the runtime was unable to run *other* tasks.
+    max_heartbeat_lag_ms: u128,
+    heartbeats: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    tasks: usize,
+    ops_per_task: usize,
+    hold_ms: u64,
+    std_rwlock: BenchResult,
+    tokio_rwlock: BenchResult,
+    notes: [&'static str; 3],
+}
+
+/// GET /admin/lock-bench?tasks=16&ops=10&hold_ms=1
+pub async fn lock_bench(Query(query): Query<BenchQuery>) -> Json<BenchReport> {
+    // Bounded so a curious `curl` can't tie the server up for minutes
+    let tasks = query.tasks.unwrap_or(16).clamp(1, 32);
+    let ops = query.ops.unwrap_or(10).clamp(1, 20);
+    let hold = Duration::from_millis(query.hold_ms.unwrap_or(1).clamp(1, 5));
+
+    let std_rwlock = with_heartbeat(std_contention(tasks, ops, hold)).await;
+    let tokio_rwlock = with_heartbeat(tokio_contention(tasks, ops, hold)).await;
+
+    Json(BenchReport {
+        tasks,
+        ops_per_task: ops,
+        hold_ms: hold.as_millis() as u64,
+        std_rwlock,
+        tokio_rwlock,
+        notes: [
+            "std: a task waiting for the lock blocks its worker thread, so the heartbeat lags",
+            "tokio: waiting tasks yield, other tasks (the heartbeat) keep running",
+            "std locks poison after a panic; tokio locks don't. Keep std guards short and never across .await",
+        ],
+    })
+}
+
+/// Work done while holding a std guard can't `.await`, so it blocks the thread
+async fn std_contention(tasks: usize, ops: usize, hold: Duration) -> usize {
+    let store = Arc::new(std::sync::RwLock::new(HashMap::<usize, usize>::new()));
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for op in 0..ops {
+                    if op % 4 == 0 {
+                        let mut map = store.write().unwrap();
+                        std::thread::sleep(hold);
+                        map.insert(task, op);
+                    } else {
+                        let map = store.read().unwrap();
+                        std::thread::sleep(hold);
+                        let _ = map.get(&task);
+                    }
+                    // Give the scheduler a chance, as real handlers would
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    tasks * ops
+}
+
+/// The same critical sections, but the guard is held across an `.await`
+async fn tokio_contention(tasks: usize, ops: usize, hold: Duration) -> usize {
+    let store = Arc::new(RwLock::new(HashMap::<usize, usize>::new()));
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for op in 0..ops {
+                    if op % 4 == 0 {
+                        let mut map = store.write().await;
+                        tokio::time::sleep(hold).await;
+                        map.insert(task, op);
+                    } else {
+                        let map = store.read().await;
+                        tokio::time::sleep(hold).await;
+                        let _ = map.get(&task);
+                    }
+                    tokio::task::yield_now().await;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+    tasks * ops
+}
+
+/// Run `work` while a separate task repeatedly sleeps 1ms and records how
+/// late it wakes up
+async fn with_heartbeat(work: impl std::future::Future<Output = usize>) -> BenchResult {
+    let stop = Arc::new(AtomicBool::new(false));
+    let heartbeat = tokio::spawn({
+        let stop = stop.clone();
+        async move {
+            let (mut max_lag, mut beats) = (Duration::ZERO, 0u64);
+            while !stop.load(Ordering::Relaxed) {
+                let start = Instant::now();
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                let lag = start.elapsed().saturating_sub(Duration::from_millis(1));
+                max_lag = max_lag.max(lag);
+                beats += 1;
+            }
+            (max_lag, beats)
+        }
+    });
+
+    let start = Instant::now();
+    let operations = work.await;
+    let elapsed = start.elapsed();
+    stop.store(true, Ordering::Relaxed);
+    let (max_lag, heartbeats) = heartbeat.await.unwrap();
+
+    BenchResult {
+        elapsed_ms: elapsed.as_millis(),
+        operations,
+        max_heartbeat_lag_ms: max_lag.as_millis(),
+        heartbeats,
+    }
+}
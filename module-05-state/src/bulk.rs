@@ -0,0 +1,185 @@
+//! # Bulk Creates with Per-Item Results
+//!
+//! `POST /todos/bulk` takes an array of todos and stores each one on its
+//! own, so one empty title doesn't throw away the other 99. The answer is
+//! module 07's bulk envelope (its `bulk.rs` is the reference copy): a
+//! `summary` of counts and one result per todo, with `200` when every item
+//! succeeded, `207 Multi-Status` when some did and `400` when none did. Because it's written against `TodoRepo`, every
+//! store gets it: `/todos/bulk`, `/todos-dash/bulk`, `/todos-redis/bulk`.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{ids::SharedIds, CreateTodo, StoreError, Todo, TodoRepo};
+
+/// More than this is rejected as a whole, before anything is stored
+const MAX_BATCH: usize = 100;
+
+#[derive(Debug, Serialize)]
+pub struct BulkResponse<T> {
+    pub summary: BulkSummary,
+    pub results: Vec<BulkItem<T>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BulkSummary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkItem<T> {
+    /// Position of the item in the request
+    pub index: usize,
+    pub status: u16,
+    /// The resource's id, when the item got far enough to have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retryable: Option<bool>,
+}
+
+/// Why one item failed
+pub struct ItemError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl From<StoreError> for ItemError {
+    fn from(error: StoreError) -> Self {
+        match error {
+            StoreError::Unavailable(why) => {
+                eprintln!("⚠️  Todo store unavailable: {}", why);
+                ItemError {
+                    status: StatusCode::SERVICE_UNAVAILABLE,
+                    code: "STORE_UNAVAILABLE",
+                    message: "The todo store is unavailable".to_string(),
+                    retryable: true,
+                }
+            }
+            StoreError::Corrupt(why) => {
+                eprintln!("⚠️  Todo store returned bad data: {}", why);
+                ItemError {
+                    status: StatusCode::INTERNAL_SERVER_ERROR,
+                    code: "STORE_CORRUPT",
+                    message: "The todo store returned bad data".to_string(),
+                    retryable: false,
+                }
+            }
+        }
+    }
+}
+
+impl<T> Default for BulkResponse<T> {
+    fn default() -> Self {
+        Self {
+            summary: BulkSummary::default(),
+            results: Vec::new(),
+        }
+    }
+}
+
+impl<T: Serialize> BulkResponse<T> {
+    /// Record the next item as a success
+    pub fn succeeded(&mut self, status: StatusCode, id: impl ToString, data: T) {
+        self.summary.succeeded += 1;
+        self.push(BulkItem {
+            index: self.results.len(),
+            status: status.as_u16(),
+            id: Some(id.to_string()),
+            data: Some(data),
+            error: None,
+            error_code: None,
+            retryable: None,
+        });
+    }
+
+    /// Record the next item as a failure
+    pub fn failed(&mut self, error: ItemError) {
+        self.summary.failed += 1;
+        self.push(BulkItem {
+            index: self.results.len(),
+            status: error.status.as_u16(),
+            id: None,
+            data: None,
+            error: Some(error.message),
+            error_code: Some(error.code),
+            retryable: Some(error.retryable),
+        });
+    }
+
+    fn push(&mut self, item: BulkItem<T>) {
+        self.summary.total += 1;
+        self.results.push(item);
+    }
+
+    /// 200 all ok, 207 mixed, 400 all failed
+    pub fn status(&self) -> StatusCode {
+        match (self.summary.succeeded, self.summary.failed) {
+            (_, 0) => StatusCode::OK,
+            (0, _) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::MULTI_STATUS,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for BulkResponse<T> {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self)).into_response()
+    }
+}
+
+/// POST /todos/bulk
+pub async fn create_todos<R: TodoRepo>(
+    State(store): State<R>,
+    State(ids): State<SharedIds>,
+    Json(batch): Json<Vec<CreateTodo>>,
+) -> Result<BulkResponse<Todo>, (StatusCode, String)> {
+    if batch.is_empty() || batch.len() > MAX_BATCH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("A batch holds 1 to {} todos", MAX_BATCH),
+        ));
+    }
+
+    let mut bulk = BulkResponse::default();
+    for input in batch {
+        match create_one(&store, &ids, input).await {
+            Ok(todo) => bulk.succeeded(StatusCode::CREATED, todo.id.clone(), todo),
+            Err(error) => bulk.failed(error),
+        }
+    }
+    Ok(bulk)
+}
+
+async fn create_one<R: TodoRepo>(
+    store: &R,
+    ids: &SharedIds,
+    input: CreateTodo,
+) -> Result<Todo, ItemError> {
+    if input.title.trim().is_empty() {
+        return Err(ItemError {
+            status: StatusCode::BAD_REQUEST,
+            code: "INVALID_TITLE",
+            message: "title is empty".to_string(),
+            retryable: false,
+        });
+    }
+    let todo = Todo::new(ids.next_id(), input.title);
+    store.insert(todo.clone()).await?;
+    Ok(todo)
+}
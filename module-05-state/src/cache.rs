@@ -0,0 +1,195 @@
+//! # An In-Process TTL Cache
+//!
+//! Some answers are expensive to compute and fine to serve a little stale:
+//! a slow query, a call to another service, a permission lookup. Keeping
+//! them in a `moka::future::Cache` in state gives every handler the same
+//! cache, with:
+//! - **TTL**: an entry is dropped `ttl` after it was computed, so staleness
+//!   is bounded
+//! - **A size bound**: past `max_capacity` entries, the least useful go first
+//! - **Stampede protection**: when a hundred requests miss the same key at
+//!   once, one of them computes it and the other 99 wait for that result,
+//!   instead of a hundred identical queries hitting the database
+//!
+//! `TtlCache::cached_fetch` wraps any fallible future that way. Errors are
+//! not cached - the next caller tries again - and hits and misses are
+//! counted for `GET /cache/stats`.
+
+use axum::{extract::State, http::StatusCode, Json};
+use moka::future::Cache;
+use serde::Serialize;
+use std::{
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+pub struct TtlCache<K, V> {
+    cache: Cache<K, V>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// A value from `cached_fetch`, and whether it was already there
+pub struct Fetched<V> {
+    pub value: V,
+    pub hit: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    /// Calls that ran the computation
+    pub misses: u64,
+    /// Computations that failed (and weren't cached)
+    pub errors: u64,
+    pub entries: u64,
+    pub ttl_secs: u64,
+}
+
+impl<K, V> TtlCache<K, V>
+where
+    K: Hash + Eq + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new(ttl: Duration, max_capacity: u64) -> Self {
+        Self {
+            cache: Cache::builder()
+                .time_to_live(ttl)
+                .max_capacity(max_capacity)
+                .build(),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached value for `key`, or the result of `fetch`, which is then
+    /// cached. Concurrent callers missing the same key share one `fetch`;
+    /// the ones that waited count as hits - they didn't compute anything.
+    pub async fn cached_fetch<E, F>(&self, key: K, fetch: F) -> Result<Fetched<V>, Arc<E>>
+    where
+        E: Send + Sync + 'static,
+        F: Future<Output = Result<V, E>>,
+    {
+        match self.cache.entry(key).or_try_insert_with(fetch).await {
+            Ok(entry) => {
+                let hit = !entry.is_fresh();
+                let counter = if hit { &self.hits } else { &self.misses };
+                counter.fetch_add(1, Ordering::Relaxed);
+                Ok(Fetched {
+                    value: entry.into_value(),
+                    hit,
+                })
+            }
+            Err(e) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        // Apply pending evictions so `entries` is current
+        self.cache.run_pending_tasks().await;
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            entries: self.cache.entry_count(),
+            ttl_secs: self.ttl.as_secs(),
+        }
+    }
+}
+
+// ============================================================================
+// THE QUERY CACHE
+// ============================================================================
+
+/// Query results by SQL text, in front of the `Database`
+pub type QueryCache = Arc<TtlCache<String, Vec<String>>>;
+
+/// `CACHE_TTL_SECS` (default 30) for the TTL
+pub fn query_cache_from_env() -> QueryCache {
+    let ttl = std::env::var("CACHE_TTL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(30);
+    Arc::new(TtlCache::new(Duration::from_secs(ttl), 1_000))
+}
+
+/// GET /cache/stats
+pub async fn cache_stats(State(cache): State<QueryCache>) -> Json<CacheStats> {
+    Json(cache.stats().await)
+}
+
+/// DELETE /cache - drop every entry; the next request recomputes
+pub async fn clear_cache(State(cache): State<QueryCache>) -> StatusCode {
+    cache.invalidate_all();
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrent_misses_compute_once() {
+        let cache = Arc::new(TtlCache::<&str, u64>::new(Duration::from_secs(60), 100));
+        let computed = Arc::new(AtomicU64::new(0));
+
+        let callers: Vec<_> = (0..20)
+            .map(|_| {
+                let (cache, computed) = (cache.clone(), computed.clone());
+                tokio::spawn(async move {
+                    cache
+                        .cached_fetch("answer", async {
+                            computed.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok::<_, String>(42)
+                        })
+                        .await
+                        .unwrap()
+                        .value
+                })
+            })
+            .collect();
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), 42);
+        }
+
+        let stats = cache.stats().await;
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+        assert_eq!((stats.misses, stats.hits), (1, 19));
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_not_cached_and_entries_expire() {
+        let cache = TtlCache::<&str, u64>::new(Duration::from_millis(50), 100);
+
+        let failed = cache.cached_fetch("k", async { Err("down") }).await;
+        let first = cache.cached_fetch("k", async { Ok::<_, &str>(1) }).await;
+        let cached = cache.cached_fetch("k", async { Ok::<_, &str>(2) }).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let expired = cache.cached_fetch("k", async { Ok::<_, &str>(3) }).await;
+
+        assert_eq!(*failed.err().unwrap(), "down");
+        assert!(!first.as_ref().unwrap().hit);
+        assert_eq!(first.unwrap().value, 1);
+        assert!(cached.as_ref().unwrap().hit);
+        assert_eq!(cached.unwrap().value, 1);
+        assert_eq!(expired.unwrap().value, 3);
+        assert_eq!(cache.stats().await.errors, 1);
+    }
+}
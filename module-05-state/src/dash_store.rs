@@ -0,0 +1,54 @@
+//! # A Sharded Store with `DashMap`
+//!
+//! `TodoStore` puts one `RwLock` around the whole `HashMap`: a write to any
+//! todo blocks reads of every other todo. `DashMap` splits the map into
+//! shards, each with its own lock, and picks the shard from the key's hash.
+//! Operations on different keys rarely touch the same lock, so writers stop
+//! queueing behind each other.
+//!
+//! The tradeoffs:
+//! - No lock is ever held by the caller across calls, so there is no way to
+//!   read-modify-write *several* entries atomically
+//! - Iterating locks shard by shard: `list` is not a consistent snapshot of
+//!   the map if writes happen meanwhile
+//! - A `Ref` from `get` still holds its shard's lock - drop it before
+//!   touching the same key again, or the thread deadlocks
+//!
+//! Both stores implement `TodoRepo`, so `/todos` and `/todos-dash` run the
+//! very same handlers.
+
+use dashmap::DashMap;
+use std::sync::Arc;
+
+use crate::{StoreError, Todo, TodoRepo, UpdateTodo};
+
+pub type DashTodoStore = Arc<DashMap<String, Todo>>;
+
+impl TodoRepo for DashTodoStore {
+    async fn list(&self) -> Result<Vec<Todo>, StoreError> {
+        Ok(self.iter().map(|entry| entry.value().clone()).collect())
+    }
+
+    async fn insert(&self, todo: Todo) -> Result<(), StoreError> {
+        DashMap::insert(self, todo.id.clone(), todo);
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Todo>, StoreError> {
+        // Clone and let the `Ref` (and its shard lock) go right away
+        Ok(DashMap::get(self, id).map(|entry| entry.value().clone()))
+    }
+
+    async fn update(&self, id: &str, input: UpdateTodo) -> Result<Option<Todo>, StoreError> {
+        // `get_mut` write-locks only this key's shard
+        let Some(mut todo) = self.get_mut(id) else {
+            return Ok(None);
+        };
+        input.apply(&mut todo);
+        Ok(Some(todo.clone()))
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, StoreError> {
+        Ok(DashMap::remove(self, id).is_some())
+    }
+}
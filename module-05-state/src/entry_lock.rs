@@ -0,0 +1,374 @@
+//! # Per-Entry Locks: Updating One Todo Without Locking the Map
+//!
+//! With one `RwLock` around the whole `HashMap`, updating one todo takes the
+//! *write* lock: every read and write of every other todo waits until it's
+//! done. `TodoStore` keeps the map lock, but each value is a `TodoEntry`
+//! with its own mutex:
+//! - the map lock guards *which* todos exist: inserts and removals take it
+//!   for writing, everything else only reads it
+//! - an entry's mutex guards *what's in* that todo
+//!
+//! ```ignore
+//! store.update_entry(&id, |todo| todo.completed = true);
+//! ```
+//!
+//! `update_entry` read-locks the map just long enough to clone the entry's
+//! `Arc`, lets the map go, then locks that entry alone. Two updates of
+//! different todos never wait for each other, and a `get` of another todo
+//! doesn't wait for either. `remove_entry` write-locks the map only to take
+//! the key out; it never waits for an update in progress.
+//!
+//! Locks are always taken map first, entry second, and an entry is never
+//! locked while waiting for the map - so the two can't deadlock. An update
+//! that raced a delete finishes on the removed entry: it ordered itself
+//! before the delete, and nobody can see the difference.
+//!
+//! `GET /admin/entry-lock-bench` runs the same contended updates against
+//! a whole-map write lock and against per-entry locks.
+
+use axum::{extract::Query, Json};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    snapshot::ValidateEntry,
+    stats::{HeapSize, Timestamped},
+    Todo, TodoStore,
+};
+
+/// One todo behind its own lock. Clones share the lock.
+#[derive(Debug, Clone)]
+pub(crate) struct TodoEntry(Arc<Mutex<Todo>>);
+
+impl TodoEntry {
+    pub fn new(todo: Todo) -> Self {
+        Self(Arc::new(Mutex::new(todo)))
+    }
+
+    /// A copy of the todo as it is now
+    pub fn get(&self) -> Todo {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Todo> {
+        self.0.lock().unwrap()
+    }
+}
+
+// Stored and exported as the plain todo, so snapshots and state files
+// don't change shape
+impl Serialize for TodoEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.lock().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TodoEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Todo::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl HeapSize for TodoEntry {
+    fn heap_size(&self) -> usize {
+        // The `Arc` allocation: two reference counts and the mutex
+        2 * size_of::<usize>() + size_of::<Mutex<Todo>>() + self.lock().heap_size()
+    }
+}
+
+impl Timestamped for TodoEntry {
+    fn created_at(&self) -> u64 {
+        self.lock().created_at
+    }
+}
+
+impl ValidateEntry for TodoEntry {
+    fn validate(&self, key: &str) -> Result<(), String> {
+        self.lock().validate(key)
+    }
+}
+
+// ============================================================================
+// THE PER-KEY API
+// ============================================================================
+
+/// Fine-grained access to a `TodoStore`
+pub trait EntryLocks {
+    /// Change one todo in place, holding only its lock. Returns the updated
+    /// todo, or `None` if there's no such id.
+    fn update_entry(&self, id: &str, f: impl FnOnce(&mut Todo)) -> Option<Todo>;
+    /// Take one todo out, holding the map's write lock only for that
+    fn remove_entry(&self, id: &str) -> Option<Todo>;
+}
+
+impl EntryLocks for TodoStore {
+    fn update_entry(&self, id: &str, f: impl FnOnce(&mut Todo)) -> Option<Todo> {
+        // The map guard is dropped at the end of this statement
+        let entry = self.read().unwrap().get(id).cloned()?;
+        let mut todo = entry.lock();
+        f(&mut todo);
+        Some(todo.clone())
+    }
+
+    fn remove_entry(&self, id: &str) -> Option<Todo> {
+        let entry = self.write().unwrap().remove(id)?;
+        // Waits for an update still running on it, with the map unlocked
+        Some(entry.get())
+    }
+}
+
+// ============================================================================
+// CONTENTION BENCHMARK
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct BenchQuery {
+    writers: Option<usize>,
+    ops: Option<usize>,
+    hold_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    elapsed_ms: u128,
+    updates: usize,
+    /// Slowest `get` of an untouched todo while the writers ran
+    max_read_wait_ms: u128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    writers: usize,
+    ops_per_writer: usize,
+    hold_ms: u64,
+    whole_map_lock: BenchResult,
+    per_entry_locks: BenchResult,
+    notes: [&'static str; 2],
+}
+
+/// GET /admin/entry-lock-bench?writers=8&ops=10&hold_ms=2
+pub async fn entry_lock_bench(Query(query): Query<BenchQuery>) -> Json<BenchReport> {
+    // Bounded so a curious `curl` can't tie the server up for minutes
+    let writers = query.writers.unwrap_or(8).clamp(1, 16);
+    let ops = query.ops.unwrap_or(10).clamp(1, 20);
+    let hold = Duration::from_millis(query.hold_ms.unwrap_or(2).clamp(1, 5));
+
+    // Both hold std locks while sleeping: run them off the runtime's workers
+    let (whole_map_lock, per_entry_locks) = tokio::task::spawn_blocking(move || {
+        (
+            contention(writers, ops, hold, Granularity::WholeMap),
+            contention(writers, ops, hold, Granularity::PerEntry),
+        )
+    })
+    .await
+    .unwrap();
+
+    Json(BenchReport {
+        writers,
+        ops_per_writer: ops,
+        hold_ms: hold.as_millis() as u64,
+        whole_map_lock,
+        per_entry_locks,
+        notes: [
+            "whole map: each update holds the map's write lock, so updates of different todos queue up and reads wait behind them",
+            "per entry: each update holds only its todo's lock, so writers run side by side and reads of other todos don't wait",
+        ],
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Granularity {
+    WholeMap,
+    PerEntry,
+}
+
+/// `writers` threads each update their own todo `ops` times, spending
+/// `hold` inside the critical section, while a reader keeps reading a todo
+/// nobody writes
+fn contention(writers: usize, ops: usize, hold: Duration, granularity: Granularity) -> BenchResult {
+    let store: TodoStore = Arc::new(RwLock::new(HashMap::new()));
+    for n in 0..=writers {
+        let todo = Todo {
+            id: n.to_string(),
+            title: format!("todo {}", n),
+            completed: false,
+            created_at: 0,
+            completed_at: None,
+        };
+        store
+            .write()
+            .unwrap()
+            .insert(todo.id.clone(), TodoEntry::new(todo));
+    }
+    let untouched = writers.to_string();
+
+    let start = Instant::now();
+    let max_read_wait = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..writers)
+            .map(|n| {
+                let store = &store;
+                scope.spawn(move || {
+                    let id = n.to_string();
+                    for _ in 0..ops {
+                        let update = |todo: &mut Todo| {
+                            std::thread::sleep(hold);
+                            todo.completed = !todo.completed;
+                        };
+                        match granularity {
+                            Granularity::WholeMap => {
+                                let todos = store.write().unwrap();
+                                update(&mut todos[&id].lock());
+                            }
+                            Granularity::PerEntry => {
+                                store.update_entry(&id, update);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut max_wait = Duration::ZERO;
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            let asked = Instant::now();
+            let entry = store.read().unwrap().get(&untouched).cloned();
+            if let Some(entry) = entry {
+                entry.get();
+            }
+            max_wait = max_wait.max(asked.elapsed());
+            std::thread::sleep(hold / 2);
+        }
+        max_wait
+    });
+
+    BenchResult {
+        elapsed_ms: start.elapsed().as_millis(),
+        updates: writers * ops,
+        max_read_wait_ms: max_read_wait.as_millis(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Barrier};
+
+    fn store_with(ids: &[&str]) -> TodoStore {
+        let todos = ids
+            .iter()
+            .map(|id| {
+                let todo = Todo {
+                    id: id.to_string(),
+                    title: format!("todo {}", id),
+                    completed: false,
+                    created_at: 0,
+                    completed_at: None,
+                };
+                (todo.id.clone(), TodoEntry::new(todo))
+            })
+            .collect();
+        Arc::new(RwLock::new(todos))
+    }
+
+    #[test]
+    fn test_an_update_in_progress_blocks_no_other_todo() {
+        let store = store_with(&["a", "b"]);
+        let (entered_tx, entered) = mpsc::channel();
+        let (release, release_rx) = mpsc::channel::<()>();
+
+        std::thread::scope(|scope| {
+            let store = &store;
+            let slow = scope.spawn(move || {
+                store.update_entry("a", |todo| {
+                    entered_tx.send(()).unwrap();
+                    // Hold a's lock until the other todo has been used
+                    release_rx.recv().unwrap();
+                    todo.title = "slow".to_string();
+                })
+            });
+            entered.recv().unwrap();
+
+            // None of these would return while a writer held the map's lock
+            let b = store
+                .update_entry("b", |todo| todo.completed = true)
+                .unwrap();
+            assert!(b.completed);
+            assert!(store.read().unwrap()["b"].get().completed);
+            store.write().unwrap().insert(
+                "c".to_string(),
+                TodoEntry::new(Todo {
+                    id: "c".to_string(),
+                    title: "new".to_string(),
+                    completed: false,
+                    created_at: 0,
+                    completed_at: None,
+                }),
+            );
+            assert_eq!(store.remove_entry("b").unwrap().id, "b");
+
+            release.send(()).unwrap();
+            assert_eq!(slow.join().unwrap().unwrap().title, "slow");
+        });
+
+        assert_eq!(store.read().unwrap()["a"].get().title, "slow");
+        assert!(store.update_entry("missing", |_| {}).is_none());
+    }
+
+    #[test]
+    fn test_entries_serialize_as_plain_todos() {
+        let store = store_with(&["a"]);
+        let json = serde_json::to_value(&*store.read().unwrap()).unwrap();
+        assert_eq!(json["a"]["title"], "todo a");
+
+        let back: HashMap<String, TodoEntry> = serde_json::from_value(json).unwrap();
+        assert_eq!(back["a"].get().title, "todo a");
+    }
+
+    /// No clock involved: every writer waits inside its critical section
+    /// until all of them are in theirs, which only returns if none of the
+    /// updates excludes another
+    #[test]
+    fn test_per_entry_updates_run_side_by_side() {
+        const WRITERS: usize = 8;
+        let ids: Vec<String> = (0..WRITERS).map(|n| n.to_string()).collect();
+        let store = store_with(&ids.iter().map(String::as_str).collect::<Vec<_>>());
+        let all_inside = Barrier::new(WRITERS);
+
+        std::thread::scope(|scope| {
+            for id in &ids {
+                let (store, all_inside) = (&store, &all_inside);
+                scope.spawn(move || {
+                    store.update_entry(id, |todo| {
+                        all_inside.wait();
+                        todo.completed = true;
+                    })
+                });
+            }
+        });
+        assert!(ids
+            .iter()
+            .all(|id| store.read().unwrap()[id].get().completed));
+
+        // And the whole-map lock is the one that excludes: taken by one
+        // writer, nobody else gets in
+        let held = store.write().unwrap();
+        std::thread::scope(|scope| {
+            let store = &store;
+            let blocked = scope.spawn(move || store.try_write().is_err());
+            assert!(blocked.join().unwrap());
+        });
+        drop(held);
+    }
+
+    #[test]
+    fn test_bench_counts_every_update() {
+        for granularity in [Granularity::WholeMap, Granularity::PerEntry] {
+            let result = contention(3, 2, Duration::from_millis(1), granularity);
+            assert_eq!(result.updates, 6, "{:?}", granularity);
+        }
+    }
+}
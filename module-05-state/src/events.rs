@@ -0,0 +1,178 @@
+//! # Live Updates: a Broadcast Bus in State
+//!
+//! State isn't only data to read; it can be a channel. `EventBus` wraps a
+//! `tokio::sync::broadcast::Sender<TodoEvent>` - cloning it is cheap, and
+//! every clone sends into the same channel - and lives in state like any
+//! other dependency:
+//! - **Publishing** happens in one place: `Published<R>` wraps any
+//!   `TodoRepo` and, after a write succeeds, sends `Created`, `Updated` or
+//!   `Deleted`. The CRUD (and bulk) handlers don't change at all
+//! - **Subscribing** is `GET /events`: each connection gets its own
+//!   `Receiver` and turns it into a Server-Sent Events stream (Module 10
+//!   covers SSE itself)
+//!
+//! A broadcast channel keeps the last `CAPACITY` events. A subscriber that
+//! falls further behind doesn't slow the writers down; it gets a `lagged`
+//! event saying how many it missed, and should refetch `GET /todos`.
+//! Publishing with no subscribers is not an error - the event is dropped.
+//!
+//! An SSE response never finishes on its own, and graceful shutdown waits
+//! for every open response. `EventBus::close` ends the streams, so Ctrl+C
+//! isn't stuck behind a browser tab.
+//!
+//! Only changes made through the API are published: the janitor and
+//! `/admin/import` write to the stores directly.
+
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+
+use crate::{StoreError, Todo, TodoRepo, UpdateTodo};
+
+/// Events kept for slow subscribers before they start missing some
+const CAPACITY: usize = 256;
+
+/// `store` is where it happened: "todos", "todos-dash" or "todos-redis"
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TodoEvent {
+    Created { store: &'static str, todo: Todo },
+    Updated { store: &'static str, todo: Todo },
+    Deleted { store: &'static str, id: String },
+}
+
+impl TodoEvent {
+    /// The SSE `event:` name, so browsers can `addEventListener("created")`
+    fn name(&self) -> &'static str {
+        match self {
+            TodoEvent::Created { .. } => "created",
+            TodoEvent::Updated { .. } => "updated",
+            TodoEvent::Deleted { .. } => "deleted",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<TodoEvent>,
+    closed: CancellationToken,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            closed: CancellationToken::new(),
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: TodoEvent) {
+        // `Err` only means nobody is listening right now
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TodoEvent> {
+        self.sender.subscribe()
+    }
+
+    /// End every `/events` stream, now and for later subscribers
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+}
+
+// ============================================================================
+// PUBLISHING
+// ============================================================================
+
+/// A `TodoRepo` that announces every successful write on the bus
+#[derive(Clone)]
+pub struct Published<R> {
+    inner: R,
+    bus: EventBus,
+    store: &'static str,
+}
+
+impl<R> Published<R> {
+    pub fn new(inner: R, bus: EventBus, store: &'static str) -> Self {
+        Self { inner, bus, store }
+    }
+}
+
+impl<R: TodoRepo> TodoRepo for Published<R> {
+    async fn list(&self) -> Result<Vec<Todo>, StoreError> {
+        self.inner.list().await
+    }
+
+    async fn insert(&self, todo: Todo) -> Result<(), StoreError> {
+        self.inner.insert(todo.clone()).await?;
+        self.bus.publish(TodoEvent::Created {
+            store: self.store,
+            todo,
+        });
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Todo>, StoreError> {
+        self.inner.get(id).await
+    }
+
+    async fn update(&self, id: &str, input: UpdateTodo) -> Result<Option<Todo>, StoreError> {
+        let updated = self.inner.update(id, input).await?;
+        if let Some(todo) = &updated {
+            self.bus.publish(TodoEvent::Updated {
+                store: self.store,
+                todo: todo.clone(),
+            });
+        }
+        Ok(updated)
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, StoreError> {
+        let removed = self.inner.remove(id).await?;
+        if removed {
+            self.bus.publish(TodoEvent::Deleted {
+                store: self.store,
+                id: id.to_string(),
+            });
+        }
+        Ok(removed)
+    }
+}
+
+// ============================================================================
+// SUBSCRIBING
+// ============================================================================
+
+/// GET /events - every todo change from now on, as Server-Sent Events
+pub async fn events(
+    State(bus): State<EventBus>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Subscribe before returning, so nothing published after this request
+    // was answered can be missed
+    let receiver = bus.subscribe();
+    let stream = stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(event) => Event::default()
+                .event(event.name())
+                .json_data(&event)
+                .expect("events serialize"),
+            Err(RecvError::Lagged(missed)) => Event::default()
+                .event("lagged")
+                .data(format!(r#"{{"missed":{}}}"#, missed)),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), receiver))
+    })
+    .take_until(bus.closed.cancelled_owned());
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,44 @@
+//! # Where New Ids Come From
+//!
+//! A handler that calls `Uuid::new_v4()` answers differently every run, so
+//! its tests can only check that an id "looks like" a UUID. New todos get
+//! their id from an `IdProvider` in state instead:
+//! - `RandomIds` in the server: `Uuid::new_v4()`, as before
+//! - `SequentialIds` in tests: `...0001`, `...0002`, ... - a test knows the
+//!   id it will get and can compare whole responses
+//!
+//! Every store takes it the same way: the CRUD routes through `Crud<R>`
+//! state, the actor when it is spawned. Module 11 covers the pattern.
+
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub trait IdProvider: Send + Sync {
+    fn next_id(&self) -> Uuid;
+}
+
+/// How the provider is kept in state
+pub type SharedIds = Arc<dyn IdProvider>;
+
+pub struct RandomIds;
+
+impl IdProvider for RandomIds {
+    fn next_id(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// `00000000-0000-0000-0000-000000000001`, then `...0002`, ...
+#[cfg(test)]
+#[derive(Default)]
+pub struct SequentialIds(AtomicU64);
+
+#[cfg(test)]
+impl IdProvider for SequentialIds {
+    fn next_id(&self) -> Uuid {
+        let n = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        Uuid::from_u128(n as u128)
+    }
+}
@@ -4,14 +4,71 @@
 //! - Immutable shared state with State<T>
 //! - Mutable shared state with Arc<Mutex<T>>
 //! - Database connection pools
-//! - Multiple state types
+//! - Multiple state types, extracted separately via `FromRef`
+//! - Store statistics and memory accounting (see `stats.rs`)
+//! - Snapshot export and atomic restore of all stores (see `snapshot.rs`)
+//! - Cursor pagination with RFC 8288 `Link` headers (see `pagination.rs`)
+//! - Building the app from injected dependencies, so every test gets its own stores (see `build_app`)
+//! - `tokio::sync::RwLock` vs `std::sync::RwLock` under load (see `async_store.rs`)
+//! - One handler set over two stores: `RwLock<HashMap>` and `DashMap` (see `dash_store.rs`)
+//! - Per-entry locks: updating one todo without write-locking the map (see `entry_lock.rs`)
+//! - Surviving restarts: load at startup, flush periodically and on shutdown (see `persist.rs`)
+//! - A background janitor sharing the stores, stopped by a `CancellationToken` (see `maintenance.rs`)
+//! - A lock-free store owned by one task, reached over channels (see `actor_store.rs`)
+//! - A tiny DI container: dependencies provided and looked up by type (see `registry.rs`)
+//! - State shared across server instances, in Redis (see `redis_store.rs`)
+//! - An in-process TTL cache with stampede protection (see `cache.rs`)
+//! - Bulk creates with a status per item (see `bulk.rs`)
+//! - A broadcast event bus in state, streamed live over SSE (see `events.rs`)
+//! - Ids from an injected provider, so tests know them in advance (see `ids.rs`)
 
-use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+mod actor_store;
+mod async_store;
+mod bulk;
+mod cache;
+mod dash_store;
+mod entry_lock;
+mod events;
+mod ids;
+mod maintenance;
+mod pagination;
+mod persist;
+mod redis_store;
+mod registry;
+mod snapshot;
+mod stats;
+
+use actor_store::StoreHandle;
+use async_store::AsyncTodoStore;
+use axum::{
+    extract::{DefaultBodyLimit, FromRef, MatchedPath, Request, State},
+    handler::Handler,
+    http::StatusCode,
+    middleware::{self, Next},
+    response::Response,
+    Json,
+};
+use cache::QueryCache;
+use course_routes::Routes;
+use dash_store::DashTodoStore;
+use entry_lock::{EntryLocks, TodoEntry};
+use events::{EventBus, Published};
+use futures::future::BoxFuture;
+use ids::{RandomIds, SharedIds};
+use maintenance::JanitorConfig;
+use persist::Persistence;
+use redis_store::RedisTodoStore;
+use registry::{FromRegistry, Registry};
 use serde::{Deserialize, Serialize};
+use snapshot::{SnapshotRegistry, ValidateEntry, Whole};
+use stats::{HeapSize, StoreRegistry, Timestamped};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    future::Future,
     sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
 };
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 // ============================================================================
@@ -45,6 +102,58 @@ struct Todo {
     id: String,
     title: String,
     completed: bool,
+    /// Unix timestamp (seconds)
+    created_at: u64,
+    /// When it was last marked completed; the janitor expires old ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    completed_at: Option<u64>,
+}
+
+impl HeapSize for Todo {
+    fn heap_size(&self) -> usize {
+        self.id.heap_size() + self.title.heap_size()
+    }
+}
+
+impl Timestamped for Todo {
+    fn created_at(&self) -> u64 {
+        self.created_at
+    }
+}
+
+impl ValidateEntry for Todo {
+    fn validate(&self, key: &str) -> Result<(), String> {
+        if self.id != key {
+            return Err(format!("id {:?} doesn't match its key", self.id));
+        }
+        if Uuid::parse_str(&self.id).is_err() {
+            return Err("id is not a UUID".to_string());
+        }
+        if self.title.trim().is_empty() {
+            return Err("title is empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Todo {
+    /// Open, created now
+    fn new(id: Uuid, title: String) -> Self {
+        Todo {
+            id: id.to_string(),
+            title,
+            completed: false,
+            created_at: now_unix(),
+            completed_at: None,
+        }
+    }
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,117 +161,306 @@ struct CreateTodo {
     title: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 struct UpdateTodo {
     title: Option<String>,
     completed: Option<bool>,
 }
 
-/// Our mutable state - a thread-safe HashMap
-type TodoStore = Arc<RwLock<HashMap<String, Todo>>>;
+/// Our mutable state - a thread-safe HashMap, with a lock per todo as well
+/// (see `entry_lock.rs`)
+type TodoStore = Arc<RwLock<HashMap<String, TodoEntry>>>;
+
+/// What the CRUD handlers need from a store. The handlers are generic over
+/// it, so the same code serves the `RwLock<HashMap>` store at `/todos`, the
+/// `DashMap` one at `/todos-dash` (see `dash_store.rs`) and the Redis one at
+/// `/todos-redis` (see `redis_store.rs`).
+///
+/// Methods are async and fallible because a store may live across the
+/// network; the in-memory ones are always ready and never fail.
+trait TodoRepo: Clone + Send + Sync + 'static {
+    fn list(&self) -> impl Future<Output = Result<Vec<Todo>, StoreError>> + Send;
+    fn insert(&self, todo: Todo) -> impl Future<Output = Result<(), StoreError>> + Send;
+    fn get(&self, id: &str) -> impl Future<Output = Result<Option<Todo>, StoreError>> + Send;
+    /// The updated todo, or `None` if there's no such id
+    fn update(
+        &self,
+        id: &str,
+        input: UpdateTodo,
+    ) -> impl Future<Output = Result<Option<Todo>, StoreError>> + Send;
+    fn remove(&self, id: &str) -> impl Future<Output = Result<bool, StoreError>> + Send;
+}
+
+/// Why a store couldn't answer
+#[derive(Debug)]
+enum StoreError {
+    /// The backend can't be reached or gave up; worth retrying later
+    Unavailable(String),
+    /// The backend returned something that isn't a todo
+    Corrupt(String),
+}
+
+impl From<StoreError> for StatusCode {
+    fn from(error: StoreError) -> Self {
+        match error {
+            StoreError::Unavailable(why) => {
+                eprintln!("⚠️  Todo store unavailable: {}", why);
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            StoreError::Corrupt(why) => {
+                eprintln!("⚠️  Todo store returned bad data: {}", why);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// The map lock only says which todos exist; updates lock just their own
+/// todo, so writers of different todos don't wait for each other
+impl TodoRepo for TodoStore {
+    async fn list(&self) -> Result<Vec<Todo>, StoreError> {
+        // Clone the entries, then read each one with the map unlocked
+        let entries: Vec<TodoEntry> = self.read().unwrap().values().cloned().collect();
+        Ok(entries.iter().map(TodoEntry::get).collect())
+    }
+
+    async fn insert(&self, todo: Todo) -> Result<(), StoreError> {
+        self.write()
+            .unwrap()
+            .insert(todo.id.clone(), TodoEntry::new(todo));
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Todo>, StoreError> {
+        let entry = self.read().unwrap().get(id).cloned();
+        Ok(entry.map(|entry| entry.get()))
+    }
+
+    async fn update(&self, id: &str, input: UpdateTodo) -> Result<Option<Todo>, StoreError> {
+        Ok(self.update_entry(id, |todo| input.apply(todo)))
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, StoreError> {
+        Ok(self.remove_entry(id).is_some())
+    }
+}
+
+impl UpdateTodo {
+    fn apply(self, todo: &mut Todo) {
+        if let Some(title) = self.title {
+            todo.title = title;
+        }
+        if let Some(completed) = self.completed {
+            if completed != todo.completed {
+                todo.completed_at = completed.then(now_unix);
+            }
+            todo.completed = completed;
+        }
+    }
+}
 
 // List all todos
-async fn list_todos(State(store): State<TodoStore>) -> Json<Vec<Todo>> {
-    let todos = store.read().unwrap();
-    let todos_vec: Vec<Todo> = todos.values().cloned().collect();
-    Json(todos_vec)
+async fn list_todos<R: TodoRepo>(State(store): State<R>) -> Result<Json<Vec<Todo>>, StatusCode> {
+    Ok(Json(store.list().await?))
 }
 
 // Create a new todo
-async fn create_todo(
-    State(store): State<TodoStore>,
+async fn create_todo<R: TodoRepo>(
+    State(store): State<R>,
+    State(ids): State<SharedIds>,
     Json(input): Json<CreateTodo>,
-) -> (StatusCode, Json<Todo>) {
-    let todo = Todo {
-        id: Uuid::new_v4().to_string(),
-        title: input.title,
-        completed: false,
-    };
+) -> Result<(StatusCode, Json<Todo>), StatusCode> {
+    let todo = Todo::new(ids.next_id(), input.title);
 
-    store.write().unwrap().insert(todo.id.clone(), todo.clone());
+    store.insert(todo.clone()).await?;
 
-    (StatusCode::CREATED, Json(todo))
+    Ok((StatusCode::CREATED, Json(todo)))
 }
 
 // Get a single todo
-async fn get_todo(
-    State(store): State<TodoStore>,
+async fn get_todo<R: TodoRepo>(
+    State(store): State<R>,
     axum::extract::Path(id): axum::extract::Path<String>,
 ) -> Result<Json<Todo>, StatusCode> {
-    let todos = store.read().unwrap();
-    todos
-        .get(&id)
-        .cloned()
-        .map(Json)
-        .ok_or(StatusCode::NOT_FOUND)
+    store.get(&id).await?.map(Json).ok_or(StatusCode::NOT_FOUND)
 }
 
 // Update a todo
-async fn update_todo(
-    State(store): State<TodoStore>,
+async fn update_todo<R: TodoRepo>(
+    State(store): State<R>,
     axum::extract::Path(id): axum::extract::Path<String>,
     Json(input): Json<UpdateTodo>,
 ) -> Result<Json<Todo>, StatusCode> {
-    let mut todos = store.write().unwrap();
-
-    if let Some(todo) = todos.get_mut(&id) {
-        if let Some(title) = input.title {
-            todo.title = title;
-        }
-        if let Some(completed) = input.completed {
-            todo.completed = completed;
-        }
-        Ok(Json(todo.clone()))
-    } else {
-        Err(StatusCode::NOT_FOUND)
-    }
+    store
+        .update(&id, input)
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
 }
 
 // Delete a todo
-async fn delete_todo(
-    State(store): State<TodoStore>,
+async fn delete_todo<R: TodoRepo>(
+    State(store): State<R>,
     axum::extract::Path(id): axum::extract::Path<String>,
-) -> StatusCode {
-    let mut todos = store.write().unwrap();
-    if todos.remove(&id).is_some() {
-        StatusCode::NO_CONTENT
-    } else {
-        StatusCode::NOT_FOUND
+) -> Result<StatusCode, StatusCode> {
+    match store.remove(&id).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// State of one set of CRUD routes: the store, and where new ids come from
+#[derive(Clone)]
+struct Crud<R> {
+    store: R,
+    ids: SharedIds,
+}
+
+impl<R> FromRef<Crud<R>> for SharedIds {
+    fn from_ref(crud: &Crud<R>) -> Self {
+        crud.ids.clone()
+    }
+}
+
+// Not for any `R` - axum owns `FromRef` - but every CRUD store is `Published`
+impl<R: Clone> FromRef<Crud<Published<R>>> for Published<R> {
+    fn from_ref(crud: &Crud<Published<R>>) -> Self {
+        crud.store.clone()
+    }
+}
+
+impl FromRef<Crud<AsyncTodoStore>> for AsyncTodoStore {
+    fn from_ref(crud: &Crud<AsyncTodoStore>) -> Self {
+        crud.store.clone()
     }
 }
 
+/// The CRUD routes for any store
+fn crud_routes<R, S>(store: R, ids: SharedIds) -> Routes<S>
+where
+    R: TodoRepo + FromRef<Crud<R>>,
+    S: Clone + Send + Sync + 'static,
+{
+    Routes::new()
+        .get("/", list_todos::<R>, "List all todos")
+        .post("/", create_todo::<R>, "Create todo")
+        .post(
+            "/bulk",
+            bulk::create_todos::<R>,
+            "Create many, a status per item",
+        )
+        .get("/{id}", get_todo::<R>, "Get single todo")
+        .put("/{id}", update_todo::<R>, "Update todo")
+        .delete("/{id}", delete_todo::<R>, "Delete todo")
+        .with_state(Crud { store, ids })
+}
+
 // ============================================================================
 // LESSON 3: Multiple State Types
 // ============================================================================
 
-/// When you need multiple independent state types, combine them
-#[derive(Clone)]
-#[allow(dead_code)] // Fields shown for demonstration
+/// When you need multiple independent state types, combine them into one
+/// router state. `FromRef` tells axum how to get each part out of it, so a
+/// handler still asks only for what it uses: `State<TodoStore>`,
+/// `State<Arc<RwLock<Metrics>>>`, ... Every field type must be distinct.
+///
+/// `#[derive(FromRef)]` writes one impl per field (it needs axum's `macros`
+/// feature); `config` opts out to show what such an impl looks like.
+#[derive(Clone, FromRef)]
 struct CombinedState {
+    #[from_ref(skip)]
     config: Arc<AppConfig>,
     todos: TodoStore,
     metrics: Arc<RwLock<Metrics>>,
+    db: Arc<dyn Database>,
+    query_cache: QueryCache,
+    events: EventBus,
+    stores: StoreRegistry,
+    snapshots: SnapshotRegistry,
+}
+
+/// What the derive generates for every other field
+impl FromRef<CombinedState> for Arc<AppConfig> {
+    fn from_ref(state: &CombinedState) -> Self {
+        state.config.clone()
+    }
 }
 
-#[derive(Debug, Default)]
+// And the other direction: every field is looked up in a `Registry` by type
+registry::from_registry!(CombinedState {
+    config,
+    todos,
+    metrics,
+    db,
+    query_cache,
+    events,
+    stores,
+    snapshots,
+});
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct Metrics {
     request_count: u64,
     error_count: u64,
+    /// Removed by the background janitor; absent from older snapshots
+    #[serde(default)]
+    expired_todos: u64,
+    /// Keyed by route template (`/todos/{id}`), so ids don't explode the map
+    #[serde(default)]
+    per_route: BTreeMap<String, RouteMetrics>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RouteMetrics {
+    requests: u64,
+    errors: u64,
 }
 
-// You can extract the whole state or use From traits for convenience
-async fn get_metrics(State(state): State<CombinedState>) -> Json<serde_json::Value> {
-    let metrics = state.metrics.read().unwrap();
+/// Requests that matched no route share one bucket
+const UNMATCHED_ROUTE: &str = "(unmatched)";
+
+/// Counts every request and every 4xx/5xx response, per matched route.
+/// Handlers no longer touch `Metrics` - they can't forget to, and a
+/// rejection that never reaches the handler is counted too.
+async fn track_metrics(
+    State(metrics): State<Arc<RwLock<Metrics>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, |path| path.as_str())
+        .to_string();
+    let response = next.run(request).await;
+
+    let failed = response.status().is_client_error() || response.status().is_server_error();
+    let mut metrics = metrics.write().unwrap();
+    metrics.request_count += 1;
+    metrics.error_count += failed as u64;
+    let route = metrics.per_route.entry(route).or_default();
+    route.requests += 1;
+    route.errors += failed as u64;
+    response
+}
+
+// Extract just the parts you need - one `State` per substate
+async fn get_metrics(
+    State(metrics): State<Arc<RwLock<Metrics>>>,
+    State(config): State<Arc<AppConfig>>,
+) -> Json<serde_json::Value> {
+    let metrics = metrics.read().unwrap();
     Json(serde_json::json!({
         "requests": metrics.request_count,
         "errors": metrics.error_count,
-        "app_version": state.config.version
+        "expired_todos": metrics.expired_todos,
+        "routes": metrics.per_route,
+        "app_version": config.version
     }))
 }
 
-async fn increment_request_count(State(state): State<CombinedState>) -> &'static str {
-    let mut metrics = state.metrics.write().unwrap();
-    metrics.request_count += 1;
+/// Does nothing: `track_metrics` counts it like any other request
+async fn increment_request_count() -> &'static str {
     "Request counted!"
 }
 
@@ -170,6 +468,12 @@ async fn increment_request_count(State(state): State<CombinedState>) -> &'static
 // LESSON 4: Database Connection Pool Pattern
 // ============================================================================
 
+/// What handlers need from a database. Handlers hold an `Arc<dyn Database>`,
+/// so tests can hand the app a fake without touching the real pool.
+trait Database: Send + Sync {
+    fn query<'a>(&'a self, sql: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>>;
+}
+
 /// Simulating a database connection pool
 /// In a real app, this would be sqlx::PgPool or similar
 #[derive(Clone)]
@@ -186,21 +490,43 @@ impl DbPool {
             max_connections: 10,
         }
     }
+}
 
+impl Database for DbPool {
     // Simulated query
-    async fn query(&self, _sql: &str) -> Result<Vec<String>, String> {
+    fn query<'a>(&'a self, _sql: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
         // In real app: sqlx::query!(...).fetch_all(&self.pool).await
-        Ok(vec!["result1".to_string(), "result2".to_string()])
+        Box::pin(async { Ok(vec!["result1".to_string(), "result2".to_string()]) })
     }
 }
 
-async fn db_query(State(pool): State<DbPool>) -> Json<Vec<String>> {
-    match pool.query("SELECT * FROM users").await {
+async fn db_query(State(db): State<Arc<dyn Database>>) -> Json<Vec<String>> {
+    match db.query("SELECT * FROM users").await {
         Ok(results) => Json(results),
         Err(_) => Json(vec![]),
     }
 }
 
+/// The same query through the TTL cache (see `cache.rs`): within the TTL,
+/// the database sees it once, however many requests ask
+async fn db_query_cached(
+    State(db): State<Arc<dyn Database>>,
+    State(cache): State<QueryCache>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let sql = "SELECT * FROM users";
+    let fetched = cache
+        .cached_fetch(sql.to_string(), db.query(sql))
+        .await
+        .map_err(|e| {
+            eprintln!("⚠️  Query failed: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        })?;
+    Ok(Json(serde_json::json!({
+        "rows": fetched.value,
+        "cached": fetched.hit,
+    })))
+}
+
 // ============================================================================
 // LESSON 5: State with Extension Pattern
 // ============================================================================
@@ -222,72 +548,302 @@ async fn get_current_user(Extension(user): Extension<CurrentUser>) -> Json<serde
 }
 
 // ============================================================================
-// MAIN
+// LESSON 6: App Factories with Injected Dependencies
 // ============================================================================
 
-#[tokio::main]
-async fn main() {
-    // Initialize immutable config
-    let config = Arc::new(AppConfig {
-        app_name: "Axum Todo API".to_string(),
-        version: "1.0.0".to_string(),
-        max_items_per_page: 100,
-    });
+/// Everything the app reads or writes outside its own handlers. `main`
+/// passes the real ones; each test passes fresh (or fake) ones, so tests
+/// share nothing and can run in parallel.
+#[derive(Clone)]
+struct AppDeps {
+    config: Arc<AppConfig>,
+    todos: TodoStore,
+    async_todos: AsyncTodoStore,
+    dash_todos: DashTodoStore,
+    actor_todos: StoreHandle,
+    redis_todos: RedisTodoStore,
+    metrics: Arc<RwLock<Metrics>>,
+    db: Arc<dyn Database>,
+    query_cache: QueryCache,
+    events: EventBus,
+    ids: SharedIds,
+    current_user: CurrentUser,
+}
 
-    // Initialize mutable todo store
-    let todo_store: TodoStore = Arc::new(RwLock::new(HashMap::new()));
+impl AppDeps {
+    /// What the server runs with: demo config, one seeded todo, the
+    /// simulated pool
+    fn demo() -> Self {
+        let todos: TodoStore = Arc::new(RwLock::new(HashMap::new()));
+        // Pre-populate with some todos
+        {
+            let mut store = todos.write().unwrap();
+            let todo = Todo {
+                // fixed id for testing
+                id: "bb7c1970-2b44-4d85-ac0b-a4f9f86ffd9b".to_string(), //Uuid::new_v4().to_string(),
+                title: "Learn Axum".to_string(),
+                completed: false,
+                created_at: now_unix(),
+                completed_at: None,
+            };
+            store.insert(todo.id.clone(), TodoEntry::new(todo));
+        }
 
-    // Pre-populate with some todos
-    {
-        let mut store = todo_store.write().unwrap();
-        let todo = Todo {
-            // fixed id for testing
-            id: "bb7c1970-2b44-4d85-ac0b-a4f9f86ffd9b".to_string(), //Uuid::new_v4().to_string(),
-            title: "Learn Axum".to_string(),
-            completed: false,
-        };
-        store.insert(todo.id.clone(), todo);
+        let ids: SharedIds = Arc::new(RandomIds);
+
+        Self {
+            config: Arc::new(AppConfig {
+                app_name: "Axum Todo API".to_string(),
+                version: "1.0.0".to_string(),
+                max_items_per_page: 100,
+            }),
+            todos,
+            async_todos: AsyncTodoStore::default(),
+            dash_todos: DashTodoStore::default(),
+            actor_todos: StoreHandle::spawn(ids.clone()),
+            redis_todos: RedisTodoStore::from_env(),
+            metrics: Arc::new(RwLock::new(Metrics::default())),
+            db: Arc::new(DbPool::new("postgres://localhost/myapp")),
+            query_cache: cache::query_cache_from_env(),
+            events: EventBus::default(),
+            ids,
+            // Current user (normally set by auth middleware)
+            current_user: CurrentUser {
+                id: "user-123".to_string(),
+                name: "Demo User".to_string(),
+            },
+        }
     }
 
-    // Combined state for complex apps
-    let combined_state = CombinedState {
-        config: config.clone(),
-        todos: todo_store.clone(),
-        metrics: Arc::new(RwLock::new(Metrics::default())),
-    };
+    /// The stores behind /admin/export + /admin/import, also persisted to disk
+    fn snapshot_registry(&self) -> SnapshotRegistry {
+        SnapshotRegistry::default()
+            .register("todos", self.todos.clone())
+            .register("metrics", Whole(self.metrics.clone()))
+    }
+
+    /// Every dependency, provided by type. Tests can `.provide(..)` a
+    /// replacement for any of them before building the app.
+    fn into_registry(self) -> Registry {
+        let snapshots = self.snapshot_registry();
+        Registry::new()
+            .provide(self.config)
+            .provide(self.todos)
+            .provide(self.async_todos)
+            .provide(self.dash_todos)
+            .provide(self.actor_todos)
+            .provide(self.redis_todos)
+            .provide(self.metrics)
+            .provide(self.db)
+            .provide(self.query_cache)
+            .provide(self.events)
+            .provide(self.ids)
+            .provide(self.current_user)
+            .provide(snapshots)
+            // Every in-memory store registers itself for /admin/stores;
+            // assembled from whichever stores were provided, on first use
+            .provide_lazy(|registry| {
+                StoreRegistry::default().register("todos", registry.require::<TodoStore>())
+            })
+    }
+}
 
-    // Simulated DB pool
-    let db_pool = DbPool::new("postgres://localhost/myapp");
+/// The whole app, built only from `deps` - no globals, no statics
+fn build_app(deps: AppDeps) -> Routes {
+    build_app_from(&deps.into_registry())
+}
 
-    // Current user (normally set by auth middleware)
-    let current_user = CurrentUser {
-        id: "user-123".to_string(),
-        name: "Demo User".to_string(),
-    };
+/// The same, from whatever `registry` provides. A missing provider panics
+/// here, while the app is assembled, not when a request needs it.
+fn build_app_from(registry: &Registry) -> Routes {
+    // One state for the whole router; handlers pick their parts via FromRef
+    let combined_state =
+        CombinedState::from_registry(registry).unwrap_or_else(|missing| panic!("{}", missing));
+    let todo_store: TodoStore = registry.require();
+    // Writes through these routes are announced on the bus
+    let events: EventBus = registry.require();
+    let ids: SharedIds = registry.require();
 
     // Build routes for todo CRUD
-    let todo_routes = Router::new()
-        .route("/", get(list_todos).post(create_todo))
-        .route("/{id}", get(get_todo).put(update_todo).delete(delete_todo))
-        .with_state(todo_store);
+    let todo_routes = Routes::new()
+        .get(
+            "/page",
+            pagination::list_todos_page,
+            "Cursor pagination + Link header (?limit=&after=)",
+        )
+        .merge(crud_routes(
+            Published::new(todo_store, events.clone(), "todos"),
+            ids.clone(),
+        ));
+
+    // The same CRUD behind tokio::sync::RwLock
+    let async_todo_routes = Routes::new()
+        .get("/", async_store::list_todos, "List all todos")
+        .post("/", async_store::create_todo, "Create todo")
+        .get("/{id}", async_store::get_todo, "Get single todo")
+        .put("/{id}", async_store::update_todo, "Update todo")
+        .delete("/{id}", async_store::delete_todo, "Delete todo")
+        .with_state(Crud {
+            store: registry.require::<AsyncTodoStore>(),
+            ids: ids.clone(),
+        });
+
+    // And once more, through the actor's handle
+    let actor_todo_routes = Routes::new()
+        .get("/", actor_store::list_todos, "List all todos")
+        .post("/", actor_store::create_todo, "Create todo")
+        .get("/{id}", actor_store::get_todo, "Get single todo")
+        .put("/{id}", actor_store::update_todo, "Update todo")
+        .delete("/{id}", actor_store::delete_todo, "Delete todo")
+        .with_state(registry.require::<StoreHandle>());
 
     // Build main app
-    let app = Router::new()
+    Routes::new()
         // Config endpoint
-        .route("/config", get(get_config))
-        .with_state(config)
-        // Merge todo routes
-        .merge(Router::new().nest("/todos", todo_routes))
+        .get("/config", get_config, "App configuration")
+        // Todo routes
+        .nest("/todos", todo_routes)
+        .nest("/todos-async", async_todo_routes)
+        .nest(
+            "/todos-dash",
+            crud_routes(
+                Published::new(
+                    registry.require::<DashTodoStore>(),
+                    events.clone(),
+                    "todos-dash",
+                ),
+                ids.clone(),
+            ),
+        )
+        .nest("/todos-actor", actor_todo_routes)
+        .nest(
+            "/todos-redis",
+            crud_routes(
+                Published::new(registry.require::<RedisTodoStore>(), events, "todos-redis"),
+                ids,
+            ),
+        )
+        .get(
+            "/admin/lock-bench",
+            async_store::lock_bench,
+            "std vs tokio RwLock under load (?tasks=&ops=&hold_ms=)",
+        )
+        .get(
+            "/admin/entry-lock-bench",
+            entry_lock::entry_lock_bench,
+            "Whole-map vs per-todo locks (?writers=&ops=&hold_ms=)",
+        )
+        // Live todo changes
+        .get(
+            "/events",
+            events::events,
+            "Live created/updated/deleted events (SSE)",
+        )
         // Metrics endpoints
-        .route("/metrics", get(get_metrics))
-        .route("/track", get(increment_request_count))
-        .with_state(combined_state)
+        .get(
+            "/metrics",
+            get_metrics,
+            "Request/error counts per route (middleware)",
+        )
+        .get(
+            "/track",
+            increment_request_count,
+            "Does nothing; counted like any request",
+        )
         // Database endpoint
-        .route("/db/users", get(db_query))
-        .with_state(db_pool)
+        .get("/db/users", db_query, "Query the database")
+        .get(
+            "/db/users/cached",
+            db_query_cached,
+            "Same query through a TTL cache (CACHE_TTL_SECS)",
+        )
+        // Query cache
+        .get(
+            "/cache/stats",
+            cache::cache_stats,
+            "Cache hits, misses, entries",
+        )
+        .delete("/cache", cache::clear_cache, "Clear the cache")
+        // Store statistics
+        .get(
+            "/admin/stores",
+            stats::store_stats,
+            "Store statistics & memory usage",
+        )
+        // Snapshot & restore
+        .get(
+            "/admin/export",
+            snapshot::export,
+            "Versioned JSON dump of all stores",
+        )
+        .post(
+            "/admin/import",
+            snapshot::import.layer(DefaultBodyLimit::max(snapshot::MAX_SNAPSHOT_BYTES)),
+            "Validate and restore a dump",
+        )
+        .with_state(combined_state)
         // Extension-based state
-        .route("/me", get(get_current_user))
-        .layer(Extension(current_user));
+        .get("/me", get_current_user, "Current user (Extension)")
+        .layer(Extension(registry.require::<CurrentUser>()))
+        // Outermost, so it sees every route's final status
+        .layer(middleware::from_fn_with_state(
+            registry.require::<Arc<RwLock<Metrics>>>(),
+            track_metrics,
+        ))
+}
+
+// ============================================================================
+// MAIN
+// ============================================================================
+
+#[tokio::main]
+async fn main() {
+    let deps = AppDeps::demo();
+
+    // Replace the demo data with whatever the last run left behind
+    let persistence = Arc::new(Persistence::new(
+        persist::state_file(),
+        deps.snapshot_registry(),
+    ));
+    let state_file = persistence.path().display().to_string();
+    match persistence.load() {
+        Ok(Some(report)) => println!(
+            "💾 Restored from {}: {}",
+            state_file,
+            serde_json::to_string(&report).unwrap()
+        ),
+        Ok(None) => println!("💾 No snapshot at {}, starting with demo data", state_file),
+        Err(e) => panic!(
+            "Cannot restore {}: {} (fix or remove the file)",
+            state_file, e
+        ),
+    }
+    let flusher = persistence
+        .clone()
+        .spawn_periodic_flush(persist::flush_interval());
+
+    // One token stops both the server and the background janitor
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let (shutdown, events) = (shutdown.clone(), deps.events.clone());
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+            // Open SSE streams would hold graceful shutdown up forever
+            events.close();
+        }
+    });
+    let janitor_config = JanitorConfig::from_env();
+    let janitor = maintenance::spawn_janitor(
+        deps.todos.clone(),
+        deps.metrics.clone(),
+        janitor_config,
+        shutdown.clone(),
+    );
+
+    let routes = build_app(deps);
+    let table = routes.to_string();
+    let app = routes.into_router();
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
@@ -296,20 +852,446 @@ async fn main() {
     println!("🚀 Module 05: State Management");
     println!("   Server running on http://localhost:3000");
     println!();
-    println!("📝 Todo CRUD Endpoints:");
-    println!("   GET    /todos      - List all todos");
-    println!("   POST   /todos      - Create todo");
-    println!("   GET    /todos/:id  - Get single todo");
-    println!("   PUT    /todos/:id  - Update todo");
-    println!("   DELETE /todos/:id  - Delete todo");
+    println!("📝 Endpoints:");
+    print!("{}", table);
     println!();
-    println!("📝 Other Endpoints:");
-    println!("   GET /config   - App configuration");
-    println!("   GET /metrics  - Request metrics");
-    println!("   GET /me       - Current user (Extension)");
+    println!(
+        "💾 State file: {} (flushed every {:?} and on Ctrl+C)",
+        state_file,
+        persist::flush_interval()
+    );
+    println!(
+        "🧹 Janitor: expires todos completed over {:?} ago, every {:?}",
+        janitor_config.retention, janitor_config.every
+    );
     println!();
     println!("💡 Try: curl -X POST -H 'Content-Type: application/json' \\");
     println!("        -d '{{\"title\":\"New Todo\"}}' http://localhost:3000/todos");
 
-    axum::serve(listener, app).await.expect("Server failed");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .expect("Server failed");
+
+    // No more requests or sweeps can change the stores; write them one last time
+    janitor.await.expect("Janitor panicked");
+    flusher.abort();
+    match persistence.flush() {
+        Ok(_) => println!("💾 State saved to {}", state_file),
+        Err(e) => eprintln!("⚠️  Failed to save state to {}: {}", state_file, e),
+    }
+}
+
+/// Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, response::Response, Router};
+    use course_routes::conformance::{self, Probes};
+    use http_body_util::BodyExt;
+    use ids::SequentialIds;
+    use tower::ServiceExt;
+
+    /// Fresh, empty dependencies - nothing is shared with any other test
+    fn test_deps() -> AppDeps {
+        let ids: SharedIds = Arc::new(SequentialIds::default());
+        AppDeps {
+            config: Arc::new(AppConfig {
+                app_name: "test".to_string(),
+                version: "0.0.0-test".to_string(),
+                max_items_per_page: 10,
+            }),
+            todos: Arc::new(RwLock::new(HashMap::new())),
+            async_todos: AsyncTodoStore::default(),
+            dash_todos: DashTodoStore::default(),
+            actor_todos: StoreHandle::spawn(ids.clone()),
+            // Nothing listens on port 1: every call fails to connect
+            redis_todos: RedisTodoStore::new("redis://127.0.0.1:1"),
+            metrics: Arc::new(RwLock::new(Metrics::default())),
+            db: Arc::new(FakeDb(vec!["alice", "bob", "carol"])),
+            query_cache: Arc::new(cache::TtlCache::new(
+                std::time::Duration::from_secs(60),
+                100,
+            )),
+            events: EventBus::default(),
+            ids,
+            current_user: CurrentUser {
+                id: "test-user".to_string(),
+                name: "Test User".to_string(),
+            },
+        }
+    }
+
+    struct FakeDb(Vec<&'static str>);
+
+    impl Database for FakeDb {
+        fn query<'a>(&'a self, _sql: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+            let rows = self.0.iter().map(|row| row.to_string()).collect();
+            Box::pin(async move { Ok(rows) })
+        }
+    }
+
+    async fn send(app: &Router, method: &str, uri: &str, body: Option<&str>) -> Response {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body.map(|b| Body::from(b.to_string())).unwrap_or_default())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn json(response: Response) -> serde_json::Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_each_app_has_its_own_store() {
+        let first = build_app(test_deps()).into_router();
+        let second = build_app(test_deps()).into_router();
+
+        let response = send(&first, "POST", "/todos", Some(r#"{"title":"only here"}"#)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let listed = json(send(&first, "GET", "/todos", None).await).await;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        let listed = json(send(&second, "GET", "/todos", None).await).await;
+        assert_eq!(listed, serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_new_todos_get_ids_from_the_injected_provider() {
+        let app = build_app(test_deps()).into_router();
+
+        let created = json(send(&app, "POST", "/todos", Some(r#"{"title":"a"}"#)).await).await;
+        let async_created = send(&app, "POST", "/todos-async", Some(r#"{"title":"b"}"#)).await;
+        let actor_created = send(&app, "POST", "/todos-actor", Some(r#"{"title":"c"}"#)).await;
+        let batch = r#"[{"title":"d"},{"title":"e"}]"#;
+        let bulk = json(send(&app, "POST", "/todos-dash/bulk", Some(batch)).await).await;
+
+        // One provider for every store, so the sequence runs across them
+        assert_eq!(created["id"], "00000000-0000-0000-0000-000000000001");
+        assert_eq!(
+            json(async_created).await["id"],
+            "00000000-0000-0000-0000-000000000002"
+        );
+        assert_eq!(
+            json(actor_created).await["id"],
+            "00000000-0000-0000-0000-000000000003"
+        );
+        assert_eq!(
+            bulk["results"][1]["id"],
+            "00000000-0000-0000-0000-000000000005"
+        );
+        // The first app's ids don't move the next app's sequence
+        let other = build_app(test_deps()).into_router();
+        let created = json(send(&other, "POST", "/todos", Some(r#"{"title":"a"}"#)).await).await;
+        assert_eq!(created["id"], "00000000-0000-0000-0000-000000000001");
+    }
+
+    #[tokio::test]
+    async fn test_database_is_injected() {
+        let app = build_app(test_deps()).into_router();
+
+        let rows = json(send(&app, "GET", "/db/users", None).await).await;
+
+        assert_eq!(rows, serde_json::json!(["alice", "bob", "carol"]));
+    }
+
+    #[tokio::test]
+    async fn test_registry_override_replaces_a_dependency() {
+        let registry = test_deps()
+            .into_registry()
+            .provide::<Arc<dyn Database>>(Arc::new(FakeDb(vec!["override"])));
+        let app = build_app_from(&registry).into_router();
+
+        let rows = json(send(&app, "GET", "/db/users", None).await).await;
+
+        assert_eq!(rows, serde_json::json!(["override"]));
+    }
+
+    #[tokio::test]
+    async fn test_cached_query_hits_until_cleared() {
+        let app = build_app(test_deps()).into_router();
+
+        let first = json(send(&app, "GET", "/db/users/cached", None).await).await;
+        let second = json(send(&app, "GET", "/db/users/cached", None).await).await;
+        send(&app, "DELETE", "/cache", None).await;
+        let third = json(send(&app, "GET", "/db/users/cached", None).await).await;
+        let stats = json(send(&app, "GET", "/cache/stats", None).await).await;
+
+        assert_eq!(first["rows"], serde_json::json!(["alice", "bob", "carol"]));
+        assert_eq!(first["cached"], false);
+        assert_eq!(second["cached"], true);
+        assert_eq!(third["cached"], false);
+        assert_eq!(stats["hits"], 1);
+        assert_eq!(stats["misses"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis_is_a_503() {
+        let app = build_app(test_deps()).into_router();
+
+        let listed = send(&app, "GET", "/todos-redis", None).await;
+        let created = send(&app, "POST", "/todos-redis", Some(r#"{"title":"lost"}"#)).await;
+
+        assert_eq!(listed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(created.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_create_reports_each_todo() {
+        let app = build_app(test_deps()).into_router();
+        let batch = r#"[{"title":"one"},{"title":"  "},{"title":"two"}]"#;
+
+        let response = send(&app, "POST", "/todos/bulk", Some(batch)).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let body = json(response).await;
+        let listed = json(send(&app, "GET", "/todos", None).await).await;
+        let unavailable = send(&app, "POST", "/todos-redis/bulk", Some(batch)).await;
+
+        assert_eq!(
+            body["summary"],
+            serde_json::json!({ "total": 3, "succeeded": 2, "failed": 1 })
+        );
+        assert_eq!(body["results"][0]["status"], 201);
+        assert_eq!(body["results"][0]["data"]["title"], "one");
+        assert_eq!(body["results"][1]["error_code"], "INVALID_TITLE");
+        assert_eq!(body["results"][2]["index"], 2);
+        assert_eq!(listed.as_array().unwrap().len(), 2);
+        // Every item failed: the store is down, so each is retryable
+        assert_eq!(unavailable.status(), StatusCode::BAD_REQUEST);
+        let body = json(unavailable).await;
+        assert_eq!(body["results"][0]["status"], 503);
+        assert_eq!(body["results"][0]["retryable"], true);
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_streamed_to_event_subscribers() {
+        let app = build_app(test_deps()).into_router();
+        let stream = send(&app, "GET", "/events", None).await;
+        assert_eq!(stream.headers()["content-type"], "text/event-stream");
+        let mut stream = stream.into_body();
+
+        let created =
+            json(send(&app, "POST", "/todos-dash", Some(r#"{"title":"live"}"#)).await).await;
+        let id = created["id"].as_str().unwrap();
+        let uri = format!("/todos-dash/{}", id);
+        send(&app, "PUT", &uri, Some(r#"{"completed":true}"#)).await;
+        send(&app, "DELETE", &uri, None).await;
+        // Failed writes publish nothing
+        send(&app, "DELETE", &uri, None).await;
+
+        let mut received = String::new();
+        while received.matches("event: ").count() < 3 {
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(1), stream.frame())
+                .await
+                .expect("event arrives")
+                .unwrap()
+                .unwrap();
+            received.push_str(std::str::from_utf8(frame.data_ref().unwrap()).unwrap());
+        }
+        let events: Vec<serde_json::Value> = received
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+
+        assert!(received.starts_with("event: created\n"));
+        assert_eq!(events[0]["type"], "created");
+        assert_eq!(events[0]["store"], "todos-dash");
+        assert_eq!(events[0]["todo"]["title"], "live");
+        assert_eq!(events[1]["type"], "updated");
+        assert_eq!(events[1]["todo"]["completed"], true);
+        assert_eq!(
+            events[2],
+            serde_json::json!({ "type": "deleted", "store": "todos-dash", "id": id })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics_start_at_zero() {
+        let app = build_app(test_deps()).into_router();
+        send(&app, "GET", "/track", None).await;
+        send(&app, "GET", "/track", None).await;
+
+        let metrics = json(send(&app, "GET", "/metrics", None).await).await;
+
+        assert_eq!(metrics["requests"], 2);
+        assert_eq!(metrics["app_version"], "0.0.0-test");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_layer_counts_every_route_and_error() {
+        let app = build_app(test_deps()).into_router();
+        send(&app, "GET", "/todos", None).await;
+        send(&app, "GET", "/todos/nope", None).await;
+        send(&app, "GET", "/todos/still-nope", None).await;
+        send(&app, "GET", "/no-such-route", None).await;
+
+        let metrics = json(send(&app, "GET", "/metrics", None).await).await;
+
+        assert_eq!(metrics["requests"], 4);
+        assert_eq!(metrics["errors"], 3);
+        assert_eq!(
+            metrics["routes"]["/todos/{id}"],
+            serde_json::json!({"requests": 2, "errors": 2})
+        );
+        assert_eq!(metrics["routes"]["/todos"]["errors"], 0);
+        assert_eq!(metrics["routes"]["(unmatched)"]["requests"], 1);
+    }
+
+    /// Eight threads create, update and delete through `TodoRepo` at once,
+    /// with readers in between: no update may be lost, no deleted todo may
+    /// survive, whichever map is behind the trait. The in-memory stores'
+    /// futures are always ready, so `block_on` never actually waits.
+    fn stress<R: TodoRepo>(store: R) {
+        use futures::executor::block_on;
+
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 250;
+
+        std::thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let store = store.clone();
+                scope.spawn(move || {
+                    for i in 0..PER_THREAD {
+                        let id = format!("{}-{}", thread, i);
+                        block_on(store.insert(Todo {
+                            id: id.clone(),
+                            title: format!("todo {}", id),
+                            completed: false,
+                            created_at: 0,
+                            completed_at: None,
+                        }))
+                        .unwrap();
+                        let done = UpdateTodo {
+                            title: None,
+                            completed: Some(true),
+                        };
+                        assert!(block_on(store.update(&id, done)).unwrap().is_some());
+                        if i % 2 == 1 {
+                            assert!(block_on(store.remove(&id)).unwrap());
+                        }
+                        if i % 25 == 0 {
+                            block_on(store.list()).unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        let todos = block_on(store.list()).unwrap();
+        assert_eq!(todos.len(), THREADS * PER_THREAD / 2);
+        assert!(todos.iter().all(|todo| todo.completed));
+        assert!(block_on(store.get("7-0")).unwrap().is_some());
+        assert!(block_on(store.get("7-1")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rwlock_store_under_concurrent_load() {
+        stress(TodoStore::default());
+    }
+
+    #[test]
+    fn test_dashmap_store_under_concurrent_load() {
+        stress(DashTodoStore::default());
+    }
+
+    #[tokio::test]
+    async fn test_dashmap_store_serves_the_same_api() {
+        let app = build_app(test_deps()).into_router();
+
+        let created =
+            json(send(&app, "POST", "/todos-dash", Some(r#"{"title":"sharded"}"#)).await).await;
+        let uri = format!("/todos-dash/{}", created["id"].as_str().unwrap());
+        let updated = json(send(&app, "PUT", &uri, Some(r#"{"completed":true}"#)).await).await;
+        assert_eq!(updated["title"], "sharded");
+        assert_eq!(updated["completed"], true);
+
+        assert_eq!(
+            send(&app, "DELETE", &uri, None).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&app, "GET", &uri, None).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        // The RwLock store is a different map
+        let listed = json(send(&app, "GET", "/todos", None).await).await;
+        assert_eq!(listed, serde_json::json!([]));
+    }
+
+    /// Eight apps hammered at once on a multi-threaded runtime: each must
+    /// see exactly its own writes, however the tasks interleave
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_parallel_apps_are_isolated() {
+        let tasks: Vec<_> = (1..=8)
+            .map(|n| {
+                tokio::spawn(async move {
+                    let app = build_app(test_deps()).into_router();
+                    for i in 0..n {
+                        let body = format!(r#"{{"title":"app {} todo {}"}}"#, n, i);
+                        send(&app, "POST", "/todos", Some(&body)).await;
+                        send(&app, "GET", "/track", None).await;
+                    }
+                    let todos = json(send(&app, "GET", "/todos", None).await).await;
+                    let metrics = json(send(&app, "GET", "/metrics", None).await).await;
+                    (
+                        n,
+                        todos.as_array().unwrap().len(),
+                        metrics["requests"].clone(),
+                    )
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            let (n, todos, requests) = task.await.unwrap();
+            assert_eq!(todos, n);
+            // Every POST and /track, plus the final GET /todos
+            assert_eq!(requests, 2 * n + 1);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "unknown routes and rejected bodies keep Axum's plain-text answers"]
+    async fn test_error_conformance() {
+        let probes = Probes {
+            get_route: "/config",
+            json_route: Some("/todos"),
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        conformance::assert_conforms(build_app(test_deps()).into_router(), &probes).await;
+    }
 }
@@ -0,0 +1,149 @@
+//! # Background Tasks Sharing App State
+//!
+//! Handlers aren't the only code that touches state. The janitor is a
+//! `tokio::spawn`-ed loop that holds clones of the same `Arc`s the router
+//! holds, and every `every` it:
+//! - removes completed todos whose `completed_at` is older than `retention`
+//! - adds the number it removed to `Metrics::expired_todos`
+//!
+//! Its lifetime is tied to the server's with a `CancellationToken`:
+//! - `main` creates one token; Ctrl+C / SIGTERM cancels it
+//! - `axum::serve(..).with_graceful_shutdown(token.cancelled_owned())` stops
+//!   accepting connections and finishes in-flight requests
+//! - The janitor `select!`s on the same token and returns between sweeps,
+//!   never halfway through one, and `main` awaits its `JoinHandle`
+//!
+//! Aborting the task would also stop it, but at whatever `.await` it
+//! happened to be parked on. Cancellation lets it stop where it chooses.
+
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{now_unix, Metrics, TodoStore};
+
+#[derive(Debug, Clone, Copy)]
+pub struct JanitorConfig {
+    /// How long a completed todo is kept
+    pub retention: Duration,
+    /// Time between sweeps
+    pub every: Duration,
+}
+
+impl JanitorConfig {
+    /// `TODO_RETENTION_SECS` (default 600) and `JANITOR_EVERY_SECS` (default 30)
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            let secs = std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default);
+            Duration::from_secs(secs)
+        };
+        Self {
+            retention: secs("TODO_RETENTION_SECS", 600),
+            every: secs("JANITOR_EVERY_SECS", 30).max(Duration::from_secs(1)),
+        }
+    }
+}
+
+/// Remove completed todos finished at or before `now - retention`.
+/// Returns how many were removed.
+pub fn expire_completed(todos: &TodoStore, retention: Duration, now: u64) -> usize {
+    let cutoff = now.saturating_sub(retention.as_secs());
+    let mut todos = todos.write().unwrap();
+    let before = todos.len();
+    // Each todo's own lock is taken under the map's: the order updates use too
+    todos.retain(|_, entry| entry.get().completed_at.is_none_or(|at| at > cutoff));
+    before - todos.len()
+}
+
+/// Start the janitor. It runs until `shutdown` is cancelled.
+pub fn spawn_janitor(
+    todos: TodoStore,
+    metrics: Arc<RwLock<Metrics>>,
+    config: JanitorConfig,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(config.every);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticks.tick() => {}
+            }
+            // Two std locks, taken one after the other, neither held
+            // across an `.await`
+            let expired = expire_completed(&todos, config.retention, now_unix());
+            if expired > 0 {
+                metrics.write().unwrap().expired_todos += expired as u64;
+                println!("🧹 Janitor expired {} completed todo(s)", expired);
+            }
+        }
+        println!("🧹 Janitor stopped");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entry_lock::TodoEntry, Todo};
+
+    fn todo(id: &str, completed_at: Option<u64>) -> (String, TodoEntry) {
+        let todo = Todo {
+            id: id.to_string(),
+            title: id.to_string(),
+            completed: completed_at.is_some(),
+            created_at: 0,
+            completed_at,
+        };
+        (id.to_string(), TodoEntry::new(todo))
+    }
+
+    #[test]
+    fn test_only_old_completed_todos_expire() {
+        let store: TodoStore = Arc::new(RwLock::new(
+            [
+                todo("open", None),
+                todo("done-long-ago", Some(1_000)),
+                todo("done-just-now", Some(1_590)),
+            ]
+            .into_iter()
+            .collect(),
+        ));
+
+        let expired = expire_completed(&store, Duration::from_secs(60), 1_600);
+
+        assert_eq!(expired, 1);
+        let todos = store.read().unwrap();
+        assert!(todos.contains_key("open"));
+        assert!(todos.contains_key("done-just-now"));
+    }
+
+    #[tokio::test]
+    async fn test_janitor_sweeps_until_cancelled() {
+        let store: TodoStore = Arc::new(RwLock::new([todo("done", Some(1))].into_iter().collect()));
+        let metrics = Arc::new(RwLock::new(Metrics::default()));
+        let shutdown = CancellationToken::new();
+        let config = JanitorConfig {
+            retention: Duration::ZERO,
+            every: Duration::from_millis(10),
+        };
+
+        let janitor = spawn_janitor(store.clone(), metrics.clone(), config, shutdown.clone());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(1), janitor)
+            .await
+            .expect("janitor should stop once cancelled")
+            .unwrap();
+
+        assert!(store.read().unwrap().is_empty());
+        assert_eq!(metrics.read().unwrap().expired_todos, 1);
+    }
+}
@@ -0,0 +1,166 @@
+//! # Cursor Pagination with `Link` Headers
+//!
+//! `?page=3&per_page=10` skips and duplicates items when rows are inserted
+//! or deleted between requests, and gets slower the deeper you page. A
+//! cursor says "continue after *this* item" instead:
+//! - Items are ordered by a unique key, here `(created_at, id)`
+//! - The cursor is that key for the last (or first) item on a page,
+//!   base64url-encoded so clients treat it as opaque
+//! - The next/previous page URLs go in an RFC 8288 `Link` header as well as
+//!   the JSON body, so generic HTTP clients can follow them
+//!
+//! ```text
+//! Link: </todos/page?limit=2&after=MTcz...>; rel="next",
+//!       </todos/page?limit=2&before=MTcz...>; rel="prev"
+//! ```
+
+use axum::{
+    extract::{OriginalUri, Query, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{entry_lock::TodoEntry, Todo, TodoStore};
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 100;
+
+// ============================================================================
+// CURSOR ENCODING
+// ============================================================================
+
+/// The sort key of the last item seen. `id` breaks ties between todos
+/// created in the same second, so the order is total and stable.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    created_at: u64,
+    id: String,
+}
+
+impl Cursor {
+    fn of(todo: &Todo) -> Self {
+        Self {
+            created_at: todo.created_at,
+            id: todo.id.clone(),
+        }
+    }
+
+    /// `"<created_at>:<id>"`, base64url without padding (safe in a query string)
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.created_at, self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let text = String::from_utf8(bytes).ok()?;
+        let (created_at, id) = text.split_once(':')?;
+        Some(Self {
+            created_at: created_at.parse().ok()?,
+            id: id.to_string(),
+        })
+    }
+}
+
+// ============================================================================
+// ENDPOINT
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    limit: Option<usize>,
+    /// Items after this cursor (next page)
+    after: Option<String>,
+    /// Items before this cursor (previous page)
+    before: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Page {
+    items: Vec<Todo>,
+    next_cursor: Option<String>,
+    prev_cursor: Option<String>,
+}
+
+fn bad_request(message: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": message })),
+    )
+        .into_response()
+}
+
+/// GET /todos/page?limit=&after= | &before=
+pub async fn list_todos_page(
+    State(store): State<TodoStore>,
+    OriginalUri(uri): OriginalUri,
+    Query(query): Query<PageQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let decode = |cursor: &Option<String>| match cursor {
+        Some(cursor) => Cursor::decode(cursor).map(Some).ok_or(()),
+        None => Ok(None),
+    };
+    let (Ok(after), Ok(before)) = (decode(&query.after), decode(&query.before)) else {
+        return bad_request("Invalid cursor");
+    };
+    if after.is_some() && before.is_some() {
+        return bad_request("Use either `after` or `before`, not both");
+    }
+
+    // The store is a HashMap, so every request sorts; a BTreeMap keyed by
+    // `Cursor` (or a database index) would make this a range scan
+    let mut todos: Vec<Todo> = store.read().unwrap().values().map(TodoEntry::get).collect();
+    todos.sort_by_key(Cursor::of);
+
+    // Window of `limit` items right after `after`, or right before `before`
+    let (start, end) = match (&after, &before) {
+        (_, Some(before)) => {
+            let end = todos.partition_point(|t| Cursor::of(t) < *before);
+            (end.saturating_sub(limit), end)
+        }
+        (Some(after), None) => {
+            let start = todos.partition_point(|t| Cursor::of(t) <= *after);
+            (start, (start + limit).min(todos.len()))
+        }
+        (None, None) => (0, limit.min(todos.len())),
+    };
+    let has_prev = start > 0;
+    let has_next = end < todos.len();
+    let items = todos[start..end].to_vec();
+
+    let next_cursor = has_next
+        .then(|| items.last().map(|t| Cursor::of(t).encode()))
+        .flatten();
+    let prev_cursor = has_prev
+        .then(|| items.first().map(|t| Cursor::of(t).encode()))
+        .flatten();
+
+    let path = uri.path();
+    let mut links = vec![format!("<{}?limit={}>; rel=\"first\"", path, limit)];
+    if let Some(cursor) = &next_cursor {
+        links.push(format!(
+            "<{}?limit={}&after={}>; rel=\"next\"",
+            path, limit, cursor
+        ));
+    }
+    if let Some(cursor) = &prev_cursor {
+        links.push(format!(
+            "<{}?limit={}&before={}>; rel=\"prev\"",
+            path, limit, cursor
+        ));
+    }
+
+    let mut response = Json(Page {
+        items,
+        next_cursor,
+        prev_cursor,
+    })
+    .into_response();
+    if let Ok(value) = HeaderValue::from_str(&links.join(", ")) {
+        response.headers_mut().insert(header::LINK, value);
+    }
+    response
+}
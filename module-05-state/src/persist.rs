@@ -0,0 +1,233 @@
+//! # Persisting State Across Restarts
+//!
+//! `snapshot.rs` moves state between runs by hand. `Persistence` does it
+//! automatically, using the same registry and the same file format:
+//! - Startup: `load` restores every store from the snapshot file (all or
+//!   nothing, exactly like `POST /admin/import`)
+//! - While running: `spawn_periodic_flush` writes the file in the background
+//!   whenever something changed, so a crash loses at most one interval
+//! - Graceful shutdown: `main` stops accepting requests, lets in-flight ones
+//!   finish, then calls `flush` one last time
+//!
+//! Writes go to a temporary file that is then renamed over the old one, so a
+//! crash mid-write leaves the previous snapshot intact rather than half a
+//! JSON document.
+
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    now_unix,
+    snapshot::{ImportReport, SnapshotFile, SnapshotRegistry, SNAPSHOT_VERSION},
+};
+
+/// `STATE_FILE`, default `todos.snapshot.json` in the working directory
+pub fn state_file() -> PathBuf {
+    std::env::var("STATE_FILE")
+        .unwrap_or_else(|_| "todos.snapshot.json".into())
+        .into()
+}
+
+/// `STATE_FLUSH_SECS`, default 30
+pub fn flush_interval() -> Duration {
+    parse_flush_secs(std::env::var("STATE_FLUSH_SECS").ok().as_deref())
+}
+
+/// 0 is refused like garbage: `tokio::time::interval` panics on a zero period
+fn parse_flush_secs(value: Option<&str>) -> Duration {
+    let secs = value
+        .and_then(|v| v.parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(30);
+    Duration::from_secs(secs)
+}
+
+pub struct Persistence {
+    path: PathBuf,
+    registry: SnapshotRegistry,
+    /// Stores as last written (or loaded), to skip flushes that change nothing
+    last_written: Mutex<Option<BTreeMap<&'static str, Value>>>,
+}
+
+impl Persistence {
+    pub fn new(path: impl Into<PathBuf>, registry: SnapshotRegistry) -> Self {
+        Self {
+            path: path.into(),
+            registry,
+            last_written: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Restore from the snapshot file. `Ok(None)` means there is no file
+    /// yet (first run) and the stores keep their initial contents.
+    pub fn load(&self) -> Result<Option<ImportReport>, String> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        let file: SnapshotFile = serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+        let report = self.registry.restore(file).map_err(|e| e.to_string())?;
+
+        *self.last_written.lock().unwrap() = self.registry.export_all().ok();
+        Ok(Some(report))
+    }
+
+    /// Write every store if anything changed since the last flush. Returns
+    /// whether the file was written.
+    pub fn flush(&self) -> io::Result<bool> {
+        let stores = self.registry.export_all()?;
+        let mut last_written = self.last_written.lock().unwrap();
+        if last_written.as_ref() == Some(&stores) {
+            return Ok(false);
+        }
+
+        let document = serde_json::json!({
+            "version": SNAPSHOT_VERSION,
+            "exported_at": now_unix(),
+            "stores": &stores,
+        });
+        let tmp = self.path.with_extension("json.tmp");
+        {
+            let mut file = fs::File::create(&tmp)?;
+            serde_json::to_writer(&mut file, &document)?;
+            file.flush()?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+
+        *last_written = Some(stores);
+        Ok(true)
+    }
+
+    /// Flush every `every` until the returned handle is aborted
+    pub fn spawn_periodic_flush(self: Arc<Self>, every: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(every);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick fires immediately; nothing has changed yet
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                // File I/O blocks, so keep it off the async worker threads
+                let persistence = self.clone();
+                match tokio::task::spawn_blocking(move || persistence.flush()).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => eprintln!("⚠️  Periodic state flush failed: {}", e),
+                    Err(e) => eprintln!("⚠️  Periodic state flush panicked: {}", e),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entry_lock::TodoEntry, Todo, TodoStore};
+    use std::collections::HashMap;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("module-05-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    fn store_with(count: usize) -> TodoStore {
+        let todos = (0..count)
+            .map(|i| {
+                let todo = Todo {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    title: format!("todo {}", i),
+                    completed: false,
+                    created_at: 1,
+                    completed_at: None,
+                };
+                (todo.id.clone(), TodoEntry::new(todo))
+            })
+            .collect::<HashMap<_, _>>();
+        Arc::new(std::sync::RwLock::new(todos))
+    }
+
+    #[test]
+    fn test_flush_interval_never_zero() {
+        assert_eq!(parse_flush_secs(Some("5")), Duration::from_secs(5));
+        assert_eq!(parse_flush_secs(Some("0")), Duration::from_secs(30));
+        assert_eq!(parse_flush_secs(Some("soon")), Duration::from_secs(30));
+        assert_eq!(parse_flush_secs(None), Duration::from_secs(30));
+    }
+
+    fn ids(store: &TodoStore) -> Vec<String> {
+        let mut ids: Vec<_> = store.read().unwrap().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_flush_then_load_restores_stores() {
+        let path = temp_path();
+        let before = store_with(2);
+        let writer = Persistence::new(
+            &path,
+            SnapshotRegistry::default().register("todos", before.clone()),
+        );
+        assert!(writer.flush().unwrap());
+        // Nothing changed since, so nothing is written
+        assert!(!writer.flush().unwrap());
+
+        let after = store_with(1);
+        let reader = Persistence::new(
+            &path,
+            SnapshotRegistry::default().register("todos", after.clone()),
+        );
+        assert!(reader.load().unwrap().is_some());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(ids(&after), ids(&before));
+    }
+
+    #[test]
+    fn test_missing_file_is_a_fresh_start() {
+        let store = store_with(1);
+        let seeded = ids(&store);
+        let persistence = Persistence::new(
+            temp_path(),
+            SnapshotRegistry::default().register("todos", store.clone()),
+        );
+
+        assert!(persistence.load().unwrap().is_none());
+        assert_eq!(ids(&store), seeded);
+    }
+
+    #[test]
+    fn test_corrupt_file_leaves_stores_untouched() {
+        let path = temp_path();
+        fs::write(
+            &path,
+            r#"{"version":1,"stores":{"todos":{"x":{"id":"x"}}}}"#,
+        )
+        .unwrap();
+        let store = store_with(1);
+        let seeded = ids(&store);
+        let persistence = Persistence::new(
+            &path,
+            SnapshotRegistry::default().register("todos", store.clone()),
+        );
+
+        let result = persistence.load();
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+        assert_eq!(ids(&store), seeded);
+    }
+}
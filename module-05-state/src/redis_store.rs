@@ -0,0 +1,216 @@
+//! # A Todo Store in Redis
+//!
+//! `TodoStore` and `DashTodoStore` live inside one process: start a second
+//! instance behind a load balancer and each has its own todos. Moving the
+//! data into Redis makes every instance see the same todos, and the state
+//! each instance holds shrinks to a connection pool.
+//!
+//! - **The pool lives in state.** A `deadpool` pool of Redis connections is
+//!   an `Arc` inside, so `RedisTodoStore` is cheap to clone into every
+//!   request, and connections are opened lazily and reused
+//! - **One key per todo**, `module05:todo:{id}`, holding its JSON, plus the
+//!   set `module05:todo-ids` to list them. Creates and deletes change both
+//!   in one `MULTI`
+//! - **Updates are read-modify-write**, so they run under `WATCH` on that
+//!   todo's key alone: if another instance writes it between our read and
+//!   our `EXEC`, the transaction is dropped and we try again. Writes to
+//!   other todos don't get in the way
+//! - **Failures are 503s.** Redis down, a pool timeout or a broken
+//!   connection become `StoreError::Unavailable`; a value that isn't a todo
+//!   is `StoreError::Corrupt`, a 500
+//!
+//! Each pooled connection is its own TCP connection, because `WATCH` belongs
+//! to the connection: a connection shared between requests would share their
+//! watches too. Every way out of an update `UNWATCH`es, and so does the pool
+//! before it hands a connection out again, in case a request was dropped
+//! halfway.
+//!
+//! Point it at a server with `REDIS_URL` (default `redis://127.0.0.1:6379`).
+
+use deadpool::{
+    managed::{self, Metrics, Object, RecycleResult},
+    Runtime,
+};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client, RedisError};
+use std::time::Duration;
+
+use crate::{StoreError, Todo, TodoRepo, UpdateTodo};
+
+/// Every todo's id, so they can be listed without `SCAN`
+const IDS_KEY: &str = "module05:todo-ids";
+/// How long a request may wait for a connection, or for Redis to answer a
+/// connect, before it gets a 503 instead of hanging
+const POOL_TIMEOUT: Duration = Duration::from_secs(2);
+const POOL_SIZE: usize = 16;
+/// `WATCH` retries before an update gives up
+const UPDATE_ATTEMPTS: usize = 5;
+
+fn todo_key(id: &str) -> String {
+    format!("module05:todo:{}", id)
+}
+
+/// Opens the pool's connections, and clears a connection's `WATCH`es before
+/// it is reused
+struct RedisManager {
+    client: Client,
+}
+
+impl managed::Manager for RedisManager {
+    type Type = MultiplexedConnection;
+    type Error = RedisError;
+
+    async fn create(&self) -> Result<MultiplexedConnection, RedisError> {
+        self.client.get_multiplexed_async_connection().await
+    }
+
+    async fn recycle(
+        &self,
+        conn: &mut MultiplexedConnection,
+        _: &Metrics,
+    ) -> RecycleResult<RedisError> {
+        redis::cmd("UNWATCH").query_async::<()>(conn).await?;
+        Ok(())
+    }
+}
+
+type Pool = managed::Pool<RedisManager>;
+type Connection = Object<RedisManager>;
+
+#[derive(Clone)]
+pub struct RedisTodoStore {
+    pool: Pool,
+}
+
+impl RedisTodoStore {
+    /// Set up the pool. Nothing connects yet, so this works with Redis down.
+    pub fn new(url: &str) -> Self {
+        let client = Client::open(url).expect("Invalid Redis URL");
+        let pool = Pool::builder(RedisManager { client })
+            .max_size(POOL_SIZE)
+            .wait_timeout(Some(POOL_TIMEOUT))
+            .create_timeout(Some(POOL_TIMEOUT))
+            .recycle_timeout(Some(POOL_TIMEOUT))
+            .runtime(Runtime::Tokio1)
+            .build()
+            .expect("Invalid Redis pool settings");
+        Self { pool }
+    }
+
+    pub fn from_env() -> Self {
+        let url =
+            std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+        Self::new(&url)
+    }
+
+    async fn connection(&self) -> Result<Connection, StoreError> {
+        self.pool.get().await.map_err(unavailable)
+    }
+}
+
+fn unavailable(error: impl std::fmt::Display) -> StoreError {
+    StoreError::Unavailable(format!("redis: {}", error))
+}
+
+fn encode(todo: &Todo) -> Result<String, StoreError> {
+    serde_json::to_string(todo).map_err(|e| StoreError::Corrupt(e.to_string()))
+}
+
+fn decode(json: &str) -> Result<Todo, StoreError> {
+    serde_json::from_str(json).map_err(|e| StoreError::Corrupt(format!("{}: {}", e, json)))
+}
+
+/// Don't hand the connection back to the pool still watching
+async fn unwatch(conn: &mut Connection) -> Result<(), StoreError> {
+    redis::cmd("UNWATCH")
+        .query_async::<()>(&mut **conn)
+        .await
+        .map_err(unavailable)
+}
+
+impl TodoRepo for RedisTodoStore {
+    async fn list(&self) -> Result<Vec<Todo>, StoreError> {
+        let mut conn = self.connection().await?;
+        let ids: Vec<String> = conn.smembers(IDS_KEY).await.map_err(unavailable)?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = ids.iter().map(|id| todo_key(id)).collect();
+        let values: Vec<Option<String>> = conn.mget(keys).await.map_err(unavailable)?;
+        // A todo deleted between the two commands is simply not listed
+        values.iter().flatten().map(|json| decode(json)).collect()
+    }
+
+    async fn insert(&self, todo: Todo) -> Result<(), StoreError> {
+        let json = encode(&todo)?;
+        let mut conn = self.connection().await?;
+        redis::pipe()
+            .atomic()
+            .set(todo_key(&todo.id), json)
+            .sadd(IDS_KEY, &todo.id)
+            .query_async::<()>(&mut *conn)
+            .await
+            .map_err(unavailable)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Todo>, StoreError> {
+        let mut conn = self.connection().await?;
+        let json: Option<String> = conn.get(todo_key(id)).await.map_err(unavailable)?;
+        json.as_deref().map(decode).transpose()
+    }
+
+    async fn update(&self, id: &str, input: UpdateTodo) -> Result<Option<Todo>, StoreError> {
+        let key = todo_key(id);
+        let mut conn = self.connection().await?;
+        for _ in 0..UPDATE_ATTEMPTS {
+            redis::cmd("WATCH")
+                .arg(&key)
+                .query_async::<()>(&mut *conn)
+                .await
+                .map_err(unavailable)?;
+            let json: Option<String> = conn.get(&key).await.map_err(unavailable)?;
+            let Some(json) = json else {
+                unwatch(&mut conn).await?;
+                return Ok(None);
+            };
+            let updated = decode(&json).and_then(|mut todo| {
+                input.clone().apply(&mut todo);
+                encode(&todo).map(|json| (todo, json))
+            });
+            let (todo, json) = match updated {
+                Ok(updated) => updated,
+                Err(error) => {
+                    unwatch(&mut conn).await?;
+                    return Err(error);
+                }
+            };
+
+            // `EXEC` answers nil instead of the `SET` reply when the todo
+            // changed since `WATCH`
+            let committed: Option<()> = redis::pipe()
+                .atomic()
+                .set(&key, json)
+                .query_async(&mut *conn)
+                .await
+                .map_err(unavailable)?;
+            if committed.is_some() {
+                return Ok(Some(todo));
+            }
+        }
+        Err(StoreError::Unavailable(format!(
+            "update of {} lost {} races in a row",
+            id, UPDATE_ATTEMPTS
+        )))
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, StoreError> {
+        let mut conn = self.connection().await?;
+        let (removed, _): (usize, usize) = redis::pipe()
+            .atomic()
+            .del(todo_key(id))
+            .srem(IDS_KEY, id)
+            .query_async(&mut *conn)
+            .await
+            .map_err(unavailable)?;
+        Ok(removed > 0)
+    }
+}
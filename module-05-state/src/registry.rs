@@ -0,0 +1,169 @@
+//! # A Tiny Dependency Injection Container
+//!
+//! `AppDeps` wires everything by hand: one field per dependency, and every
+//! new dependency means touching the struct, `demo()`, the tests' copy and
+//! `build_app`. A `Registry` holds dependencies by *type* instead:
+//! - `provide(value)` registers a ready value
+//! - `provide_lazy(|registry| ...)` registers a singleton that is built on
+//!   first use - and can pull its own dependencies out of the registry
+//! - `get::<T>()` hands out a clone of the `T`, or says which type is missing
+//! - Providing a type again replaces it, which is how tests swap in fakes
+//!
+//! `from_registry!` then writes the boring part: a `FromRegistry` impl that
+//! fills every field of a state struct with `registry.get()?`, much like
+//! `#[derive(FromRef)]` does in the other direction.
+//!
+//! What it doesn't do: check at compile time. A missing provider is found
+//! when the app is assembled, at startup - never mid-request, but later
+//! than a struct field would be.
+
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::{Arc, OnceLock},
+};
+
+type Shared = Arc<dyn Any + Send + Sync>;
+type Factory = Box<dyn Fn(&Registry) -> Shared + Send + Sync>;
+
+enum Provider {
+    Ready(Shared),
+    /// Built at most once, on the first `get`
+    Lazy(Factory, OnceLock<Shared>),
+}
+
+#[derive(Default)]
+pub struct Registry {
+    providers: HashMap<TypeId, Provider>,
+}
+
+/// `get` was asked for a type nobody provided
+#[derive(Debug)]
+pub struct Missing(pub &'static str);
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no provider registered for `{}`", self.0)
+    }
+}
+
+impl std::error::Error for Missing {}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `value`, replacing any earlier provider of `T`
+    pub fn provide<T: Clone + Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.providers
+            .insert(TypeId::of::<T>(), Provider::Ready(Arc::new(value)));
+        self
+    }
+
+    /// Register a singleton built from the registry the first time someone
+    /// asks for it. Factories may `get` other types, but must not (even
+    /// indirectly) ask for their own.
+    pub fn provide_lazy<T, F>(mut self, factory: F) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Fn(&Registry) -> T + Send + Sync + 'static,
+    {
+        let factory: Factory = Box::new(move |registry| Arc::new(factory(registry)));
+        self.providers
+            .insert(TypeId::of::<T>(), Provider::Lazy(factory, OnceLock::new()));
+        self
+    }
+
+    /// A clone of the registered `T`. Dependencies are `Arc`s and handles,
+    /// so the clone is cheap and shares state with every other clone.
+    pub fn get<T: Clone + 'static>(&self) -> Result<T, Missing> {
+        let shared = match self.providers.get(&TypeId::of::<T>()) {
+            Some(Provider::Ready(value)) => value,
+            Some(Provider::Lazy(factory, built)) => built.get_or_init(|| factory(self)),
+            None => return Err(Missing(type_name::<T>())),
+        };
+        Ok(shared
+            .downcast_ref::<T>()
+            .expect("providers are keyed by their own TypeId")
+            .clone())
+    }
+
+    /// `get` for wiring code, where a missing provider is a bug
+    pub fn require<T: Clone + 'static>(&self) -> T {
+        self.get().unwrap_or_else(|missing| panic!("{}", missing))
+    }
+}
+
+/// A type that can be assembled from a `Registry`
+pub trait FromRegistry: Sized {
+    fn from_registry(registry: &Registry) -> Result<Self, Missing>;
+}
+
+/// `from_registry!(CombinedState { config, todos, metrics })` implements
+/// `FromRegistry` by getting every listed field from the registry by type
+macro_rules! from_registry {
+    ($state:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::registry::FromRegistry for $state {
+            fn from_registry(
+                registry: &$crate::registry::Registry,
+            ) -> Result<Self, $crate::registry::Missing> {
+                Ok(Self {
+                    $($field: registry.get()?,)*
+                })
+            }
+        }
+    };
+}
+pub(crate) use from_registry;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone)]
+    struct Config(&'static str);
+
+    #[derive(Clone)]
+    struct Greeter(String);
+
+    #[derive(Clone)]
+    struct Both {
+        config: Config,
+        greeter: Greeter,
+    }
+    from_registry!(Both { config, greeter });
+
+    #[test]
+    fn test_lazy_singleton_is_built_once_from_other_providers() {
+        let builds = Arc::new(AtomicUsize::new(0));
+        let counter = builds.clone();
+        let registry = Registry::new()
+            .provide(Config("prod"))
+            .provide_lazy(move |registry| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Greeter(format!("hello from {}", registry.require::<Config>().0))
+            });
+        assert_eq!(builds.load(Ordering::SeqCst), 0);
+
+        let both = Both::from_registry(&registry).unwrap();
+        registry.get::<Greeter>().unwrap();
+
+        assert_eq!(both.greeter.0, "hello from prod");
+        assert_eq!(both.config.0, "prod");
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_later_provider_overrides_and_missing_is_named() {
+        let registry = Registry::new()
+            .provide(Config("prod"))
+            .provide(Config("test"));
+        assert_eq!(registry.require::<Config>().0, "test");
+
+        let missing = Both::from_registry(&registry).err().unwrap();
+        assert!(missing.to_string().contains("Greeter"));
+    }
+}
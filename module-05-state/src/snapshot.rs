@@ -0,0 +1,369 @@
+//! # Snapshot & Restore
+//!
+//! In-memory state disappears when the server stops. This lesson adds an
+//! admin API that moves it between runs:
+//! - `GET /admin/export` streams a versioned JSON dump of every registered
+//!   store, one store at a time, each read under its own lock
+//! - `POST /admin/import` restores such a dump
+//!
+//! Both ends share one size limit, `MAX_SNAPSHOT_BYTES`: import's body limit
+//! and export's cap. An export that would outgrow it is refused with a 413
+//! before the first byte goes out, rather than producing a dump that import
+//! would turn away.
+//!
+//! Import is all-or-nothing: every store in the file is parsed and validated
+//! first, and only if all of them pass are they swapped in, each with a
+//! single `mem::replace` under its write lock. A bad entry anywhere leaves
+//! the running server untouched.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::stream;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use crate::now_unix;
+
+/// Bump when the dump format changes incompatibly
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// Largest dump `/admin/export` writes and `/admin/import` accepts
+pub const MAX_SNAPSHOT_BYTES: usize = 8 * 1024 * 1024;
+
+// ============================================================================
+// SNAPSHOT TRAIT
+// ============================================================================
+
+/// Swaps the parsed data into the store; only run once everything validated
+pub type Commit = Box<dyn FnOnce() + Send>;
+
+/// A store that can be dumped to and restored from JSON
+pub trait Snapshot: Send + Sync {
+    fn export(&self) -> Result<Value, serde_json::Error>;
+    /// Parse and validate without touching the store. Returns the number of
+    /// entries and the commit step.
+    fn prepare(&self, value: Value) -> Result<(usize, Commit), String>;
+}
+
+/// Per-entry checks beyond "it deserialized"
+pub trait ValidateEntry {
+    fn validate(&self, key: &str) -> Result<(), String>;
+}
+
+// Every `Arc<RwLock<HashMap<String, V>>>` store can be snapshotted
+impl<V> Snapshot for Arc<RwLock<HashMap<String, V>>>
+where
+    V: Serialize + DeserializeOwned + ValidateEntry + Send + Sync + 'static,
+{
+    fn export(&self) -> Result<Value, serde_json::Error> {
+        serde_json::to_value(&*self.read().unwrap())
+    }
+
+    fn prepare(&self, value: Value) -> Result<(usize, Commit), String> {
+        let entries: HashMap<String, V> =
+            serde_json::from_value(value).map_err(|e| e.to_string())?;
+        for (key, entry) in &entries {
+            entry
+                .validate(key)
+                .map_err(|e| format!("entry {:?}: {}", key, e))?;
+        }
+
+        let count = entries.len();
+        let store = self.clone();
+        let commit: Commit = Box::new(move || {
+            // The lock is released at the end of this statement; the old map
+            // is freed afterwards, outside the lock
+            let previous = std::mem::replace(&mut *store.write().unwrap(), entries);
+            drop(previous);
+        });
+        Ok((count, commit))
+    }
+}
+
+/// A single value (counters, settings) rather than a keyed map
+pub struct Whole<T>(pub Arc<RwLock<T>>);
+
+impl<T> Snapshot for Whole<T>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn export(&self) -> Result<Value, serde_json::Error> {
+        serde_json::to_value(&*self.0.read().unwrap())
+    }
+
+    fn prepare(&self, value: Value) -> Result<(usize, Commit), String> {
+        let parsed: T = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        let store = self.0.clone();
+        let commit: Commit = Box::new(move || *store.write().unwrap() = parsed);
+        Ok((1, commit))
+    }
+}
+
+// ============================================================================
+// REGISTRY
+// ============================================================================
+
+/// Every store included in export/import
+#[derive(Clone, Default)]
+pub struct SnapshotRegistry {
+    stores: Vec<(&'static str, Arc<dyn Snapshot>)>,
+}
+
+impl SnapshotRegistry {
+    pub fn register(mut self, name: &'static str, store: impl Snapshot + 'static) -> Self {
+        self.stores.push((name, Arc::new(store)));
+        self
+    }
+
+    /// Every store at once, for callers that want a value rather than a stream
+    pub fn export_all(&self) -> Result<BTreeMap<&'static str, Value>, serde_json::Error> {
+        self.stores
+            .iter()
+            .map(|(name, store)| Ok((*name, store.export()?)))
+            .collect()
+    }
+
+    /// Validate every store in `file`, then swap them all in - or none
+    pub fn restore(&self, mut file: SnapshotFile) -> Result<ImportReport, ImportError> {
+        if file.version != SNAPSHOT_VERSION {
+            return Err(ImportError::new(
+                format!(
+                    "Unsupported snapshot version {} (expected {})",
+                    file.version, SNAPSHOT_VERSION
+                ),
+                None,
+            ));
+        }
+        if let Some(unknown) = file
+            .stores
+            .keys()
+            .find(|name| !self.stores.iter().any(|(n, _)| n == name))
+        {
+            return Err(ImportError::new(
+                "Unknown store".to_string(),
+                Some(unknown.clone()),
+            ));
+        }
+
+        // Phase 1: parse and validate everything, touching nothing
+        let mut commits = Vec::new();
+        let mut report = ImportReport {
+            restored: BTreeMap::new(),
+            unchanged: Vec::new(),
+        };
+        for (name, store) in &self.stores {
+            let Some(value) = file.stores.remove(*name) else {
+                report.unchanged.push(name);
+                continue;
+            };
+            let (count, commit) = store
+                .prepare(value)
+                .map_err(|e| ImportError::new(e, Some(name.to_string())))?;
+            report.restored.insert(name, count);
+            commits.push(commit);
+        }
+
+        // Phase 2: swap
+        for commit in commits {
+            commit();
+        }
+        Ok(report)
+    }
+}
+
+// ============================================================================
+// ENDPOINTS
+// ============================================================================
+
+/// Why `/admin/export` sent no dump
+#[derive(Debug)]
+pub enum ExportError {
+    Serialize(serde_json::Error),
+    /// The dump passed `limit` while writing `store`
+    TooLarge {
+        store: &'static str,
+        limit: usize,
+    },
+}
+
+impl IntoResponse for ExportError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            ExportError::Serialize(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                serde_json::json!({ "error": e.to_string() }),
+            ),
+            ExportError::TooLarge { store, limit } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                serde_json::json!({
+                    "error": format!(
+                        "Snapshot is over the {} byte limit import accepts",
+                        limit
+                    ),
+                    "store": store,
+                }),
+            ),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+impl SnapshotRegistry {
+    /// The dump in chunks, one per store, refused once it passes `limit`.
+    /// At most `limit` bytes (plus the store that crossed it) are held.
+    fn export_chunks(&self, limit: usize) -> Result<Vec<Bytes>, ExportError> {
+        let head = format!(
+            r#"{{"version":{},"exported_at":{},"stores":{{"#,
+            SNAPSHOT_VERSION,
+            now_unix()
+        );
+        let tail = Bytes::from_static(b"}}");
+        let mut size = head.len() + tail.len();
+        let mut chunks = vec![Bytes::from(head)];
+
+        for (i, (name, store)) in self.stores.iter().enumerate() {
+            let body = store
+                .export()
+                .and_then(|value| serde_json::to_string(&value))
+                .map_err(ExportError::Serialize)?;
+            let key = serde_json::to_string(name).map_err(ExportError::Serialize)?;
+            let separator = if i == 0 { "" } else { "," };
+            let chunk = Bytes::from(format!("{}{}:{}", separator, key, body));
+
+            size += chunk.len();
+            if size > limit {
+                return Err(ExportError::TooLarge { store: name, limit });
+            }
+            chunks.push(chunk);
+        }
+        chunks.push(tail);
+        Ok(chunks)
+    }
+}
+
+/// GET /admin/export
+///
+/// `{"version":1,"exported_at":...,"stores":{"todos":{...},"metrics":{...}}}`,
+/// sent store by store so the whole dump is never copied into one string
+pub async fn export(State(registry): State<SnapshotRegistry>) -> Result<Response, ExportError> {
+    // Sized before the status goes out, so an oversized dump is a clean 413
+    let chunks = registry.export_chunks(MAX_SNAPSHOT_BYTES)?;
+    let stream = stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+
+    let filename = format!("attachment; filename=\"snapshot-{}.json\"", now_unix());
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::CONTENT_DISPOSITION, filename),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct SnapshotFile {
+    version: u32,
+    #[allow(dead_code)] // Informational; kept so dumps are self-describing
+    exported_at: Option<u64>,
+    stores: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    /// store -> entries restored
+    restored: BTreeMap<&'static str, usize>,
+    /// Registered stores missing from the file, left as they were
+    unchanged: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportError {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    store: Option<String>,
+}
+
+impl ImportError {
+    fn new(error: String, store: Option<String>) -> Self {
+        Self { error, store }
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.store {
+            Some(store) => write!(f, "store {:?}: {}", store, self.error),
+            None => f.write_str(&self.error),
+        }
+    }
+}
+
+/// POST /admin/import - bodies over `MAX_SNAPSHOT_BYTES` get a 413
+pub async fn import(
+    State(registry): State<SnapshotRegistry>,
+    Json(file): Json<SnapshotFile>,
+) -> Result<Json<ImportReport>, (StatusCode, Json<ImportError>)> {
+    registry
+        .restore(file)
+        .map(Json)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, Json(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entry_lock::TodoEntry, Todo, TodoStore};
+
+    fn store_with(count: usize) -> TodoStore {
+        let todos = (0..count)
+            .map(|i| {
+                let todo = Todo {
+                    id: uuid::Uuid::from_u128(i as u128 + 1).to_string(),
+                    title: "x".repeat(100),
+                    completed: false,
+                    created_at: 1,
+                    completed_at: None,
+                };
+                (todo.id.clone(), TodoEntry::new(todo))
+            })
+            .collect::<HashMap<_, _>>();
+        Arc::new(RwLock::new(todos))
+    }
+
+    #[test]
+    fn test_export_fits_the_import_limit() {
+        let registry = SnapshotRegistry::default().register("todos", store_with(50));
+        let chunks = registry.export_chunks(MAX_SNAPSHOT_BYTES).unwrap();
+        let dump: Vec<u8> = chunks.concat();
+
+        let file: SnapshotFile = serde_json::from_slice(&dump).unwrap();
+        let report = registry.restore(file).unwrap();
+        assert_eq!(report.restored["todos"], 50);
+    }
+
+    #[test]
+    fn test_oversized_export_is_refused_up_front() {
+        let registry = SnapshotRegistry::default()
+            .register("small", store_with(1))
+            .register("big", store_with(100));
+        let fits = registry.export_chunks(usize::MAX).unwrap().concat().len();
+
+        match registry.export_chunks(fits - 1) {
+            Err(ExportError::TooLarge { store, limit }) => {
+                assert_eq!(store, "big");
+                assert_eq!(limit, fits - 1);
+            }
+            other => panic!("expected TooLarge, got {:?}", other.map(|c| c.len())),
+        }
+        assert!(registry.export_chunks(fits).is_ok());
+    }
+}
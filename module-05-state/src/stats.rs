@@ -0,0 +1,159 @@
+//! # Store Statistics & Memory Accounting
+//!
+//! In-memory state grows silently. This lesson adds a way to look inside:
+//! - `HeapSize` estimates how many heap bytes a value owns
+//! - `StoreInspector` reports entry count, approximate size and the
+//!   oldest/newest entry of a store
+//! - `StoreRegistry` collects every store so `GET /admin/stores` can
+//!   report on all of them at once
+//!
+//! Sizes are estimates: allocator overhead and hash-table control bytes
+//! are ignored, but the numbers grow and shrink the way the real ones do.
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    mem::size_of,
+    sync::{Arc, RwLock},
+};
+
+// ============================================================================
+// HEAP SIZE ESTIMATION
+// ============================================================================
+
+/// Approximate number of heap bytes owned by a value (not counting
+/// `size_of::<Self>()` itself - the container accounts for that)
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for u64 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for bool {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, HeapSize::heap_size)
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(HeapSize::heap_size).sum::<usize>()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for HashMap<K, V> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * (size_of::<K>() + size_of::<V>())
+            + self
+                .iter()
+                .map(|(k, v)| k.heap_size() + v.heap_size())
+                .sum::<usize>()
+    }
+}
+
+/// Entries that know when they were created (unix seconds)
+pub trait Timestamped {
+    fn created_at(&self) -> u64;
+}
+
+// ============================================================================
+// STORE INSPECTION
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+pub struct EntryAge {
+    key: String,
+    created_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoreStats {
+    name: &'static str,
+    entries: usize,
+    approx_bytes: usize,
+    oldest: Option<EntryAge>,
+    newest: Option<EntryAge>,
+}
+
+/// Anything that can report statistics about itself
+pub trait StoreInspector: Send + Sync {
+    fn stats(&self, name: &'static str) -> StoreStats;
+}
+
+// Every `Arc<RwLock<HashMap<String, V>>>` store gets stats for free
+impl<V> StoreInspector for Arc<RwLock<HashMap<String, V>>>
+where
+    V: HeapSize + Timestamped + Send + Sync,
+{
+    fn stats(&self, name: &'static str) -> StoreStats {
+        let map = self.read().unwrap();
+        let by_age = |(key, value): (&String, &V)| EntryAge {
+            key: key.clone(),
+            created_at: value.created_at(),
+        };
+
+        StoreStats {
+            name,
+            entries: map.len(),
+            approx_bytes: size_of::<HashMap<String, V>>() + map.heap_size(),
+            oldest: map.iter().min_by_key(|(_, v)| v.created_at()).map(by_age),
+            newest: map.iter().max_by_key(|(_, v)| v.created_at()).map(by_age),
+        }
+    }
+}
+
+// ============================================================================
+// REGISTRY + ENDPOINT
+// ============================================================================
+
+/// All stores that should show up in `/admin/stores`
+#[derive(Clone, Default)]
+pub struct StoreRegistry {
+    stores: Vec<(&'static str, Arc<dyn StoreInspector>)>,
+}
+
+impl StoreRegistry {
+    pub fn register(mut self, name: &'static str, store: impl StoreInspector + 'static) -> Self {
+        self.stores.push((name, Arc::new(store)));
+        self
+    }
+}
+
+#[derive(Serialize)]
+pub struct StoresReport {
+    total_entries: usize,
+    total_approx_bytes: usize,
+    stores: Vec<StoreStats>,
+}
+
+/// GET /admin/stores
+pub async fn store_stats(State(registry): State<StoreRegistry>) -> Json<StoresReport> {
+    let stores: Vec<StoreStats> = registry
+        .stores
+        .iter()
+        .map(|(name, store)| store.stats(name))
+        .collect();
+
+    Json(StoresReport {
+        total_entries: stores.iter().map(|s| s.entries).sum(),
+        total_approx_bytes: stores.iter().map(|s| s.approx_bytes).sum(),
+        stores,
+    })
+}
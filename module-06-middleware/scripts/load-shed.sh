@@ -0,0 +1,30 @@
+#!/usr/bin/env bash
+# Fire a burst of concurrent requests at /slow and count what came back.
+#
+# /slow admits 4 requests at a time; the rest of the burst is shed with
+# 503 + Retry-After instead of queueing behind them.
+#
+# usage: scripts/load-shed.sh [requests] [secs]   (server running: cargo run)
+set -euo pipefail
+
+REQUESTS=${1:-12}
+SECS=${2:-1}
+URL="http://127.0.0.1:3000/slow?secs=${SECS}"
+OUT=$(mktemp -d)
+trap 'rm -rf "$OUT"' EXIT
+
+echo "Firing ${REQUESTS} concurrent requests at ${URL}"
+for i in $(seq "$REQUESTS"); do
+    curl -s -o "$OUT/body-$i" -D "$OUT/headers-$i" -w "%{http_code} %{time_total}s\n" "$URL" >"$OUT/status-$i" &
+done
+wait
+
+for i in $(seq "$REQUESTS"); do
+    read -r code took <"$OUT/status-$i"
+    retry=$(grep -i '^retry-after:' "$OUT/headers-$i" | tr -d '\r' | cut -d' ' -f2 || true)
+    printf '#%-3s %s in %s%s\n' "$i" "$code" "$took" "${retry:+ (Retry-After: ${retry}s)}"
+done
+
+echo
+echo "Summary:"
+cat "$OUT"/status-* | cut -d' ' -f1 | sort | uniq -c
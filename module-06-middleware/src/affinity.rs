@@ -0,0 +1,316 @@
+//! # Sticky Sessions with a Signed Affinity Cookie
+//!
+//! Behind a load balancer, any instance may get the next request. That's
+//! fine for a stateless app, but state kept in one instance's memory - an
+//! in-process session, a cache, an upload in progress, a rate limiter's
+//! buckets - is simply missing on the others. "Sticky sessions" route a
+//! client back to the instance that served it, and the usual key is a
+//! cookie naming that instance.
+//!
+//! `sticky_affinity` issues that cookie (`affinity=<instance id>`), signed
+//! so a client can't pick its instance, and checks it on the way in:
+//! - no cookie - a first visit; the cookie is set
+//! - our own id - the balancer kept the client on this instance
+//! - another instance's id - the client **moved**: whatever that instance
+//!   held for it is gone. Logged with both ids and counted; the cookie is
+//!   re-issued for this instance
+//! - a bad signature - edited by hand, or signed with another secret
+//!   (every instance must share `AFFINITY_SECRET`). Treated like a first
+//!   visit, but counted separately
+//!
+//! Every response says who served it in `X-Served-By`, and `GET /affinity`
+//! shows the counters. A rising `moved` count means the balancer isn't
+//! sticky - or instances come and go - and in-memory state is being lost.
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, Key, SameSite, SignedCookieJar};
+use sha2::{Digest, Sha512};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+pub const AFFINITY_COOKIE: &str = "affinity";
+pub const SERVED_BY_HEADER: &str = "x-served-by";
+
+/// Only for running the course locally; set `AFFINITY_SECRET` anywhere else
+const DEV_SECRET: &str = "affinity-secret-change-in-production";
+
+#[derive(Debug, Default)]
+struct AffinityStats {
+    first_visits: AtomicU64,
+    sticky: AtomicU64,
+    moved: AtomicU64,
+    tampered: AtomicU64,
+}
+
+/// What the incoming cookie said about where the client was served last
+#[derive(Debug, Clone, PartialEq)]
+enum Arrival {
+    FirstVisit,
+    Sticky,
+    Moved { from: String },
+    Tampered,
+}
+
+#[derive(Clone)]
+pub struct Affinity {
+    instance_id: Arc<str>,
+    key: Key,
+    stats: Arc<AffinityStats>,
+}
+
+impl Affinity {
+    pub fn new(instance_id: &str, secret: &str) -> Self {
+        // `Key` wants 64 bytes; any secret length works once hashed
+        Self {
+            instance_id: instance_id.into(),
+            key: Key::from(&Sha512::digest(secret.as_bytes())),
+            stats: Arc::default(),
+        }
+    }
+
+    /// `INSTANCE_ID` (default: `HOSTNAME`, then "local") and `AFFINITY_SECRET`
+    pub fn from_env() -> Self {
+        let instance_id = std::env::var("INSTANCE_ID")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_else(|_| "local".to_string());
+        let secret = std::env::var("AFFINITY_SECRET").unwrap_or_else(|_| {
+            tracing::warn!("AFFINITY_SECRET not set - using the development secret");
+            DEV_SECRET.to_string()
+        });
+        Self::new(&instance_id, &secret)
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    fn classify(&self, request: &Request) -> Arrival {
+        let headers = request.headers();
+        if CookieJar::from_headers(headers)
+            .get(AFFINITY_COOKIE)
+            .is_none()
+        {
+            return Arrival::FirstVisit;
+        }
+        match SignedCookieJar::from_headers(headers, self.key.clone()).get(AFFINITY_COOKIE) {
+            None => Arrival::Tampered,
+            Some(cookie) if cookie.value() == &*self.instance_id => Arrival::Sticky,
+            Some(cookie) => Arrival::Moved {
+                from: cookie.value().to_string(),
+            },
+        }
+    }
+
+    fn record(&self, arrival: &Arrival, path: &str) {
+        let counter = match arrival {
+            Arrival::FirstVisit => &self.stats.first_visits,
+            Arrival::Sticky => &self.stats.sticky,
+            Arrival::Moved { from } => {
+                tracing::warn!(
+                    from = %from,
+                    to = %self.instance_id,
+                    path,
+                    "Client moved instances - state held by the previous one is not here"
+                );
+                &self.stats.moved
+            }
+            Arrival::Tampered => {
+                tracing::warn!(path, "Affinity cookie with a bad signature - ignored");
+                &self.stats.tampered
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A cookie pinning the client to this instance
+    fn cookie(&self) -> Cookie<'static> {
+        Cookie::build((AFFINITY_COOKIE, self.instance_id.to_string()))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .build()
+    }
+}
+
+/// The layer: `middleware::from_fn_with_state(affinity, sticky_affinity)`
+pub async fn sticky_affinity(
+    State(affinity): State<Affinity>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let arrival = affinity.classify(&request);
+    affinity.record(&arrival, request.uri().path());
+
+    let response = next.run(request).await;
+    let served_by = HeaderValue::from_str(affinity.instance_id()).ok();
+    let mut response = if arrival == Arrival::Sticky {
+        response
+    } else {
+        let jar = SignedCookieJar::new(affinity.key.clone()).add(affinity.cookie());
+        (jar, response).into_response()
+    };
+    if let Some(served_by) = served_by {
+        response.headers_mut().insert(SERVED_BY_HEADER, served_by);
+    }
+    response
+}
+
+/// GET /affinity - how clients have been arriving at this instance
+pub async fn affinity_stats(State(affinity): State<Affinity>) -> impl IntoResponse {
+    let stats = &affinity.stats;
+    Json(serde_json::json!({
+        "instance_id": affinity.instance_id(),
+        "first_visits": stats.first_visits.load(Ordering::Relaxed),
+        "sticky": stats.sticky.load(Ordering::Relaxed),
+        "moved": stats.moved.load(Ordering::Relaxed),
+        "tampered": stats.tampered.load(Ordering::Relaxed),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, HeaderMap},
+        middleware,
+        routing::get,
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    const SECRET: &str = "test-secret";
+
+    fn app(affinity: &Affinity) -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .route(
+                "/affinity",
+                get(affinity_stats).with_state(affinity.clone()),
+            )
+            .layer(middleware::from_fn_with_state(
+                affinity.clone(),
+                sticky_affinity,
+            ))
+    }
+
+    /// GET `uri`, sending `cookie` (a `Set-Cookie` value) back if given
+    async fn get_with(affinity: &Affinity, uri: &str, cookie: Option<&str>) -> Response {
+        let mut request = Request::builder().uri(uri);
+        if let Some(cookie) = cookie {
+            let pair = cookie.split(';').next().unwrap();
+            request = request.header(header::COOKIE, pair);
+        }
+        app(affinity)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn affinity_cookie(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find(|v| v.starts_with("affinity="))
+            .map(str::to_string)
+    }
+
+    /// The cookie instance `id` would hand out
+    async fn cookie_from(id: &str, secret: &str) -> String {
+        let response = get_with(&Affinity::new(id, secret), "/", None).await;
+        affinity_cookie(response.headers()).unwrap()
+    }
+
+    async fn stats(affinity: &Affinity) -> serde_json::Value {
+        let response = get_with(affinity, "/affinity", None).await;
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_first_visit_sets_a_signed_cookie() {
+        let affinity = Affinity::new("a", SECRET);
+
+        let response = get_with(&affinity, "/", None).await;
+
+        assert_eq!(response.headers()[SERVED_BY_HEADER], "a");
+        let cookie = affinity_cookie(response.headers()).unwrap();
+        assert!(cookie.contains("HttpOnly"));
+        // Signed, not the bare instance id
+        assert!(!cookie.starts_with("affinity=a;"));
+    }
+
+    #[tokio::test]
+    async fn test_sticky_client_is_not_reissued_a_cookie() {
+        let affinity = Affinity::new("a", SECRET);
+        let cookie = cookie_from("a", SECRET).await;
+
+        let response = get_with(&affinity, "/", Some(&cookie)).await;
+
+        assert_eq!(response.headers()[SERVED_BY_HEADER], "a");
+        assert_eq!(affinity_cookie(response.headers()), None);
+        assert_eq!(stats(&affinity).await["sticky"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_from_another_instance_is_moved_and_repinned() {
+        let affinity = Affinity::new("a", SECRET);
+        let from_b = cookie_from("b", SECRET).await;
+
+        let response = get_with(&affinity, "/", Some(&from_b)).await;
+
+        assert_eq!(response.headers()[SERVED_BY_HEADER], "a");
+        let repinned = affinity_cookie(response.headers()).unwrap();
+        assert_ne!(repinned, from_b);
+        // The new cookie sticks to "a"
+        get_with(&affinity, "/", Some(&repinned)).await;
+        let stats = stats(&affinity).await;
+        assert_eq!(stats["moved"], 1);
+        assert_eq!(stats["sticky"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_signed_with_another_secret_counts_as_tampered() {
+        let affinity = Affinity::new("a", SECRET);
+        let forged = cookie_from("a", "some-other-secret").await;
+
+        let response = get_with(&affinity, "/", Some(&forged)).await;
+
+        assert!(affinity_cookie(response.headers()).is_some());
+        let stats = stats(&affinity).await;
+        assert_eq!(stats["tampered"], 1);
+        assert_eq!(stats["sticky"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_cookie_counts_as_tampered() {
+        let affinity = Affinity::new("a", SECRET);
+
+        get_with(&affinity, "/", Some("affinity=a")).await;
+
+        assert_eq!(stats(&affinity).await["tampered"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats_report_the_instance_and_first_visits() {
+        let affinity = Affinity::new("a", SECRET);
+        get_with(&affinity, "/", None).await;
+        get_with(&affinity, "/", None).await;
+
+        let stats = stats(&affinity).await;
+
+        assert_eq!(stats["instance_id"], "a");
+        // The stats request itself arrives without a cookie too
+        assert_eq!(stats["first_visits"], 3);
+    }
+}
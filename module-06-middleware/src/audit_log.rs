@@ -0,0 +1,600 @@
+//! # Audit-Grade Access Log: a Tamper-Evident Hash Chain
+//!
+//! A plain access log proves nothing: anyone with write access to the file
+//! can edit or delete a line and nobody will ever know. `audit_trail` writes
+//! every request to an append-only JSON-lines log where each record carries
+//! the hash of the one before it:
+//!
+//! ```text
+//! hash = sha256(seq, ts_ms, kind, ..fields, prev_hash)
+//! {"seq":1,...,"prev_hash":"000..000","hash":"9f2c.."}
+//! {"seq":2,...,"prev_hash":"9f2c..",  "hash":"41ab.."}
+//! ```
+//!
+//! Changing, reordering or deleting a record breaks every link after it.
+//! - Rotation: after `max_records` access records the segment is *sealed* -
+//!   a final `seal` record is chained in, the file is made read-only and the
+//!   next segment's first record links to the seal's hash
+//! - Verification: `verify` walks every segment and reports the first
+//!   broken link. Served at `GET /admin/audit/verify` and available offline
+//!   as `cargo run -p module-06-middleware -- verify-audit [dir]`
+//!
+//! A crash mid-write can leave the newest line without its newline. That
+//! record was never finished, so `open` cuts it off and carries on from the
+//! last whole record instead of refusing to start.
+//!
+//! What a chain can't catch on its own: dropping the newest records of the
+//! active segment. Ship `head_hash` somewhere else (another system, a ticket,
+//! a daily email) and compare later - that anchors everything before it.
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use course_routes::Routes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::baggage::RequestContext;
+
+/// `prev_hash` of the very first record
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// ============================================================================
+// RECORDS
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Event {
+    Access {
+        method: String,
+        /// Path only: query strings are where tokens and emails leak into logs
+        path: String,
+        status: u16,
+        latency_ms: u64,
+        request_id: Option<String>,
+        tenant_id: Option<String>,
+    },
+    /// Last record of a segment; nothing may follow it in the same file
+    Seal { segment: u64, records: u64 },
+}
+
+/// Everything the hash covers, in a fixed field order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    ts_ms: u64,
+    #[serde(flatten)]
+    event: Event,
+    prev_hash: String,
+}
+
+/// One line of a segment file
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    #[serde(flatten)]
+    entry: Entry,
+    hash: String,
+}
+
+impl Entry {
+    fn hash(&self) -> String {
+        let canonical = serde_json::to_vec(self).expect("entries always serialize");
+        Sha256::digest(&canonical)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn segment_name(segment: u64) -> String {
+    format!("segment-{:06}.jsonl", segment)
+}
+
+/// `(number, path)` of every segment in `dir`, oldest first
+fn segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("segment-")?.strip_suffix(".jsonl"))
+            .and_then(|number| number.parse().ok());
+        if let Some(number) = number {
+            found.push((number, path));
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+fn read_lines(path: &Path) -> io::Result<Vec<String>> {
+    BufReader::new(File::open(path)?).lines().collect()
+}
+
+/// Cut a line the last run didn't finish (no trailing newline) off the end
+/// of `path`; returns how many bytes were dropped
+fn drop_torn_tail(path: &Path) -> io::Result<u64> {
+    let bytes = fs::read(path)?;
+    let complete = bytes.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    let torn = (bytes.len() - complete) as u64;
+    if torn > 0 {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(torn)
+}
+
+// ============================================================================
+// WRITER
+// ============================================================================
+
+/// The sink. Cheap to clone; all clones append to the same chain.
+#[derive(Clone)]
+pub struct AuditLog {
+    dir: PathBuf,
+    writer: Arc<Mutex<Writer>>,
+}
+
+struct Writer {
+    max_records: u64,
+    segment: u64,
+    /// Access records in the current segment
+    records: u64,
+    seq: u64,
+    head_hash: String,
+    file: File,
+}
+
+impl AuditLog {
+    /// `AUDIT_LOG_DIR` (default `audit-logs`) - shared with the CLI verifier
+    pub fn default_dir() -> PathBuf {
+        std::env::var("AUDIT_LOG_DIR")
+            .unwrap_or_else(|_| "audit-logs".into())
+            .into()
+    }
+
+    /// Open `dir`, resuming the chain where the last run left off. A torn
+    /// last line in the newest segment is dropped first.
+    pub fn open(dir: impl Into<PathBuf>, max_records: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let existing = segments(&dir)?;
+        if let Some((_, newest)) = existing.last() {
+            let torn = drop_torn_tail(newest)?;
+            if torn > 0 {
+                tracing::warn!(
+                    segment = %newest.display(),
+                    bytes = torn,
+                    "Dropped an unfinished audit record left by a crash"
+                );
+            }
+        }
+        let (mut segment, mut records, mut seq, mut head_hash) =
+            (1, 0, 0, GENESIS_HASH.to_string());
+        let mut sealed = false;
+        for (number, path) in &existing {
+            let lines = read_lines(path)?;
+            segment = *number;
+            records = 0;
+            sealed = false;
+            for line in &lines {
+                let record: Record = serde_json::from_str(line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                seq = record.entry.seq;
+                head_hash = record.hash;
+                match record.entry.event {
+                    Event::Access { .. } => records += 1,
+                    Event::Seal { .. } => sealed = true,
+                }
+            }
+        }
+        if sealed {
+            segment += 1;
+            records = 0;
+        }
+
+        let file = open_segment(&dir, segment)?;
+        Ok(Self {
+            dir,
+            writer: Arc::new(Mutex::new(Writer {
+                max_records: max_records.max(1),
+                segment,
+                records,
+                seq,
+                head_hash,
+                file,
+            })),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Hash of the newest record - publish it elsewhere to anchor the chain
+    pub fn head_hash(&self) -> String {
+        self.writer.lock().unwrap().head_hash.clone()
+    }
+
+    fn append(&self, event: Event) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write(event)?;
+        writer.records += 1;
+        if writer.records >= writer.max_records {
+            writer.seal(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    /// Seal the current segment now, e.g. at midnight or before an export.
+    /// Returns the sealed segment, or `None` if it had no records yet.
+    pub fn rotate(&self) -> io::Result<Option<u64>> {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.records == 0 {
+            return Ok(None);
+        }
+        let segment = writer.segment;
+        writer.seal(&self.dir)?;
+        Ok(Some(segment))
+    }
+}
+
+fn open_segment(dir: &Path, segment: u64) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(segment_name(segment)))
+}
+
+impl Writer {
+    /// Blocking file I/O under a std `Mutex`: one short line per request, so
+    /// it's cheaper than handing it to another thread
+    fn write(&mut self, event: Event) -> io::Result<()> {
+        let entry = Entry {
+            seq: self.seq + 1,
+            ts_ms: now_ms(),
+            event,
+            prev_hash: self.head_hash.clone(),
+        };
+        let hash = entry.hash();
+        let mut line = serde_json::to_vec(&Record {
+            entry,
+            hash: hash.clone(),
+        })?;
+        line.push(b'\n');
+        // One write per line, so a crash can't interleave half records
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.seq += 1;
+        self.head_hash = hash;
+        Ok(())
+    }
+
+    fn seal(&mut self, dir: &Path) -> io::Result<()> {
+        self.write(Event::Seal {
+            segment: self.segment,
+            records: self.records,
+        })?;
+        let sealed = dir.join(segment_name(self.segment));
+        let mut permissions = fs::metadata(&sealed)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&sealed, permissions)?;
+
+        self.segment += 1;
+        self.records = 0;
+        self.file = open_segment(dir, self.segment)?;
+        tracing::info!(segment = self.segment - 1, head_hash = %self.head_hash, "Audit segment sealed");
+        Ok(())
+    }
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// The layer: `middleware::from_fn_with_state(audit_log, audit_trail)`.
+/// Add it inside `context_propagation` so records carry the request id.
+pub async fn audit_trail(State(log): State<AuditLog>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let context = request.extensions().get::<RequestContext>().cloned();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let event = Event::Access {
+        method,
+        path,
+        status: response.status().as_u16(),
+        latency_ms: start.elapsed().as_millis() as u64,
+        request_id: context.as_ref().map(|c| c.request_id.clone()),
+        tenant_id: context.and_then(|c| c.tenant_id),
+    };
+    if let Err(err) = log.append(event) {
+        tracing::error!(error = %err, "Failed to write audit record");
+    }
+    response
+}
+
+// ============================================================================
+// VERIFICATION
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub valid: bool,
+    segments: usize,
+    sealed_segments: usize,
+    records: u64,
+    head_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tampered: Option<Tampering>,
+}
+
+/// The first broken link; everything after it is untrusted
+#[derive(Debug, Serialize)]
+pub struct Tampering {
+    segment: String,
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    reason: String,
+}
+
+/// Walk every segment in `dir` from genesis and re-check every link
+pub fn verify(dir: &Path) -> io::Result<VerifyReport> {
+    let existing = segments(dir)?;
+    let mut report = VerifyReport {
+        valid: true,
+        segments: existing.len(),
+        sealed_segments: 0,
+        records: 0,
+        head_hash: GENESIS_HASH.to_string(),
+        tampered: None,
+    };
+    let mut seq = 0;
+
+    for (index, (number, path)) in existing.iter().enumerate() {
+        let name = segment_name(*number);
+        if *number != index as u64 + 1 {
+            let reason = format!("segment {} is missing", index + 1);
+            return Ok(report.broken(name, 0, None, reason));
+        }
+        let (mut records, mut sealed) = (0, false);
+        for (i, line) in read_lines(path)?.iter().enumerate() {
+            let line_no = i + 1;
+            let Ok(record) = serde_json::from_str::<Record>(line) else {
+                return Ok(report.broken(name, line_no, None, "not a valid audit record".into()));
+            };
+            let entry = &record.entry;
+            let problem = if sealed {
+                Some("record after the segment's seal".to_string())
+            } else if entry.seq != seq + 1 {
+                Some(format!("sequence gap: expected {}", seq + 1))
+            } else if entry.prev_hash != report.head_hash {
+                Some("prev_hash does not match the previous record".to_string())
+            } else if entry.hash() != record.hash {
+                Some("hash mismatch: the record was modified".to_string())
+            } else {
+                match entry.event {
+                    Event::Access { .. } => {
+                        records += 1;
+                        None
+                    }
+                    Event::Seal {
+                        segment,
+                        records: sealed_records,
+                    } => {
+                        sealed = true;
+                        (segment != *number || sealed_records != records)
+                            .then(|| "seal does not match the segment's contents".to_string())
+                    }
+                }
+            };
+            if let Some(reason) = problem {
+                return Ok(report.broken(name, line_no, Some(entry.seq), reason));
+            }
+            seq = entry.seq;
+            report.head_hash = record.hash;
+        }
+
+        report.records += records;
+        if sealed {
+            report.sealed_segments += 1;
+        } else if index + 1 < existing.len() {
+            // Only the newest segment may still be open
+            let reason = "segment was truncated: its seal is missing".into();
+            return Ok(report.broken(name, 0, None, reason));
+        }
+    }
+    Ok(report)
+}
+
+impl VerifyReport {
+    fn broken(mut self, segment: String, line: usize, seq: Option<u64>, reason: String) -> Self {
+        self.valid = false;
+        self.tampered = Some(Tampering {
+            segment,
+            line,
+            seq,
+            reason,
+        });
+        self
+    }
+}
+
+/// `verify-audit [dir]`: print the report, exit 1 if the chain is broken
+pub fn verify_cli(dir: &Path) -> i32 {
+    match verify(dir) {
+        Ok(report) => {
+            println!("{}", serde_json::to_string_pretty(&report).unwrap());
+            if report.valid {
+                0
+            } else {
+                1
+            }
+        }
+        Err(err) => {
+            eprintln!("Cannot read audit log in {}: {}", dir.display(), err);
+            2
+        }
+    }
+}
+
+// ============================================================================
+// ADMIN ROUTES
+// ============================================================================
+
+/// GET /admin/audit/verify - 409 Conflict when the chain is broken
+async fn verify_chain(
+    State(log): State<AuditLog>,
+) -> Result<(StatusCode, Json<VerifyReport>), StatusCode> {
+    let report = verify(log.dir()).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let status = if report.valid {
+        StatusCode::OK
+    } else {
+        StatusCode::CONFLICT
+    };
+    Ok((status, Json(report)))
+}
+
+/// POST /admin/audit/rotate - seal the current segment now
+async fn rotate(State(log): State<AuditLog>) -> Result<Json<serde_json::Value>, StatusCode> {
+    let sealed = log
+        .rotate()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(serde_json::json!({
+        "sealed_segment": sealed,
+        "head_hash": log.head_hash(),
+    })))
+}
+
+pub fn routes<S>(log: AuditLog) -> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Routes::new()
+        .get("/verify", verify_chain, "Check the audit hash chain")
+        .post("/rotate", rotate, "Seal the current audit segment")
+        .with_state(log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An audit directory no other test uses
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "module-06-audit-{}-{}-{}",
+            name,
+            std::process::id(),
+            now_ms()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn access(status: u16) -> Event {
+        Event::Access {
+            method: "GET".into(),
+            path: "/".into(),
+            status,
+            latency_ms: 1,
+            request_id: None,
+            tenant_id: None,
+        }
+    }
+
+    /// A log in `dir` with `n` access records, all in the first segment
+    fn write_records(dir: &Path, n: u16) -> PathBuf {
+        let log = AuditLog::open(dir, 100).unwrap();
+        for i in 0..n {
+            log.append(access(200 + i)).unwrap();
+        }
+        dir.join(segment_name(1))
+    }
+
+    fn tampering(dir: &Path) -> Tampering {
+        let report = verify(dir).unwrap();
+        assert!(!report.valid);
+        report.tampered.unwrap()
+    }
+
+    #[test]
+    fn test_a_torn_last_line_is_dropped_on_open() {
+        let dir = test_dir("torn");
+        let segment = write_records(&dir, 2);
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(br#"{"seq":3,"ts_ms":17"#).unwrap();
+
+        let log = AuditLog::open(&dir, 100).unwrap();
+        let report = verify(&dir).unwrap();
+        assert!(report.valid);
+        assert_eq!(report.records, 2);
+        assert_eq!(report.head_hash, log.head_hash());
+
+        // The chain carries on from the last whole record
+        log.append(access(201)).unwrap();
+        let report = verify(&dir).unwrap();
+        assert!(report.valid, "{:?}", report.tampered);
+        assert_eq!(report.records, 3);
+    }
+
+    #[test]
+    fn test_verify_detects_an_edited_record() {
+        let dir = test_dir("edited");
+        let segment = write_records(&dir, 3);
+        assert!(verify(&dir).unwrap().valid);
+
+        // Turn the second request's 201 into a 500, keeping its hash
+        let mut lines = read_lines(&segment).unwrap();
+        let mut record: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        record["status"] = 500.into();
+        lines[1] = record.to_string();
+        fs::write(&segment, lines.join("\n") + "\n").unwrap();
+
+        let found = tampering(&dir);
+        assert_eq!(found.line, 2);
+        assert_eq!(found.seq, Some(2));
+        assert!(found.reason.contains("modified"), "{}", found.reason);
+    }
+
+    #[test]
+    fn test_verify_detects_a_removed_record() {
+        let dir = test_dir("removed");
+        let segment = write_records(&dir, 3);
+
+        let mut lines = read_lines(&segment).unwrap();
+        lines.remove(1);
+        fs::write(&segment, lines.join("\n") + "\n").unwrap();
+
+        let found = tampering(&dir);
+        assert_eq!(found.line, 2);
+        assert_eq!(found.seq, Some(3));
+        assert!(found.reason.contains("sequence gap"), "{}", found.reason);
+    }
+}
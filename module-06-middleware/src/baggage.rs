@@ -0,0 +1,285 @@
+//! # Context Propagation with W3C Baggage
+//!
+//! A request id is only useful if every service that touches the request
+//! logs the same one. W3C Baggage (`baggage: key=value,key2=value2`) is the
+//! standard header for carrying such context between services:
+//! - Inbound: `context_propagation` reads `baggage` (plus `x-request-id` /
+//!   `x-tenant-id`), fills in what's missing and stores a `RequestContext`
+//! - In handlers: `RequestContext` is an extractor
+//! - Outbound: `RequestContext::inject` copies the context onto a `reqwest`
+//!   request, so the downstream service extracts exactly the same values
+//!
+//! Baggage entries this service doesn't understand are passed through
+//! untouched - they may matter to a service further down the chain.
+
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{request::Parts, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+};
+use tracing::Instrument;
+
+pub const BAGGAGE_HEADER: &str = "baggage";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TENANT_ID_HEADER: &str = "x-tenant-id";
+
+pub const REQUEST_ID_KEY: &str = "request.id";
+const TENANT_ID_KEY: &str = "tenant.id";
+/// `exp.checkout=one-click` = assigned to variant "one-click" of "checkout"
+const EXPERIMENT_PREFIX: &str = "exp.";
+
+/// Limits from the W3C Baggage spec
+const MAX_MEMBERS: usize = 180;
+const MAX_BYTES: usize = 8192;
+
+/// Experiments this service assigns when the caller hasn't already
+const EXPERIMENTS: &[(&str, &[&str])] = &[("checkout", &["control", "one-click"])];
+
+// ============================================================================
+// BAGGAGE HEADER
+// ============================================================================
+
+#[derive(Debug, Clone)]
+struct Member {
+    key: String,
+    value: String,
+    /// `;key=value` metadata, kept verbatim
+    properties: String,
+}
+
+/// Parsed `baggage` header, preserving member order and properties
+#[derive(Debug, Clone, Default)]
+pub struct Baggage {
+    members: Vec<Member>,
+}
+
+impl Baggage {
+    /// Parse every `baggage` header; malformed members are skipped
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut baggage = Baggage::default();
+        let mut bytes = 0;
+
+        for member in headers
+            .get_all(BAGGAGE_HEADER)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+        {
+            bytes += member.len() + 1;
+            if bytes > MAX_BYTES || baggage.members.len() == MAX_MEMBERS {
+                break;
+            }
+            let (pair, properties) = member.split_once(';').unwrap_or((member, ""));
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let key = key.trim();
+            if key.is_empty() || !key.bytes().all(is_token_byte) {
+                continue;
+            }
+            if let Some(value) = percent_decode(value.trim()) {
+                baggage.set_with_properties(key, &value, properties.trim());
+            }
+        }
+        baggage
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.members
+            .iter()
+            .find(|m| m.key == key)
+            .map(|m| m.value.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.set_with_properties(key, value, "");
+    }
+
+    fn set_with_properties(&mut self, key: &str, value: &str, properties: &str) {
+        match self.members.iter_mut().find(|m| m.key == key) {
+            Some(member) => {
+                member.value = value.to_string();
+                member.properties = properties.to_string();
+            }
+            None => self.members.push(Member {
+                key: key.to_string(),
+                value: value.to_string(),
+                properties: properties.to_string(),
+            }),
+        }
+    }
+
+    fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.members.iter().filter_map(move |m| {
+            m.key
+                .strip_prefix(prefix)
+                .map(|name| (name, m.value.as_str()))
+        })
+    }
+
+    pub fn to_header_value(&self) -> Option<HeaderValue> {
+        let value = self
+            .members
+            .iter()
+            .map(|m| {
+                let mut member = format!("{}={}", m.key, percent_encode(&m.value));
+                if !m.properties.is_empty() {
+                    member.push(';');
+                    member.push_str(&m.properties);
+                }
+                member
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+/// RFC 7230 token characters
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Encode everything outside the spec's `baggage-octet` range
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'!' | b'#'..=b'+' | b'-'..=b':' | b'<'..=b'[' | b']'..=b'~' if b != b'%' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// ============================================================================
+// REQUEST CONTEXT
+// ============================================================================
+
+/// What every log line and downstream call should know about this request
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub tenant_id: Option<String>,
+    /// experiment name -> assigned variant
+    pub experiments: BTreeMap<String, String>,
+    #[serde(skip)]
+    baggage: Baggage,
+}
+
+impl RequestContext {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let mut baggage = Baggage::from_headers(headers);
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .filter(|v| !v.is_empty() && v.len() <= 128)
+                .map(str::to_string)
+        };
+
+        // Explicit headers win over baggage; generate what's still missing
+        let request_id = header(REQUEST_ID_HEADER)
+            .or_else(|| baggage.get(REQUEST_ID_KEY).map(str::to_string))
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let tenant_id =
+            header(TENANT_ID_HEADER).or_else(|| baggage.get(TENANT_ID_KEY).map(str::to_string));
+
+        baggage.set(REQUEST_ID_KEY, &request_id);
+        if let Some(tenant) = &tenant_id {
+            baggage.set(TENANT_ID_KEY, tenant);
+        }
+
+        // Assign experiments the caller hasn't; sticky per tenant (or request)
+        let sticky_key = tenant_id.as_deref().unwrap_or(&request_id);
+        for (name, variants) in EXPERIMENTS {
+            let key = format!("{}{}", EXPERIMENT_PREFIX, name);
+            if baggage.get(&key).is_none() {
+                baggage.set(&key, assign_variant(name, sticky_key, variants));
+            }
+        }
+        let experiments = baggage
+            .with_prefix(EXPERIMENT_PREFIX)
+            .map(|(name, variant)| (name.to_string(), variant.to_string()))
+            .collect();
+
+        Self {
+            request_id,
+            tenant_id,
+            experiments,
+            baggage,
+        }
+    }
+
+    /// Copy the context onto an outbound request
+    pub fn inject(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let request = request.header(REQUEST_ID_HEADER, &self.request_id);
+        match self.baggage.to_header_value() {
+            Some(baggage) => request.header(BAGGAGE_HEADER, baggage),
+            None => request,
+        }
+    }
+}
+
+fn assign_variant<'a>(experiment: &str, key: &str, variants: &[&'a str]) -> &'a str {
+    let mut hasher = DefaultHasher::new();
+    (experiment, key).hash(&mut hasher);
+    variants[hasher.finish() as usize % variants.len()]
+}
+
+impl<S> FromRequestParts<S> for RequestContext
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<RequestContext>()
+            .cloned()
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// Extract (or create) the context and run the request inside a span
+/// carrying the tenant. The request id is already on the outer span, and
+/// `PropagateRequestIdLayer` echoes it back (see `request_id.rs`).
+pub async fn context_propagation(mut request: Request, next: Next) -> Response {
+    let context = RequestContext::from_headers(request.headers());
+    let span = tracing::info_span!(
+        "context",
+        tenant_id = context.tenant_id.as_deref().unwrap_or("-"),
+    );
+    request.extensions_mut().insert(context);
+
+    next.run(request).instrument(span).await
+}
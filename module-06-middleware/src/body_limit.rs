@@ -0,0 +1,140 @@
+//! # Request Body Limits with a JSON 413
+//!
+//! Without a limit, a client decides how much memory a handler buffers.
+//! Axum's extractors (`Json`, `Bytes`, `String`, `Form`) stop at 2 MB by
+//! default (`DefaultBodyLimit`), which is too much for a JSON API and too
+//! little for uploads, so each group of routes gets its own:
+//! - JSON routes: `RequestBodyLimitLayer` at 16 KB
+//! - Upload routes: `DefaultBodyLimit::disable()`, so the extractor doesn't
+//!   stop at 2 MB, and `RequestBodyLimitLayer` at 10 MB instead
+//!
+//! `RequestBodyLimitLayer` refuses a too-large `Content-Length` before the
+//! handler runs; a chunked body is cut off while it's read, and the
+//! extractor rejects it. Both end up as a bare `413 Payload Too Large`, so
+//! `too_large_as_json` - outside the limit - rewrites any 413 into the same
+//! JSON shape as the rate limiter's 429, stating the limit.
+
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json,
+};
+use course_routes::Routes;
+use tower_http::limit::RequestBodyLimitLayer;
+
+pub const JSON_LIMIT: usize = 16 * 1024;
+pub const UPLOAD_LIMIT: usize = 10 * 1024 * 1024;
+
+/// The layer: `middleware::from_fn_with_state(limit_bytes, too_large_as_json)`
+pub async fn too_large_as_json(
+    State(limit): State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE {
+        return response;
+    }
+    tracing::warn!(path, limit_bytes = limit, "Request body too large");
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(serde_json::json!({
+            "error": "Payload too large",
+            "limit_bytes": limit,
+        })),
+    )
+        .into_response()
+}
+
+/// POST /echo - small JSON documents
+async fn echo(Json(body): Json<serde_json::Value>) -> Json<serde_json::Value> {
+    Json(body)
+}
+
+/// POST /upload - raw bytes, much larger
+async fn upload(body: Bytes) -> impl IntoResponse {
+    Json(serde_json::json!({ "received_bytes": body.len() }))
+}
+
+pub fn routes<S>() -> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let json = Routes::new()
+        .post("/echo", echo, "JSON, 16 KB max (413 JSON past that)")
+        .layer(RequestBodyLimitLayer::new(JSON_LIMIT))
+        .layer(middleware::from_fn_with_state(
+            JSON_LIMIT,
+            too_large_as_json,
+        ));
+
+    let uploads = Routes::new()
+        .post("/upload", upload, "Raw bytes, 10 MB max")
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(UPLOAD_LIMIT))
+        .layer(middleware::from_fn_with_state(
+            UPLOAD_LIMIT,
+            too_large_as_json,
+        ));
+
+    json.merge(uploads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::header};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn post(uri: &str, content_type: &str, body: Vec<u8>) -> (StatusCode, serde_json::Value) {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(Body::from(body))
+            .unwrap();
+        let response = routes::<()>().into_router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// A JSON string of exactly `len` bytes
+    fn json_of_len(len: usize) -> Vec<u8> {
+        format!("\"{}\"", "x".repeat(len - 2)).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_oversized_json_gets_a_json_413() {
+        let (status, _) = post("/echo", "application/json", json_of_len(JSON_LIMIT)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = post("/echo", "application/json", json_of_len(JSON_LIMIT + 1)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "Payload too large", "limit_bytes": JSON_LIMIT })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_uploads_have_their_own_larger_limit() {
+        // Past both the JSON limit and axum's 2 MB default
+        let (status, body) = post("/upload", "application/octet-stream", vec![0; 3 << 20]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["received_bytes"], 3 << 20);
+
+        let (status, body) = post(
+            "/upload",
+            "application/octet-stream",
+            vec![0; UPLOAD_LIMIT + 1],
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["limit_bytes"], UPLOAD_LIMIT);
+    }
+}
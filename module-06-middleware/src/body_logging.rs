@@ -0,0 +1,283 @@
+//! # Logging Request and Response Bodies (Development Only)
+//!
+//! "What did the client actually send?" is the first question when an API
+//! call misbehaves, and the access log can't answer it. Reading a body in
+//! middleware is awkward, though: a body is a stream that can be read only
+//! once. `log_bodies` reads it whole, logs it, and puts an identical body
+//! back before the handler runs - and does the same to the response on the
+//! way out:
+//!
+//! ```ignore
+//! let (parts, body) = request.into_parts();
+//! let bytes = to_bytes(body, limit).await?;
+//! log(&bytes);
+//! let request = Request::from_parts(parts, Body::from(bytes));
+//! ```
+//!
+//! Buffering costs memory and latency, and turns streams into one chunk,
+//! so it's careful about what it touches:
+//! - only bodies that declare a size up to `MAX_BUFFERED` are read; bigger
+//!   or unsized ones (uploads, SSE, chunked) pass through unread and are
+//!   logged as skipped
+//! - JSON has the values of secret-looking fields (`password`, `token`,
+//!   ...) replaced with `[redacted]`, at any depth; form bodies too
+//! - what's logged is cut at `max_logged` bytes
+//!
+//! It's for a developer's machine: `LOG_BODIES=true` turns it on, and
+//! without it the layer isn't even in the stack.
+
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+/// Bodies bigger than this are never buffered
+const MAX_BUFFERED: u64 = 64 * 1024;
+
+const REDACTED: &str = "[redacted]";
+
+/// Field names (compared without case) whose values never reach a log
+const SECRET_FIELDS: &[&str] = &[
+    "password",
+    "new_password",
+    "token",
+    "access_token",
+    "refresh_token",
+    "secret",
+    "api_key",
+    "authorization",
+    "credit_card",
+];
+
+#[derive(Debug, Clone)]
+pub struct BodyLogging {
+    /// Longer bodies are logged up to here and marked as cut
+    pub max_logged: usize,
+    secret_fields: Arc<[String]>,
+}
+
+impl BodyLogging {
+    pub fn new(max_logged: usize) -> Self {
+        Self {
+            max_logged,
+            secret_fields: SECRET_FIELDS.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// `Some` only with `LOG_BODIES=true`; `LOG_BODIES_MAX` (default 2048)
+    /// caps what's logged of each body
+    pub fn from_env() -> Option<Self> {
+        if !std::env::var("LOG_BODIES").is_ok_and(|v| v == "true") {
+            return None;
+        }
+        let max_logged = std::env::var("LOG_BODIES_MAX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2048);
+        Some(Self::new(max_logged))
+    }
+
+    fn is_secret(&self, field: &str) -> bool {
+        self.secret_fields
+            .iter()
+            .any(|secret| secret.eq_ignore_ascii_case(field))
+    }
+
+    /// What the log gets for a body of this content type
+    fn render(&self, headers: &HeaderMap, bytes: &[u8]) -> String {
+        if bytes.is_empty() {
+            return "<empty>".to_string();
+        }
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let text = if content_type.contains("json") {
+            match serde_json::from_slice::<serde_json::Value>(bytes) {
+                Ok(mut json) => {
+                    self.redact_json(&mut json);
+                    json.to_string()
+                }
+                // Not the JSON it claims to be: the raw text is the interesting part
+                Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+            }
+        } else if content_type.starts_with("application/x-www-form-urlencoded") {
+            self.redact_form(&String::from_utf8_lossy(bytes))
+        } else if content_type.starts_with("text/") {
+            String::from_utf8_lossy(bytes).into_owned()
+        } else {
+            return format!("<{} bytes of {:?}>", bytes.len(), content_type);
+        };
+        truncate(text, self.max_logged)
+    }
+
+    fn redact_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.is_secret(key) {
+                        *value = serde_json::Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_json(value);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => {
+                items.iter_mut().for_each(|item| self.redact_json(item));
+            }
+            _ => {}
+        }
+    }
+
+    fn redact_form(&self, form: &str) -> String {
+        form.split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if self.is_secret(name) => format!("{}={}", name, REDACTED),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// The body back intact, and what to log about it. Only a stream that
+    /// breaks partway is an error: what was read of it is gone.
+    async fn tap(&self, headers: &HeaderMap, body: Body) -> Result<(Body, String), axum::Error> {
+        match body.size_hint().exact() {
+            Some(size) if size <= MAX_BUFFERED => {
+                let bytes = to_bytes(body, MAX_BUFFERED as usize).await?;
+                let logged = self.render(headers, &bytes);
+                Ok((Body::from(bytes), logged))
+            }
+            Some(size) => Ok((body, format!("<{} bytes, not buffered>", size))),
+            None => Ok((body, "<streamed, not buffered>".to_string())),
+        }
+    }
+}
+
+/// At most `max` bytes, cut on a character boundary
+fn truncate(mut text: String, max: usize) -> String {
+    if text.len() <= max {
+        return text;
+    }
+    let total = text.len();
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    format!("{}... ({} bytes total)", text, total)
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// The layer, only when enabled: `option_layer(BodyLogging::from_env()
+/// .map(|logging| middleware::from_fn_with_state(logging, log_bodies)))`
+pub async fn log_bodies(
+    State(logging): State<BodyLogging>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let body = match logging.tap(&parts.headers, body).await {
+        Ok((body, logged)) => {
+            tracing::info!(method = %parts.method, uri = %parts.uri, body = %logged, "Request body");
+            body
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "Request body could not be read");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    let response = next.run(Request::from_parts(parts, body)).await;
+
+    let (parts, body) = response.into_parts();
+    match logging.tap(&parts.headers, body).await {
+        Ok((body, logged)) => {
+            tracing::info!(status = parts.status.as_u16(), body = %logged, "Response body");
+            Response::from_parts(parts, body)
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Response body could not be read");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::post, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn json_headers() -> HeaderMap {
+        HeaderMap::from_iter([(header::CONTENT_TYPE, "application/json".parse().unwrap())])
+    }
+
+    #[test]
+    fn test_secrets_are_redacted_at_any_depth() {
+        let logging = BodyLogging::new(1024);
+        let body = br#"{"user":"ann","Password":"hunter2","sessions":[{"token":"abc","id":1}]}"#;
+        let logged = logging.render(&json_headers(), body);
+        assert!(
+            !logged.contains("hunter2") && !logged.contains("abc"),
+            "{}",
+            logged
+        );
+        assert!(logged.contains(r#""user":"ann""#));
+        assert!(logged.contains(r#""id":1"#));
+
+        let form = HeaderMap::from_iter([(
+            header::CONTENT_TYPE,
+            "application/x-www-form-urlencoded".parse().unwrap(),
+        )]);
+        assert_eq!(
+            logging.render(&form, b"user=ann&password=hunter2"),
+            "user=ann&password=[redacted]"
+        );
+    }
+
+    #[test]
+    fn test_long_bodies_are_cut_on_a_character_boundary() {
+        let logging = BodyLogging::new(5);
+        let text = HeaderMap::from_iter([(header::CONTENT_TYPE, "text/plain".parse().unwrap())]);
+        // 'é' is two bytes; byte 5 falls inside the third one
+        assert_eq!(
+            logging.render(&text, "ééééé".as_bytes()),
+            "éé... (10 bytes total)"
+        );
+        assert_eq!(
+            logging.render(&HeaderMap::new(), &[0xff; 3]),
+            r#"<3 bytes of "">"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bodies_reach_the_handler_and_the_client_unchanged() {
+        let app = Router::new()
+            .route("/echo", post(|body: String| async move { body }))
+            .layer(middleware::from_fn_with_state(
+                BodyLogging::new(8),
+                log_bodies,
+            ));
+
+        let small = r#"{"password":"hunter2","n":1}"#.to_string();
+        let big = "x".repeat(MAX_BUFFERED as usize + 1);
+        for body in [small, big] {
+            let request = Request::post("/echo")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.clone()))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            let echoed = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(echoed, body.as_bytes());
+        }
+    }
+}
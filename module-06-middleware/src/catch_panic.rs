@@ -0,0 +1,79 @@
+//! # Turning Panics into a JSON 500
+//!
+//! A panic in a handler unwinds through hyper's connection task, which
+//! drops the connection: the client sees "empty reply from server" and no
+//! status at all, and nothing is logged apart from the panic message on
+//! stderr, without the request it belongs to.
+//!
+//! `tower_http::catch_panic::CatchPanicLayer` catches the unwind around
+//! the inner service and asks `handle_panic` for a response instead:
+//! - `500` with the same `{"error": ..}` shape as the other errors here -
+//!   the panic message stays in the logs, it can say anything
+//! - an error log with the message. The layer sits inside `TraceLayer`, so
+//!   the line carries the request span - method, uri and request id - and
+//!   the response still gets its `x-request-id`, which is what a user
+//!   reports and what the log is searched for
+//!
+//! A panic is still a bug. This only makes it a visible, traceable one.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::any::Any;
+
+/// `CatchPanicLayer::custom(handle_panic)`
+pub fn handle_panic(payload: Box<dyn Any + Send + 'static>) -> Response {
+    // `panic!("literal")` carries a &str, `panic!("{}", x)` a String
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(non-string panic payload)");
+    tracing::error!(panic = %message, "Handler panicked");
+
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "error": "Internal server error" })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request, routing::get, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+    use tower_http::catch_panic::CatchPanicLayer;
+
+    async fn boom() -> &'static str {
+        panic!("index out of bounds: the len is 0 but the index is 3")
+    }
+
+    #[tokio::test]
+    async fn test_a_panicking_handler_gets_a_json_500() {
+        let app = Router::new()
+            .route("/ok", get(|| async { "fine" }))
+            .route("/boom", get(boom))
+            .layer(CatchPanicLayer::custom(handle_panic));
+
+        let ok = Request::get("/ok").body(Body::empty()).unwrap();
+        assert_eq!(
+            app.clone().oneshot(ok).await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        let boom = Request::get("/boom").body(Body::empty()).unwrap();
+        let response = app.oneshot(boom).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        // The panic message is logged, never sent
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "Internal server error" })
+        );
+    }
+}
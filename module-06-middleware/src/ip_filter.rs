@@ -0,0 +1,382 @@
+//! # IP Allowlist / Denylist for Route Groups
+//!
+//! An API key can leak; a network boundary is a second lock on the door.
+//! `ip_filter` lets a route group (here everything under `/admin`) answer
+//! only clients whose address is in an allowlist of CIDR ranges:
+//! - a client in `deny` is refused, even if `allow` covers it too
+//! - otherwise it must be in `allow` - an empty allowlist admits nobody
+//! - refused clients get `403` before the auth check, so outsiders don't
+//!   even learn that an API key would be accepted
+//!
+//! ## Who is the client?
+//!
+//! The peer address from `ConnectInfo` is the client only without a proxy
+//! in between. Behind one it's the proxy, and the client is in
+//! `X-Forwarded-For` - a header anyone can send. So the header is read only
+//! when the peer is one of `TRUSTED_PROXIES`, and from the right: each
+//! trusted proxy appends the address it saw, so the first entry from the
+//! right that isn't a trusted proxy is the real client. Whatever a client
+//! writes at the left end is never reached.
+//!
+//! ## Changing the lists at runtime
+//!
+//! The rules live in shared state, read on every request. `PUT
+//! /admin/ip-rules` replaces them after checking every entry (all or
+//! nothing) and refuses a change that would lock out the client making it.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use course_routes::Routes;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, RwLock},
+};
+
+/// Loopback only: the admin routes answer from this machine out of the box
+const DEFAULT_ALLOWLIST: &str = "127.0.0.0/8, ::1";
+
+// ============================================================================
+// RULES
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpRules {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    pub fn admits(&self, ip: IpAddr) -> bool {
+        // `::ffff:10.0.0.1` is 10.0.0.1 arriving on an IPv6 socket
+        let ip = ip.to_canonical();
+        let covers = |nets: &[IpNet]| nets.iter().any(|net| net.contains(&ip));
+        !covers(&self.deny) && covers(&self.allow)
+    }
+}
+
+/// The JSON form: `{"allow": ["10.0.0.0/8", "192.168.1.7"], "deny": []}`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IpRulesBody {
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl From<&IpRules> for IpRulesBody {
+    fn from(rules: &IpRules) -> Self {
+        let strings = |nets: &[IpNet]| nets.iter().map(ToString::to_string).collect();
+        Self {
+            allow: strings(&rules.allow),
+            deny: strings(&rules.deny),
+        }
+    }
+}
+
+impl TryFrom<IpRulesBody> for IpRules {
+    /// Every entry that didn't parse
+    type Error = Vec<String>;
+
+    fn try_from(body: IpRulesBody) -> Result<Self, Self::Error> {
+        let (allow, mut invalid) = parse_nets(&body.allow);
+        let (deny, invalid_deny) = parse_nets(&body.deny);
+        invalid.extend(invalid_deny);
+        if invalid.is_empty() {
+            Ok(Self { allow, deny })
+        } else {
+            Err(invalid)
+        }
+    }
+}
+
+/// `10.0.0.0/8` or a single address (`10.0.0.7` is `10.0.0.7/32`)
+fn parse_net(entry: &str) -> Option<IpNet> {
+    let entry = entry.trim();
+    entry
+        .parse()
+        .ok()
+        .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+fn parse_nets<S: AsRef<str>>(entries: &[S]) -> (Vec<IpNet>, Vec<String>) {
+    let mut nets = Vec::new();
+    let mut invalid = Vec::new();
+    for entry in entries.iter().map(AsRef::as_ref) {
+        match parse_net(entry) {
+            Some(net) => nets.push(net),
+            None => invalid.push(entry.to_string()),
+        }
+    }
+    (nets, invalid)
+}
+
+/// A comma-separated list from the environment; a typo stops startup
+/// rather than silently opening (or closing) the door
+fn nets_from_env(var: &str, default: &str) -> Vec<IpNet> {
+    let value = std::env::var(var).unwrap_or_else(|_| default.to_string());
+    let entries: Vec<&str> = value.split(',').filter(|e| !e.trim().is_empty()).collect();
+    let (nets, invalid) = parse_nets(&entries);
+    if !invalid.is_empty() {
+        panic!("{}: not an IP address or CIDR range: {:?}", var, invalid);
+    }
+    nets
+}
+
+// ============================================================================
+// SHARED STATE
+// ============================================================================
+
+/// The rules (replaceable at runtime) and the proxies trusted to report
+/// the client's address
+#[derive(Debug, Clone)]
+pub struct IpFilter {
+    rules: Arc<RwLock<IpRules>>,
+    trusted_proxies: Arc<[IpNet]>,
+}
+
+impl IpFilter {
+    pub fn new(rules: IpRules, trusted_proxies: Vec<IpNet>) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+            trusted_proxies: trusted_proxies.into(),
+        }
+    }
+
+    /// `IP_ALLOWLIST` (default loopback), `IP_DENYLIST` and
+    /// `TRUSTED_PROXIES`, each comma-separated
+    pub fn from_env() -> Self {
+        let rules = IpRules {
+            allow: nets_from_env("IP_ALLOWLIST", DEFAULT_ALLOWLIST),
+            deny: nets_from_env("IP_DENYLIST", ""),
+        };
+        Self::new(rules, nets_from_env("TRUSTED_PROXIES", ""))
+    }
+
+    pub fn rules(&self) -> IpRules {
+        self.rules.read().unwrap().clone()
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The peer, or - when the peer is a trusted proxy - the rightmost
+    /// `X-Forwarded-For` entry that isn't one
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted_proxy(peer) {
+            return peer;
+        }
+        let forwarded: Vec<&str> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect();
+        let mut client = peer;
+        for entry in forwarded.iter().rev() {
+            // Garbage where an address should be: stop at the last good one
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted_proxy(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// The layer: `route_layer(middleware::from_fn_with_state(filter, ip_filter))`
+pub async fn ip_filter(State(filter): State<IpFilter>, request: Request, next: Next) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        // Served without connect info: nobody can be vouched for
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let client = filter.client_ip(peer, request.headers());
+
+    if filter.rules.read().unwrap().admits(client) {
+        return next.run(request).await;
+    }
+    tracing::warn!(client = %client, peer = %peer, path = %request.uri().path(), "IP not allowed");
+    (
+        StatusCode::FORBIDDEN,
+        Json(serde_json::json!({ "error": "Forbidden" })),
+    )
+        .into_response()
+}
+
+// ============================================================================
+// ADMIN ENDPOINTS
+// ============================================================================
+
+/// GET /admin/ip-rules
+async fn show_rules(State(filter): State<IpFilter>) -> Json<IpRulesBody> {
+    Json(IpRulesBody::from(&filter.rules()))
+}
+
+/// PUT /admin/ip-rules - replace both lists at once
+async fn replace_rules(
+    State(filter): State<IpFilter>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(body): Json<IpRulesBody>,
+) -> Response {
+    let rules = match IpRules::try_from(body) {
+        Ok(rules) => rules,
+        Err(invalid) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "Not an IP address or CIDR range",
+                    "invalid": invalid,
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    let client = filter.client_ip(peer.ip(), &headers);
+    if !rules.admits(client) {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "error": "These rules would lock you out",
+                "client_ip": client.to_string(),
+            })),
+        )
+            .into_response();
+    }
+
+    tracing::info!(allow = ?rules.allow, deny = ?rules.deny, by = %client, "IP rules replaced");
+    let body = IpRulesBody::from(&rules);
+    *filter.rules.write().unwrap() = rules;
+    Json(body).into_response()
+}
+
+/// Mounted at `/admin/ip-rules`, inside the filter it controls
+pub fn routes<S>(filter: IpFilter) -> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Routes::new()
+        .get("/", show_rules, "Allowed/denied ranges for /admin")
+        .put("/", replace_rules, "Replace them at runtime")
+        .with_state(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn nets(entries: &[&str]) -> Vec<IpNet> {
+        entries.iter().map(|e| parse_net(e).unwrap()).collect()
+    }
+
+    fn filter(allow: &[&str], deny: &[&str], proxies: &[&str]) -> IpFilter {
+        let rules = IpRules {
+            allow: nets(allow),
+            deny: nets(deny),
+        };
+        IpFilter::new(rules, nets(proxies))
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_deny_wins_and_the_allowlist_is_required() {
+        let rules = filter(&["10.0.0.0/8", "::1"], &["10.6.6.0/24"], &[]).rules();
+        assert!(rules.admits(ip("10.1.2.3")));
+        assert!(rules.admits(ip("::1")));
+        // IPv4 seen through an IPv6 socket
+        assert!(rules.admits(ip("::ffff:10.1.2.3")));
+        assert!(!rules.admits(ip("10.6.6.6")));
+        assert!(!rules.admits(ip("192.168.1.1")));
+        assert!(!filter(&[], &[], &[]).rules().admits(ip("10.1.2.3")));
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_believed_from_trusted_proxies() {
+        let filter = filter(&[], &[], &["10.0.0.0/24"]);
+        let headers = HeaderMap::from_iter([(
+            "x-forwarded-for".parse().unwrap(),
+            // The client made up the first entry; the proxies added the rest
+            "127.0.0.1, 203.0.113.9, 10.0.0.2".parse().unwrap(),
+        )]);
+
+        assert_eq!(
+            filter.client_ip(ip("10.0.0.1"), &headers),
+            ip("203.0.113.9")
+        );
+        // Straight from the internet, the header is the client's say-so
+        assert_eq!(
+            filter.client_ip(ip("198.51.100.1"), &headers),
+            ip("198.51.100.1")
+        );
+        // A trusted proxy with nothing to report is the client
+        assert_eq!(
+            filter.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+
+    fn request(method: &str, uri: &str, peer: &str, body: Option<&str>) -> Request {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.unwrap_or_default().to_string()))
+            .unwrap();
+        let peer = SocketAddr::new(ip(peer), 40000);
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request
+    }
+
+    fn app(filter: IpFilter) -> Router {
+        Router::new()
+            .route("/admin/ping", get(|| async { "pong" }))
+            .nest("/admin/ip-rules", routes(filter.clone()).into_router())
+            .route_layer(middleware::from_fn_with_state(filter, ip_filter))
+    }
+
+    async fn status(app: &Router, request: Request) -> StatusCode {
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_rules_reload_at_runtime_without_locking_out_the_caller() {
+        let app = app(filter(&["127.0.0.1"], &[], &[]));
+        let put = |body| request("PUT", "/admin/ip-rules", "127.0.0.1", Some(body));
+        let ping = |peer| request("GET", "/admin/ping", peer, None);
+
+        assert_eq!(status(&app, ping("10.1.1.1")).await, StatusCode::FORBIDDEN);
+
+        let bad = r#"{"allow": ["127.0.0.1", "10.0.0.0/33"]}"#;
+        assert_eq!(status(&app, put(bad)).await, StatusCode::BAD_REQUEST);
+        let lockout = r#"{"allow": ["10.0.0.0/8"]}"#;
+        assert_eq!(status(&app, put(lockout)).await, StatusCode::CONFLICT);
+        let widen = r#"{"allow": ["127.0.0.1", "10.0.0.0/8"], "deny": ["10.6.6.6"]}"#;
+        assert_eq!(status(&app, put(widen)).await, StatusCode::OK);
+
+        assert_eq!(status(&app, ping("10.1.1.1")).await, StatusCode::OK);
+        assert_eq!(status(&app, ping("10.6.6.6")).await, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,126 @@
+//! # Concurrency Limits and Load Shedding
+//!
+//! Rate limits count requests per client over time; they don't stop a
+//! burst of slow requests from many clients piling up in one handler.
+//! `ConcurrencyLimitLayer` caps how many requests are *in flight*, but on
+//! its own it queues the rest: latency grows without bound and every
+//! queued client times out anyway. Shedding refuses them instead:
+//! - `GlobalConcurrencyLimitLayer::new(n)` is not ready while `n`
+//!   requests run
+//! - `LoadShedLayer` answers "not ready" with an `Overloaded` error
+//!   straight away, instead of waiting
+//! - `HandleErrorLayer::new(handle_overload)` turns that error into
+//!   `503 Service Unavailable` with `Retry-After`
+//!
+//! ```ignore
+//! ServiceBuilder::new()
+//!     .layer(HandleErrorLayer::new(handle_overload))
+//!     .layer(LoadShedLayer::new())
+//!     .layer(GlobalConcurrencyLimitLayer::new(SLOW_MAX_IN_FLIGHT))
+//! ```
+//!
+//! `Global`, because the plain `ConcurrencyLimitLayer` creates a new
+//! semaphore every time it wraps a service - and a route layer may wrap
+//! the handler more than once (per request, before `with_state`). One
+//! semaphore in the layer is one limit however often it's applied.
+//!
+//! A shed request costs almost nothing, the ones admitted keep their
+//! latency, and a client told to come back in a moment can. Module 12
+//! limits connections for the whole server (`ConcurrencyLimitLayer`
+//! without shedding, so excess requests wait); this is backpressure for
+//! one expensive route.
+//!
+//! `scripts/load-shed.sh` fires a burst at `/slow` to watch it happen.
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError, Json,
+};
+use tower::load_shed::error::Overloaded;
+
+/// `/slow` requests allowed at once; the rest are shed
+pub const SLOW_MAX_IN_FLIGHT: usize = 4;
+/// A slot frees up once an admitted `/slow` request finishes or times out
+pub const RETRY_AFTER_SECS: u64 = 2;
+
+/// `HandleErrorLayer::new(handle_overload)`, in front of a `LoadShedLayer`
+pub async fn handle_overload(error: BoxError) -> Response {
+    if error.is::<Overloaded>() {
+        tracing::warn!("Shedding a request - concurrency limit reached");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECS))],
+            Json(serde_json::json!({
+                "error": "Server busy",
+                "error_code": "SERVICE_UNAVAILABLE",
+                "retryable": true,
+                "retry_after_secs": RETRY_AFTER_SECS,
+            })),
+        )
+            .into_response();
+    }
+    tracing::error!(error = %error, "Unhandled middleware error");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "error": "Internal server error" })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body, error_handling::HandleErrorLayer, extract::Request, routing::get, Router,
+    };
+    use std::time::Duration;
+    use tower::{
+        limit::GlobalConcurrencyLimitLayer, load_shed::LoadShedLayer, ServiceBuilder, ServiceExt,
+    };
+
+    #[tokio::test]
+    async fn test_requests_past_the_limit_are_shed_with_a_503() {
+        let app = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "done"
+            })
+            .layer(
+                ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(handle_overload))
+                    .layer(LoadShedLayer::new())
+                    .layer(GlobalConcurrencyLimitLayer::new(2)),
+            ),
+        );
+
+        let burst = (0..5).map(|_| {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let request = Request::get("/slow").body(Body::empty()).unwrap();
+                app.oneshot(request).await.unwrap()
+            })
+        });
+        let mut responses = Vec::new();
+        for handle in burst.collect::<Vec<_>>() {
+            responses.push(handle.await.unwrap());
+        }
+
+        let ok = responses
+            .iter()
+            .filter(|r| r.status() == StatusCode::OK)
+            .count();
+        let shed: Vec<_> = responses
+            .iter()
+            .filter(|r| r.status() == StatusCode::SERVICE_UNAVAILABLE)
+            .collect();
+        assert_eq!(ok, 2);
+        assert_eq!(shed.len(), 3);
+        assert_eq!(shed[0].headers()[header::RETRY_AFTER], "2");
+
+        // Once the burst is over, there's room again
+        let request = Request::get("/slow").body(Body::empty()).unwrap();
+        assert_eq!(app.oneshot(request).await.unwrap().status(), StatusCode::OK);
+    }
+}
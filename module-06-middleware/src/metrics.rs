@@ -0,0 +1,253 @@
+//! # Per-Route Latency Histograms
+//!
+//! An `X-Response-Time` header tells one client how long one request took.
+//! Operating a service needs the other view: for each route, how slow is it
+//! usually (p50), and how slow for the unlucky few (p95, p99)? And how many
+//! of its answers were errors?
+//!
+//! `record_latency` still sets the header, and also records every response
+//! into shared `Metrics`, keyed by the route's `MatchedPath` (`/orders/{id}`,
+//! not `/orders/7`, so a million ids are still one row). Requests that match
+//! no route share a single `<unmatched>` row, so a scanner probing random
+//! paths can't grow the map without bound.
+//!
+//! Latencies go into a histogram: a count per fixed bucket (`<= 1ms`,
+//! `<= 2ms`, `<= 5ms`, ... `<= 10s`, and everything slower). Memory stays
+//! constant however many requests arrive, and the percentiles are estimated
+//! from the buckets by interpolating within the one the rank falls in - the
+//! same estimate Prometheus' `histogram_quantile` makes, so the numbers from
+//! `GET /metrics` line up with what module-12 exports.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of the buckets, in milliseconds; one more bucket catches
+/// everything slower
+pub const BUCKETS_MS: &[f64] = &[
+    1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+
+/// The row for requests that matched no route
+pub const UNMATCHED: &str = "<unmatched>";
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    /// One per `BUCKETS_MS` entry, plus the overflow bucket; not cumulative
+    counts: Vec<u64>,
+    count: u64,
+    sum_ms: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, ms: f64) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+
+    /// Estimated `q` quantile (0.0-1.0) in milliseconds: linear within the
+    /// bucket the rank lands in. Past the last bound there's nothing to
+    /// interpolate towards, so that bound is the answer.
+    fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = q * self.count as f64;
+        let mut below = 0;
+        for (i, &in_bucket) in self.counts.iter().enumerate() {
+            if in_bucket > 0 && (below + in_bucket) as f64 >= rank {
+                let Some(&upper) = BUCKETS_MS.get(i) else {
+                    return BUCKETS_MS[BUCKETS_MS.len() - 1];
+                };
+                let lower = if i == 0 { 0.0 } else { BUCKETS_MS[i - 1] };
+                let fraction = (rank - below as f64) / in_bucket as f64;
+                return lower + (upper - lower) * fraction;
+            }
+            below += in_bucket;
+        }
+        BUCKETS_MS[BUCKETS_MS.len() - 1]
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct RouteMetrics {
+    latency: Histogram,
+    statuses: BTreeMap<u16, u64>,
+}
+
+/// Shared by every request; one short lock per response
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    routes: Arc<Mutex<HashMap<String, RouteMetrics>>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteSummary {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    /// Responses per status code
+    pub statuses: BTreeMap<u16, u64>,
+}
+
+impl Metrics {
+    pub fn record(&self, route: &str, status: u16, elapsed: Duration) {
+        let mut routes = self.routes.lock().unwrap();
+        let metrics = routes.entry(route.to_string()).or_default();
+        metrics.latency.observe(elapsed.as_secs_f64() * 1000.0);
+        *metrics.statuses.entry(status).or_default() += 1;
+    }
+
+    /// Every route seen so far, sorted by path
+    pub fn summary(&self) -> BTreeMap<String, RouteSummary> {
+        let routes = self.routes.lock().unwrap();
+        routes
+            .iter()
+            .map(|(route, metrics)| {
+                let latency = &metrics.latency;
+                let summary = RouteSummary {
+                    count: latency.count,
+                    mean_ms: round(latency.sum_ms / latency.count as f64),
+                    p50_ms: round(latency.quantile(0.50)),
+                    p95_ms: round(latency.quantile(0.95)),
+                    p99_ms: round(latency.quantile(0.99)),
+                    statuses: metrics.statuses.clone(),
+                };
+                (route.clone(), summary)
+            })
+            .collect()
+    }
+}
+
+/// Two decimals are plenty for milliseconds
+fn round(ms: f64) -> f64 {
+    (ms * 100.0).round() / 100.0
+}
+
+// ============================================================================
+// MIDDLEWARE AND ENDPOINT
+// ============================================================================
+
+/// Times every response: `X-Response-Time` for the client, the histogram
+/// of its route for `GET /metrics`
+pub async fn record_latency(
+    State(metrics): State<Metrics>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // `Router::layer` wraps each route, so the path is already known here
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED, MatchedPath::as_str)
+        .to_string();
+    let start = Instant::now();
+    let mut response = next.run(request).await;
+    let elapsed = start.elapsed();
+
+    metrics.record(&route, response.status().as_u16(), elapsed);
+    response.headers_mut().insert(
+        "X-Response-Time",
+        HeaderValue::from_str(&format!("{}ms", elapsed.as_millis())).unwrap(),
+    );
+    response
+}
+
+/// `GET /metrics`: count, mean, p50/p95/p99 and statuses per route
+pub async fn metrics_report(
+    State(metrics): State<Metrics>,
+) -> Json<BTreeMap<String, RouteSummary>> {
+    Json(metrics.summary())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_quantiles_interpolate_within_buckets() {
+        let mut histogram = Histogram::default();
+        // 90 fast requests in (5, 10] and 10 slow ones in (250, 500]
+        for _ in 0..90 {
+            histogram.observe(7.0);
+        }
+        for _ in 0..10 {
+            histogram.observe(300.0);
+        }
+        // Rank 50 of the 90 in (5, 10]
+        assert!((histogram.quantile(0.50) - (5.0 + 5.0 * 50.0 / 90.0)).abs() < 1e-9);
+        // Rank 95 is the 5th of the 10 in (250, 500]
+        assert_eq!(histogram.quantile(0.95), 375.0);
+        assert_eq!(histogram.quantile(0.99), 475.0);
+
+        // Slower than every bound: the last bound, not a made-up number
+        let mut slow = Histogram::default();
+        slow.observe(60_000.0);
+        assert_eq!(slow.quantile(0.99), 10_000.0);
+        assert_eq!(Histogram::default().quantile(0.5), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_recorded_per_matched_path() {
+        let metrics = Metrics::default();
+        let app = Router::new()
+            .route("/orders/{id}", get(|| async { "order" }))
+            .layer(middleware::from_fn_with_state(
+                metrics.clone(),
+                record_latency,
+            ));
+
+        for uri in [
+            "/orders/1",
+            "/orders/2",
+            "/orders/3",
+            "/nope",
+            "/nope/again",
+        ] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert!(response.headers().contains_key("x-response-time"));
+        }
+
+        let summary = metrics.summary();
+        assert_eq!(
+            summary.keys().collect::<Vec<_>>(),
+            ["/orders/{id}", UNMATCHED]
+        );
+        assert_eq!(summary["/orders/{id}"].count, 3);
+        assert_eq!(summary["/orders/{id}"].statuses, BTreeMap::from([(200, 3)]));
+        assert_eq!(
+            summary[UNMATCHED].statuses,
+            BTreeMap::from([(StatusCode::NOT_FOUND.as_u16(), 2)])
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! # Rate Limiting with a Token Bucket per Client
+//!
+//! Every client IP gets a bucket of `capacity` tokens that refills evenly
+//! over `per`. A request takes one token; with none left it's answered
+//! `429 Too Many Requests` without reaching the handler. Bursts up to
+//! `capacity` are fine, and the sustained rate is `capacity / per`.
+//!
+//! The buckets are state shared by every request: a `Mutex<HashMap>` in an
+//! `Arc`, handed to the middleware with `from_fn_with_state`. Each limiter
+//! has its own map, so limits stack - a stricter one on `/login` as a
+//! `route_layer`, inside the default one on every route.
+//!
+//! Every response says where the client stands (IETF `RateLimit` draft):
+//! - `RateLimit-Limit` - the bucket size
+//! - `RateLimit-Remaining` - tokens left after this request
+//! - `RateLimit-Reset` - seconds until the bucket is full again
+//! - `Retry-After` (429 only) - seconds until the next token
+//!
+//! With stacked limiters the innermost (most specific) one's headers win.
+//!
+//! The key is the peer address from `ConnectInfo`, so serve with
+//! `into_make_service_with_connect_info::<SocketAddr>()`. Behind a proxy
+//! that's the proxy: key on a header the proxy sets instead, never on one
+//! the client can.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Past this many tracked clients, buckets that have refilled are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+static RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+static RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+static RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// Burst size: requests allowed back to back from a full bucket
+    pub capacity: u32,
+    /// Time to refill an empty bucket
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub const fn per_minute(capacity: u32) -> Self {
+        Self {
+            capacity,
+            per: Duration::from_secs(60),
+        }
+    }
+
+    /// Tokens added per second
+    fn rate(&self) -> f64 {
+        self.capacity as f64 / self.per.as_secs_f64()
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The outcome of one `check`, and what goes into the headers
+#[derive(Debug, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Until the bucket is full again
+    pub reset: Duration,
+    /// Until the next token, when `allowed` is false
+    pub retry_after: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Take a token from `client`'s bucket, if there is one. `now` is a
+    /// parameter so the boundaries can be tested without sleeping.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Decision {
+        let (capacity, rate) = (self.limit.capacity as f64, self.limit.rate());
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            // A full bucket is the same as no bucket
+            buckets.retain(|_, b| {
+                b.tokens + now.saturating_duration_since(b.updated).as_secs_f64() * rate < capacity
+            });
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let refill = now.saturating_duration_since(bucket.updated).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Decision {
+            allowed,
+            limit: self.limit.capacity,
+            remaining: bucket.tokens.floor() as u32,
+            reset: Duration::from_secs_f64((capacity - bucket.tokens) / rate),
+            retry_after: (!allowed).then(|| Duration::from_secs_f64((1.0 - bucket.tokens) / rate)),
+        }
+    }
+}
+
+/// Whole seconds, rounded up: "retry in 0s" would be a lie
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+impl Decision {
+    /// Only fills headers an inner limiter hasn't set already
+    fn write_headers(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            (&RATELIMIT_LIMIT, HeaderValue::from(self.limit)),
+            (&RATELIMIT_REMAINING, HeaderValue::from(self.remaining)),
+            (&RATELIMIT_RESET, HeaderValue::from(seconds(self.reset))),
+        ] {
+            headers.entry(name).or_insert(value);
+        }
+    }
+}
+
+fn client_ip(request: &Request) -> IpAddr {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        // Served without connect info: every client shares one bucket
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// The layer: `middleware::from_fn_with_state(limiter, rate_limit)`
+pub async fn rate_limit(
+    State(limiter): State<RateLimiter>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let decision = limiter.check(client_ip(&request), Instant::now());
+    let mut response = match decision.retry_after {
+        None => next.run(request).await,
+        Some(retry_after) => {
+            let retry_after = seconds(retry_after);
+            tracing::warn!(path = %request.uri().path(), retry_after, "Rate limit exceeded");
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, HeaderValue::from(retry_after))],
+                Json(serde_json::json!({
+                    "error": "Too many requests",
+                    "error_code": "TOO_MANY_REQUESTS",
+                    "retryable": true,
+                    "retry_after_secs": retry_after,
+                })),
+            )
+                .into_response()
+        }
+    };
+    decision.write_headers(response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    #[test]
+    fn test_a_full_bucket_allows_exactly_capacity() {
+        let limiter = RateLimiter::new(RateLimit::per_minute(3));
+        let now = Instant::now();
+
+        let remaining: Vec<_> = (0..3)
+            .map(|_| limiter.check(CLIENT, now).remaining)
+            .collect();
+        let denied = limiter.check(CLIENT, now);
+
+        assert_eq!(remaining, [2, 1, 0]);
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        // 3 per minute: one token every 20s
+        assert_eq!(denied.retry_after, Some(Duration::from_secs(20)));
+        assert_eq!(denied.reset, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_one_token_comes_back_per_interval() {
+        let limiter = RateLimiter::new(RateLimit::per_minute(3));
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.check(CLIENT, start);
+        }
+
+        let too_early = limiter.check(CLIENT, start + Duration::from_millis(19_900));
+        let refilled = limiter.check(CLIENT, start + Duration::from_secs(20));
+        let again = limiter.check(CLIENT, start + Duration::from_secs(20));
+
+        assert!(!too_early.allowed);
+        assert!(refilled.allowed);
+        assert_eq!(refilled.remaining, 0);
+        assert!(!again.allowed);
+    }
+
+    #[test]
+    fn test_clients_have_separate_buckets() {
+        let limiter = RateLimiter::new(RateLimit::per_minute(1));
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let now = Instant::now();
+
+        assert!(limiter.check(CLIENT, now).allowed);
+        assert!(!limiter.check(CLIENT, now).allowed);
+        assert!(limiter.check(other, now).allowed);
+    }
+}
@@ -0,0 +1,81 @@
+//! # Request IDs with tower-http
+//!
+//! One id per request, decided once at the very edge and then used
+//! everywhere: in the tracing span, in `RequestContext`, on outbound calls
+//! and in the response. tower-http provides the two halves:
+//! - `SetRequestIdLayer` keeps an incoming `x-request-id`, or asks a
+//!   `MakeRequestId` for one, and stores it as a header and as a `RequestId`
+//!   extension (so handlers can take `Extension<RequestId>`)
+//! - `PropagateRequestIdLayer` copies it onto the response
+//!
+//! Between them sits `TraceLayer`, whose span is built by `request_span`
+//! and so carries `request_id` on every line logged while handling the
+//! request - including the logging middleware's "Request completed".
+//!
+//! The client chooses the id when it sends one, so it's checked first:
+//! `drop_invalid_request_id` removes empty, overlong or non-printable ids
+//! and a fresh one is generated instead. A caller that only sends W3C
+//! baggage keeps its `request.id` (see `baggage.rs`).
+
+use axum::{
+    extract::Request,
+    http::{self, HeaderValue},
+};
+use tower_http::request_id::{MakeRequestId, RequestId};
+use tracing::Span;
+
+use crate::baggage::{Baggage, REQUEST_ID_HEADER, REQUEST_ID_KEY};
+
+/// Long enough for a UUID, a ULID or a trace id, short enough to log
+const MAX_LEN: usize = 128;
+
+fn is_valid(id: &[u8]) -> bool {
+    (1..=MAX_LEN).contains(&id.len()) && id.iter().all(u8::is_ascii_graphic)
+}
+
+/// `ServiceBuilder::map_request`, outside `SetRequestIdLayer`
+pub fn drop_invalid_request_id(mut request: Request) -> Request {
+    let headers = request.headers_mut();
+    if let Some(id) = headers.get(REQUEST_ID_HEADER) {
+        if !is_valid(id.as_bytes()) {
+            tracing::warn!(len = id.len(), "Replacing an invalid x-request-id");
+            headers.remove(REQUEST_ID_HEADER);
+        }
+    }
+    request
+}
+
+/// The id for a request that came without `x-request-id`: the baggage's
+/// `request.id` if it has a usable one, a new UUID otherwise
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BaggageOrUuid;
+
+impl MakeRequestId for BaggageOrUuid {
+    fn make_request_id<B>(&mut self, request: &http::Request<B>) -> Option<RequestId> {
+        let baggage = Baggage::from_headers(request.headers());
+        let id = baggage
+            .get(REQUEST_ID_KEY)
+            .filter(|id| is_valid(id.as_bytes()))
+            .and_then(|id| HeaderValue::from_str(id).ok())
+            .unwrap_or_else(|| {
+                HeaderValue::try_from(uuid::Uuid::new_v4().to_string())
+                    .expect("a UUID is a valid header value")
+            });
+        Some(RequestId::new(id))
+    }
+}
+
+/// `TraceLayer::make_span_with`: the request's span, with its id
+pub fn request_span(request: &Request) -> Span {
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or("-");
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = %request_id,
+    )
+}
@@ -0,0 +1,505 @@
+//! # Server-Side Sessions with `tower-sessions`
+//!
+//! A JWT carries its data to the client and back; a session keeps the data
+//! on the server and gives the client only an id, in a cookie. The data can
+//! change (or be thrown away) without re-issuing anything, and nothing in
+//! the cookie is readable. `SessionManagerLayer` does the plumbing:
+//! - loads the session named by the cookie before the handler runs, and
+//!   hands it over as a `Session` extractor
+//! - saves it afterwards if the handler changed it, and sets the cookie
+//!
+//! The store here lives in memory: sessions are lost on restart and not
+//! shared between instances (module 05 shows Redis for that). It is not the
+//! crate's `MemoryStore`, which only hides an expired session when that
+//! session's cookie comes back - a visitor who never returns stays in its
+//! map for good. `ExpiringMemoryStore` is the same map plus
+//! `ExpiredDeletion`, and `sweep_expired` runs it on a timer.
+//!
+//! ## Typed data
+//!
+//! `Session` is a map of JSON values by string key. `Typed<T>` gives one
+//! key a type instead: `T: SessionData` names its key, `Typed<T>` loads it
+//! (or `T::default()`), derefs to it, and `save()` writes it back. Several
+//! types can share a session without stepping on each other's keys.
+//!
+//! ## Cookie attributes
+//!
+//! Set from `SessionConfig` (`SESSION_SAME_SITE`, `SESSION_SECURE`,
+//! `SESSION_IDLE_MINS`): `SameSite` (default `Lax`), `Secure` (on in
+//! production - the cookie then only travels over HTTPS), and an idle
+//! expiry that every request pushes back. `HttpOnly` is always on.
+//!
+//! ## Regeneration
+//!
+//! `Session::cycle_id` moves the data to a new id and drops the old one.
+//! Do it whenever a session gains privileges (login): an id an attacker
+//! planted beforehand (session fixation) is then worth nothing.
+//! `Session::flush` ends the session and removes the cookie.
+
+use axum::{
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use course_routes::Routes;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tower_sessions::{
+    cookie::{
+        time::{Duration, OffsetDateTime},
+        SameSite,
+    },
+    session::{Id, Record},
+    session_store::{self, ExpiredDeletion, SessionStore},
+    Expiry, Session, SessionManagerLayer,
+};
+
+pub const SESSION_COOKIE: &str = "sid";
+/// How often `sweep_expired` clears out sessions nobody came back for
+pub const SWEEP_EVERY: std::time::Duration = std::time::Duration::from_secs(60);
+
+// ============================================================================
+// CONFIGURATION
+// ============================================================================
+
+#[derive(Debug, Clone, Copy)]
+pub struct SessionConfig {
+    pub same_site: SameSite,
+    pub secure: bool,
+    /// The session ends after this long without a request
+    pub idle: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            same_site: SameSite::Lax,
+            secure: false,
+            idle: Duration::minutes(30),
+        }
+    }
+}
+
+impl SessionConfig {
+    /// `SESSION_SAME_SITE` (strict | lax | none), `SESSION_SECURE` (true |
+    /// false) and `SESSION_IDLE_MINS`; defaults suit http://localhost
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let same_site = match std::env::var("SESSION_SAME_SITE").as_deref() {
+            Ok("strict") => SameSite::Strict,
+            Ok("none") => SameSite::None,
+            _ => default.same_site,
+        };
+        let secure = std::env::var("SESSION_SECURE").is_ok_and(|v| v == "true");
+        let idle = std::env::var("SESSION_IDLE_MINS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::minutes)
+            .unwrap_or(default.idle);
+        Self {
+            same_site,
+            secure,
+            idle,
+        }
+        .checked()
+    }
+
+    /// Browsers drop `SameSite=None` cookies that aren't `Secure`
+    fn checked(mut self) -> Self {
+        if self.same_site == SameSite::None && !self.secure {
+            tracing::warn!("SameSite=None needs Secure - turning Secure on");
+            self.secure = true;
+        }
+        self
+    }
+
+    pub fn layer(&self, store: ExpiringMemoryStore) -> SessionManagerLayer<ExpiringMemoryStore> {
+        SessionManagerLayer::new(store)
+            .with_name(SESSION_COOKIE)
+            .with_http_only(true)
+            .with_same_site(self.same_site)
+            .with_secure(self.secure)
+            .with_path("/")
+            .with_expiry(Expiry::OnInactivity(self.idle))
+    }
+}
+
+// ============================================================================
+// STORE
+// ============================================================================
+
+/// Sessions in a `HashMap`, with expired ones deleted by `sweep_expired`
+#[derive(Debug, Clone, Default)]
+pub struct ExpiringMemoryStore(Arc<Mutex<HashMap<Id, Record>>>);
+
+fn is_active(record: &Record) -> bool {
+    record.expiry_date > OffsetDateTime::now_utc()
+}
+
+#[async_trait::async_trait]
+impl SessionStore for ExpiringMemoryStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        let mut sessions = self.0.lock().unwrap();
+        // Ids are random; on the rare collision, draw another
+        while sessions.contains_key(&record.id) {
+            record.id = Id::default();
+        }
+        sessions.insert(record.id, record.clone());
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.0.lock().unwrap().insert(record.id, record.clone());
+        Ok(())
+    }
+
+    async fn load(&self, id: &Id) -> session_store::Result<Option<Record>> {
+        let mut sessions = self.0.lock().unwrap();
+        match sessions.get(id) {
+            Some(record) if is_active(record) => Ok(Some(record.clone())),
+            // Expired: gone now rather than at the next sweep
+            Some(_) => {
+                sessions.remove(id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, id: &Id) -> session_store::Result<()> {
+        self.0.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl ExpiredDeletion for ExpiringMemoryStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        self.0.lock().unwrap().retain(|_, record| is_active(record));
+        Ok(())
+    }
+}
+
+/// Background task: delete expired sessions every `every`
+pub async fn sweep_expired(store: ExpiringMemoryStore, every: std::time::Duration) {
+    let mut ticks = tokio::time::interval(every);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if let Err(e) = store.delete_expired().await {
+            tracing::warn!(error = %e, "Session sweep failed");
+        }
+    }
+}
+
+// ============================================================================
+// TYPED SESSION DATA
+// ============================================================================
+
+/// A type stored in the session under its own key
+pub trait SessionData: Serialize + DeserializeOwned + Default + Send + Sync {
+    const KEY: &'static str;
+}
+
+/// `T` from the session (or `T::default()`); `save` writes it back
+pub struct Typed<T> {
+    session: Session,
+    data: T,
+}
+
+impl<T: SessionData> Typed<T> {
+    pub async fn save(&self) -> Result<(), SessionError> {
+        Ok(self.session.insert(T::KEY, &self.data).await?)
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl<T> Deref for Typed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T> DerefMut for Typed<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+impl<S, T> FromRequestParts<S> for Typed<T>
+where
+    S: Send + Sync,
+    T: SessionData,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        // A value that no longer deserializes (the type changed) starts over
+        let data = match session.get::<T>(T::KEY).await {
+            Ok(data) => data.unwrap_or_default(),
+            Err(tower_sessions::session::Error::SerdeJson(_)) => T::default(),
+            Err(e) => return Err(SessionError(e).into_response()),
+        };
+        Ok(Self { session, data })
+    }
+}
+
+/// The store failed; the client can't fix that
+#[derive(Debug)]
+pub struct SessionError(tower_sessions::session::Error);
+
+impl From<tower_sessions::session::Error> for SessionError {
+    fn from(error: tower_sessions::session::Error) -> Self {
+        Self(error)
+    }
+}
+
+impl IntoResponse for SessionError {
+    fn into_response(self) -> Response {
+        tracing::error!(error = %self.0, "Session store error");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": "Session unavailable" })),
+        )
+            .into_response()
+    }
+}
+
+// ============================================================================
+// LOGIN-LESS DEMO: A VISIT COUNTER
+// ============================================================================
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Visits {
+    count: u64,
+    first_visit: u64,
+    last_visit: u64,
+}
+
+impl SessionData for Visits {
+    const KEY: &'static str = "visits";
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn describe(visits: &Typed<Visits>) -> serde_json::Value {
+    serde_json::json!({
+        "visits": visits.count,
+        "first_visit": visits.first_visit,
+        "last_visit": visits.last_visit,
+        "expires_in_secs": visits.session().expiry_age().whole_seconds(),
+    })
+}
+
+/// GET /session/visits - counts this browser's visits
+async fn count_visit(mut visits: Typed<Visits>) -> Result<Json<serde_json::Value>, SessionError> {
+    let now = unix_now();
+    if visits.count == 0 {
+        visits.first_visit = now;
+    }
+    visits.count += 1;
+    visits.last_visit = now;
+    visits.save().await?;
+    Ok(Json(describe(&visits)))
+}
+
+/// POST /session/regenerate - same data, new id (what login should do)
+async fn regenerate(visits: Typed<Visits>) -> Result<Json<serde_json::Value>, SessionError> {
+    visits.session().cycle_id().await?;
+    Ok(Json(describe(&visits)))
+}
+
+/// DELETE /session - forget everything and remove the cookie
+async fn end_session(session: Session) -> Result<StatusCode, SessionError> {
+    session.flush().await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Mounted at `/session`, with its own session layer over `store`
+pub fn routes<S>(config: SessionConfig, store: ExpiringMemoryStore) -> Routes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Routes::new()
+        .delete("/", end_session, "End the session")
+        .get(
+            "/visits",
+            count_visit,
+            "Visit counter kept in a server-side session",
+        )
+        .post("/regenerate", regenerate, "New session id, same data")
+        .layer(config.layer(store))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header, HeaderMap},
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new().nest(
+            "/session",
+            routes(SessionConfig::default(), ExpiringMemoryStore::default()).into_router(),
+        )
+    }
+
+    /// The `sid` cookie a response set, if any
+    fn session_cookie(headers: &HeaderMap) -> Option<String> {
+        headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find(|v| v.starts_with("sid="))
+            .map(str::to_string)
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        cookie: Option<&str>,
+    ) -> (StatusCode, Option<String>, serde_json::Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let (status, set_cookie) = (response.status(), session_cookie(response.headers()));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+        (status, set_cookie, json)
+    }
+
+    /// `sid=...` out of a `Set-Cookie` header
+    fn pair(set_cookie: &str) -> &str {
+        set_cookie.split(';').next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_visits_are_counted_per_session() {
+        let app = app();
+        let (status, set_cookie, first) = send(&app, "GET", "/session/visits", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["visits"], 1);
+        let set_cookie = set_cookie.unwrap();
+        for attribute in ["HttpOnly", "SameSite=Lax", "Path=/", "Max-Age="] {
+            assert!(set_cookie.contains(attribute), "{}", set_cookie);
+        }
+
+        let cookie = pair(&set_cookie);
+        let (_, _, second) = send(&app, "GET", "/session/visits", Some(cookie)).await;
+        assert_eq!(second["visits"], 2);
+        // A browser without the cookie is somebody else
+        let (_, _, other) = send(&app, "GET", "/session/visits", None).await;
+        assert_eq!(other["visits"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_regeneration_keeps_the_data_under_a_new_id() {
+        let app = app();
+        let (_, set_cookie, _) = send(&app, "GET", "/session/visits", None).await;
+        let old = set_cookie.unwrap();
+        let old = pair(&old);
+
+        let (_, set_cookie, _) = send(&app, "POST", "/session/regenerate", Some(old)).await;
+        let new = set_cookie.unwrap();
+        let new = pair(&new);
+        assert_ne!(old, new);
+
+        let (_, _, visits) = send(&app, "GET", "/session/visits", Some(new)).await;
+        assert_eq!(visits["visits"], 2);
+        // The old id is dead: whoever still has it starts from scratch
+        let (_, _, visits) = send(&app, "GET", "/session/visits", Some(old)).await;
+        assert_eq!(visits["visits"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_ending_the_session_removes_the_cookie() {
+        let app = app();
+        let (_, set_cookie, _) = send(&app, "GET", "/session/visits", None).await;
+        let set_cookie = set_cookie.unwrap();
+        let cookie = pair(&set_cookie);
+
+        let (status, removal, _) = send(&app, "DELETE", "/session", Some(cookie)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(removal.unwrap().contains("Max-Age=0"));
+
+        let (_, _, visits) = send(&app, "GET", "/session/visits", Some(cookie)).await;
+        assert_eq!(visits["visits"], 1);
+    }
+
+    fn record(expires_in: Duration) -> Record {
+        Record {
+            id: Id::default(),
+            data: HashMap::new(),
+            expiry_date: OffsetDateTime::now_utc() + expires_in,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions_are_swept_not_kept_forever() {
+        let store = ExpiringMemoryStore::default();
+        let live = record(Duration::minutes(30));
+        for expired in [record(-Duration::minutes(1)), record(-Duration::hours(2))] {
+            store.save(&expired).await.unwrap();
+        }
+        store.save(&live).await.unwrap();
+        assert_eq!(store.0.lock().unwrap().len(), 3);
+
+        store.delete_expired().await.unwrap();
+        let left: Vec<Id> = store.0.lock().unwrap().keys().copied().collect();
+        assert_eq!(left, vec![live.id]);
+    }
+
+    #[tokio::test]
+    async fn test_loading_an_expired_session_drops_it() {
+        let store = ExpiringMemoryStore::default();
+        let expired = record(-Duration::seconds(1));
+        store.save(&expired).await.unwrap();
+
+        assert_eq!(store.load(&expired.id).await.unwrap(), None);
+        assert!(store.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_the_sweeper_runs_on_its_timer() {
+        let store = ExpiringMemoryStore::default();
+        store.save(&record(-Duration::minutes(1))).await.unwrap();
+        tokio::spawn(sweep_expired(store.clone(), SWEEP_EVERY));
+
+        tokio::time::sleep(SWEEP_EVERY / 2).await;
+        assert_eq!(store.0.lock().unwrap().len(), 1);
+        tokio::time::sleep(SWEEP_EVERY).await;
+        assert!(store.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+//! # Ad-hoc Errors with anyhow
+//!
+//! The `thiserror` enum in `main.rs` is a contract: every variant has a
+//! status, a code and a log level, and clients branch on them. That is the
+//! right tool for errors a client can act on. It is a lot of ceremony for
+//! the rest - a config value that doesn't parse, a file that isn't there -
+//! where the only possible answer is a 500 and the only reader is whoever
+//! reads the logs.
+//!
+//! `AppError` here wraps an `anyhow::Error`, and the blanket `From` impl
+//! lets `?` convert *any* error into it:
+//!
+//! ```ignore
+//! async fn handler() -> Result<Json<Setting>, anyhow_error::AppError> {
+//!     let value: u64 = raw.parse().context("timeout_ms is not a number")?;
+//!     let limits: Limits = serde_json::from_str(&text)?;
+//!     // ...
+//! }
+//! ```
+//!
+//! Both patterns mix: an error that started as the typed `crate::AppError`
+//! is found again with `downcast` and keeps its own status and code, so a
+//! handler can return this type and still answer `404 USER_NOT_FOUND`.
+//! Everything else is a `500 INTERNAL`: the outermost context as the
+//! message, the rest of the chain in `causes` and anyhow's backtrace - which
+//! `sanitize.rs` drops in production, like any other error body.
+//!
+//! Which to use:
+//! - a client needs to tell this error apart (status, code, retry): a
+//!   `crate::AppError` variant
+//! - only an operator will ever read it: `?` it into this one, with
+//!   `.context(...)` saying what was being done
+
+use anyhow::Context;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{new_error_id, AppPath, ErrorBody};
+
+/// Any error, answered as a 500 unless it is a typed `crate::AppError`
+#[derive(Debug)]
+pub struct AppError(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for AppError {
+    fn from(error: E) -> Self {
+        AppError(error.into())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let error = match self.0.downcast::<crate::AppError>() {
+            Ok(typed) => return typed.into_response(),
+            Err(error) => error,
+        };
+        let error_id = new_error_id();
+        let causes: Vec<String> = error.chain().skip(1).map(|e| e.to_string()).collect();
+        // Captured by anyhow where the error was created, if RUST_BACKTRACE=1
+        let backtrace = error.backtrace().to_string();
+        tracing::error!(error_id, ?causes, backtrace, "Unhandled error: {}", error);
+
+        ErrorBody {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            code: "INTERNAL",
+            title: "Internal server error",
+            message: error.to_string(),
+            retryable: false,
+            context: Default::default(),
+            error_id,
+            causes,
+            // anyhow's own `Debug` would repeat the backtrace
+            debug: format!("{:?}", error.root_cause()),
+            backtrace,
+        }
+        .into_response()
+    }
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+/// Raw settings, as they'd come from a file or the environment
+const RAW_SETTINGS: &[(&str, &str)] = &[
+    ("max_connections", "32"),
+    ("timeout_ms", "2500"),
+    ("retries", "three"),
+    ("pool_size", "0"),
+];
+
+#[derive(Serialize)]
+pub struct Setting {
+    name: String,
+    value: u64,
+}
+
+/// GET /settings/{name} - `?` on a typed error, a std error and `anyhow!`
+pub async fn get_setting(AppPath(name): AppPath<String>) -> Result<Json<Setting>, AppError> {
+    let raw = RAW_SETTINGS
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, raw)| *raw)
+        // Typed: the client asked for something that isn't there
+        .ok_or_else(|| crate::AppError::InvalidInput(format!("Unknown setting: {}", name)))?;
+
+    // Ad-hoc: a broken setting is our problem, not the client's
+    let value: u64 = raw
+        .parse()
+        .with_context(|| format!("Setting {} is not a number ({:?})", name, raw))?;
+    if value == 0 {
+        // `ensure!`/`bail!` return an `anyhow::Error`, not this type
+        return Err(anyhow::anyhow!("Setting {} must be positive", name).into());
+    }
+
+    Ok(Json(Setting { name, value }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, tests::message, Environment};
+    use axum::{body::Body, extract::Request};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn get(env: Environment, uri: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app(env).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ad_hoc_errors_are_500s_with_their_context() {
+        let (status, json) = get(Environment::Dev, "/settings/retries").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(json["error_code"], "INTERNAL");
+        assert_eq!(
+            message(&json),
+            r#"Setting retries is not a number ("three")"#
+        );
+        assert_eq!(
+            json["causes"],
+            serde_json::json!(["invalid digit found in string"])
+        );
+
+        let (status, json) = get(Environment::Dev, "/settings/pool_size").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(message(&json), "Setting pool_size must be positive");
+
+        // Production keeps the contract and drops the details
+        let (_, json) = get(Environment::Prod, "/settings/retries").await;
+        assert_eq!(message(&json), "Internal Server Error");
+        assert_eq!(json["error_code"], "INTERNAL");
+        assert!(json.get("debug").is_none(), "{}", json);
+    }
+
+    #[tokio::test]
+    async fn test_typed_errors_keep_their_status_and_code() {
+        let (status, json) = get(Environment::Dev, "/settings/colour").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["error_code"], "INVALID_INPUT");
+        assert_eq!(message(&json), "Invalid input: Unknown setting: colour");
+
+        let (status, json) = get(Environment::Dev, "/settings/timeout_ms").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            json,
+            serde_json::json!({ "name": "timeout_ms", "value": 2500 })
+        );
+    }
+}
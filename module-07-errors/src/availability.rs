@@ -0,0 +1,307 @@
+//! # Rate Limits and Maintenance, as AppErrors
+//!
+//! A 429 or a 503 is the one error that tells the client exactly what to
+//! do: wait, then try again. `AppError::TooManyRequests { retry_after }` and
+//! `AppError::ServiceUnavailable { retry_after }` carry the wait in seconds;
+//! their responses have a `Retry-After` header, `retryable: true` and the
+//! same number again as the `retry_after_secs` extension member:
+//!
+//! ```json
+//! {
+//!   "type": "https://example.com/problems/too-many-requests",
+//!   "title": "Too many requests",
+//!   "status": 429,
+//!   "detail": "Too many requests, retry in 42s",
+//!   "error_code": "TOO_MANY_REQUESTS",
+//!   "retryable": true,
+//!   "retry_after_secs": 42
+//! }
+//! ```
+//!
+//! Middleware returns them just like a handler would - a
+//! `Result<Response, AppError>` is a response too - so a request turned
+//! away before its handler looks like any other error:
+//! - `rate_limit`: a fixed window over the report routes, shared by every
+//!   client
+//! - `maintenance_mode`: while the switch is on, a 503 for everything but
+//!   `/health`, `/ready`, `/metrics` and `/admin`
+//!
+//! module-06's rate limiter and load shedder and module-12's maintenance
+//! switch answer with the same `error_code`, `retryable` and
+//! `retry_after_secs`, so a client handles a throttled request the same way
+//! whichever of them sent it.
+//!
+//! The switch is module-12's too: `PUT /admin/maintenance` takes
+//! `{"enabled": true, "notice": {"message": "...", "retry_after_secs": 60}}`
+//! and answers with the new state, `GET` reads it back. The 503's `detail`
+//! comes from the locale catalogs like every other error's, so here the
+//! notice's `message` is only for whoever reads `GET /admin/maintenance`.
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{AppError, AppJson};
+
+/// Served whatever the maintenance switch says
+const EXEMPT_PREFIXES: &[&str] = &["/health", "/ready", "/metrics", "/admin"];
+
+/// At most `limit` requests per `window`, counted for everybody together
+#[derive(Clone)]
+pub struct FixedWindow {
+    limit: u32,
+    window: Duration,
+    /// When the current window started, and the requests it has seen
+    current: Arc<Mutex<(Instant, u32)>>,
+}
+
+impl FixedWindow {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            current: Arc::new(Mutex::new((Instant::now(), 0))),
+        }
+    }
+
+    /// Counts a request, or says how many seconds until the next window
+    fn acquire(&self, now: Instant) -> Result<(), u64> {
+        let mut current = self.current.lock().unwrap();
+        let (started, count) = &mut *current;
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+        if *count < self.limit {
+            *count += 1;
+            return Ok(());
+        }
+        let left = self.window - now.duration_since(*started);
+        // Rounded up: retrying on the dot must not land in the old window
+        Err(left.as_secs() + u64::from(left.subsec_nanos() > 0))
+    }
+}
+
+/// `middleware::from_fn_with_state(window, rate_limit)`, as a route layer
+pub async fn rate_limit(
+    State(window): State<FixedWindow>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    window
+        .acquire(Instant::now())
+        .map_err(|retry_after| AppError::TooManyRequests { retry_after })?;
+    Ok(next.run(request).await)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Notice {
+    pub message: String,
+    /// How long clients are told to wait (`Retry-After`)
+    pub retry_after_secs: u64,
+}
+
+impl Default for Notice {
+    fn default() -> Self {
+        Self {
+            message: "Down for scheduled maintenance".to_string(),
+            retry_after_secs: 300,
+        }
+    }
+}
+
+/// The maintenance switch, shared by the middleware and the admin routes
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    enabled: AtomicBool,
+    notice: RwLock<Notice>,
+}
+
+impl Maintenance {
+    fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// The notice is replaced before the switch flips, so no request sees
+    /// the new state with the old notice
+    fn set(&self, enabled: bool, notice: Option<Notice>) {
+        if let Some(notice) = notice {
+            *self.notice.write().unwrap() = notice;
+        }
+        self.enabled.store(enabled, Ordering::SeqCst);
+        tracing::warn!(enabled, "Maintenance mode switched");
+    }
+
+    fn status(&self) -> MaintenanceStatus {
+        MaintenanceStatus {
+            enabled: self.is_enabled(),
+            notice: self.notice.read().unwrap().clone(),
+        }
+    }
+}
+
+/// Probes keep answering, or an orchestrator restarts the instance; `/admin`
+/// too, or the switch could never be turned off again
+fn is_exempt(path: &str) -> bool {
+    EXEMPT_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// `middleware::from_fn_with_state(maintenance, maintenance_mode)`
+pub async fn maintenance_mode(
+    State(maintenance): State<Arc<Maintenance>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if maintenance.is_enabled() && !is_exempt(request.uri().path()) {
+        let retry_after = maintenance.notice.read().unwrap().retry_after_secs;
+        return Err(AppError::ServiceUnavailable { retry_after });
+    }
+    Ok(next.run(request).await)
+}
+
+#[derive(Debug, Serialize)]
+pub struct MaintenanceStatus {
+    enabled: bool,
+    notice: Notice,
+}
+
+#[derive(Deserialize)]
+pub struct SetMaintenance {
+    enabled: bool,
+    /// Replaces the current notice; kept as it is when absent
+    notice: Option<Notice>,
+}
+
+/// GET /admin/maintenance
+pub(crate) async fn get_maintenance(
+    State(maintenance): State<Arc<Maintenance>>,
+) -> Json<MaintenanceStatus> {
+    Json(maintenance.status())
+}
+
+/// PUT /admin/maintenance - `{"enabled": true, "notice": {...}}`
+pub(crate) async fn set_maintenance(
+    State(maintenance): State<Arc<Maintenance>>,
+    AppJson(input): AppJson<SetMaintenance>,
+) -> Json<MaintenanceStatus> {
+    maintenance.set(input.enabled, input.notice);
+    Json(maintenance.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, Environment};
+    use axum::{body::Body, http::StatusCode, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, request: Request) -> Response {
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn json(response: Response) -> serde_json::Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn test_a_window_admits_limit_requests_then_says_when_to_retry() {
+        let window = FixedWindow::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+        assert_eq!(window.acquire(start), Ok(()));
+        assert_eq!(window.acquire(start), Ok(()));
+        assert_eq!(window.acquire(start + Duration::from_millis(500)), Err(60));
+        assert_eq!(window.acquire(start + Duration::from_secs(59)), Err(1));
+        // A new window starts from nothing
+        assert_eq!(window.acquire(start + Duration::from_secs(60)), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_reports_get_a_429_with_retry_after() {
+        let app = app(Environment::Prod);
+        for _ in 0..crate::REPORTS_PER_MINUTE {
+            let request = Request::get("/reports/monthly")
+                .body(Body::empty())
+                .unwrap();
+            assert_eq!(
+                send(&app, request).await.status(),
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+
+        let request = Request::get("/reports/export").body(Body::empty()).unwrap();
+        let response = send(&app, request).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = response.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let json = json(response).await;
+        assert_eq!(json["error_code"], "TOO_MANY_REQUESTS");
+        assert_eq!(json["retryable"], true);
+        assert_eq!(json["retry_after_secs"].to_string(), retry_after);
+
+        // Other routes aren't limited
+        let request = Request::get("/users/1").body(Body::empty()).unwrap();
+        assert_eq!(send(&app, request).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode_answers_503_except_for_admin() {
+        let app = app(Environment::Dev);
+        let switch = |body: &'static str| {
+            Request::put("/admin/maintenance")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let get_user = || Request::get("/users/1").body(Body::empty()).unwrap();
+
+        let on = r#"{"enabled": true, "notice": {"message": "Upgrading", "retry_after_secs": 60}}"#;
+        let response = send(&app, switch(on)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let status = json(response).await;
+        assert_eq!(status["enabled"], true);
+        assert_eq!(status["notice"]["message"], "Upgrading");
+
+        let response = send(&app, get_user()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "60");
+        let json_body = json(response).await;
+        assert_eq!(json_body["error_code"], "SERVICE_UNAVAILABLE");
+        assert_eq!(json_body["retry_after_secs"], 60);
+
+        // The switch itself still answers, and keeps the notice
+        let response = send(&app, switch(r#"{"enabled": false}"#)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let status = json(response).await;
+        assert_eq!(status["enabled"], false);
+        assert_eq!(status["notice"]["retry_after_secs"], 60);
+        assert_eq!(send(&app, get_user()).await.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_the_same_routes_are_exempt_as_in_module_12() {
+        for path in ["/health", "/ready", "/metrics", "/admin/maintenance"] {
+            assert!(is_exempt(path), "{}", path);
+        }
+        for path in ["/healthz", "/users/1", "/administrator"] {
+            assert!(!is_exempt(path), "{}", path);
+        }
+    }
+}
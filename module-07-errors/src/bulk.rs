@@ -0,0 +1,182 @@
+//! # Bulk Results: One Status per Item
+//!
+//! A bulk endpoint can't answer with one status: three items were created,
+//! one was invalid, one hit a database error. Borrowing the idea of WebDAV's
+//! `207 Multi-Status`, the answer is an envelope with a status per item:
+//!
+//! ```json
+//! {
+//!   "summary": { "total": 3, "succeeded": 2, "failed": 1 },
+//!   "results": [
+//!     { "index": 0, "status": 201, "id": "100", "data": { ... } },
+//!     { "index": 1, "status": 400, "error": "Invalid input: ...",
+//!       "error_code": "INVALID_INPUT", "retryable": false },
+//!     { "index": 2, "status": 201, "id": "102", "data": { ... } }
+//!   ]
+//! }
+//! ```
+//!
+//! Item errors carry the same `error`/`error_code`/`retryable` contract as a
+//! single-item error body, so clients branch on them the same way. The
+//! status of the whole response only says how the batch went:
+//! - `200` - every item succeeded (or there were none)
+//! - `207` - some succeeded, some failed: read `results`
+//! - `400` - every item failed
+//!
+//! `results` is in request order, and `index` points back into the request,
+//! so a client can retry exactly the items marked `retryable`.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::AppError;
+
+#[derive(Debug, Serialize)]
+pub struct BulkResponse<T> {
+    pub summary: BulkSummary,
+    pub results: Vec<BulkItem<T>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BulkSummary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkItem<T> {
+    /// Position of the item in the request
+    pub index: usize,
+    pub status: u16,
+    /// The resource's id, when the item got far enough to have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retryable: Option<bool>,
+}
+
+impl<T> Default for BulkResponse<T> {
+    fn default() -> Self {
+        Self {
+            summary: BulkSummary::default(),
+            results: Vec::new(),
+        }
+    }
+}
+
+impl<T: Serialize> BulkResponse<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the next item as a success
+    pub fn succeeded(&mut self, status: StatusCode, id: impl ToString, data: T) {
+        self.summary.succeeded += 1;
+        self.push(BulkItem {
+            index: self.results.len(),
+            status: status.as_u16(),
+            id: Some(id.to_string()),
+            data: Some(data),
+            error: None,
+            error_code: None,
+            retryable: None,
+        });
+    }
+
+    /// Record the next item as a failure. It's logged like any `AppError`.
+    pub fn failed(&mut self, id: Option<String>, error: AppError) {
+        error.log();
+        self.summary.failed += 1;
+        self.push(BulkItem {
+            index: self.results.len(),
+            status: error.status().as_u16(),
+            id,
+            data: None,
+            error: Some(error.to_string()),
+            error_code: Some(error.code()),
+            retryable: Some(error.retryable()),
+        });
+    }
+
+    fn push(&mut self, item: BulkItem<T>) {
+        self.summary.total += 1;
+        self.results.push(item);
+    }
+
+    /// 200 all ok, 207 mixed, 400 all failed
+    pub fn status(&self) -> StatusCode {
+        match (self.summary.succeeded, self.summary.failed) {
+            (_, 0) => StatusCode::OK,
+            (0, _) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::MULTI_STATUS,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for BulkResponse<T> {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An envelope with one item per outcome: `true` succeeded
+    fn bulk(outcomes: &[bool]) -> BulkResponse<u32> {
+        let mut bulk = BulkResponse::new();
+        for &ok in outcomes {
+            if ok {
+                bulk.succeeded(StatusCode::CREATED, 1, 1);
+            } else {
+                bulk.failed(None, AppError::InvalidInput("no".to_string()));
+            }
+        }
+        bulk
+    }
+
+    #[test]
+    fn test_overall_status_follows_the_items() {
+        assert_eq!(bulk(&[true, true]).status(), StatusCode::OK);
+        assert_eq!(bulk(&[true, false]).status(), StatusCode::MULTI_STATUS);
+        assert_eq!(bulk(&[false, false]).status(), StatusCode::BAD_REQUEST);
+        assert_eq!(bulk(&[]).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_items_keep_request_order_and_error_contract() {
+        let mut bulk = BulkResponse::new();
+        bulk.succeeded(StatusCode::CREATED, 7, "seven");
+        bulk.failed(
+            Some("8".to_string()),
+            AppError::DatabaseError("timeout".to_string()),
+        );
+
+        let json = serde_json::to_value(&bulk).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "summary": { "total": 2, "succeeded": 1, "failed": 1 },
+                "results": [
+                    { "index": 0, "status": 201, "id": "7", "data": "seven" },
+                    { "index": 1, "status": 500, "id": "8",
+                      "error": "Database error: timeout",
+                      "error_code": "DATABASE_ERROR", "retryable": true }
+                ]
+            })
+        );
+    }
+}
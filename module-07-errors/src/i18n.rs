@@ -0,0 +1,318 @@
+//! # Localized Error Messages
+//!
+//! `error_code` is for programs and never changes. `title` and `detail` are
+//! for people, and people read their own language. Each locale has a
+//! message catalog in `locales/<locale>.json`, keyed by error code:
+//!
+//! ```json
+//! { "USER_NOT_FOUND": { "title": "Utilisateur introuvable", "detail": "Utilisateur introuvable : {0}" } }
+//! ```
+//!
+//! `{0}` and `{retry_after}` are filled in from the error, the way
+//! thiserror fills in its `#[error(...)]` messages. The values themselves
+//! aren't translated: an invalid field's message or a path stays as it is.
+//!
+//! The `Locale` extractor picks the first tag of `Accept-Language` that has
+//! a catalog, like module-03's. The `localize` middleware extracts it and
+//! keeps it in a task-local for the rest of the request, because
+//! `IntoResponse` has no access to the request. The fallback rules:
+//! - `fr-CH` is served by `fr` when there is no `fr-CH` catalog
+//! - No acceptable locale, or no header at all: `en`
+//! - A code missing from the chosen catalog: the `en` message
+//!
+//! Logs stay in English whatever the client reads.
+
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, convert::Infallible, sync::LazyLock};
+
+/// Served when nothing the client accepts has a catalog
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Every catalog, read once
+pub static CATALOGS: LazyLock<Catalogs> = LazyLock::new(|| {
+    Catalogs::parse(&[
+        ("en", include_str!("../locales/en.json")),
+        ("fr", include_str!("../locales/fr.json")),
+    ])
+});
+
+#[derive(Debug, Deserialize)]
+pub struct Message {
+    pub title: String,
+    /// With `{0}`-style placeholders
+    pub detail: String,
+}
+
+/// Locale, then error code, to message
+pub struct Catalogs(HashMap<&'static str, HashMap<String, Message>>);
+
+impl Catalogs {
+    fn parse(sources: &[(&'static str, &str)]) -> Self {
+        let catalogs = sources
+            .iter()
+            .map(|(locale, json)| {
+                let catalog = serde_json::from_str(json)
+                    .unwrap_or_else(|e| panic!("locales/{}.json: {}", locale, e));
+                (*locale, catalog)
+            })
+            .collect();
+        Catalogs(catalogs)
+    }
+
+    /// The message for `code` in `locale`, else in the default locale,
+    /// with the locale it is in
+    pub fn message(&self, locale: &str, code: &str) -> Option<(&'static str, &Message)> {
+        [locale, DEFAULT_LOCALE].into_iter().find_map(|locale| {
+            let (&locale, catalog) = self.0.get_key_value(locale)?;
+            Some((locale, catalog.get(code)?))
+        })
+    }
+
+    /// The first requested tag with a catalog: exact match first, then the
+    /// primary language ("fr-ch" -> "fr"), then the default
+    fn negotiate(&self, requested: &[String]) -> &'static str {
+        for tag in requested {
+            if tag == "*" {
+                break;
+            }
+            let primary = tag.split('-').next().unwrap_or(tag);
+            let found = [tag.as_str(), primary]
+                .into_iter()
+                .find_map(|candidate| self.0.get_key_value(candidate));
+            if let Some((&locale, _)) = found {
+                return locale;
+            }
+        }
+        DEFAULT_LOCALE
+    }
+}
+
+/// Parse `Accept-Language` into tags ordered by preference
+///
+/// `fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5` -> ["fr-ch", "fr", "en", "*"]
+/// Entries with `q=0` mean "not acceptable" and are dropped, and so are
+/// entries whose parameters don't parse: a broken `q` isn't a `q=1`.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.trim().split(';');
+            let tag = pieces.next()?.trim().to_lowercase();
+            let mut q = 1.0;
+            for param in pieces.filter(|p| !p.trim().is_empty()) {
+                let (name, value) = param.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = parse_qvalue(value.trim())?;
+                }
+            }
+            (!tag.is_empty() && q > 0.0).then_some((tag, q))
+        })
+        .collect();
+
+    // Stable sort keeps header order for equal q-values
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// A weight is `0` to `1` with at most three decimals (RFC 9110 12.4.2);
+/// anything else - `q=abc`, `q=2`, `q=inf` - is `None`
+fn parse_qvalue(value: &str) -> Option<f32> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let well_formed = matches!(whole, "0" | "1")
+        && fraction.len() <= 3
+        && fraction.bytes().all(|b| b.is_ascii_digit());
+    let q = value.parse::<f32>().ok()?;
+    (well_formed && q <= 1.0).then_some(q)
+}
+
+/// Fill `{name}` placeholders
+pub fn fill(template: &str, args: &[(&str, String)]) -> String {
+    args.iter()
+        .fold(template.to_string(), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), value)
+        })
+}
+
+/// The best locale with a catalog for this request, e.g. "fr"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Locale(pub &'static str);
+
+impl<S> FromRequestParts<S> for Locale
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let requested = parts
+            .headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .map(parse_accept_language)
+            .unwrap_or_default();
+        Ok(Locale(CATALOGS.negotiate(&requested)))
+    }
+}
+
+tokio::task_local! {
+    static LOCALE: Locale;
+}
+
+/// The locale `localize` chose for the current request; the default
+/// outside of one
+pub fn current() -> Locale {
+    LOCALE
+        .try_with(|locale| *locale)
+        .unwrap_or(Locale(DEFAULT_LOCALE))
+}
+
+/// The layer: `middleware::from_fn(localize)`, outside everything that
+/// produces errors
+pub async fn localize(locale: Locale, request: Request, next: Next) -> Response {
+    let mut response = LOCALE.scope(locale, next.run(request)).await;
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        // The body depends on the header: caches must keep them apart
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("accept-language"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, AppError, Environment, InternalError};
+    use axum::{body::Body, http::StatusCode};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn get(uri: &str, accept_language: Option<&str>) -> (Response, serde_json::Value) {
+        let mut request = Request::get(uri);
+        if let Some(accept_language) = accept_language {
+            request = request.header("accept-language", accept_language);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let response = app(Environment::Prod).oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        let json = serde_json::from_slice(&body).unwrap();
+        (Response::from_parts(parts, Body::empty()), json)
+    }
+
+    #[test]
+    fn test_parse_accept_language_drops_unacceptable_and_malformed_entries() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5"),
+            ["fr-ch", "fr", "en", "*"]
+        );
+        // q=0 is "not acceptable", in any spelling
+        assert_eq!(parse_accept_language("fr;q=0, de;q=0.000, en"), ["en"]);
+        // A broken weight drops its entry instead of promoting it to q=1
+        for broken in [
+            "q=abc", "q=", "q=2", "q=1.5", "q=inf", "q=NaN", "q=-1", "q=0.1234",
+        ] {
+            assert_eq!(
+                parse_accept_language(&format!("fr;{}, en;q=0.5", broken)),
+                ["en"],
+                "{}",
+                broken
+            );
+        }
+        // Parameter names are case-insensitive, and spaces are allowed
+        assert_eq!(
+            parse_accept_language("fr;Q=0.2, en ; q = 0.7"),
+            ["en", "fr"]
+        );
+        assert_eq!(parse_accept_language("fr;, en;q=1.000"), ["fr", "en"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn test_negotiation_falls_back_to_the_primary_language_then_english() {
+        let negotiate = |header: &str| CATALOGS.negotiate(&parse_accept_language(header));
+        assert_eq!(negotiate("fr-CH, fr;q=0.9, en;q=0.8"), "fr");
+        assert_eq!(negotiate("de-DE, fr;q=0.5"), "fr");
+        assert_eq!(negotiate("de-DE, es"), "en");
+        assert_eq!(negotiate("fr;q=0, en"), "en");
+        assert_eq!(negotiate("*"), "en");
+        assert_eq!(negotiate(""), "en");
+    }
+
+    #[test]
+    fn test_missing_messages_fall_back_to_english() {
+        // A panic's message is a developer's, so `fr` has no entry for it
+        let (locale, message) = CATALOGS.message("fr", "HANDLER_PANICKED").unwrap();
+        assert_eq!(locale, "en");
+        assert_eq!(message.detail, "Handler panicked: {0}");
+        assert_eq!(CATALOGS.message("fr", "USER_NOT_FOUND").unwrap().0, "fr");
+        assert!(CATALOGS.message("fr", "NO_SUCH_CODE").is_none());
+    }
+
+    #[test]
+    fn test_the_english_catalog_matches_every_error_message() {
+        let errors = [
+            AppError::UserNotFound(7),
+            AppError::InvalidInput("too short".to_string()),
+            AppError::DatabaseError("timeout".to_string()),
+            AppError::Unauthorized,
+            AppError::Internal(InternalError {
+                context: "Loading".to_string(),
+                source: "gone".into(),
+                backtrace: std::backtrace::Backtrace::disabled(),
+            }),
+            AppError::RouteNotFound("/nope".to_string()),
+            AppError::MethodNotAllowed,
+            AppError::MalformedJson("EOF".to_string()),
+            AppError::UnsupportedMediaType("application/json"),
+            AppError::PayloadTooLarge,
+            AppError::Panicked("boom".to_string()),
+            AppError::TooManyRequests { retry_after: 3 },
+            AppError::ServiceUnavailable { retry_after: 60 },
+        ];
+        for error in errors {
+            let (_, message) = CATALOGS.message("en", error.code()).unwrap();
+            assert_eq!(
+                fill(&message.detail, &error.message_args()),
+                error.to_string()
+            );
+            assert_eq!(message.title, error.title());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_error_bodies_follow_accept_language() {
+        let (response, json) = get("/users/999", Some("fr-CH, fr;q=0.9, en;q=0.8")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-language"], "fr");
+        assert_eq!(response.headers()["vary"], "accept-language");
+        assert_eq!(
+            crate::tests::message(&json),
+            "Utilisateur introuvable : 999"
+        );
+        if cfg!(not(feature = "envelope")) {
+            assert_eq!(json["title"], "Utilisateur introuvable");
+        }
+        // The contract doesn't change with the language
+        assert_eq!(json["error_code"], "USER_NOT_FOUND");
+
+        let (_, json) = get("/users?page=two", Some("fr")).await;
+        assert_eq!(
+            crate::tests::message(&json),
+            "Paramètres de requête invalides : query.page: invalid digit found in string"
+        );
+
+        for accept_language in [None, Some("de-DE"), Some("*")] {
+            let (response, json) = get("/users/999", accept_language).await;
+            assert_eq!(response.headers()["content-language"], "en");
+            assert_eq!(crate::tests::message(&json), "User not found: 999");
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! # Rejections from Axum's Extractors
+//!
+//! `Json`, `Query`, `Path` and `Form` refuse bad input with responses of
+//! their own: plain text such as `Failed to deserialize query string: page:
+//! invalid digit found in string`, which breaks every client that expects
+//! JSON. Each has an `App*` twin here whose rejection is an `AppError`, so
+//! bad input is answered in the module's error format, with a pointer to
+//! the offending value:
+//!
+//! ```json
+//! {
+//!   "type": "https://example.com/problems/invalid-json-body",
+//!   "title": "Invalid JSON body",
+//!   "status": 422,
+//!   "detail": "Invalid JSON body: body[1].name: missing field",
+//!   "error_code": "INVALID_JSON_BODY",
+//!   "retryable": false,
+//!   "pointer": "body[1].name"
+//! }
+//! ```
+//!
+//! A pointer starts with where the value came from - `body`, `query` or
+//! `path` - followed by serde's path to it. Axum has no app-wide hook for
+//! rejections: the customization is the extractor a handler names, so
+//! every handler in this module takes `AppPath<T>` rather than `Path<T>`.
+//! A `Path` that doesn't match its route is a bug rather than bad input,
+//! and stays a 500.
+
+use axum::{
+    extract::{
+        path::ErrorKind,
+        rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
+        FromRequest, FromRequestParts, Path, Query, Request,
+    },
+    http::{request::Parts, StatusCode},
+    Form, Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{error::Error, fmt};
+
+use crate::{insert_user, AppError, NewUser, User};
+
+/// Where in the request a value was rejected, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejected {
+    /// `body.items[0].name`, `query.page`, `path.id`
+    pub pointer: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.pointer, self.reason)
+    }
+}
+
+impl Rejected {
+    /// `origin` plus serde's `path` (`.` is the root)
+    fn new(origin: &str, path: &str, message: &str) -> Self {
+        // serde_json adds "at line 1 column 9"; the pointer says it better
+        let reason = message.split(" at line ").next().unwrap_or(message);
+        let pointer = match path {
+            "." => origin.to_string(),
+            path if path.starts_with('[') => format!("{}{}", origin, path),
+            path => format!("{}.{}", origin, path),
+        };
+        // serde reports a missing field at its parent; point at the field
+        match reason
+            .strip_prefix("missing field `")
+            .and_then(|field| field.strip_suffix('`'))
+        {
+            Some(field) => Rejected {
+                pointer: format!("{}.{}", pointer, field),
+                reason: "missing field".to_string(),
+            },
+            None => Rejected {
+                pointer,
+                reason: reason.to_string(),
+            },
+        }
+    }
+
+    /// A rejection's source is axum's `Error`, and its source the
+    /// `serde_path_to_error::Error<E>` that knows the path
+    fn of<E: Error + 'static>(origin: &str, rejection: &(dyn Error + 'static)) -> Self {
+        let located = rejection
+            .source()
+            .and_then(|error| error.source())
+            .and_then(|error| error.downcast_ref::<serde_path_to_error::Error<E>>());
+        match located {
+            Some(error) => Rejected::new(
+                origin,
+                &error.path().to_string(),
+                &error.inner().to_string(),
+            ),
+            None => Rejected {
+                pointer: origin.to_string(),
+                reason: rejection.to_string(),
+            },
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::JsonDataError(e) => {
+                AppError::InvalidJsonBody(Rejected::of::<serde_json::Error>("body", &e))
+            }
+            JsonRejection::JsonSyntaxError(e) => AppError::MalformedJson(e.body_text()),
+            JsonRejection::MissingJsonContentType(_) => {
+                AppError::UnsupportedMediaType("application/json")
+            }
+            // Reading the body failed; past the limit is the common case
+            rejection if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                AppError::PayloadTooLarge
+            }
+            rejection => AppError::MalformedJson(rejection.body_text()),
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        match rejection {
+            QueryRejection::FailedToDeserializeQueryString(e) => {
+                AppError::InvalidQuery(Rejected::of::<serde_urlencoded::de::Error>("query", &e))
+            }
+            rejection => AppError::InvalidQuery(Rejected {
+                pointer: "query".to_string(),
+                reason: rejection.body_text(),
+            }),
+        }
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        match rejection {
+            PathRejection::FailedToDeserializePathParams(e) if e.status().is_client_error() => {
+                let pointer = match e.kind() {
+                    ErrorKind::ParseErrorAtKey { key, .. }
+                    | ErrorKind::DeserializeError { key, .. }
+                    | ErrorKind::InvalidUtf8InPathParam { key } => format!("path.{}", key),
+                    ErrorKind::ParseErrorAtIndex { index, .. } => format!("path[{}]", index),
+                    _ => "path".to_string(),
+                };
+                AppError::InvalidPathParams(Rejected {
+                    pointer,
+                    reason: e.kind().to_string(),
+                })
+            }
+            // The route and the extractor disagree: a bug, not bad input
+            rejection => AppError::internal("Extracting path parameters", rejection),
+        }
+    }
+}
+
+impl From<FormRejection> for AppError {
+    fn from(rejection: FormRejection) -> Self {
+        match rejection {
+            FormRejection::InvalidFormContentType(_) => {
+                AppError::UnsupportedMediaType("application/x-www-form-urlencoded")
+            }
+            // `Form` reads GET and HEAD forms from the query string
+            FormRejection::FailedToDeserializeForm(e) => {
+                AppError::InvalidQuery(Rejected::of::<serde_urlencoded::de::Error>("query", &e))
+            }
+            FormRejection::FailedToDeserializeFormBody(e) => {
+                AppError::InvalidFormBody(Rejected::of::<serde_urlencoded::de::Error>("body", &e))
+            }
+            rejection if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                AppError::PayloadTooLarge
+            }
+            rejection => AppError::InvalidFormBody(Rejected {
+                pointer: "body".to_string(),
+                reason: rejection.body_text(),
+            }),
+        }
+    }
+}
+
+/// `Json<T>`, but a rejected body is an `AppError`
+pub(crate) struct AppJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for AppJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        Ok(AppJson(value))
+    }
+}
+
+/// `Query<T>`, but a rejected query string is an `AppError`
+pub(crate) struct AppQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for AppQuery<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await?;
+        Ok(AppQuery(value))
+    }
+}
+
+/// `Path<T>`, but a rejected path parameter is an `AppError`
+pub(crate) struct AppPath<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for AppPath<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state).await?;
+        Ok(AppPath(value))
+    }
+}
+
+/// `Form<T>`, but a rejected form is an `AppError`
+pub(crate) struct AppForm<T>(pub T);
+
+impl<S, T> FromRequest<S> for AppForm<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Form(value) = Form::<T>::from_request(req, state).await?;
+        Ok(AppForm(value))
+    }
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    page: u64,
+    per_page: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct Page {
+    page: u64,
+    users: Vec<User>,
+}
+
+/// GET /users?page=2&per_page=3 - `page` is required
+pub(crate) async fn list_users(
+    AppQuery(pagination): AppQuery<Pagination>,
+) -> Result<Json<Page>, AppError> {
+    let per_page = pagination.per_page.unwrap_or(10).min(100);
+    // A page that parses as a u64 can still be too far out to have ids
+    let first = pagination
+        .page
+        .saturating_sub(1)
+        .checked_mul(per_page)
+        .and_then(|skipped| skipped.checked_add(1))
+        .filter(|first| first.checked_add(per_page).is_some())
+        .ok_or_else(|| {
+            AppError::InvalidQuery(Rejected::new("query", "page", "page is too large"))
+        })?;
+    let users = (first..first + per_page)
+        .map(|id| User {
+            id,
+            name: format!("User{}", id),
+        })
+        .collect();
+    Ok(Json(Page {
+        page: pagination.page,
+        users,
+    }))
+}
+
+/// POST /users - one user, from an HTML form
+pub(crate) async fn create_user(
+    AppForm(new_user): AppForm<NewUser>,
+) -> Result<(StatusCode, Json<User>), AppError> {
+    let user = insert_user(0, new_user)?;
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, tests::message, Environment};
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn send(env: Environment, request: Request) -> (StatusCode, serde_json::Value) {
+        let response = app(env).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn post(uri: &str, content_type: &str, body: &str) -> Request {
+        Request::post(uri)
+            .header("content-type", content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pointers_name_the_value() {
+        let rejected = Rejected::new("body", "[1].tags[0]", "invalid type: integer `7`");
+        assert_eq!(rejected.pointer, "body[1].tags[0]");
+        assert_eq!(
+            rejected.to_string(),
+            "body[1].tags[0]: invalid type: integer `7`"
+        );
+
+        let rejected = Rejected::new("query", ".", "missing field `page`");
+        assert_eq!(rejected.to_string(), "query.page: missing field");
+
+        let rejected = Rejected::new("body", "user", "missing field `name` at line 1 column 11");
+        assert_eq!(rejected.to_string(), "body.user.name: missing field");
+    }
+
+    #[tokio::test]
+    async fn test_json_rejections_point_into_the_body() {
+        let request = post(
+            "/users/bulk",
+            "application/json",
+            r#"[{"name":"Carol"},{}]"#,
+        );
+        let (status, json) = send(Environment::Dev, request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json["error_code"], "INVALID_JSON_BODY");
+        assert_eq!(
+            message(&json),
+            "Invalid JSON body: body[1].name: missing field"
+        );
+        assert_eq!(json["pointer"], "body[1].name");
+    }
+
+    #[tokio::test]
+    async fn test_query_and_path_rejections_are_app_errors() {
+        let uri_cases = [
+            ("/users", "query.page", "missing field"),
+            (
+                "/users?page=two",
+                "query.page",
+                "invalid digit found in string",
+            ),
+            ("/users/abc", "path", "Cannot parse `abc` to a `u64`"),
+        ];
+        // Rejections are the client's own input: production keeps them
+        for env in [Environment::Dev, Environment::Prod] {
+            for (uri, pointer, reason) in uri_cases {
+                let request = Request::get(uri).body(Body::empty()).unwrap();
+                let (status, json) = send(env, request).await;
+                assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+                assert_eq!(json["pointer"], pointer, "{}", uri);
+                assert!(message(&json).ends_with(reason), "{}: {}", uri, json);
+            }
+        }
+
+        let request = Request::get("/users?page=2&per_page=2")
+            .body(Body::empty())
+            .unwrap();
+        let (status, json) = send(Environment::Dev, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["users"][0]["id"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_a_page_past_the_last_id_is_rejected_not_overflowed() {
+        let uri = format!("/users?page={}&per_page=100", u64::MAX);
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let (status, json) = send(Environment::Dev, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["error_code"], "INVALID_QUERY");
+        assert_eq!(json["pointer"], "query.page");
+        assert_eq!(
+            message(&json),
+            "Invalid query string: query.page: page is too large"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_form_rejections_are_app_errors() {
+        let form = "application/x-www-form-urlencoded";
+        let (status, json) = send(Environment::Dev, post("/users", form, "nickname=al")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json["error_code"], "INVALID_FORM_BODY");
+        assert_eq!(json["pointer"], "body.name");
+
+        let request = post("/users", "application/json", r#"{"name":"Carol"}"#);
+        let (status, json) = send(Environment::Dev, request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            message(&json),
+            "Expected Content-Type: application/x-www-form-urlencoded"
+        );
+
+        let (status, json) = send(Environment::Dev, post("/users", form, "name=Carol")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(json["name"], "Carol");
+    }
+}
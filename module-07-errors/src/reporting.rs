@@ -0,0 +1,249 @@
+//! # Error Reporting
+//!
+//! A log line is only read by someone already looking. Server errors also
+//! deserve a push to wherever an on-call engineer will see them: Sentry,
+//! Rollbar, a chat webhook. None of that belongs in handlers or in
+//! `AppError`, and the vendor shouldn't leak into either. The app depends on
+//! one trait instead:
+//!
+//! ```ignore
+//! pub trait ErrorReporter: Send + Sync {
+//!     fn report(&self, report: &ErrorReport);
+//! }
+//! ```
+//!
+//! `NoopReporter` is the default; `WebhookReporter` POSTs each report as
+//! JSON to `ERROR_WEBHOOK_URL`. A Sentry reporter would be one more impl.
+//!
+//! `report_errors` is the middleware that ties it together. It wraps the
+//! request in a span carrying the request id and the matched route, so
+//! `AppError`'s log lines say which request failed. After the handler, it
+//! hands every 5xx to the reporter, with the error id and causes that
+//! `ErrorBody` left in the response's extensions - the same details the
+//! log line has, never the scrubbed body the client sees.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+use tower_http::request_id::RequestId;
+use tracing::Instrument;
+
+use crate::ErrorBody;
+
+/// Somewhere server errors are sent, besides the logs
+pub trait ErrorReporter: Send + Sync {
+    /// Called on the request's task: anything slow belongs in a spawned one
+    fn report(&self, report: &ErrorReport);
+}
+
+/// What `ErrorBody` leaves in the response's extensions
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorDetails {
+    pub error_id: String,
+    pub code: &'static str,
+    pub message: String,
+    pub causes: Vec<String>,
+}
+
+impl From<&ErrorBody> for ErrorDetails {
+    fn from(body: &ErrorBody) -> Self {
+        ErrorDetails {
+            error_id: body.error_id.clone(),
+            code: body.code,
+            message: body.message.clone(),
+            causes: body.causes.clone(),
+        }
+    }
+}
+
+/// One 5xx, with the request it answered
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub status: u16,
+    pub method: String,
+    /// The route, `/users/{id}`, rather than the URI: reports group by it
+    pub matched_path: Option<String>,
+    pub request_id: Option<String>,
+    /// `None` for a 5xx that didn't come from an error type
+    #[serde(flatten)]
+    pub error: Option<ErrorDetails>,
+}
+
+/// Reports nowhere
+pub struct NoopReporter;
+
+impl ErrorReporter for NoopReporter {
+    fn report(&self, _report: &ErrorReport) {}
+}
+
+/// POSTs each report as JSON to a URL
+pub struct WebhookReporter {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookReporter {
+    pub fn new(url: impl Into<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .expect("a client with a timeout builds");
+        WebhookReporter {
+            client,
+            url: url.into(),
+        }
+    }
+}
+
+impl ErrorReporter for WebhookReporter {
+    fn report(&self, report: &ErrorReport) {
+        let request = self.client.post(&self.url).json(report);
+        // The response never waits for the webhook, and a webhook that is
+        // down is a warning, not another error to report
+        tokio::spawn(async move {
+            let sent = request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = sent {
+                tracing::warn!(error = %e, "Error report not delivered");
+            }
+        });
+    }
+}
+
+/// `WebhookReporter` if `ERROR_WEBHOOK_URL` is set, `NoopReporter` if not
+pub fn from_env() -> Arc<dyn ErrorReporter> {
+    match std::env::var("ERROR_WEBHOOK_URL") {
+        Ok(url) if !url.is_empty() => Arc::new(WebhookReporter::new(url)),
+        _ => Arc::new(NoopReporter),
+    }
+}
+
+/// The layer: `middleware::from_fn_with_state(reporter, report_errors)`,
+/// inside `SetRequestIdLayer` so the id is there
+pub async fn report_errors(
+    State(reporter): State<Arc<dyn ErrorReporter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let matched_path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_string);
+    let span = tracing::info_span!(
+        "request",
+        method = %method,
+        matched_path = matched_path.as_deref().unwrap_or("-"),
+        request_id = request_id.as_deref().unwrap_or("-"),
+    );
+
+    let mut response = next.run(request).instrument(span).await;
+    if response.status().is_server_error() {
+        reporter.report(&ErrorReport {
+            status: response.status().as_u16(),
+            method,
+            matched_path,
+            request_id,
+            error: response.extensions_mut().remove::<ErrorDetails>(),
+        });
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app_with_reporter, Environment};
+    use axum::{body::Body, http::StatusCode, routing::post, Json, Router};
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    /// Keeps every report for the test to look at
+    #[derive(Default)]
+    struct RecordingReporter(Mutex<Vec<ErrorReport>>);
+
+    impl ErrorReporter for RecordingReporter {
+        fn report(&self, report: &ErrorReport) {
+            self.0.lock().unwrap().push(report.clone());
+        }
+    }
+
+    async fn get(reporter: Arc<dyn ErrorReporter>, uri: &str) -> StatusCode {
+        let request = Request::get(uri)
+            .header("x-request-id", "req-42")
+            .body(Body::empty())
+            .unwrap();
+        let app = app_with_reporter(Environment::Prod, reporter);
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_reported_with_their_request() {
+        let reporter = Arc::new(RecordingReporter::default());
+        for uri in ["/users/1", "/users/999", "/database/query", "/buggy"] {
+            get(reporter.clone(), uri).await;
+        }
+
+        // 200 and 404 aren't reported; the 500s are, unscrubbed
+        let reports = reporter.0.lock().unwrap();
+        assert_eq!(reports.len(), 2, "{:?}", reports);
+        let report = &reports[0];
+        assert_eq!(report.status, 500);
+        assert_eq!(report.method, "GET");
+        assert_eq!(report.matched_path.as_deref(), Some("/database/query"));
+        assert_eq!(report.request_id.as_deref(), Some("req-42"));
+        let error = report.error.as_ref().unwrap();
+        assert_eq!(error.code, "DATABASE_ERROR");
+        assert!(error.message.contains("SELECT id, email"));
+        assert_eq!(reports[1].error.as_ref().unwrap().code, "HANDLER_PANICKED");
+    }
+
+    #[tokio::test]
+    async fn test_unstructured_server_errors_are_reported_too() {
+        let reporter = Arc::new(RecordingReporter::default());
+        let status = get(reporter.clone(), "/reports/export").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+        let reports = reporter.0.lock().unwrap();
+        assert_eq!(reports[0].matched_path.as_deref(), Some("/reports/export"));
+        assert!(reports[0].error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_webhook_reporter_posts_the_report() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let webhook = Router::new().route(
+            "/hook",
+            post(move |Json(report): Json<serde_json::Value>| async move {
+                sender.send(report).unwrap();
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, webhook).await.unwrap() });
+
+        get(Arc::new(WebhookReporter::new(url)), "/reports/monthly").await;
+
+        let report = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("the webhook was called")
+            .unwrap();
+        assert_eq!(report["status"], 500);
+        assert_eq!(report["matched_path"], "/reports/monthly");
+        assert_eq!(report["request_id"], "req-42");
+        assert_eq!(report["code"], "INTERNAL");
+        assert!(report["error_id"].is_string());
+    }
+}
@@ -0,0 +1,205 @@
+//! # Aggregated Field Validation
+//!
+//! module-03's `ValidatedJson` checks one rule at a time and answers with
+//! the first that fails: a form with three mistakes takes three round trips
+//! to fix. `ValidJson<T>` runs every rule the `validator` derive declares on
+//! `T` and reports every failing field at once, in a single `422`:
+//!
+//! ```json
+//! {
+//!   "type": "https://example.com/problems/validation-failed",
+//!   "title": "Validation failed",
+//!   "status": 422,
+//!   "detail": "Validation failed: age, email",
+//!   "error_code": "VALIDATION_FAILED",
+//!   "retryable": false,
+//!   "errors": [
+//!     { "field": "age", "code": "range", "message": "Must be at least 13" },
+//!     { "field": "email", "code": "email", "message": "Not an email address" }
+//!   ]
+//! }
+//! ```
+//!
+//! A body that isn't JSON, or JSON of the wrong shape, is still rejected by
+//! `AppJson` first - there is nothing to validate yet. Fields are sorted by
+//! name so the order is stable, and one field may fail several rules.
+
+use axum::{
+    extract::{FromRequest, Request},
+    http::StatusCode,
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+use validator::{Validate, ValidationErrors};
+
+use crate::{AppError, AppJson, User};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    /// The rule that failed: `length`, `email`, `range`, ...
+    pub code: String,
+    pub message: String,
+}
+
+/// Every failing field of one request
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldErrors(pub Vec<FieldError>);
+
+impl From<ValidationErrors> for FieldErrors {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields: Vec<FieldError> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |error| FieldError {
+                    field: field.to_string(),
+                    code: error.code.to_string(),
+                    message: error.message.as_ref().map_or_else(
+                        || format!("Failed the {} check", error.code),
+                        |message| message.to_string(),
+                    ),
+                })
+            })
+            .collect();
+        // Stable: a field's rules stay in the order they were declared
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+        FieldErrors(fields)
+    }
+}
+
+/// The failing field names: "age, email"
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&str> = self.0.iter().map(|e| e.field.as_str()).collect();
+        names.dedup();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// `AppJson<T>`, then every `validator` rule on `T`
+pub(crate) struct ValidJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let AppJson(value) = AppJson::<T>::from_request(req, state).await?;
+        value
+            .validate()
+            .map_err(|errors| AppError::ValidationFailed(errors.into()))?;
+        Ok(ValidJson(value))
+    }
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct Signup {
+    #[validate(length(min = 3, max = 20, message = "Must be 3 to 20 characters"))]
+    username: String,
+    #[validate(email(message = "Not an email address"))]
+    email: String,
+    #[validate(length(min = 8, message = "Must be at least 8 characters"))]
+    password: String,
+    #[validate(range(min = 13, message = "Must be at least 13"))]
+    age: u32,
+}
+
+/// POST /signup - every invalid field in one 422
+pub async fn signup(ValidJson(signup): ValidJson<Signup>) -> (StatusCode, Json<User>) {
+    let user = User {
+        id: 1,
+        name: signup.username,
+    };
+    (StatusCode::CREATED, Json(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, Environment};
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn post_signup(
+        env: Environment,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/signup")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app(env).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn test_every_failing_rule_is_collected_and_sorted() {
+        let signup = Signup {
+            username: "al".to_string(),
+            email: "nope".to_string(),
+            password: "hunter2hunter2".to_string(),
+            age: 9,
+        };
+        let errors = FieldErrors::from(signup.validate().unwrap_err());
+
+        let fields: Vec<_> = errors.0.iter().map(|e| (&*e.field, &*e.code)).collect();
+        assert_eq!(
+            fields,
+            [("age", "range"), ("email", "email"), ("username", "length")]
+        );
+        assert_eq!(errors.to_string(), "age, email, username");
+        assert_eq!(errors.0[0].message, "Must be at least 13");
+    }
+
+    #[tokio::test]
+    async fn test_all_invalid_fields_come_back_in_one_422() {
+        let body = serde_json::json!({
+            "username": "al", "email": "nope", "password": "short", "age": 9
+        });
+        // Field errors are the client's own input: production keeps them
+        for env in [Environment::Dev, Environment::Prod] {
+            let (status, json) = post_signup(env, body.clone()).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(json["error_code"], "VALIDATION_FAILED");
+            assert_eq!(
+                json["errors"],
+                serde_json::json!([
+                    { "field": "age", "code": "range", "message": "Must be at least 13" },
+                    { "field": "email", "code": "email", "message": "Not an email address" },
+                    { "field": "password", "code": "length",
+                      "message": "Must be at least 8 characters" },
+                    { "field": "username", "code": "length",
+                      "message": "Must be 3 to 20 characters" }
+                ])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_valid_bodies_pass_and_broken_ones_are_still_rejections() {
+        let body = serde_json::json!({
+            "username": "alice", "email": "alice@example.com",
+            "password": "correct horse", "age": 30
+        });
+        let (status, json) = post_signup(Environment::Dev, body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(json["name"], "alice");
+
+        // The wrong shape never reaches validation
+        let (status, json) = post_signup(Environment::Dev, serde_json::json!({ "age": 30 })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json["error_code"], "INVALID_JSON_BODY");
+    }
+}
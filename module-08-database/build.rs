@@ -0,0 +1,7 @@
+//! `sqlx::migrate!` embeds `migrations/` (and `migrations/sqlite/`) at
+//! compile time, so a new or edited migration has to trigger a rebuild.
+
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=migrations/sqlite");
+}
@@ -0,0 +1,21 @@
+-- The schema the module started with. `IF NOT EXISTS` lets a database
+-- created before migrations existed adopt this one without errors.
+
+CREATE TABLE IF NOT EXISTS users (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Where archive.rs moves old users
+CREATE TABLE IF NOT EXISTS users_archive (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- The archive batches scan users oldest first
+CREATE INDEX IF NOT EXISTS users_created_at_idx ON users (created_at);
@@ -0,0 +1,4 @@
+-- When a user was last changed. Existing rows start at the time the
+-- migration runs.
+
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,10 @@
+-- Full-text search over users (search.rs). Postgres keeps the generated
+-- column up to date on every write; the GIN index makes `search @@ query`
+-- an index lookup. 'simple' rather than 'english': names have no stems.
+
+ALTER TABLE users ADD COLUMN search TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', name), 'A') ||
+    setweight(to_tsvector('simple', email), 'B')
+) STORED;
+
+CREATE INDEX users_search_idx ON users USING GIN (search);
@@ -0,0 +1,20 @@
+-- Where archive.rs moves an archived user's profile and posts. They're
+-- copied in the same transaction that deletes the user, before the
+-- cascade removes them from `profiles` and `posts`.
+
+CREATE TABLE profiles_archive (
+    user_id UUID PRIMARY KEY,
+    bio TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE posts_archive (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL,
+    title TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX posts_archive_user_id_idx ON posts_archive (user_id);
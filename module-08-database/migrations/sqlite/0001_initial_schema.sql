@@ -0,0 +1,19 @@
+-- The same schema as ../0001_initial_schema.sql, in SQLite's types: UUIDs
+-- are 16-byte BLOBs and timestamps RFC 3339 TEXT, as sqlx encodes them.
+
+CREATE TABLE IF NOT EXISTS users (
+    id BLOB PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS users_archive (
+    id BLOB PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    archived_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS users_created_at_idx ON users (created_at);
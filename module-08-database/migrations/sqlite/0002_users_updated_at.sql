@@ -0,0 +1,5 @@
+-- SQLite only adds NOT NULL columns with a constant default: start every
+-- existing row at its creation time instead.
+
+ALTER TABLE users ADD COLUMN updated_at TEXT NOT NULL DEFAULT '';
+UPDATE users SET updated_at = created_at;
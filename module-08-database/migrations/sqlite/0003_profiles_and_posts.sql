@@ -0,0 +1,17 @@
+-- As ../0003_profiles_and_posts.sql. sqlx turns on `PRAGMA foreign_keys`,
+-- so the cascades work here too.
+
+CREATE TABLE profiles (
+    user_id BLOB PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    bio TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE posts (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    title TEXT NOT NULL CHECK (length(title) BETWEEN 1 AND 200),
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX posts_user_id_idx ON posts (user_id);
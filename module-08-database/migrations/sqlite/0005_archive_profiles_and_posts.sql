@@ -0,0 +1,18 @@
+-- As ../0005_archive_profiles_and_posts.sql.
+
+CREATE TABLE profiles_archive (
+    user_id BLOB PRIMARY KEY NOT NULL,
+    bio TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    archived_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE TABLE posts_archive (
+    id BLOB PRIMARY KEY NOT NULL,
+    user_id BLOB NOT NULL,
+    title TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    archived_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX posts_archive_user_id_idx ON posts_archive (user_id);
@@ -0,0 +1,65 @@
+//! # Admin Auth
+//!
+//! `/admin/*` starts jobs that delete rows from `users`, so reaching the
+//! port isn't enough: every admin request carries
+//! `Authorization: Bearer <ADMIN_TOKEN>`. With no `ADMIN_TOKEN` set the
+//! admin routes are closed, not open.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+/// The shared secret for `/admin/*`; `None` refuses everyone
+#[derive(Clone, Default)]
+pub struct AdminToken(Option<Arc<str>>);
+
+impl AdminToken {
+    pub fn new(token: Option<String>) -> Self {
+        Self(token.filter(|t| !t.is_empty()).map(Arc::from))
+    }
+
+    /// `ADMIN_TOKEN`, unset by default
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("ADMIN_TOKEN").ok())
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// Middleware: 401 unless the bearer token matches `ADMIN_TOKEN`
+pub async fn require_admin(
+    State(token): State<AdminToken>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match (token.0.as_deref(), presented) {
+        (Some(expected), Some(presented))
+            if constant_time_eq(expected.as_bytes(), presented.as_bytes()) =>
+        {
+            next.run(request).await
+        }
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Admin token required",
+        )
+            .into_response(),
+    }
+}
+
+/// Compare without returning early, so timing doesn't reveal the prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,539 @@
+//! # Archiving Old Rows in Batches
+//!
+//! Tables that only grow get slower to scan, vacuum and back up. The
+//! archive job moves users created before a retention cutoff from `users`
+//! into `users_archive` (created by `migrations/0001_initial_schema.sql`),
+//! which keeps the identifying columns plus `archived_at`. Their profiles
+//! and posts go with them, to `profiles_archive` and `posts_archive`
+//! (`migrations/0005_archive_profiles_and_posts.sql`).
+//!
+//! It never moves everything in one statement. Each batch is its own
+//! transaction:
+//! - `SELECT .. ORDER BY created_at LIMIT batch_size FOR UPDATE SKIP LOCKED`
+//!   picks the oldest users into a temp table - a range scan on the
+//!   `created_at` index, and rows another transaction is writing are left
+//!   for the next run
+//! - their profiles and posts are copied to `profiles_archive` and
+//!   `posts_archive`, the users to `users_archive`
+//! - `DELETE FROM users` removes exactly those users, and the foreign keys
+//!   cascade to the profiles and posts just copied. One transaction, so a
+//!   row is in exactly one table whatever happens
+//! - `COMMIT`, report progress, and start the next batch until one comes
+//!   back short
+//!
+//! Short transactions hold locks briefly and don't bloat the WAL, and a
+//! crash loses at most the batch in flight (it rolls back). Moving rows in
+//! `created_at` order is also what makes a later switch to a table
+//! partitioned by `created_at` painless: the archive becomes "detach the
+//! oldest partition".
+//!
+//! The job is a background task like the replication one: it runs every
+//! `every`, or when `POST /admin/archive/run` wakes it, never two runs at
+//! once. `GET /admin/archive` shows the run in progress and the last one.
+//! Both need the admin token (see `admin.rs`), and a manual run never cuts
+//! closer than `MIN_OLDER_THAN`: `older_than_secs=0` would archive every
+//! user, including the one created a moment ago.
+//!
+//! Deleted rows fire the `users` change trigger, so the read model drops
+//! archived users too. Module 08 has no todos table; the same job applies
+//! to any table with a timestamp to cut on.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+use crate::db::DbPool;
+
+/// The youngest a user can be and still be archived by a manual run
+pub const MIN_OLDER_THAN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveConfig {
+    /// Users created longer ago than this are archived
+    pub retention: Duration,
+    /// Time between scheduled runs
+    pub every: Duration,
+    /// Rows moved per transaction
+    pub batch_size: i64,
+}
+
+impl ArchiveConfig {
+    /// `USER_RETENTION_DAYS` (default 365, at least 1), `ARCHIVE_EVERY_SECS`
+    /// (default 3600) and `ARCHIVE_BATCH_SIZE` (default 500)
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            retention: Duration::from_secs(
+                var("USER_RETENTION_DAYS", 365)
+                    .max(1)
+                    .saturating_mul(86_400),
+            ),
+            every: Duration::from_secs(var("ARCHIVE_EVERY_SECS", 3600).max(1)),
+            batch_size: var("ARCHIVE_BATCH_SIZE", 500).clamp(1, 10_000) as i64,
+        }
+    }
+}
+
+// ============================================================================
+// RUN STATUS
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Trigger {
+    Schedule,
+    Manual,
+}
+
+/// One run, updated after every batch
+#[derive(Debug, Clone, Serialize)]
+struct ArchiveRun {
+    trigger: Trigger,
+    /// Rows created before this are moved
+    cutoff: DateTime<Utc>,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    batches: u64,
+    rows_moved: u64,
+    /// Why the run stopped early; the batches before it are committed
+    error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ArchiveStatus {
+    running: Option<ArchiveRun>,
+    last: Option<ArchiveRun>,
+    total_rows_moved: u64,
+    /// A manual run asked for, with its own retention
+    requested: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct Archiver {
+    pool: DbPool,
+    config: ArchiveConfig,
+    status: Arc<Mutex<ArchiveStatus>>,
+    wake: Arc<Notify>,
+}
+
+impl Archiver {
+    pub fn new(pool: DbPool, config: ArchiveConfig) -> Self {
+        Self {
+            pool,
+            config,
+            status: Arc::default(),
+            wake: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> ArchiveConfig {
+        self.config
+    }
+
+    /// Background task: a run every `every`, or as soon as one is requested
+    pub async fn run_scheduled(self) {
+        let mut ticks = tokio::time::interval(self.config.every);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick is immediate; let the server start first
+        ticks.tick().await;
+
+        loop {
+            let woken = tokio::select! {
+                _ = ticks.tick() => false,
+                _ = self.wake.notified() => true,
+            };
+            // A request that arrived while a scheduled run was starting is
+            // served by that run, leaving a stale wake-up behind
+            let requested = self.status.lock().unwrap().requested.take();
+            let (trigger, retention) = match requested {
+                Some(retention) => (Trigger::Manual, retention),
+                None if woken => continue,
+                None => (Trigger::Schedule, self.config.retention),
+            };
+            self.run(trigger, retention).await;
+        }
+    }
+
+    /// Queue a manual run; `false` if one is already running or queued
+    fn request(&self, retention: Duration) -> bool {
+        let mut status = self.status.lock().unwrap();
+        if status.running.is_some() || status.requested.is_some() {
+            return false;
+        }
+        status.requested = Some(retention);
+        // Stored as a permit if the task is busy sleeping on the interval
+        self.wake.notify_one();
+        true
+    }
+
+    async fn run(&self, trigger: Trigger, retention: Duration) {
+        // A retention too large to subtract archives nothing
+        let cutoff = chrono::Duration::from_std(retention)
+            .ok()
+            .and_then(|retention| Utc::now().checked_sub_signed(retention))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        let mut run = ArchiveRun {
+            trigger,
+            cutoff,
+            started_at: Utc::now(),
+            finished_at: None,
+            batches: 0,
+            rows_moved: 0,
+            error: None,
+        };
+        self.status.lock().unwrap().running = Some(run.clone());
+
+        loop {
+            match self.move_batch(cutoff).await {
+                Ok(moved) => {
+                    run.batches += 1;
+                    run.rows_moved += moved;
+                    self.status.lock().unwrap().running = Some(run.clone());
+                    if moved < self.config.batch_size as u64 {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "⚠️  archive run failed after {} row(s): {}",
+                        run.rows_moved, e
+                    );
+                    run.error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+
+        run.finished_at = Some(Utc::now());
+        if run.rows_moved > 0 {
+            println!(
+                "🗄️  Archived {} user(s) created before {} in {} batch(es)",
+                run.rows_moved, cutoff, run.batches
+            );
+        }
+        let mut status = self.status.lock().unwrap();
+        status.total_rows_moved += run.rows_moved;
+        status.running = None;
+        status.last = Some(run);
+    }
+
+    /// Move up to `batch_size` of the oldest users, with their profiles and
+    /// posts, in one transaction
+    async fn move_batch(&self, cutoff: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(BATCH_TABLE).execute(&mut *tx).await?;
+        sqlx::query(PICK_BATCH)
+            .bind(cutoff)
+            .bind(self.config.batch_size)
+            .execute(&mut *tx)
+            .await?;
+        for copy in [
+            "INSERT INTO profiles_archive (user_id, bio, created_at)
+            SELECT user_id, bio, created_at FROM profiles
+            WHERE user_id IN (SELECT id FROM archive_batch)",
+            "INSERT INTO posts_archive (id, user_id, title, created_at)
+            SELECT id, user_id, title, created_at FROM posts
+            WHERE user_id IN (SELECT id FROM archive_batch)",
+            "INSERT INTO users_archive (id, name, email, created_at)
+            SELECT id, name, email, created_at FROM users
+            WHERE id IN (SELECT id FROM archive_batch)",
+        ] {
+            sqlx::query(copy).execute(&mut *tx).await?;
+        }
+        // The foreign keys cascade to the profiles and posts just copied
+        let moved = sqlx::query("DELETE FROM users WHERE id IN (SELECT id FROM archive_batch)")
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        // Rolled back with the rest if anything above failed
+        sqlx::query("DROP TABLE archive_batch")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(moved)
+    }
+}
+
+/// The batch's user ids, for this transaction only
+#[cfg(not(feature = "sqlite"))]
+const BATCH_TABLE: &str = "CREATE TEMP TABLE archive_batch (id UUID PRIMARY KEY)";
+#[cfg(feature = "sqlite")]
+const BATCH_TABLE: &str = "CREATE TEMP TABLE archive_batch (id BLOB PRIMARY KEY)";
+
+/// The oldest users before the cutoff. On Postgres the lock skips users
+/// another transaction is writing, and holds off new profiles and posts
+/// for the batch until the commit: inserting one locks its user too.
+#[cfg(not(feature = "sqlite"))]
+const PICK_BATCH: &str = "INSERT INTO archive_batch
+    SELECT id FROM users
+    WHERE created_at < $1
+    ORDER BY created_at
+    LIMIT $2
+    FOR UPDATE SKIP LOCKED";
+/// SQLite has no row locks, and only one writer at a time anyway
+#[cfg(feature = "sqlite")]
+const PICK_BATCH: &str = "INSERT INTO archive_batch
+    SELECT id FROM users
+    WHERE created_at < $1
+    ORDER BY created_at
+    LIMIT $2";
+
+// ============================================================================
+// ADMIN HANDLERS
+// ============================================================================
+
+/// GET /admin/archive - the run in progress, the last one, and the totals
+pub async fn archive_status(State(archiver): State<Archiver>) -> impl IntoResponse {
+    let status = archiver.status.lock().unwrap();
+    Json(serde_json::json!({
+        "retention_days": archiver.config.retention.as_secs() / 86_400,
+        "every_secs": archiver.config.every.as_secs(),
+        "batch_size": archiver.config.batch_size,
+        "running": status.running,
+        "last": status.last,
+        "total_rows_moved": status.total_rows_moved,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RunParams {
+    /// Override the retention for this run, e.g. `60` to archive everything
+    /// older than a minute; never below `MIN_OLDER_THAN`
+    older_than_secs: Option<u64>,
+}
+
+/// POST /admin/archive/run - start a run now; 202, 400 if `older_than_secs`
+/// is too small, or 409 if one is running
+pub async fn trigger_archive(
+    State(archiver): State<Archiver>,
+    Query(params): Query<RunParams>,
+) -> Response {
+    let retention = params
+        .older_than_secs
+        .map(Duration::from_secs)
+        .unwrap_or(archiver.config.retention);
+    if retention < MIN_OLDER_THAN {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "older_than_secs must be at least {}",
+                MIN_OLDER_THAN.as_secs()
+            ),
+        )
+            .into_response();
+    }
+    if !archiver.request(retention) {
+        return (
+            StatusCode::CONFLICT,
+            "An archive run is already in progress - see GET /admin/archive",
+        )
+            .into_response();
+    }
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "status": "queued",
+            "older_than_secs": retention.as_secs(),
+            "monitor": "/admin/archive",
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{app, repository::InMemoryUserRepository, AppState, TEST_ADMIN_TOKEN};
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        Router,
+    };
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    /// No scheduler runs here, so a queued run stays queued
+    fn test_app() -> Router {
+        app(AppState::for_tests(Arc::new(
+            InMemoryUserRepository::default(),
+        )))
+    }
+
+    async fn post_run(app: &Router, query: &str, token: Option<&str>) -> StatusCode {
+        let mut request = Request::post(format!("/admin/archive/run{}", query));
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = request.body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_need_the_token() {
+        let app = test_app();
+        let query = "?older_than_secs=3600";
+        assert_eq!(post_run(&app, query, None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            post_run(&app, query, Some("guess")).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let status = Request::get("/admin/archive").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(status).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_manual_runs_keep_a_minimum_age() {
+        let app = test_app();
+        let token = Some(TEST_ADMIN_TOKEN);
+        assert_eq!(
+            post_run(&app, "?older_than_secs=0", token).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post_run(&app, "?older_than_secs=59", token).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post_run(&app, "?older_than_secs=60", token).await,
+            StatusCode::ACCEPTED
+        );
+        // Still queued: a second run waits its turn
+        assert_eq!(
+            post_run(&app, "?older_than_secs=60", token).await,
+            StatusCode::CONFLICT
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    mod sqlite {
+        use super::super::*;
+        use crate::{db, query_control::QueryTimeouts};
+        use uuid::Uuid;
+
+        async fn pool() -> DbPool {
+            let pool = db::connect("sqlite::memory:", QueryTimeouts::from_env())
+                .await
+                .unwrap();
+            db::migrate(&pool).await.unwrap();
+            pool
+        }
+
+        async fn insert_user(pool: &DbPool, id: Uuid, age_days: i64) {
+            sqlx::query("INSERT INTO users (id, name, email, created_at) VALUES ($1, $2, $3, $4)")
+                .bind(id)
+                .bind("User")
+                .bind(format!("{}@example.com", id))
+                .bind(Utc::now() - chrono::Duration::days(age_days))
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+
+        async fn count(pool: &DbPool, table: &str) -> i64 {
+            sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(pool)
+                .await
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn test_batches_delete_only_the_rows_they_copied() {
+            let pool = pool().await;
+            // Archived long ago, and since then a live user has the same id
+            let reused = Uuid::from_u128(1);
+            sqlx::query(
+                "INSERT INTO users_archive (id, name, email, created_at) VALUES ($1, 'Old', 'old@example.com', $2)",
+            )
+            .bind(reused)
+            .bind(Utc::now() - chrono::Duration::days(900))
+            .execute(&pool)
+            .await
+            .unwrap();
+            insert_user(&pool, reused, 0).await;
+            for n in 2..=6 {
+                insert_user(&pool, Uuid::from_u128(n), 400).await;
+            }
+
+            let config = ArchiveConfig {
+                retention: Duration::from_secs(365 * 86_400),
+                every: Duration::from_secs(3600),
+                batch_size: 2,
+            };
+            let archiver = Archiver::new(pool.clone(), config);
+            archiver.run(Trigger::Manual, config.retention).await;
+
+            let last = archiver.status.lock().unwrap().last.clone().unwrap();
+            assert_eq!(last.error, None);
+            assert_eq!((last.rows_moved, last.batches), (5, 3));
+            assert_eq!(count(&pool, "users").await, 1);
+            assert_eq!(count(&pool, "users_archive").await, 6);
+            let live: Uuid = sqlx::query_scalar("SELECT id FROM users")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(live, reused);
+        }
+
+        #[tokio::test]
+        async fn test_profiles_and_posts_are_archived_with_their_user() {
+            let pool = pool().await;
+            let (old, young) = (Uuid::from_u128(1), Uuid::from_u128(2));
+            insert_user(&pool, old, 400).await;
+            insert_user(&pool, young, 0).await;
+            for user in [old, young] {
+                sqlx::query(
+                    "INSERT INTO profiles (user_id, bio, created_at) VALUES ($1, 'Bio', $2)",
+                )
+                .bind(user)
+                .bind(Utc::now())
+                .execute(&pool)
+                .await
+                .unwrap();
+                for _ in 0..2 {
+                    sqlx::query(
+                        "INSERT INTO posts (id, user_id, title, created_at) VALUES ($1, $2, 'Hi', $3)",
+                    )
+                    .bind(Uuid::new_v4())
+                    .bind(user)
+                    .bind(Utc::now())
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                }
+            }
+
+            let archiver = Archiver::new(pool.clone(), ArchiveConfig::from_env());
+            archiver
+                .run(Trigger::Manual, Duration::from_secs(86_400))
+                .await;
+
+            assert_eq!(archiver.status.lock().unwrap().total_rows_moved, 1);
+            for (table, live, archived) in [("users", 1, 1), ("profiles", 1, 1), ("posts", 2, 2)] {
+                assert_eq!(count(&pool, table).await, live, "{}", table);
+                let archive = format!("{}_archive", table);
+                assert_eq!(count(&pool, &archive).await, archived, "{}", archive);
+            }
+            let archived_posts: Vec<Uuid> = sqlx::query_scalar("SELECT user_id FROM posts_archive")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+            assert_eq!(archived_posts, [old, old]);
+        }
+    }
+}
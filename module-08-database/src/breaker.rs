@@ -0,0 +1,428 @@
+//! # Circuit Breaker & Graceful Degradation
+//!
+//! When Postgres is down or the pool is exhausted, every request waits for
+//! `acquire_timeout` and then fails - piling more load onto a database that
+//! is already struggling. A circuit breaker notices the failures and stops
+//! trying for a while:
+//! - **Closed**: queries run; the outcome of the last `WINDOW` calls is kept
+//! - **Open**: too many of them failed - queries are skipped for `COOLDOWN`
+//! - **Half-open**: after the cooldown one probe query is let through; it
+//!   closes the breaker again or re-opens it
+//!
+//! While the breaker is open the API degrades instead of failing:
+//! - Reads are served from the read model (the Module 05 style in-memory
+//!   copy, see `read_model.rs`) with `X-Data-Source: cache` and a `Warning`
+//! - Writes can't be faked, so they get `503` with `Retry-After`
+//!
+//! Only infrastructure failures count. A unique violation or a missing row
+//! means the database answered - that's a healthy database.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::DbError;
+
+/// Outcomes remembered while closed
+const WINDOW: usize = 20;
+/// Don't judge the failure rate on fewer calls than this
+const MIN_CALLS: usize = 5;
+/// Trip when at least this share of the window failed
+const FAILURE_RATIO: f64 = 0.5;
+/// How long to stay open before probing
+const COOLDOWN: Duration = Duration::from_secs(10);
+
+pub const DATA_SOURCE_HEADER: &str = "x-data-source";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: BreakerState,
+    /// `true` = failed
+    recent: VecDeque<bool>,
+    /// Open: when probing may start. Half-open: when the probe is given up on.
+    until: Instant,
+    trips: u64,
+}
+
+/// Shared by every handler that talks to the pool
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Mutex<Inner>>,
+}
+
+/// Why a guarded query didn't produce a value
+#[derive(Debug)]
+pub enum BreakerError {
+    /// Skipped without touching the pool; try again after `retry_after`
+    Open {
+        retry_after: Duration,
+    },
+    Query(sqlx::Error),
+}
+
+impl BreakerError {
+    /// The database can't answer right now - callers may degrade
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            BreakerError::Open { .. } => true,
+            BreakerError::Query(e) => is_infrastructure_failure(e),
+        }
+    }
+}
+
+impl From<BreakerError> for DbError {
+    fn from(error: BreakerError) -> Self {
+        match error {
+            BreakerError::Open { retry_after } => DbError::Unavailable(retry_after),
+            // The breaker may not have tripped yet, but the answer is the
+            // same: 503, worth retrying in a moment (`Retry-After: 1`)
+            BreakerError::Query(e) if is_infrastructure_failure(&e) => {
+                eprintln!("⚠️  Database unavailable: {}", e);
+                DbError::Unavailable(Duration::ZERO)
+            }
+            BreakerError::Query(e) => DbError::Sqlx(e),
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                state: BreakerState::Closed,
+                recent: VecDeque::with_capacity(WINDOW),
+                until: Instant::now(),
+                trips: 0,
+            })),
+        }
+    }
+}
+
+/// Errors that say "the database (or the way to it) is unwell"
+fn is_infrastructure_failure(error: &sqlx::Error) -> bool {
+    matches!(
+        error,
+        sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::Protocol(_)
+            | sqlx::Error::WorkerCrashed
+    ) || crate::query_control::is_query_canceled(error)
+}
+
+impl CircuitBreaker {
+    /// Run `query` unless the breaker is open, and record how it went
+    pub async fn call<T>(
+        &self,
+        query: impl Future<Output = Result<T, sqlx::Error>>,
+    ) -> Result<T, BreakerError> {
+        self.try_acquire()?;
+        let result = query.await;
+        let failed = matches!(&result, Err(e) if is_infrastructure_failure(e));
+        self.record(failed);
+        result.map_err(BreakerError::Query)
+    }
+
+    fn try_acquire(&self) -> Result<(), BreakerError> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        match inner.state {
+            BreakerState::Closed => Ok(()),
+            // Cooldown over: this caller becomes the probe
+            BreakerState::Open if now >= inner.until => {
+                inner.state = BreakerState::HalfOpen;
+                // If the probe never reports back (client gone), allow another
+                inner.until = now + COOLDOWN;
+                Ok(())
+            }
+            BreakerState::HalfOpen if now >= inner.until => {
+                inner.until = now + COOLDOWN;
+                Ok(())
+            }
+            BreakerState::Open | BreakerState::HalfOpen => Err(BreakerError::Open {
+                retry_after: inner
+                    .until
+                    .saturating_duration_since(now)
+                    .max(Duration::from_secs(1)),
+            }),
+        }
+    }
+
+    fn record(&self, failed: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            BreakerState::HalfOpen if failed => inner.trip(),
+            BreakerState::HalfOpen => {
+                inner.state = BreakerState::Closed;
+                inner.recent.clear();
+                println!("✅ Circuit breaker closed: database is back");
+            }
+            BreakerState::Closed => {
+                if inner.recent.len() == WINDOW {
+                    inner.recent.pop_front();
+                }
+                inner.recent.push_back(failed);
+                let failures = inner.recent.iter().filter(|f| **f).count();
+                if inner.recent.len() >= MIN_CALLS
+                    && failures as f64 >= FAILURE_RATIO * inner.recent.len() as f64
+                {
+                    inner.trip();
+                }
+            }
+            // A call that started before the breaker tripped; nothing to learn
+            BreakerState::Open => {}
+        }
+    }
+}
+
+impl Inner {
+    fn trip(&mut self) {
+        self.state = BreakerState::Open;
+        self.until = Instant::now() + COOLDOWN;
+        self.recent.clear();
+        self.trips += 1;
+        println!(
+            "⚠️  Circuit breaker opened for {:?}: database unhealthy",
+            COOLDOWN
+        );
+    }
+}
+
+// ============================================================================
+// DEGRADED RESPONSES
+// ============================================================================
+
+/// A read answered from the read model because the database couldn't be
+pub struct FromCache<T> {
+    pub body: T,
+    pub staleness_ms: i64,
+}
+
+impl<T: Serialize> IntoResponse for FromCache<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.body).into_response();
+        let headers = response.headers_mut();
+        headers.insert(DATA_SOURCE_HEADER, HeaderValue::from_static("cache"));
+        // RFC 7234 warn-code 110: "Response is Stale"
+        headers.insert(
+            header::WARNING,
+            HeaderValue::from_static("110 - \"Database unavailable, serving cached data\""),
+        );
+        if let Ok(age) = HeaderValue::from_str(&(self.staleness_ms / 1000).max(0).to_string()) {
+            headers.insert(header::AGE, age);
+        }
+        response
+    }
+}
+
+/// A read answered by the database, labelled so the difference is visible
+pub struct FromDatabase<T>(pub T);
+
+impl<T: Serialize> IntoResponse for FromDatabase<T> {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.0).into_response();
+        response
+            .headers_mut()
+            .insert(DATA_SOURCE_HEADER, HeaderValue::from_static("database"));
+        response
+    }
+}
+
+// ============================================================================
+// STATUS ENDPOINT
+// ============================================================================
+
+#[derive(Serialize)]
+pub struct BreakerStatus {
+    state: BreakerState,
+    recent_calls: usize,
+    recent_failures: usize,
+    trips: u64,
+    /// While open: seconds until the next probe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
+}
+
+/// GET /health/db
+pub async fn breaker_status(State(breaker): State<CircuitBreaker>) -> Json<BreakerStatus> {
+    let inner = breaker.inner.lock().unwrap();
+    let retry_after_secs = (inner.state == BreakerState::Open).then(|| {
+        inner
+            .until
+            .saturating_duration_since(Instant::now())
+            .as_secs()
+    });
+    Json(BreakerStatus {
+        state: inner.state,
+        recent_calls: inner.recent.len(),
+        recent_failures: inner.recent.iter().filter(|f| **f).count(),
+        trips: inner.trips,
+        retry_after_secs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// What a query sees while the database is unreachable
+    async fn db_down() -> Result<(), sqlx::Error> {
+        Err(sqlx::Error::PoolTimedOut)
+    }
+
+    async fn db_up() -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn trip(breaker: &CircuitBreaker) {
+        for _ in 0..MIN_CALLS {
+            assert!(breaker.call(db_down()).await.is_err());
+        }
+    }
+
+    /// `GET /health/db`, as JSON
+    async fn status(breaker: &CircuitBreaker) -> serde_json::Value {
+        let Json(status) = breaker_status(State(breaker.clone())).await;
+        serde_json::to_value(status).unwrap()
+    }
+
+    fn retry_after(result: Result<(), BreakerError>) -> Duration {
+        match result {
+            Err(BreakerError::Open { retry_after }) => retry_after,
+            other => panic!("expected the breaker to be open, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_opens_on_infrastructure_failures_and_skips_the_database() {
+        let breaker = CircuitBreaker::default();
+        // The database answered each time: not failures
+        for _ in 0..MIN_CALLS {
+            let missing = breaker.call(async { Err::<(), _>(sqlx::Error::RowNotFound) });
+            assert!(missing.await.is_err());
+        }
+        assert_eq!(status(&breaker).await["recent_failures"], 0);
+
+        // Half the window failing trips it: 4 of 9 doesn't, 5 of 10 does
+        for _ in 1..MIN_CALLS {
+            let _ = breaker.call(db_down()).await;
+        }
+        let before = status(&breaker).await;
+        assert_eq!(before["state"], "closed");
+        assert_eq!(before["recent_calls"], 2 * MIN_CALLS - 1);
+        assert_eq!(before["recent_failures"], MIN_CALLS - 1);
+
+        let _ = breaker.call(db_down()).await;
+        let open = status(&breaker).await;
+        assert_eq!(open["state"], "open");
+        assert_eq!(open["trips"], 1);
+        assert_eq!(open["recent_calls"], 0);
+        assert_eq!(open["retry_after_secs"], COOLDOWN.as_secs());
+
+        let touched = AtomicBool::new(false);
+        let skipped = breaker
+            .call(async {
+                touched.store(true, Ordering::SeqCst);
+                Ok(())
+            })
+            .await;
+        assert_eq!(retry_after(skipped), COOLDOWN);
+        assert!(!touched.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_after_counts_down_the_cooldown() {
+        let breaker = CircuitBreaker::default();
+        trip(&breaker).await;
+
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert_eq!(
+            retry_after(breaker.call(db_up()).await),
+            Duration::from_secs(6)
+        );
+        assert_eq!(status(&breaker).await["retry_after_secs"], 6);
+
+        // Never less than a second, so `Retry-After` is never 0
+        tokio::time::advance(Duration::from_millis(5_500)).await;
+        assert_eq!(
+            retry_after(breaker.call(db_up()).await),
+            Duration::from_secs(1)
+        );
+
+        let response = DbError::from(BreakerError::Open {
+            retry_after: Duration::from_secs(6),
+        })
+        .into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "6");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_probe_after_the_cooldown_closes_or_reopens() {
+        let breaker = CircuitBreaker::default();
+        trip(&breaker).await;
+
+        // Still down: the probe fails and the cooldown starts over
+        tokio::time::advance(COOLDOWN).await;
+        assert!(breaker.call(db_down()).await.is_err());
+        let reopened = status(&breaker).await;
+        assert_eq!(reopened["state"], "open");
+        assert_eq!(reopened["trips"], 2);
+        assert_eq!(retry_after(breaker.call(db_up()).await), COOLDOWN);
+
+        // Back up: while the probe runs everyone else is still turned away
+        tokio::time::advance(COOLDOWN).await;
+        let probe = breaker.call(async {
+            assert_eq!(status(&breaker).await["state"], "half_open");
+            assert!(breaker.call(db_up()).await.is_err());
+            Ok(())
+        });
+        assert!(probe.await.is_ok());
+        let closed = status(&breaker).await;
+        assert_eq!(closed["state"], "closed");
+        assert_eq!(closed["trips"], 2);
+        assert!(closed.get("retry_after_secs").is_none());
+        assert!(breaker.call(db_up()).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_probe_that_never_reports_back_is_replaced() {
+        let breaker = CircuitBreaker::default();
+        trip(&breaker).await;
+        tokio::time::advance(COOLDOWN).await;
+
+        // The probe's client goes away while the query hangs
+        let hung = breaker.call(std::future::pending::<Result<(), sqlx::Error>>());
+        assert!(tokio::time::timeout(Duration::from_secs(1), hung)
+            .await
+            .is_err());
+        assert_eq!(status(&breaker).await["state"], "half_open");
+        assert!(breaker.call(db_up()).await.is_err());
+
+        tokio::time::advance(COOLDOWN).await;
+        assert!(breaker.call(db_up()).await.is_ok());
+        assert_eq!(status(&breaker).await["state"], "closed");
+    }
+}
@@ -0,0 +1,260 @@
+//! # Bulk Inserts with Per-Item Results
+//!
+//! `POST /users/bulk` takes an array of users and inserts them with one
+//! statement, in one transaction (`UserRepository::create_many`):
+//!
+//! ```sql
+//! INSERT INTO users (id, name, email, created_at, updated_at)
+//! VALUES ($1, $2, $3, $4, $5), ($6, $7, $8, $9, $10), ...
+//! ON CONFLICT (email) DO NOTHING RETURNING *
+//! ```
+//!
+//! One round trip instead of one per user, and a taken email still fails
+//! only that user: the conflicting rows are skipped, not the statement, and
+//! whatever `RETURNING` leaves out was a conflict.
+//!
+//! The answer is module 07's bulk envelope (its `bulk.rs` is the reference
+//! copy): a `summary` of counts, and in `results` one item per input, in
+//! order, with its own `status` and either the created `data` or an
+//! `error`/`error_code`/`retryable`:
+//! - `201` created, `409 EMAIL_TAKEN` on a conflict, `400 INVALID_INPUT`
+//!   for a user that was never sent to the database
+//! - if the statement itself fails (or the breaker is open), nothing was
+//!   committed and every valid user gets the same error, e.g. `503`
+//!   marked `retryable`
+//!
+//! The response itself is `200` when every user was created, `207
+//! Multi-Status` when some were and `400` when none were.
+//!
+//! Two guards run before any of that. `MAX_BODY_BYTES` is a
+//! `DefaultBodyLimit` on this route only: `Json` stops reading a larger
+//! body and answers `413 Payload Too Large`, without buffering it
+//! (module 06 covers body limits). `MAX_BATCH` then caps the number of
+//! users, which also keeps the statement under the databases' limits on
+//! bind parameters (5 per user).
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{breaker::CircuitBreaker, repository::SharedUserRepository, CreateUser, DbError, User};
+
+/// More than this is rejected as a whole, before anything is inserted
+const MAX_BATCH: usize = 1_000;
+/// Room for `MAX_BATCH` users of about 100 bytes each, with some to spare
+pub const MAX_BODY_BYTES: usize = 128 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct BulkResponse<T> {
+    pub summary: BulkSummary,
+    pub results: Vec<BulkItem<T>>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BulkSummary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkItem<T> {
+    /// Position of the item in the request
+    pub index: usize,
+    pub status: u16,
+    /// The resource's id, when the item got far enough to have one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retryable: Option<bool>,
+}
+
+/// Why one item failed. `Clone`: a failed statement fails every item alike.
+#[derive(Clone)]
+pub struct ItemError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl ItemError {
+    fn invalid(message: &str) -> Self {
+        ItemError {
+            status: StatusCode::BAD_REQUEST,
+            code: "INVALID_INPUT",
+            message: message.to_string(),
+            retryable: false,
+        }
+    }
+
+    fn email_taken() -> Self {
+        ItemError {
+            status: StatusCode::CONFLICT,
+            code: "EMAIL_TAKEN",
+            message: "Email already registered".to_string(),
+            retryable: false,
+        }
+    }
+}
+
+impl From<DbError> for ItemError {
+    fn from(error: DbError) -> Self {
+        let (status, code, retryable) = match &error {
+            DbError::Sqlx(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                (StatusCode::CONFLICT, "EMAIL_TAKEN", false)
+            }
+            DbError::NotFound => (StatusCode::NOT_FOUND, "NOT_FOUND", false),
+            DbError::InvalidQuery(_) => (StatusCode::BAD_REQUEST, "INVALID_INPUT", false),
+            DbError::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, "TIMEOUT", true),
+            DbError::NotReady | DbError::Cancelled | DbError::Unavailable(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "UNAVAILABLE", true)
+            }
+            DbError::Sqlx(_) => (StatusCode::INTERNAL_SERVER_ERROR, "DATABASE_ERROR", true),
+        };
+        let message = match code {
+            "EMAIL_TAKEN" => "Email already registered".to_string(),
+            // Driver messages quote SQL and hosts; keep them in the logs
+            "DATABASE_ERROR" => {
+                eprintln!("⚠️  Bulk insert failed: {}", error);
+                "Database error".to_string()
+            }
+            _ => error.to_string(),
+        };
+        ItemError {
+            status,
+            code,
+            message,
+            retryable,
+        }
+    }
+}
+
+impl<T> Default for BulkResponse<T> {
+    fn default() -> Self {
+        Self {
+            summary: BulkSummary::default(),
+            results: Vec::new(),
+        }
+    }
+}
+
+impl<T: Serialize> BulkResponse<T> {
+    /// Record the next item as a success
+    pub fn succeeded(&mut self, status: StatusCode, id: impl ToString, data: T) {
+        self.summary.succeeded += 1;
+        self.push(BulkItem {
+            index: self.results.len(),
+            status: status.as_u16(),
+            id: Some(id.to_string()),
+            data: Some(data),
+            error: None,
+            error_code: None,
+            retryable: None,
+        });
+    }
+
+    /// Record the next item as a failure
+    pub fn failed(&mut self, error: ItemError) {
+        self.summary.failed += 1;
+        self.push(BulkItem {
+            index: self.results.len(),
+            status: error.status.as_u16(),
+            id: None,
+            data: None,
+            error: Some(error.message),
+            error_code: Some(error.code),
+            retryable: Some(error.retryable),
+        });
+    }
+
+    fn push(&mut self, item: BulkItem<T>) {
+        self.summary.total += 1;
+        self.results.push(item);
+    }
+
+    /// 200 all ok, 207 mixed, 400 all failed
+    pub fn status(&self) -> StatusCode {
+        match (self.summary.succeeded, self.summary.failed) {
+            (_, 0) => StatusCode::OK,
+            (0, _) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::MULTI_STATUS,
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for BulkResponse<T> {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self)).into_response()
+    }
+}
+
+/// POST /users/bulk
+pub async fn create_users(
+    State(users): State<SharedUserRepository>,
+    State(breaker): State<CircuitBreaker>,
+    Json(batch): Json<Vec<CreateUser>>,
+) -> Result<BulkResponse<User>, (StatusCode, String)> {
+    if batch.is_empty() || batch.len() > MAX_BATCH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("A batch holds 1 to {} users", MAX_BATCH),
+        ));
+    }
+
+    // Invalid users are answered here and left out of the INSERT
+    let mut invalid = Vec::with_capacity(batch.len());
+    let mut valid = Vec::new();
+    for input in batch {
+        let error = validate(&input).err();
+        if error.is_none() {
+            valid.push(input);
+        }
+        invalid.push(error);
+    }
+
+    let valid_count = valid.len();
+    let inserted: Vec<Result<User, ItemError>> = match breaker.call(users.create_many(valid)).await
+    {
+        Ok(created) => created
+            .into_iter()
+            .map(|user| user.ok_or_else(ItemError::email_taken))
+            .collect(),
+        // Rolled back: the same error for every user that was sent
+        Err(e) => vec![Err(ItemError::from(DbError::from(e))); valid_count],
+    };
+
+    let mut inserted = inserted.into_iter();
+    let mut bulk = BulkResponse::default();
+    for error in invalid {
+        let result = match error {
+            Some(error) => Err(error),
+            None => inserted.next().expect("one result per valid user"),
+        };
+        match result {
+            Ok(user) => bulk.succeeded(StatusCode::CREATED, user.id, user),
+            Err(error) => bulk.failed(error),
+        }
+    }
+    Ok(bulk)
+}
+
+fn validate(input: &CreateUser) -> Result<(), ItemError> {
+    if input.name.trim().is_empty() {
+        return Err(ItemError::invalid("name is empty"));
+    }
+    if !input.email.contains('@') {
+        return Err(ItemError::invalid("email is not an address"));
+    }
+    Ok(())
+}
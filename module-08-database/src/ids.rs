@@ -0,0 +1,44 @@
+//! # Where New Ids Come From
+//!
+//! Rows get their `id` from Rust, not the database, and a bare
+//! `Uuid::new_v4()` makes every run different: a test could only check that
+//! an id "looks like" a UUID. Everything that inserts asks an `IdProvider`
+//! instead:
+//! - `RandomIds` in the server: `Uuid::new_v4()`, as before
+//! - `SequentialIds` in tests: `...0001`, `...0002`, ...
+//!
+//! `AppState` holds one for the handlers (onboarding), the repository is
+//! built with one, and `--seed` is handed one. Module 11 covers the pattern.
+
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub trait IdProvider: Send + Sync {
+    fn next_id(&self) -> Uuid;
+}
+
+/// How the provider is kept in state
+pub type SharedIds = Arc<dyn IdProvider>;
+
+pub struct RandomIds;
+
+impl IdProvider for RandomIds {
+    fn next_id(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// `00000000-0000-0000-0000-000000000001`, then `...0002`, ...
+#[cfg(test)]
+#[derive(Default)]
+pub struct SequentialIds(AtomicU64);
+
+#[cfg(test)]
+impl IdProvider for SequentialIds {
+    fn next_id(&self) -> Uuid {
+        let n = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        Uuid::from_u128(n as u128)
+    }
+}
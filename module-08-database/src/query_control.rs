@@ -0,0 +1,223 @@
+//! # Statement Timeouts & Cancellation on Disconnect
+//!
+//! Two ways a query can outlive its usefulness:
+//! - It is simply too slow: `statement_timeout` makes Postgres abort it.
+//!   The pool sets a default on every connection; a request can ask for a
+//!   different (capped) value, applied with `SET LOCAL` inside a transaction.
+//! - The client gave up: axum drops the handler future, but dropping a sqlx
+//!   future does *not* stop the query on the server - it keeps burning a
+//!   connection and CPU. So the query runs in a task that watches a drop
+//!   signal from the handler and calls `pg_cancel_backend` when it fires.
+
+#[cfg(not(feature = "sqlite"))]
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+#[cfg(not(feature = "sqlite"))]
+use serde::{Deserialize, Serialize};
+#[cfg(not(feature = "sqlite"))]
+use sqlx::{postgres::PgPoolOptions, Executor, PgPool};
+use std::time::Duration;
+#[cfg(not(feature = "sqlite"))]
+use std::time::Instant;
+#[cfg(not(feature = "sqlite"))]
+use tokio::sync::oneshot;
+
+#[cfg(not(feature = "sqlite"))]
+use crate::DbError;
+
+/// Postgres SQLSTATE for "query_canceled" (timeouts and pg_cancel_backend)
+const QUERY_CANCELED: &str = "57014";
+
+// ============================================================================
+// CONFIG
+// ============================================================================
+
+/// Statement timeout policy, stored in `AppState`
+#[derive(Debug, Clone, Copy)]
+// Only Postgres enforces them
+#[cfg_attr(feature = "sqlite", allow(dead_code))]
+pub struct QueryTimeouts {
+    /// Applied to every pooled connection
+    pub default: Duration,
+    /// Upper bound for per-request overrides
+    pub max: Duration,
+}
+
+impl QueryTimeouts {
+    /// `STATEMENT_TIMEOUT_MS` (default 5000) and `MAX_STATEMENT_TIMEOUT_MS` (default 30000).
+    /// `0` means "no timeout" to Postgres, so it is ignored like a typo.
+    pub fn from_env() -> Self {
+        let ms = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|ms| *ms > 0)
+                .map_or(Duration::from_millis(default), Duration::from_millis)
+        };
+        Self {
+            default: ms("STATEMENT_TIMEOUT_MS", 5_000),
+            max: ms("MAX_STATEMENT_TIMEOUT_MS", 30_000),
+        }
+    }
+
+    /// The timeout to use for one request: at most `max`, and at least
+    /// 1 ms - `SET LOCAL statement_timeout = 0` would switch it off
+    #[cfg(not(feature = "sqlite"))]
+    pub fn resolve(&self, requested_ms: Option<u64>) -> Duration {
+        requested_ms
+            .map(Duration::from_millis)
+            .unwrap_or(self.default)
+            .clamp(Duration::from_millis(1), self.max)
+    }
+
+    /// Set the default `statement_timeout` on every new pooled connection
+    #[cfg(not(feature = "sqlite"))]
+    pub fn apply_to(&self, options: PgPoolOptions) -> PgPoolOptions {
+        let default_ms = self.default.as_millis();
+        options.after_connect(move |conn, _meta| {
+            Box::pin(async move {
+                conn.execute(format!("SET statement_timeout = {}", default_ms).as_str())
+                    .await?;
+                Ok(())
+            })
+        })
+    }
+}
+
+/// Was this error Postgres aborting the statement?
+pub fn is_query_canceled(error: &sqlx::Error) -> bool {
+    error
+        .as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == QUERY_CANCELED)
+}
+
+// ============================================================================
+// CANCELLABLE QUERY
+// ============================================================================
+
+#[cfg(not(feature = "sqlite"))]
+#[derive(Debug, Deserialize)]
+pub struct SlowQueryParams {
+    /// How long the query sleeps (capped at 120)
+    seconds: Option<f64>,
+    /// Per-request statement timeout
+    timeout_ms: Option<u64>,
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[derive(Debug, Serialize)]
+pub struct SlowQueryResult {
+    backend_pid: i32,
+    slept_seconds: f64,
+    statement_timeout_ms: u128,
+    elapsed_ms: u128,
+}
+
+#[cfg(not(feature = "sqlite"))]
+/// GET /slow-query?seconds=10&timeout_ms=2000
+///
+/// Disconnect mid-query (Ctrl+C on curl) and watch the server log the
+/// cancellation; `SELECT * FROM pg_stat_activity` shows the query is gone.
+pub async fn slow_query(
+    State(pool): State<PgPool>,
+    State(timeouts): State<QueryTimeouts>,
+    Query(params): Query<SlowQueryParams>,
+) -> Result<Json<SlowQueryResult>, DbError> {
+    let seconds = params.seconds.unwrap_or(10.0).clamp(0.0, 120.0);
+    let timeout = timeouts.resolve(params.timeout_ms);
+
+    // Held by this handler future; dropped when the client disconnects
+    let (_client_connected, disconnected) = oneshot::channel::<()>();
+
+    tokio::spawn(run_cancellable(pool, timeout, seconds, disconnected))
+        .await
+        .map_err(|_| DbError::Cancelled)?
+        .map(Json)
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn run_cancellable(
+    pool: PgPool,
+    timeout: Duration,
+    seconds: f64,
+    mut disconnected: oneshot::Receiver<()>,
+) -> Result<SlowQueryResult, DbError> {
+    let started = Instant::now();
+    let mut tx = pool.begin().await?;
+
+    // SET doesn't take bind parameters; the value is an integer we computed
+    let timeout_ms = timeout.as_millis();
+    tx.execute(format!("SET LOCAL statement_timeout = {}", timeout_ms).as_str())
+        .await?;
+    let backend_pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let query = sqlx::query("SELECT pg_sleep($1)")
+        .bind(seconds)
+        .execute(&mut *tx);
+
+    tokio::select! {
+        result = query => {
+            result.map_err(|e| {
+                if is_query_canceled(&e) {
+                    DbError::Timeout(timeout_ms)
+                } else {
+                    DbError::Sqlx(e)
+                }
+            })?;
+        }
+        // `Err(RecvError)` = the sender was dropped = the handler is gone
+        _ = &mut disconnected => {
+            let cancelled: bool = sqlx::query_scalar("SELECT pg_cancel_backend($1)")
+                .bind(backend_pid)
+                .fetch_one(&pool)
+                .await?;
+            println!(
+                "⚠️  Client disconnected after {:?}; cancelled backend {}: {}",
+                started.elapsed(),
+                backend_pid,
+                cancelled
+            );
+            return Err(DbError::Cancelled);
+        }
+    }
+
+    tx.commit().await?;
+    Ok(SlowQueryResult {
+        backend_pid,
+        slept_seconds: seconds,
+        statement_timeout_ms: timeout_ms,
+        elapsed_ms: started.elapsed().as_millis(),
+    })
+}
+
+/// GET /slow-query - `pg_sleep`, `statement_timeout` and
+/// `pg_cancel_backend` have no SQLite equivalent
+#[cfg(feature = "sqlite")]
+pub async fn slow_query() -> (axum::http::StatusCode, &'static str) {
+    (
+        axum::http::StatusCode::NOT_IMPLEMENTED,
+        "Statement timeouts and cancellation need PostgreSQL",
+    )
+}
+
+#[cfg(all(test, not(feature = "sqlite")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_caps_and_never_disables_the_timeout() {
+        let timeouts = QueryTimeouts {
+            default: Duration::from_secs(5),
+            max: Duration::from_secs(30),
+        };
+        assert_eq!(timeouts.resolve(None), Duration::from_secs(5));
+        assert_eq!(timeouts.resolve(Some(2_000)), Duration::from_secs(2));
+        assert_eq!(timeouts.resolve(Some(60_000)), Duration::from_secs(30));
+        assert_eq!(timeouts.resolve(Some(0)), Duration::from_millis(1));
+    }
+}
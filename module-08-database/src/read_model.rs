@@ -0,0 +1,346 @@
+//! # Read Model (CQRS-lite)
+//!
+//! Keeps an in-memory, denormalized copy of the `users` table that is fed by
+//! Postgres `LISTEN/NOTIFY` change events:
+//! - A trigger publishes every INSERT/UPDATE/DELETE on `users`
+//! - A background task applies those events to a `HashMap` (Module 05 style state)
+//! - `GET /users/fast` serves reads from memory with staleness metadata
+//!
+//! Reads never touch the database, so they are fast - but they can lag
+//! behind the source of truth. The response tells the client by how much.
+//! An idle table sends no events, so silence alone can't tell "nothing
+//! changed" from "the listener is gone": every `HEARTBEAT_EVERY` the task
+//! sends a notification to itself, and once it comes back every change
+//! committed before it has been applied. Staleness counts from there.
+//!
+//! SQLite has no `LISTEN/NOTIFY`; with the `sqlite` feature the task takes a
+//! fresh snapshot every second instead.
+
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+#[cfg(not(feature = "sqlite"))]
+use serde::Deserialize;
+use serde::Serialize;
+#[cfg(not(feature = "sqlite"))]
+use sqlx::postgres::PgListener;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::{breaker::FromCache, db::DbPool, DbError, User};
+
+/// Channel the `users` trigger publishes to
+#[cfg(not(feature = "sqlite"))]
+pub const USERS_CHANNEL: &str = "users_changes";
+
+/// Channel the replication task pings itself on; the payload is the time
+/// the ping was sent
+#[cfg(not(feature = "sqlite"))]
+pub const HEARTBEAT_CHANNEL: &str = "users_read_model_heartbeat";
+
+/// How often an idle listener proves it's still connected and caught up
+#[cfg(not(feature = "sqlite"))]
+pub const HEARTBEAT_EVERY: Duration = Duration::from_secs(5);
+
+// ============================================================================
+// SCHEMA: change-event trigger
+// ============================================================================
+
+/// Install the trigger that turns row changes into notifications.
+///
+/// The payload is a small JSON document: `{"op": "INSERT", "id": ..., "row": {...}}`.
+/// DELETE events carry only the id. `row` leaves out the `search` tsvector:
+/// the read model has no use for it, and a notification holds under 8000 bytes.
+#[cfg(not(feature = "sqlite"))]
+pub async fn install_change_trigger(pool: &DbPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "CREATE OR REPLACE FUNCTION notify_users_change() RETURNS trigger AS $$
+        BEGIN
+            IF TG_OP = 'DELETE' THEN
+                PERFORM pg_notify('users_changes',
+                    json_build_object('op', TG_OP, 'id', OLD.id)::text);
+                RETURN OLD;
+            END IF;
+            PERFORM pg_notify('users_changes',
+                json_build_object('op', TG_OP, 'id', NEW.id, 'row', to_jsonb(NEW) - 'search')::text);
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DROP TRIGGER IF EXISTS users_notify ON users")
+        .execute(pool)
+        .await?;
+
+    sqlx::query(
+        "CREATE TRIGGER users_notify
+            AFTER INSERT OR UPDATE OR DELETE ON users
+            FOR EACH ROW EXECUTE FUNCTION notify_users_change()",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// ============================================================================
+// READ MODEL STATE
+// ============================================================================
+
+/// A change event as published by the trigger
+#[cfg(not(feature = "sqlite"))]
+#[derive(Debug, Deserialize)]
+struct UserChange {
+    op: String,
+    id: Uuid,
+    row: Option<User>,
+}
+
+/// The in-memory projection of the `users` table
+#[derive(Debug, Default)]
+pub struct ReadModel {
+    users: HashMap<Uuid, User>,
+    /// When the projection was last (re)built from a full snapshot
+    snapshot_at: Option<DateTime<Utc>>,
+    /// When the last change event was applied
+    last_event_at: Option<DateTime<Utc>>,
+    /// Everything committed before this has been applied: the send time
+    /// of the last heartbeat that came back
+    confirmed_at: Option<DateTime<Utc>>,
+    events_applied: u64,
+}
+
+pub type SharedReadModel = Arc<RwLock<ReadModel>>;
+
+impl ReadModel {
+    fn load_snapshot(&mut self, users: Vec<User>) {
+        self.users = users.into_iter().map(|u| (u.id, u)).collect();
+        self.snapshot_at = Some(Utc::now());
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn apply(&mut self, change: UserChange) {
+        match (change.op.as_str(), change.row) {
+            ("DELETE", _) => {
+                self.users.remove(&change.id);
+            }
+            (_, Some(row)) => {
+                self.users.insert(change.id, row);
+            }
+            (op, None) => {
+                eprintln!("⚠️  read model: {} event for {} without row", op, change.id);
+                return;
+            }
+        }
+        self.last_event_at = Some(Utc::now());
+        self.events_applied += 1;
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn confirm(&mut self, sent_at: DateTime<Utc>) {
+        self.confirmed_at = self.confirmed_at.max(Some(sent_at));
+    }
+
+    /// The last moment we know the projection matched the database. An
+    /// applied event doesn't count: it says nothing about the ones after it.
+    fn synced_at(&self) -> Option<DateTime<Utc>> {
+        self.snapshot_at.max(self.confirmed_at)
+    }
+}
+
+// ============================================================================
+// REPLICATION TASK
+// ============================================================================
+
+/// Background task: subscribe, snapshot, then apply events forever.
+///
+/// We LISTEN *before* taking the snapshot so no change can slip in between.
+/// If the listener connection drops, notifications sent in the meantime are
+/// lost, so we rebuild the projection from a fresh snapshot.
+pub async fn run_replication(pool: DbPool, model: SharedReadModel) {
+    loop {
+        if let Err(e) = replicate(&pool, &model).await {
+            eprintln!("⚠️  read model replication failed: {}, retrying in 1s", e);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn replicate(pool: &DbPool, model: &SharedReadModel) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener
+        .listen_all([USERS_CHANNEL, HEARTBEAT_CHANNEL])
+        .await?;
+
+    let users = sqlx::query_as::<_, User>("SELECT * FROM users")
+        .fetch_all(pool)
+        .await?;
+    model.write().unwrap().load_snapshot(users);
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_EVERY);
+    loop {
+        // `try_recv` only takes a notification once it has all of it, so
+        // losing the race to a tick drops nothing
+        let notification = tokio::select! {
+            notification = listener.try_recv() => notification?,
+            _ = heartbeat.tick() => {
+                send_heartbeat(pool).await;
+                continue;
+            }
+        };
+        // `None` when the connection was lost
+        let Some(notification) = notification else {
+            return Ok(());
+        };
+
+        if notification.channel() == HEARTBEAT_CHANNEL {
+            // Notifications arrive in commit order, so every change
+            // committed before the heartbeat was sent has been applied
+            match notification.payload().parse::<DateTime<Utc>>() {
+                Ok(sent_at) => model.write().unwrap().confirm(sent_at),
+                Err(e) => eprintln!("⚠️  read model: bad heartbeat: {}", e),
+            }
+            continue;
+        }
+        match serde_json::from_str::<UserChange>(notification.payload()) {
+            Ok(change) => model.write().unwrap().apply(change),
+            Err(e) => eprintln!("⚠️  read model: bad payload: {}", e),
+        }
+    }
+}
+
+/// A failed heartbeat only lets the staleness grow; the listener decides
+/// whether to reconnect
+#[cfg(not(feature = "sqlite"))]
+async fn send_heartbeat(pool: &DbPool) {
+    let sent = sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(HEARTBEAT_CHANNEL)
+        .bind(Utc::now().to_rfc3339())
+        .execute(pool)
+        .await;
+    if let Err(e) = sent {
+        eprintln!("⚠️  read model: heartbeat not sent: {}", e);
+    }
+}
+
+/// SQLite has no `LISTEN`: re-read the whole table, every second
+#[cfg(feature = "sqlite")]
+async fn replicate(pool: &DbPool, model: &SharedReadModel) -> Result<(), sqlx::Error> {
+    loop {
+        let users = sqlx::query_as::<_, User>("SELECT * FROM users")
+            .fetch_all(pool)
+            .await?;
+        model.write().unwrap().load_snapshot(users);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// ============================================================================
+// HANDLER
+// ============================================================================
+
+#[derive(Serialize)]
+pub struct Staleness {
+    source: &'static str,
+    snapshot_at: Option<DateTime<Utc>>,
+    last_event_at: Option<DateTime<Utc>>,
+    confirmed_at: Option<DateTime<Utc>>,
+    /// Milliseconds since the projection was last known to be in sync
+    staleness_ms: i64,
+    events_applied: u64,
+}
+
+#[derive(Serialize)]
+pub struct FastUsersResponse {
+    data: Vec<User>,
+    meta: Staleness,
+}
+
+/// GET /users/fast - served entirely from memory
+pub async fn list_users_fast(
+    State(model): State<SharedReadModel>,
+) -> Result<Json<FastUsersResponse>, DbError> {
+    let model = model.read().unwrap();
+
+    // Not replicated yet - don't pretend an empty list is the truth
+    let synced_at = model.synced_at().ok_or(DbError::NotReady)?;
+
+    let mut data: Vec<User> = model.users.values().cloned().collect();
+    data.sort_by_key(|u| std::cmp::Reverse(u.created_at));
+
+    Ok(Json(FastUsersResponse {
+        data,
+        meta: Staleness {
+            source: "read_model",
+            snapshot_at: model.snapshot_at,
+            last_event_at: model.last_event_at,
+            confirmed_at: model.confirmed_at,
+            staleness_ms: (Utc::now() - synced_at).num_milliseconds(),
+            events_applied: model.events_applied,
+        },
+    }))
+}
+
+// ============================================================================
+// FALLBACK READS
+// ============================================================================
+
+/// `GET /users` while the database is unavailable
+pub fn cached_users(model: &SharedReadModel) -> Result<FromCache<Vec<User>>, DbError> {
+    let model = model.read().unwrap();
+    let synced_at = model.synced_at().ok_or(DbError::NotReady)?;
+
+    let mut users: Vec<User> = model.users.values().cloned().collect();
+    users.sort_by_key(|u| std::cmp::Reverse(u.created_at));
+    Ok(FromCache {
+        body: users,
+        staleness_ms: (Utc::now() - synced_at).num_milliseconds(),
+    })
+}
+
+/// `GET /users/{id}` while the database is unavailable
+pub fn cached_user(model: &SharedReadModel, id: Uuid) -> Result<FromCache<User>, DbError> {
+    let model = model.read().unwrap();
+    let synced_at = model.synced_at().ok_or(DbError::NotReady)?;
+
+    let user = model.users.get(&id).cloned().ok_or(DbError::NotFound)?;
+    Ok(FromCache {
+        body: user,
+        staleness_ms: (Utc::now() - synced_at).num_milliseconds(),
+    })
+}
+
+#[cfg(all(test, not(feature = "sqlite")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_staleness_counts_from_the_last_heartbeat_not_the_last_event() {
+        let mut model = ReadModel::default();
+        assert_eq!(model.synced_at(), None);
+        model.load_snapshot(Vec::new());
+        let snapshot_at = model.snapshot_at;
+
+        // An event applied later doesn't prove the ones after it arrived
+        model.apply(UserChange {
+            op: "DELETE".to_string(),
+            id: Uuid::nil(),
+            row: None,
+        });
+        assert_eq!(model.synced_at(), snapshot_at);
+
+        // An idle table: only the heartbeats move it forward
+        let sent_at = Utc::now() + chrono::Duration::seconds(5);
+        model.confirm(sent_at);
+        assert_eq!(model.synced_at(), Some(sent_at));
+        // A heartbeat that was overtaken doesn't move it back
+        model.confirm(sent_at - chrono::Duration::seconds(1));
+        assert_eq!(model.synced_at(), Some(sent_at));
+    }
+}
@@ -0,0 +1,550 @@
+//! # Repository: Handlers Without SQL
+//!
+//! A handler that runs `sqlx::query(...)` on a `PgPool` can only be tested
+//! against a real database. The CRUD handlers depend on a trait instead,
+//! and the state holds whichever implementation the app was built with:
+//!
+//! ```ignore
+//! trait UserRepository: Send + Sync {
+//!     fn find(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>>;
+//!     fn list(..), fn create(..), fn create_many(..), fn update(..), fn delete(..)
+//! }
+//!
+//! async fn get_user(State(users): State<SharedUserRepository>, ..) {
+//!     users.find(id).await
+//! }
+//! ```
+//!
+//! - `PgUserRepository`: the SQL, on the `DbPool` (a `SqlitePool` with the
+//!   `sqlite` feature - the queries are the same)
+//! - `InMemoryUserRepository`: a `HashMap`, for tests. It can also be told
+//!   to fail like an unreachable database, to test the breaker's fallback.
+//!
+//! The methods return boxed futures, like module 05's `Database`, because
+//! `async fn` in a trait can't be called through `dyn`. Errors stay
+//! `sqlx::Error`: the circuit breaker needs to tell an outage from a bad
+//! query, whichever implementation it wraps.
+//!
+//! Bulk inserts go through `create_many`. Onboarding doesn't: its three
+//! writes share one transaction, which is the pool's business. Neither does
+//! search: `tsvector` is Postgres SQL, with no portable equivalent.
+
+use futures::future::BoxFuture;
+use sqlx::QueryBuilder;
+use std::{collections::HashMap, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    db::{Db, DbPool},
+    ids::SharedIds,
+    listing::{ListUsers, Pagination, UserPage},
+    CreateUser, UpdateUser, User,
+};
+
+/// What the user handlers need from storage
+pub(crate) trait UserRepository: Send + Sync {
+    fn find(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>>;
+
+    fn list<'a>(
+        &'a self,
+        query: &'a ListUsers,
+        pagination: Pagination,
+    ) -> BoxFuture<'a, Result<UserPage, sqlx::Error>>;
+
+    fn create(&self, input: CreateUser) -> BoxFuture<'_, Result<User, sqlx::Error>>;
+
+    /// All or nothing, one result per input in order: `None` where the
+    /// email was taken, by an existing user or earlier in `inputs`
+    fn create_many(
+        &self,
+        inputs: Vec<CreateUser>,
+    ) -> BoxFuture<'_, Result<Vec<Option<User>>, sqlx::Error>>;
+
+    /// `None` if there is no such user
+    fn update(
+        &self,
+        id: Uuid,
+        input: UpdateUser,
+    ) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>>;
+
+    /// `false` if there was no such user
+    fn delete(&self, id: Uuid) -> BoxFuture<'_, Result<bool, sqlx::Error>>;
+}
+
+/// How the repository is kept in `AppState`
+pub(crate) type SharedUserRepository = Arc<dyn UserRepository>;
+
+// ============================================================================
+// SQL
+// ============================================================================
+
+pub struct PgUserRepository {
+    pool: DbPool,
+    ids: SharedIds,
+}
+
+impl PgUserRepository {
+    /// New users get their id from `ids`
+    pub fn new(pool: DbPool, ids: SharedIds) -> Self {
+        Self { pool, ids }
+    }
+}
+
+impl UserRepository for PgUserRepository {
+    fn find(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(
+            sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&self.pool),
+        )
+    }
+
+    fn list<'a>(
+        &'a self,
+        query: &'a ListUsers,
+        pagination: Pagination,
+    ) -> BoxFuture<'a, Result<UserPage, sqlx::Error>> {
+        Box::pin(query.fetch(&self.pool, pagination))
+    }
+
+    fn create(&self, input: CreateUser) -> BoxFuture<'_, Result<User, sqlx::Error>> {
+        Box::pin(async move {
+            sqlx::query_as::<_, User>(
+                "INSERT INTO users (id, name, email, created_at, updated_at) VALUES ($1, $2, $3, $4, $4) RETURNING *",
+            )
+            .bind(self.ids.next_id())
+            .bind(&input.name)
+            .bind(&input.email)
+            .bind(chrono::Utc::now())
+            .fetch_one(&self.pool)
+            .await
+        })
+    }
+
+    /// One multi-row `INSERT .. ON CONFLICT (email) DO NOTHING RETURNING *`:
+    /// the rows it skipped are the missing ids
+    fn create_many(
+        &self,
+        inputs: Vec<CreateUser>,
+    ) -> BoxFuture<'_, Result<Vec<Option<User>>, sqlx::Error>> {
+        Box::pin(async move {
+            if inputs.is_empty() {
+                return Ok(Vec::new());
+            }
+            let now = chrono::Utc::now();
+            let ids: Vec<Uuid> = inputs.iter().map(|_| self.ids.next_id()).collect();
+            let mut insert = QueryBuilder::<Db>::new(
+                "INSERT INTO users (id, name, email, created_at, updated_at) ",
+            );
+            insert.push_values(ids.iter().zip(&inputs), |mut row, (id, input)| {
+                row.push_bind(id)
+                    .push_bind(&input.name)
+                    .push_bind(&input.email)
+                    .push_bind(now)
+                    .push_bind(now);
+            });
+            insert.push(" ON CONFLICT (email) DO NOTHING RETURNING *");
+
+            let mut tx = self.pool.begin().await?;
+            let created = insert.build_query_as::<User>().fetch_all(&mut *tx).await?;
+            tx.commit().await?;
+
+            let mut created: HashMap<Uuid, User> =
+                created.into_iter().map(|user| (user.id, user)).collect();
+            Ok(ids.iter().map(|id| created.remove(id)).collect())
+        })
+    }
+
+    fn update(
+        &self,
+        id: Uuid,
+        input: UpdateUser,
+    ) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+        Box::pin(async move {
+            sqlx::query_as::<_, User>(
+                "UPDATE users SET name = COALESCE($2, name), email = COALESCE($3, email), updated_at = $4 WHERE id = $1 RETURNING *"
+            )
+            .bind(id)
+            .bind(&input.name)
+            .bind(&input.email)
+            .bind(chrono::Utc::now())
+            .fetch_optional(&self.pool)
+            .await
+        })
+    }
+
+    fn delete(&self, id: Uuid) -> BoxFuture<'_, Result<bool, sqlx::Error>> {
+        Box::pin(async move {
+            let result = sqlx::query("DELETE FROM users WHERE id = $1")
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+}
+
+// ============================================================================
+// IN-MEMORY FAKE
+// ============================================================================
+
+#[cfg(test)]
+pub use fake::InMemoryUserRepository;
+
+#[cfg(test)]
+mod fake {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::ids::{IdProvider, SequentialIds};
+
+    /// Users in a `HashMap`, with sequential ids; every call fails while
+    /// `down` is set. Emails are unique, as `users_email_key` makes them.
+    #[derive(Default)]
+    pub struct InMemoryUserRepository {
+        users: Mutex<HashMap<Uuid, User>>,
+        ids: SequentialIds,
+        down: AtomicBool,
+    }
+
+    impl InMemoryUserRepository {
+        /// Fail every call from now on, as a dead pool would
+        pub fn go_down(&self) {
+            self.down.store(true, Ordering::SeqCst);
+        }
+
+        fn check(&self) -> Result<(), sqlx::Error> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(sqlx::Error::PoolTimedOut);
+            }
+            Ok(())
+        }
+    }
+
+    /// What Postgres reports for a second user with the same email
+    #[derive(Debug)]
+    struct EmailTaken;
+
+    impl std::fmt::Display for EmailTaken {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(EMAIL_TAKEN)
+        }
+    }
+
+    impl std::error::Error for EmailTaken {}
+
+    const EMAIL_TAKEN: &str = "duplicate key value violates unique constraint \"users_email_key\"";
+
+    impl sqlx::error::DatabaseError for EmailTaken {
+        fn message(&self) -> &str {
+            EMAIL_TAKEN
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::UniqueViolation
+        }
+    }
+
+    fn email_taken() -> sqlx::Error {
+        sqlx::Error::Database(Box::new(EmailTaken))
+    }
+
+    impl UserRepository for InMemoryUserRepository {
+        fn find(&self, id: Uuid) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+            Box::pin(async move {
+                self.check()?;
+                Ok(self.users.lock().unwrap().get(&id).cloned())
+            })
+        }
+
+        fn list<'a>(
+            &'a self,
+            query: &'a ListUsers,
+            pagination: Pagination,
+        ) -> BoxFuture<'a, Result<UserPage, sqlx::Error>> {
+            Box::pin(async move {
+                self.check()?;
+                let users = self.users.lock().unwrap().values().cloned().collect();
+                Ok(query.page_of(users, pagination))
+            })
+        }
+
+        fn create(&self, input: CreateUser) -> BoxFuture<'_, Result<User, sqlx::Error>> {
+            Box::pin(async move {
+                self.check()?;
+                let mut users = self.users.lock().unwrap();
+                if users.values().any(|user| user.email == input.email) {
+                    return Err(email_taken());
+                }
+                let now = chrono::Utc::now();
+                let user = User {
+                    id: self.ids.next_id(),
+                    name: input.name,
+                    email: input.email,
+                    created_at: now,
+                    updated_at: now,
+                };
+                users.insert(user.id, user.clone());
+                Ok(user)
+            })
+        }
+
+        fn create_many(
+            &self,
+            inputs: Vec<CreateUser>,
+        ) -> BoxFuture<'_, Result<Vec<Option<User>>, sqlx::Error>> {
+            Box::pin(async move {
+                self.check()?;
+                let now = chrono::Utc::now();
+                let mut users = self.users.lock().unwrap();
+                Ok(inputs
+                    .into_iter()
+                    .map(|input| {
+                        if users.values().any(|user| user.email == input.email) {
+                            return None;
+                        }
+                        let user = User {
+                            id: self.ids.next_id(),
+                            name: input.name,
+                            email: input.email,
+                            created_at: now,
+                            updated_at: now,
+                        };
+                        users.insert(user.id, user.clone());
+                        Some(user)
+                    })
+                    .collect())
+            })
+        }
+
+        fn update(
+            &self,
+            id: Uuid,
+            input: UpdateUser,
+        ) -> BoxFuture<'_, Result<Option<User>, sqlx::Error>> {
+            Box::pin(async move {
+                self.check()?;
+                let mut users = self.users.lock().unwrap();
+                if let Some(email) = &input.email {
+                    if users
+                        .values()
+                        .any(|user| user.id != id && &user.email == email)
+                    {
+                        return Err(email_taken());
+                    }
+                }
+                let Some(user) = users.get_mut(&id) else {
+                    return Ok(None);
+                };
+                if let Some(name) = input.name {
+                    user.name = name;
+                }
+                if let Some(email) = input.email {
+                    user.email = email;
+                }
+                user.updated_at = chrono::Utc::now();
+                Ok(Some(user.clone()))
+            })
+        }
+
+        fn delete(&self, id: Uuid) -> BoxFuture<'_, Result<bool, sqlx::Error>> {
+            Box::pin(async move {
+                self.check()?;
+                Ok(self.users.lock().unwrap().remove(&id).is_some())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, AppState};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use course_routes::conformance::{self, Probes};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    /// The whole app on a fake repository; nothing here touches a database
+    fn test_app() -> (Router, Arc<InMemoryUserRepository>) {
+        let repository = Arc::new(InMemoryUserRepository::default());
+        (app(AppState::for_tests(repository.clone())), repository)
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(json) => request
+                .header("content-type", "application/json")
+                .body(Body::from(json.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let json = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+        (status, json)
+    }
+
+    #[tokio::test]
+    async fn test_crud_handlers_run_on_the_fake() {
+        let (app, _) = test_app();
+
+        let alice = serde_json::json!({ "name": "Alice", "email": "alice@example.com" });
+        let (status, created) = send(&app, "POST", "/users", Some(alice)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["id"], "00000000-0000-0000-0000-000000000001");
+        let uri = format!("/users/{}", created["id"].as_str().unwrap());
+
+        let (status, found) = send(&app, "GET", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found["email"], "alice@example.com");
+
+        let rename = serde_json::json!({ "name": "Alicia" });
+        let (status, updated) = send(&app, "PUT", &uri, Some(rename)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["name"], "Alicia");
+        assert_eq!(updated["email"], "alice@example.com");
+
+        let (status, _) = send(&app, "DELETE", &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(send(&app, "GET", &uri, None).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            send(&app, "DELETE", &uri, None).await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_filters_and_sorts() {
+        let (app, _) = test_app();
+        for name in ["Carol", "alan", "Bob", "Alice"] {
+            let email = format!("{}@example.com", name.to_lowercase());
+            let user = serde_json::json!({ "name": name, "email": email });
+            send(&app, "POST", "/users", Some(user)).await;
+        }
+
+        let (status, page) = send(&app, "GET", "/users?name=AL&sort=email&per_page=1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["data"][0]["name"], "alan");
+        assert_eq!(page["meta"]["total"], 2);
+        assert_eq!(page["meta"]["total_pages"], 2);
+
+        let (_, page) = send(&app, "GET", "/users?sort=-name&page=2&per_page=3", None).await;
+        assert_eq!(page["data"].as_array().unwrap().len(), 1);
+        assert_eq!(page["data"][0]["name"], "Alice");
+
+        let (status, _) = send(&app, "GET", "/users?sort=password", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_reports_conflicts_per_user_and_limits_the_body() {
+        let (app, _) = test_app();
+        let alice = serde_json::json!({ "name": "Alice", "email": "alice@example.com" });
+        send(&app, "POST", "/users", Some(alice)).await;
+
+        let batch = serde_json::json!([
+            { "name": "Bob", "email": "bob@example.com" },
+            { "name": "Alice again", "email": "alice@example.com" },
+            { "name": "", "email": "nobody@example.com" },
+            { "name": "Bob again", "email": "bob@example.com" },
+        ]);
+        let (status, bulk) = send(&app, "POST", "/users/bulk", Some(batch)).await;
+        assert_eq!(status, StatusCode::MULTI_STATUS);
+        let statuses: Vec<u64> = bulk["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [201, 409, 400, 409]);
+        assert_eq!(bulk["results"][3]["error_code"], "EMAIL_TAKEN");
+
+        // Well under MAX_BATCH users, but over MAX_BODY_BYTES
+        let name = "x".repeat(1_000);
+        let batch: Vec<_> = (0..200)
+            .map(|n| serde_json::json!({ "name": name, "email": format!("{}@example.com", n) }))
+            .collect();
+        let (status, _) = send(&app, "POST", "/users/bulk", Some(batch.into())).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_a_taken_email_is_a_conflict() {
+        let (app, _) = test_app();
+        let alice = serde_json::json!({ "name": "Alice", "email": "alice@example.com" });
+        let bob = serde_json::json!({ "name": "Bob", "email": "bob@example.com" });
+        send(&app, "POST", "/users", Some(alice.clone())).await;
+        let (_, created) = send(&app, "POST", "/users", Some(bob)).await;
+
+        let (status, _) = send(&app, "POST", "/users", Some(alice)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let uri = format!("/users/{}", created["id"].as_str().unwrap());
+        let taken = serde_json::json!({ "email": "alice@example.com" });
+        let (status, _) = send(&app, "PUT", &uri, Some(taken)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // Keeping your own email isn't a conflict
+        let same = serde_json::json!({ "email": "bob@example.com" });
+        let (status, _) = send(&app, "PUT", &uri, Some(same)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_an_outage_falls_back_for_reads_and_fails_writes() {
+        let (app, repository) = test_app();
+        repository.go_down();
+
+        // Reads try the read model, which was never synced here
+        let (status, _) = send(&app, "GET", "/users", None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        // Writes get 503 + Retry-After, before the breaker trips too
+        let bob = serde_json::json!({ "name": "Bob", "email": "bob@example.com" });
+        let request = Request::post("/users")
+            .header("content-type", "application/json")
+            .body(Body::from(bob.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "1");
+    }
+
+    #[tokio::test]
+    #[ignore = "Axum's rejections and `DbError` both answer in plain text"]
+    async fn test_error_conformance() {
+        let probes = Probes {
+            get_route: "/users/fast",
+            json_route: Some("/users"),
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        conformance::assert_conforms(test_app().0, &probes).await;
+    }
+}
@@ -0,0 +1,185 @@
+//! # Full-Text Search
+//!
+//! `GET /users/search?q=grace hopper&page=1&per_page=20`
+//!
+//! `?name=` on `GET /users` is `ILIKE '%..%'`: every row is scanned and
+//! the matches come back in no useful order. Full-text search asks
+//! Postgres instead:
+//! - `users.search` is a `tsvector` of the name (weight A) and email
+//!   (weight B), a generated column with a GIN index
+//!   (`migrations/0004_users_search.sql`), so writes need no extra code
+//! - `websearch_to_tsquery` reads `q` like a search box: words are ANDed,
+//!   `"quoted phrase"`, `or`, and `-word` to exclude
+//! - `ts_rank` orders the matches; a hit in the name outranks one in the email
+//! - `ts_headline` returns the name and email with the matched words in
+//!   `<mark>`. The text is HTML-escaped first, so the `<mark>`s are the
+//!   only tags in it and a page can insert it as it is
+//!
+//! Pages are the same `Pagination` and `meta` as `GET /users`, sorted by
+//! `-rank`. Words match whole: `q=gra` doesn't find Grace.
+//!
+//! This is SQL only Postgres has, so it isn't part of `UserRepository`: the
+//! CRUD handlers stay portable, and this handler queries the pool itself.
+//! On SQLite it answers `501 Not Implemented`.
+
+#[cfg(not(feature = "sqlite"))]
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+#[cfg(not(feature = "sqlite"))]
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "sqlite"))]
+use crate::{
+    breaker::CircuitBreaker,
+    db::DbPool,
+    listing::{PageMeta, Pagination},
+    DbError, User,
+};
+
+/// The `ts_headline` markers around each matched word
+#[cfg(not(feature = "sqlite"))]
+const HIGHLIGHT: &str = "StartSel=<mark>, StopSel=</mark>, HighlightAll=true";
+
+/// SQL for `column` with `&`, `<` and `>` escaped. Postgres' parser reads
+/// `&lt;` and friends as entities, not words, so matching is unchanged.
+#[cfg(not(feature = "sqlite"))]
+fn html_escaped(column: &str) -> String {
+    format!(
+        "replace(replace(replace({}, '&', '&amp;'), '<', '&lt;'), '>', '&gt;')",
+        column
+    )
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    q: String,
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    user: User,
+    rank: f32,
+    highlighted_name: String,
+    highlighted_email: String,
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[derive(Debug, Serialize)]
+pub struct SearchPage {
+    data: Vec<SearchHit>,
+    meta: PageMeta,
+}
+
+#[cfg(not(feature = "sqlite"))]
+/// GET /users/search?q=...
+pub async fn search_users(
+    State(pool): State<DbPool>,
+    State(breaker): State<CircuitBreaker>,
+    Query(params): Query<SearchParams>,
+    pagination: Pagination,
+) -> Result<Json<SearchPage>, DbError> {
+    let q = params.q.trim();
+    if q.is_empty() {
+        return Err(DbError::InvalidQuery("q must not be empty".to_string()));
+    }
+    let page = breaker.call(search(&pool, q, pagination)).await?;
+    Ok(Json(page))
+}
+
+/// A `COUNT(*)` for the total, then one page of hits, best first
+#[cfg(not(feature = "sqlite"))]
+async fn search(pool: &DbPool, q: &str, pagination: Pagination) -> Result<SearchPage, sqlx::Error> {
+    let total: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE search @@ websearch_to_tsquery('simple', $1)",
+    )
+    .bind(q)
+    .fetch_one(pool)
+    .await?;
+
+    // `id` breaks ties, as in `listing.rs`
+    let sql = format!(
+        "SELECT users.*,
+                ts_rank(search, query) AS rank,
+                ts_headline('simple', {}, query, $2) AS highlighted_name,
+                ts_headline('simple', {}, query, $2) AS highlighted_email
+         FROM users, websearch_to_tsquery('simple', $1) AS query
+         WHERE search @@ query
+         ORDER BY rank DESC, id
+         LIMIT $3 OFFSET $4",
+        html_escaped("name"),
+        html_escaped("email"),
+    );
+    let data = sqlx::query_as::<_, SearchHit>(&sql)
+        .bind(q)
+        .bind(HIGHLIGHT)
+        .bind(i64::from(pagination.per_page))
+        .bind(pagination.offset() as i64)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(SearchPage {
+        data,
+        meta: PageMeta::new(pagination, total as u64, "-rank".to_string()),
+    })
+}
+
+/// GET /users/search - `tsvector`, `ts_rank` and `ts_headline` are
+/// Postgres features
+#[cfg(feature = "sqlite")]
+pub async fn search_users() -> (axum::http::StatusCode, &'static str) {
+    (
+        axum::http::StatusCode::NOT_IMPLEMENTED,
+        "Full-text search needs PostgreSQL",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{app, repository::InMemoryUserRepository, AppState};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    /// The pool in `for_tests` is never connected: these answers come
+    /// before any query
+    fn test_app() -> Router {
+        app(AppState::for_tests(Arc::new(
+            InMemoryUserRepository::default(),
+        )))
+    }
+
+    async fn status(uri: &str) -> StatusCode {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        test_app().oneshot(request).await.unwrap().status()
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    #[tokio::test]
+    async fn test_a_blank_or_missing_q_is_rejected() {
+        assert_eq!(status("/users/search?q=").await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status("/users/search?q=%20%20").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(status("/users/search").await, StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_has_no_full_text_search() {
+        assert_eq!(
+            status("/users/search?q=grace").await,
+            StatusCode::NOT_IMPLEMENTED
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! # Seeding Fake Users
+//!
+//! Paging through three users teaches nothing. `cargo run -- --seed 500`
+//! fills `users` with 500 made-up people, then exits without starting the
+//! server:
+//! - one transaction: a failed seed leaves no half-filled table
+//! - multi-row `INSERT`s of `CHUNK` users, built with `QueryBuilder::push_values`
+//! - idempotent: user `n` is always the same person with the same email,
+//!   and `ON CONFLICT (email) DO NOTHING` skips the ones already there. Run
+//!   it twice and the second run inserts nothing; ask for more and only the
+//!   new ones are added.
+//!
+//! Names come from the `fake` crate, driven by a ChaCha RNG seeded with the
+//! user's index: ChaCha gives the same stream on every platform and `rand`
+//! version, and `fake` is pinned, so user `n` never changes. Emails are
+//! `first.lastN@example.com` and sign-up dates are spread over the last 180
+//! days, so `?name=`, `?sort=` and `?created_after=` all have something to
+//! work with.
+
+use chrono::{DateTime, Duration, Utc};
+use fake::{
+    faker::name::en::{FirstName, LastName},
+    Fake,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use sqlx::QueryBuilder;
+
+use crate::{
+    db::{Db, DbPool},
+    ids::IdProvider,
+};
+
+/// Users seeded by a bare `--seed`
+pub const DEFAULT_COUNT: u32 = 200;
+/// Most users one `--seed` will add; more is refused, not quietly trimmed
+pub const MAX_COUNT: u32 = 100_000;
+/// Rows per `INSERT`: 5 parameters each stays under every database's limit
+const CHUNK: u32 = 1_000;
+/// Sign-up dates go back this far
+const SPREAD_MINUTES: i64 = 180 * 24 * 60;
+
+pub struct Seeded {
+    pub inserted: u64,
+    pub skipped: u64,
+}
+
+struct FakeUser {
+    name: String,
+    email: String,
+    created_at: DateTime<Utc>,
+}
+
+/// The `n`th fake user: the same `n` is always the same person
+fn fake_user(n: u32, now: DateTime<Utc>) -> FakeUser {
+    let mut rng = ChaCha8Rng::seed_from_u64(u64::from(n));
+    let first: String = FirstName().fake_with_rng(&mut rng);
+    let last: String = LastName().fake_with_rng(&mut rng);
+    let minutes_ago = rng.random_range(0..SPREAD_MINUTES);
+    FakeUser {
+        email: format!(
+            "{}.{}{}@example.com",
+            local_part(&first),
+            local_part(&last),
+            n
+        ),
+        name: format!("{} {}", first, last),
+        created_at: now - Duration::minutes(minutes_ago),
+    }
+}
+
+/// "O'Reilly" -> "oreilly": keep emails to plain ASCII letters
+fn local_part(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The `[count]` after `--seed`: a number from 1 to `MAX_COUNT`
+pub fn parse_count(arg: Option<&str>) -> Result<u32, String> {
+    let Some(arg) = arg else {
+        return Ok(DEFAULT_COUNT);
+    };
+    match arg.parse::<u32>() {
+        Ok(count @ 1..=MAX_COUNT) => Ok(count),
+        Ok(0) => Err("--seed needs at least 1 user".to_string()),
+        Ok(_) | Err(_) if !arg.is_empty() && arg.bytes().all(|b| b.is_ascii_digit()) => {
+            Err(format!(
+                "--seed adds at most {} users per run, got {}",
+                MAX_COUNT, arg
+            ))
+        }
+        _ => Err(format!("--seed takes a number of users, got {:?}", arg)),
+    }
+}
+
+/// `--seed [count]`: users 0 to `count - 1`, whichever aren't there yet.
+/// `count` comes from `parse_count`, so it is never above `MAX_COUNT`.
+pub async fn run(pool: &DbPool, count: u32, ids: &dyn IdProvider) -> Result<Seeded, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    let mut inserted = 0;
+
+    for start in (0..count).step_by(CHUNK as usize) {
+        let users = (start..count.min(start + CHUNK)).map(|n| fake_user(n, now));
+        let mut insert =
+            QueryBuilder::<Db>::new("INSERT INTO users (id, name, email, created_at, updated_at) ");
+        insert.push_values(users, |mut row, user| {
+            row.push_bind(ids.next_id())
+                .push_bind(user.name)
+                .push_bind(user.email)
+                .push_bind(user.created_at)
+                .push_bind(user.created_at);
+        });
+        insert.push(" ON CONFLICT (email) DO NOTHING");
+        inserted += insert.build().execute(&mut *tx).await?.rows_affected();
+    }
+
+    tx.commit().await?;
+    Ok(Seeded {
+        inserted,
+        skipped: u64::from(count) - inserted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_fake_users_are_stable_and_emails_unique() {
+        let now = Utc::now();
+        let first = fake_user(42, now);
+        let again = fake_user(42, now);
+        assert_eq!(first.name, again.name);
+        assert_eq!(first.email, again.email);
+        assert_eq!(first.created_at, again.created_at);
+
+        let emails: HashSet<String> = (0..5_000).map(|n| fake_user(n, now).email).collect();
+        assert_eq!(emails.len(), 5_000);
+        assert!((0..5_000).all(|n| now - fake_user(n, now).created_at <= Duration::days(180)));
+    }
+
+    #[test]
+    fn test_fake_users_are_varied_plain_ascii_emails() {
+        let now = Utc::now();
+        let names: HashSet<String> = (0..100).map(|n| fake_user(n, now).name).collect();
+        assert!(names.len() > 50, "only {} distinct names", names.len());
+
+        for n in 0..1_000 {
+            let email = fake_user(n, now).email;
+            let (local, domain) = email.split_once('@').unwrap();
+            assert_eq!(domain, "example.com");
+            assert!(local.ends_with(&n.to_string()), "{}", email);
+            assert!(local
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'.'));
+        }
+    }
+
+    #[test]
+    fn test_seed_counts_above_the_maximum_are_refused() {
+        assert_eq!(parse_count(None), Ok(DEFAULT_COUNT));
+        assert_eq!(parse_count(Some("500")), Ok(500));
+        assert_eq!(parse_count(Some("100000")), Ok(MAX_COUNT));
+
+        let too_many = parse_count(Some("100001")).unwrap_err();
+        assert!(too_many.contains("at most 100000"), "{}", too_many);
+        // Past u32::MAX is still "too many", not "not a number"
+        let huge = parse_count(Some("99999999999")).unwrap_err();
+        assert!(huge.contains("at most"), "{}", huge);
+
+        assert!(parse_count(Some("0")).is_err());
+        assert!(parse_count(Some("-5"))
+            .unwrap_err()
+            .contains("number of users"));
+        assert!(parse_count(Some("lots"))
+            .unwrap_err()
+            .contains("number of users"));
+    }
+}
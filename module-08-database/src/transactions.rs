@@ -0,0 +1,194 @@
+//! # Transactions: Several Writes, All or Nothing
+//!
+//! `POST /users/onboard` creates a user, their profile and a first post.
+//! Three `INSERT`s on the pool would be three transactions: if the post
+//! fails, a user without a post is left behind. Here they share one:
+//!
+//! ```ignore
+//! let mut tx = pool.begin().await?;              // BEGIN
+//! sqlx::query("INSERT ...").execute(&mut *tx)    // on the transaction's connection
+//! tx.commit().await?;                            // COMMIT - or tx.rollback()
+//! ```
+//!
+//! Until `COMMIT` nobody else sees the new rows, and every write is undone
+//! when something fails:
+//! - a step returns an error: `onboard` calls `tx.rollback()` explicitly
+//! - the handler future is dropped (client gone, panic): dropping an
+//!   uncommitted `Transaction` rolls it back too
+//!
+//! `?fail_after=user|profile|post` makes the database raise an error after
+//! that step, to show the rollback: the request fails with a 500, and
+//! repeating it without the parameter succeeds, because the email was never
+//! taken.
+//!
+//! The change trigger's `pg_notify` is transactional as well: the read
+//! model hears about the user on commit, and never about a rolled-back one.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::Executor;
+use uuid::Uuid;
+
+use crate::{
+    breaker::CircuitBreaker,
+    db::{DbConnection, DbPool},
+    ids::{IdProvider, SharedIds},
+    DbError, User,
+};
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Profile {
+    user_id: Uuid,
+    bio: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Post {
+    id: Uuid,
+    user_id: Uuid,
+    title: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OnboardUser {
+    name: String,
+    email: String,
+    bio: String,
+    first_post: String,
+}
+
+/// Everything one onboarding created
+#[derive(Debug, Serialize)]
+pub struct Onboarded {
+    user: User,
+    profile: Profile,
+    post: Post,
+}
+
+/// The steps, in order
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    User,
+    Profile,
+    Post,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OnboardParams {
+    fail_after: Option<Step>,
+}
+
+/// POST /users/onboard?fail_after=profile
+pub async fn onboard_user(
+    State(pool): State<DbPool>,
+    State(breaker): State<CircuitBreaker>,
+    State(ids): State<SharedIds>,
+    Query(params): Query<OnboardParams>,
+    Json(input): Json<OnboardUser>,
+) -> Result<(StatusCode, Json<Onboarded>), DbError> {
+    let onboarded = breaker
+        .call(onboard(&pool, ids.as_ref(), &input, params.fail_after))
+        .await?;
+    Ok((StatusCode::CREATED, Json(onboarded)))
+}
+
+/// BEGIN, the three steps, then COMMIT - or ROLLBACK at the first error
+async fn onboard(
+    pool: &DbPool,
+    ids: &dyn IdProvider,
+    input: &OnboardUser,
+    fail_after: Option<Step>,
+) -> Result<Onboarded, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    match insert_all(&mut tx, ids, input, fail_after).await {
+        Ok(onboarded) => {
+            tx.commit().await?;
+            Ok(onboarded)
+        }
+        Err(e) => {
+            tx.rollback().await?;
+            println!("↩️  Onboarding {} rolled back: {}", input.email, e);
+            Err(e)
+        }
+    }
+}
+
+/// The writes; `conn` is the transaction's connection, so none of them
+/// commits on its own
+async fn insert_all(
+    conn: &mut DbConnection,
+    ids: &dyn IdProvider,
+    input: &OnboardUser,
+    fail_after: Option<Step>,
+) -> Result<Onboarded, sqlx::Error> {
+    // One timestamp for the three rows, like `NOW()` within a transaction
+    let now = Utc::now();
+    let user = sqlx::query_as::<_, User>(
+        "INSERT INTO users (id, name, email, created_at, updated_at) VALUES ($1, $2, $3, $4, $4) RETURNING *",
+    )
+    .bind(ids.next_id())
+    .bind(&input.name)
+    .bind(&input.email)
+    .bind(now)
+    .fetch_one(&mut *conn)
+    .await?;
+    fail_if(conn, fail_after, Step::User).await?;
+
+    let profile = sqlx::query_as::<_, Profile>(
+        "INSERT INTO profiles (user_id, bio, created_at) VALUES ($1, $2, $3) RETURNING *",
+    )
+    .bind(user.id)
+    .bind(&input.bio)
+    .bind(now)
+    .fetch_one(&mut *conn)
+    .await?;
+    fail_if(conn, fail_after, Step::Profile).await?;
+
+    // An empty or overlong title breaks the CHECK constraint: a real
+    // failure after two successful inserts
+    let post = sqlx::query_as::<_, Post>(
+        "INSERT INTO posts (id, user_id, title, created_at) VALUES ($1, $2, $3, $4) RETURNING *",
+    )
+    .bind(ids.next_id())
+    .bind(user.id)
+    .bind(&input.first_post)
+    .bind(now)
+    .fetch_one(&mut *conn)
+    .await?;
+    fail_if(conn, fail_after, Step::Post).await?;
+
+    Ok(Onboarded {
+        user,
+        profile,
+        post,
+    })
+}
+
+/// Make the database fail if the client asked for it after `step`
+async fn fail_if(
+    conn: &mut DbConnection,
+    fail_after: Option<Step>,
+    step: Step,
+) -> Result<(), sqlx::Error> {
+    if fail_after == Some(step) {
+        // No bind parameters in DO; the text is our own
+        #[cfg(not(feature = "sqlite"))]
+        let sql = format!(
+            "DO $$ BEGIN RAISE EXCEPTION 'simulated failure after {:?}'; END $$",
+            step
+        );
+        // SQLite only raises in triggers: ask for a table that isn't there
+        #[cfg(feature = "sqlite")]
+        let sql = format!("SELECT * FROM \"simulated failure after {:?}\"", step);
+        conn.execute(sql.as_str()).await?;
+    }
+    Ok(())
+}
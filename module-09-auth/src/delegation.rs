@@ -0,0 +1,165 @@
+//! # Accepting Delegated Tokens in an Internal Service
+//!
+//! The other half of token exchange (see `oauth.rs`): a service that is
+//! called with an exchanged token. It doesn't trust the token because it
+//! arrived - `require_delegation` checks it on every request:
+//! - it is active (introspection; opaque tokens mean nothing on their own)
+//! - its audience is this service - a token for `orders-service` is no
+//!   good here, even with the right scopes
+//! - the actor chain is at most `MAX_DELEGATION_DEPTH` long, so a token
+//!   minted before the limit was lowered still stops working
+//!
+//! and inserts a `Delegation` for handlers: whose data it is (`subject`),
+//! who is asking for it (`actors`, nearest first) and what they may do.
+//! Scopes are per route, checked with `Delegation::require`.
+//!
+//! The demo runs the "services" in the same process, under `/internal`;
+//! in production each would call `/oauth/introspect` with its own client
+//! credentials.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use std::{collections::BTreeSet, sync::Arc};
+
+use crate::oauth::{act_claim, OAuthStore, MAX_DELEGATION_DEPTH};
+
+/// The service behind `/internal/todo-service`
+pub const TODO_SERVICE: &str = "todo-service";
+
+/// What `require_delegation` needs: where to look tokens up, and who we are
+#[derive(Clone)]
+pub struct ServiceAudience {
+    pub store: Arc<OAuthStore>,
+    pub audience: &'static str,
+}
+
+/// A verified delegated call, as an extension
+#[derive(Debug, Clone)]
+pub struct Delegation {
+    pub subject: String,
+    pub actors: Vec<String>,
+    pub scopes: BTreeSet<String>,
+}
+
+impl Delegation {
+    pub fn require(&self, scope: &str) -> Result<(), DelegationError> {
+        if self.scopes.contains(scope) {
+            Ok(())
+        } else {
+            Err(DelegationError::InsufficientScope)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DelegationError {
+    MissingToken,
+    InactiveToken,
+    WrongAudience,
+    /// Not an exchanged token: a user's own token isn't for services
+    NotDelegated,
+    TooDeep,
+    InsufficientScope,
+}
+
+impl IntoResponse for DelegationError {
+    fn into_response(self) -> Response {
+        // RFC 6750 error codes, in a `WWW-Authenticate` challenge too
+        let (status, error, description) = match self {
+            DelegationError::MissingToken => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_request",
+                "A bearer token is required",
+            ),
+            DelegationError::InactiveToken => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "Token is unknown, expired or revoked",
+            ),
+            DelegationError::WrongAudience => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "Token was not issued for this service",
+            ),
+            DelegationError::NotDelegated => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                "Exchange the token for this service first",
+            ),
+            DelegationError::TooDeep => (
+                StatusCode::FORBIDDEN,
+                "invalid_token",
+                "Delegation chain too long",
+            ),
+            DelegationError::InsufficientScope => (
+                StatusCode::FORBIDDEN,
+                "insufficient_scope",
+                "Token lacks the scope this route needs",
+            ),
+        };
+        let challenge = format!(
+            r#"Bearer error="{}", error_description="{}""#,
+            error, description
+        );
+        (
+            status,
+            [(header::WWW_AUTHENTICATE, challenge)],
+            Json(serde_json::json!({ "error": error, "error_description": description })),
+        )
+            .into_response()
+    }
+}
+
+/// The layer: `middleware::from_fn_with_state(service, require_delegation)`
+pub async fn require_delegation(
+    State(service): State<ServiceAudience>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, DelegationError> {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(DelegationError::MissingToken)?;
+    let info = service
+        .store
+        .lookup(token)
+        .ok_or(DelegationError::InactiveToken)?;
+
+    if info.audience.as_deref() != Some(service.audience) {
+        return Err(DelegationError::WrongAudience);
+    }
+    if info.actors.is_empty() {
+        return Err(DelegationError::NotDelegated);
+    }
+    if info.actors.len() > MAX_DELEGATION_DEPTH {
+        return Err(DelegationError::TooDeep);
+    }
+
+    request.extensions_mut().insert(Delegation {
+        subject: info.subject,
+        actors: info.actors,
+        scopes: info.scopes,
+    });
+    Ok(next.run(request).await)
+}
+
+/// GET /internal/todo-service/caller - who the todo service is serving,
+/// and through whom
+pub async fn delegated_caller(
+    Extension(delegation): Extension<Delegation>,
+) -> Result<impl IntoResponse, DelegationError> {
+    delegation.require("todos:read")?;
+    Ok(Json(serde_json::json!({
+        "service": TODO_SERVICE,
+        "sub": delegation.subject,
+        "act": act_claim(&delegation.actors),
+        "depth": delegation.actors.len(),
+    })))
+}
@@ -5,17 +5,36 @@
 //! - Password hashing with argon2
 //! - Auth middleware
 //! - Protected routes
+//! - Password policy with strength feedback (see `password_policy.rs`)
+//! - Organizations and membership-based access (see `orgs.rs`)
+//! - An app factory with injected dependencies, so tests run isolated
+//! - Acting as an OAuth2 provider: consent screen, codes, introspection
+//!   (see `oauth.rs`)
+//! - Token exchange for service-to-service delegation, with audience and
+//!   delegation-depth checks in the receiving service (see `delegation.rs`)
+//! - Salts, secrets and tokens from an injected random source, so tests can
+//!   predict them (see `rng.rs`)
+
+mod delegation;
+mod oauth;
+mod orgs;
+mod password_policy;
+mod rng;
 
 use axum::{
-    extract::{Request, State},
+    extract::{FromRef, Request, State},
     http::StatusCode,
     middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{get, post},
-    Json, Router,
+    Json,
 };
 use chrono::{Duration, Utc};
+use course_routes::Routes;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use oauth::OAuthStore;
+use orgs::OrgStore;
+use password_policy::{PasswordPolicy, PolicyReport};
+use rng::{OsRandom, SharedRng};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
@@ -27,6 +46,72 @@ use std::sync::Arc;
 struct AuthConfig {
     jwt_secret: String,
     jwt_expiry_hours: i64,
+    password_policy: PasswordPolicy,
+}
+
+/// Everything the app depends on. `build_app` takes it whole, so `main`
+/// and every test assemble their own and nothing is shared between them.
+#[derive(Clone)]
+struct AppState {
+    config: Arc<AuthConfig>,
+    orgs: Arc<OrgStore>,
+    users: Arc<dyn UserDirectory>,
+    oauth: Arc<OAuthStore>,
+    rng: SharedRng,
+}
+
+impl FromRef<AppState> for Arc<AuthConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<OrgStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.orgs.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn UserDirectory> {
+    fn from_ref(state: &AppState) -> Self {
+        state.users.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<OAuthStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.oauth.clone()
+    }
+}
+
+impl FromRef<AppState> for SharedRng {
+    fn from_ref(state: &AppState) -> Self {
+        state.rng.clone()
+    }
+}
+
+// ============================================================================
+// USER DIRECTORY
+// ============================================================================
+
+/// Where login credentials are checked: a database in a real app
+trait UserDirectory: Send + Sync {
+    /// `(user_id, role)` when the credentials are valid
+    fn authenticate(&self, email: &str, password: &str) -> Option<(String, String)>;
+}
+
+/// The two hard-coded demo accounts
+struct DemoUsers;
+
+impl UserDirectory for DemoUsers {
+    fn authenticate(&self, email: &str, password: &str) -> Option<(String, String)> {
+        // Simulated user validation
+        match (email, password) {
+            ("test@example.com", "password123") => Some(("user-1".into(), "user".into())),
+            ("admin@example.com", "password123") => Some(("admin-1".into(), "admin".into())),
+            _ => None,
+        }
+    }
 }
 
 // ============================================================================
@@ -60,6 +145,19 @@ struct RegisterRequest {
     password: String,
 }
 
+#[derive(Deserialize)]
+struct ResetPasswordRequest {
+    email: String,
+    new_password: String,
+}
+
+/// Nothing is changed, so it's just the password and whose it would be
+#[derive(Deserialize)]
+struct CheckPasswordRequest {
+    email: String,
+    password: String,
+}
+
 #[derive(Debug, Clone)]
 struct CurrentUser {
     id: String,
@@ -70,9 +168,12 @@ struct CurrentUser {
 // PASSWORD HASHING
 // ============================================================================
 
-fn hash_password(password: &str) -> String {
+/// A fresh 16-byte salt from `rng` for every hash
+fn hash_password(rng: &dyn rng::Rng, password: &str) -> String {
     use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
-    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    let mut salt = [0u8; 16];
+    rng.fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).unwrap();
     Argon2::default()
         .hash_password(password.as_bytes(), &salt)
         .unwrap()
@@ -122,34 +223,74 @@ fn verify_token(config: &AuthConfig, token: &str) -> Result<Claims, StatusCode>
 // HANDLERS
 // ============================================================================
 
-async fn register(Json(input): Json<RegisterRequest>) -> impl IntoResponse {
-    let _hashed = hash_password(&input.password);
-    Json(serde_json::json!({
+/// Rejected passwords come back as 422 with the full per-rule report
+fn check_password_policy(
+    config: &AuthConfig,
+    password: &str,
+    email: &str,
+) -> Result<PolicyReport, (StatusCode, Json<PolicyReport>)> {
+    let report = config.password_policy.evaluate(password, email);
+    if report.accepted {
+        Ok(report)
+    } else {
+        Err((StatusCode::UNPROCESSABLE_ENTITY, Json(report)))
+    }
+}
+
+async fn register(
+    State(config): State<Arc<AuthConfig>>,
+    State(rng): State<SharedRng>,
+    Json(input): Json<RegisterRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<PolicyReport>)> {
+    let report = check_password_policy(&config, &input.password, &input.email)?;
+    let _hashed = hash_password(rng.as_ref(), &input.password);
+    Ok(Json(serde_json::json!({
         "message": "User registered",
-        "email": input.email
-    }))
+        "email": input.email,
+        "password_strength": report.strength
+    })))
+}
+
+async fn reset_password(
+    State(config): State<Arc<AuthConfig>>,
+    State(rng): State<SharedRng>,
+    Json(input): Json<ResetPasswordRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<PolicyReport>)> {
+    // Simulated: a real reset would first verify a one-time reset token
+    let report = check_password_policy(&config, &input.new_password, &input.email)?;
+    let _hashed = hash_password(rng.as_ref(), &input.new_password);
+    Ok(Json(serde_json::json!({
+        "message": "Password updated",
+        "email": input.email,
+        "password_strength": report.strength
+    })))
+}
+
+/// Dry-run the policy, e.g. for a live strength meter in a signup form
+async fn check_password(
+    State(config): State<Arc<AuthConfig>>,
+    Json(input): Json<CheckPasswordRequest>,
+) -> Json<PolicyReport> {
+    Json(
+        config
+            .password_policy
+            .evaluate(&input.password, &input.email),
+    )
 }
 
 async fn login(
     State(config): State<Arc<AuthConfig>>,
+    State(users): State<Arc<dyn UserDirectory>>,
     Json(input): Json<LoginRequest>,
 ) -> Result<Json<LoginResponse>, StatusCode> {
-    // Simulated user validation
-    if input.email == "test@example.com" && input.password == "password123" {
-        let token = create_token(&config, "user-1", "user")?;
-        Ok(Json(LoginResponse {
-            token,
-            expires_in: config.jwt_expiry_hours * 3600,
-        }))
-    } else if input.email == "admin@example.com" && input.password == "password123" {
-        let token = create_token(&config, "admin-1", "admin")?;
-        Ok(Json(LoginResponse {
-            token,
-            expires_in: config.jwt_expiry_hours * 3600,
-        }))
-    } else {
-        Err(StatusCode::UNAUTHORIZED)
-    }
+    let (user_id, role) = users
+        .authenticate(&input.email, &input.password)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    let token = create_token(&config, &user_id, &role)?;
+    Ok(Json(LoginResponse {
+        token,
+        expires_in: config.jwt_expiry_hours * 3600,
+    }))
 }
 
 async fn protected(axum::Extension(user): axum::Extension<CurrentUser>) -> impl IntoResponse {
@@ -194,42 +335,503 @@ async fn auth_middleware(
     Ok(next.run(request).await)
 }
 
+// ============================================================================
+// APP FACTORY
+// ============================================================================
+
+impl AppState {
+    /// What the server runs with: demo secret, seeded orgs, demo logins
+    fn demo() -> Self {
+        let rng: SharedRng = Arc::new(OsRandom);
+        AppState {
+            config: Arc::new(AuthConfig {
+                jwt_secret: "super-secret-key-change-in-production".to_string(),
+                jwt_expiry_hours: 24,
+                password_policy: PasswordPolicy::default(),
+            }),
+            orgs: Arc::new(OrgStore::seeded()),
+            users: Arc::new(DemoUsers),
+            oauth: Arc::new(OAuthStore::seeded(rng.clone())),
+            rng,
+        }
+    }
+}
+
+fn build_app(deps: AppState) -> Routes {
+    let protected_routes = Routes::new()
+        .get("/me", protected, "The caller's token claims")
+        .get("/admin", admin_only, "403 unless the token's role is admin")
+        // Org-scoped: the organization comes from the X-Org-Id header
+        .get("/orgs", orgs::list_my_orgs, "Your organizations and roles")
+        .get(
+            "/org/todos",
+            orgs::list_org_todos,
+            "Org-scoped todos (X-Org-Id header)",
+        )
+        .post("/org/todos", orgs::create_org_todo, "Create (owner/member)")
+        .delete(
+            "/org/todos/{id}",
+            orgs::delete_org_todo,
+            "Owners delete anything, members their own",
+        )
+        .get(
+            "/org/members",
+            orgs::list_members,
+            "Members and their roles",
+        )
+        .put(
+            "/org/members/{user_id}",
+            orgs::set_member_role,
+            "Set role (owner)",
+        )
+        .guard(
+            "Bearer JWT",
+            middleware::from_fn_with_state(deps.config.clone(), auth_middleware),
+        );
+
+    // The OAuth2 provider side: third-party apps get scoped tokens
+    let oauth_routes = Routes::new()
+        .post(
+            "/clients",
+            oauth::register_client,
+            "Register an OAuth client",
+        )
+        .get(
+            "/authorize",
+            oauth::authorize,
+            "Consent screen (client demo-app)",
+        )
+        .post("/authorize", oauth::decide, "The user's answer")
+        .post(
+            "/token",
+            oauth::token,
+            "Exchange a code (or a token, RFC 8693) for a token",
+        )
+        .post(
+            "/introspect",
+            oauth::introspect,
+            "Is this token active, with which scopes?",
+        );
+
+    // An internal service, reached only with a token exchanged for it
+    let internal_routes = Routes::new()
+        .get(
+            "/caller",
+            delegation::delegated_caller,
+            "Who is calling, on whose behalf",
+        )
+        .guard(
+            "delegated (aud todo-service)",
+            middleware::from_fn_with_state(
+                delegation::ServiceAudience {
+                    store: deps.oauth.clone(),
+                    audience: delegation::TODO_SERVICE,
+                },
+                delegation::require_delegation,
+            ),
+        );
+
+    Routes::new()
+        .post("/register", register, "Register user")
+        .post("/login", login, "Login (test@example.com / password123)")
+        .post(
+            "/reset-password",
+            reset_password,
+            "Reset password (policy enforced)",
+        )
+        .post(
+            "/password/check",
+            check_password,
+            "Password strength feedback",
+        )
+        .nest("/protected", protected_routes)
+        .nest("/oauth", oauth_routes)
+        .nest("/internal/todo-service", internal_routes)
+        .with_state(deps)
+}
+
 // ============================================================================
 // MAIN
 // ============================================================================
 
 #[tokio::main]
 async fn main() {
-    let config = Arc::new(AuthConfig {
-        jwt_secret: "super-secret-key-change-in-production".to_string(),
-        jwt_expiry_hours: 24,
-    });
-
-    let protected_routes = Router::new()
-        .route("/me", get(protected))
-        .route("/admin", get(admin_only))
-        .route_layer(middleware::from_fn_with_state(
-            config.clone(),
-            auth_middleware,
-        ));
-
-    let app = Router::new()
-        .route("/register", post(register))
-        .route("/login", post(login))
-        .nest("/protected", protected_routes)
-        .with_state(config);
+    let common = password_policy::common_passwords().len();
+    let routes = build_app(AppState::demo());
+    let table = routes.to_string();
+    let app = routes.into_router();
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
 
     println!("🚀 Module 09: Authentication");
-    println!("   Server: http://localhost:3000\n");
+    println!("   Server: http://localhost:3000");
+    println!("   Common-password list: {} entries\n", common);
     println!("📝 Endpoints:");
-    println!("   POST /register    - Register user");
-    println!("   POST /login       - Login (test@example.com / password123)");
-    println!("   GET  /protected/me - Protected route");
+    print!("{}", table);
     println!("\n💡 Usage:");
     println!("   1. POST /login with credentials");
     println!("   2. Use token: curl -H 'Authorization: Bearer <token>' /protected/me");
+    println!("   3. Org routes: add -H 'X-Org-Id: acme' (user-1 owns acme, views globex)");
 
     axum::serve(listener, app).await.unwrap();
 }
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, Router};
+    use course_routes::conformance::{self, Probes};
+    use http_body_util::BodyExt;
+    use rng::SequentialRng;
+    use tower::ServiceExt;
+
+    /// Three accounts, all with password `pw`. In the seeded orgs `eve` is
+    /// `user-1` (owns Acme, views Globex) and `adam` is `admin-1` (member of
+    /// Acme, owns Globex); `mallory` is a global admin in no org at all.
+    struct FakeUsers;
+
+    impl UserDirectory for FakeUsers {
+        fn authenticate(&self, email: &str, password: &str) -> Option<(String, String)> {
+            let (id, role) = match email {
+                "eve@test.dev" => ("user-1", "user"),
+                "adam@test.dev" => ("admin-1", "admin"),
+                "mallory@test.dev" => ("user-9", "admin"),
+                _ => return None,
+            };
+            (password == "pw").then(|| (id.into(), role.into()))
+        }
+    }
+
+    /// A fresh org store and a per-app signing secret: tokens from one test's
+    /// app are worthless in another's
+    fn test_app(secret: &str) -> Router {
+        let rng: SharedRng = Arc::new(SequentialRng::default());
+        build_app(AppState {
+            config: Arc::new(AuthConfig {
+                jwt_secret: secret.to_string(),
+                jwt_expiry_hours: 1,
+                password_policy: PasswordPolicy::default(),
+            }),
+            orgs: Arc::new(OrgStore::seeded()),
+            users: Arc::new(FakeUsers),
+            oauth: Arc::new(OAuthStore::new(rng.clone())),
+            rng,
+        })
+        .into_router()
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> (StatusCode, serde_json::Value) {
+        send_in(app, "acme", method, uri, token, body).await
+    }
+
+    /// `send`, in the organization `org`
+    async fn send_in(
+        app: &Router,
+        org: &str,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+        body: &str,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .header(orgs::ORG_ID_HEADER, org);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    async fn login(app: &Router, email: &str) -> String {
+        let body = format!(r#"{{"email":"{}","password":"pw"}}"#, email);
+        let (status, body) = send(app, "POST", "/login", None, &body).await;
+        assert_eq!(status, StatusCode::OK);
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    async fn login_as_eve(app: &Router) -> String {
+        login(app, "eve@test.dev").await
+    }
+
+    /// Create an Acme todo as `token`'s user and return its id
+    async fn create_acme_todo(app: &Router, token: &str) -> u64 {
+        let (status, todo) = send(
+            app,
+            "POST",
+            "/protected/org/todos",
+            Some(token),
+            r#"{"title":"todo"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        todo["id"].as_u64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_non_members_get_404_whatever_their_global_role() {
+        let app = test_app("secret-a");
+        let mallory = login(&app, "mallory@test.dev").await;
+        let eve = login_as_eve(&app).await;
+
+        // The token's `admin` role grants nothing inside an org
+        let (status, not_a_member) =
+            send(&app, "GET", "/protected/org/todos", Some(&mallory), "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        // Same answer as for an org that doesn't exist
+        let (status, no_such_org) = send_in(
+            &app,
+            "initech",
+            "GET",
+            "/protected/org/todos",
+            Some(&eve),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(not_a_member, no_such_org);
+
+        let (status, _) = send_in(&app, "", "GET", "/protected/org/todos", Some(&eve), "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_viewers_read_but_cannot_create() {
+        let app = test_app("secret-a");
+        let eve = login_as_eve(&app).await;
+
+        let (status, _) = send_in(
+            &app,
+            "globex",
+            "GET",
+            "/protected/org/todos",
+            Some(&eve),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_in(
+            &app,
+            "globex",
+            "POST",
+            "/protected/org/todos",
+            Some(&eve),
+            r#"{"title":"nope"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_members_delete_their_own_todos_only() {
+        let app = test_app("secret-a");
+        let (eve, adam) = (login_as_eve(&app).await, login(&app, "adam@test.dev").await);
+        let adams = create_acme_todo(&app, &adam).await;
+        let eves = create_acme_todo(&app, &eve).await;
+
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            &format!("/protected/org/todos/{}", eves),
+            Some(&adam),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            &format!("/protected/org/todos/{}", adams),
+            Some(&adam),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let (_, todos) = send(&app, "GET", "/protected/org/todos", Some(&adam), "").await;
+        assert_eq!(todos.as_array().unwrap().len(), 1);
+        assert_eq!(todos[0]["id"], eves);
+    }
+
+    #[tokio::test]
+    async fn test_owners_delete_any_todo() {
+        let app = test_app("secret-a");
+        let (eve, adam) = (login_as_eve(&app).await, login(&app, "adam@test.dev").await);
+        let adams = create_acme_todo(&app, &adam).await;
+
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            &format!("/protected/org/todos/{}", adams),
+            Some(&eve),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        // Acme's todos don't exist from Globex, even for its owner
+        let eves = create_acme_todo(&app, &eve).await;
+        let (status, _) = send_in(
+            &app,
+            "globex",
+            "DELETE",
+            &format!("/protected/org/todos/{}", eves),
+            Some(&adam),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_only_owners_manage_members_and_one_owner_always_remains() {
+        let app = test_app("secret-a");
+        let (eve, adam) = (login_as_eve(&app).await, login(&app, "adam@test.dev").await);
+        let set_role = |token: String, user: &str, role: &str| {
+            let app = app.clone();
+            let uri = format!("/protected/org/members/{}", user);
+            let body = format!(r#"{{"role":"{}"}}"#, role);
+            async move { send(&app, "PUT", &uri, Some(&token), &body).await.0 }
+        };
+
+        assert_eq!(
+            set_role(adam.clone(), "admin-1", "owner").await,
+            StatusCode::FORBIDDEN
+        );
+        // Eve is Acme's only owner
+        assert_eq!(
+            set_role(eve.clone(), "user-1", "member").await,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            set_role(eve.clone(), "admin-1", "owner").await,
+            StatusCode::OK
+        );
+        // With a second owner she may step down
+        assert_eq!(
+            set_role(eve.clone(), "user-1", "member").await,
+            StatusCode::OK
+        );
+
+        let (_, members) = send(&app, "GET", "/protected/org/members", Some(&adam), "").await;
+        assert_eq!(members[0]["user_id"], "admin-1");
+        assert_eq!(members[0]["role"], "owner");
+        assert_eq!(members[1]["role"], "member");
+    }
+
+    #[tokio::test]
+    async fn test_login_uses_the_injected_directory() {
+        let app = test_app("secret-a");
+
+        let token = login_as_eve(&app).await;
+        let (status, me) = send(&app, "GET", "/protected/me", Some(&token), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(me["user_id"], "user-1");
+
+        // The demo accounts don't exist in this app
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/login",
+            None,
+            r#"{"email":"test@example.com","password":"password123"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_apps_share_neither_secrets_nor_stores() {
+        let first = test_app("secret-a");
+        let second = test_app("secret-b");
+        let token = login_as_eve(&first).await;
+
+        let (status, _) = send(
+            &first,
+            "POST",
+            "/protected/org/todos",
+            Some(&token),
+            r#"{"title":"first only"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _) = send(&second, "GET", "/protected/org/todos", Some(&token), "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let token = login_as_eve(&second).await;
+        let (_, todos) = send(&second, "GET", "/protected/org/todos", Some(&token), "").await;
+        assert_eq!(todos, serde_json::json!([]));
+    }
+
+    /// Eight apps at once on a multi-threaded runtime, each creating a
+    /// different number of org todos: every app sees only its own
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_parallel_apps_are_isolated() {
+        let tasks: Vec<_> = (1..=8)
+            .map(|n| {
+                tokio::spawn(async move {
+                    let app = test_app(&format!("secret-{}", n));
+                    let token = login_as_eve(&app).await;
+                    for i in 0..n {
+                        let body = format!(r#"{{"title":"todo {}"}}"#, i);
+                        send(&app, "POST", "/protected/org/todos", Some(&token), &body).await;
+                    }
+                    let (_, todos) =
+                        send(&app, "GET", "/protected/org/todos", Some(&token), "").await;
+                    (n, todos.as_array().unwrap().len())
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            let (n, todos) = task.await.unwrap();
+            assert_eq!(todos, n);
+        }
+    }
+
+    #[test]
+    fn test_salt_comes_from_the_injected_rng() {
+        let hash = hash_password(&SequentialRng::default(), "correct horse");
+        // Same bytes, same salt, same hash
+        assert_eq!(
+            hash,
+            hash_password(&SequentialRng::default(), "correct horse")
+        );
+        // Salt bytes 00..0f, in the unpadded base64 the PHC string uses
+        assert!(hash.contains("$AAECAwQFBgcICQoLDA0ODw$"));
+        assert!(verify_password("correct horse", &hash));
+
+        // The next hash from the same source gets the next 16 bytes
+        let rng = SequentialRng::default();
+        assert_ne!(hash_password(&rng, "pw"), hash_password(&rng, "pw"));
+    }
+
+    #[tokio::test]
+    #[ignore = "Axum's rejections stay plain text here"]
+    async fn test_error_conformance() {
+        let probes = Probes {
+            get_route: "/oauth/authorize",
+            json_route: Some("/login"),
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        conformance::assert_conforms(test_app("conformance"), &probes).await;
+    }
+}
@@ -0,0 +1,1144 @@
+//! # A Minimal OAuth2 Authorization Server
+//!
+//! So far this server hands out tokens to its *own* users. OAuth2 lets it
+//! hand out tokens to *third-party apps* acting on a user's behalf, limited
+//! to what the user approved (the authorization code flow, RFC 6749 §4.1):
+//!
+//! ```text
+//! app  -> POST /oauth/clients                      register, get client_id + secret
+//! user -> GET  /oauth/authorize?client_id&scope..  consent screen (HTML)
+//! user -> POST /oauth/authorize                    sign in, tick scopes, approve
+//!      <- 303 redirect_uri?code=..&state=..
+//! app  -> POST /oauth/token                        code + client secret -> access token
+//! api  -> POST /oauth/introspect                   "is this token live, which scopes?" (RFC 7662)
+//! svc  -> POST /oauth/token                        token exchange: a user token -> a narrower
+//!                                                  one for another service (RFC 8693)
+//! ```
+//!
+//! The rules that make it safe:
+//! - `redirect_uri` must be registered *exactly*; until it's verified,
+//!   errors are shown to the user and never redirected
+//! - Granted scopes = what the client may ask for ∩ what it asked for ∩
+//!   what the user ticked
+//! - Codes live 60 seconds and work once. Presenting one twice revokes the
+//!   token it produced - someone intercepted it (§4.1.2)
+//! - Access tokens are opaque random strings; only introspection can tell
+//!   what they mean, so revoking one takes effect immediately
+//!
+//! Token exchange is for service-to-service calls. A service holding a
+//! user's token trades it for one that:
+//! - is for exactly one other service (`audience`, a registered client)
+//! - carries the same user (`sub`) but at most the same scopes, usually fewer
+//! - names who is acting for the user: the exchanging client, in front of
+//!   any earlier actors (the nested `act` claim)
+//! - expires in minutes, never after the token it came from
+//!
+//! Only the client a token was issued to (or for) may exchange it, and
+//! chains stop at `MAX_DELEGATION_DEPTH` actors. Services check the same
+//! limits on every request (see `delegation.rs`).
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
+};
+use axum_extra::{
+    extract::Form,
+    headers::{authorization::Basic, Authorization},
+    TypedHeader,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    rng::{self, SharedRng},
+    UserDirectory,
+};
+
+/// Every scope this server understands, with the wording users see
+pub const SCOPES: &[(&str, &str)] = &[
+    ("profile:read", "See your user id and role"),
+    ("orgs:read", "See which organizations you belong to"),
+    ("todos:read", "Read your organizations' todos"),
+    ("todos:write", "Create and delete todos on your behalf"),
+];
+
+const CODE_TTL_SECS: i64 = 60;
+const TOKEN_TTL_SECS: i64 = 3600;
+/// Delegated tokens are for one call chain, not a session
+const EXCHANGED_TOKEN_TTL_SECS: i64 = 300;
+/// user -> service -> service is fine; a longer chain is a smell
+pub const MAX_DELEGATION_DEPTH: usize = 2;
+
+const TOKEN_EXCHANGE_GRANT: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+// ============================================================================
+// STORE
+// ============================================================================
+
+#[derive(Debug, Clone)]
+struct Client {
+    name: String,
+    secret: String,
+    redirect_uris: Vec<String>,
+    /// The most this client may ever ask for
+    scopes: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+struct AuthCode {
+    client_id: String,
+    user_id: String,
+    redirect_uri: String,
+    scopes: BTreeSet<String>,
+    expires_at: i64,
+    /// Set on first redemption, revoked on the second
+    issued_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct AccessToken {
+    client_id: String,
+    user_id: String,
+    scopes: BTreeSet<String>,
+    issued_at: i64,
+    expires_at: i64,
+    /// The one service this token is for; `None` for user-facing tokens
+    audience: Option<String>,
+    /// Clients acting for the user, most recent first; empty unless exchanged
+    actors: Vec<String>,
+}
+
+/// What a service learns about a live token (see `delegation.rs`)
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    pub subject: String,
+    pub scopes: BTreeSet<String>,
+    pub audience: Option<String>,
+    pub actors: Vec<String>,
+}
+
+#[derive(Default)]
+struct OAuthData {
+    clients: HashMap<String, Client>,
+    codes: HashMap<String, AuthCode>,
+    tokens: HashMap<String, AccessToken>,
+}
+
+/// Registered clients, outstanding codes and issued tokens
+pub struct OAuthStore {
+    data: RwLock<OAuthData>,
+    /// Client ids and secrets, codes and access tokens come from here
+    rng: SharedRng,
+}
+
+impl OAuthStore {
+    pub fn new(rng: SharedRng) -> Self {
+        OAuthStore {
+            data: RwLock::default(),
+            rng,
+        }
+    }
+
+    /// One pre-registered client, so the flow can be tried without
+    /// registering first: `demo-app` / `demo-secret`
+    pub fn seeded(rng: SharedRng) -> Self {
+        let store = Self::new(rng);
+        store.data.write().unwrap().clients.insert(
+            "demo-app".to_string(),
+            Client {
+                name: "Demo Todo Dashboard".to_string(),
+                secret: "demo-secret".to_string(),
+                redirect_uris: vec!["http://localhost:8080/callback".to_string()],
+                scopes: SCOPES.iter().map(|(s, _)| s.to_string()).collect(),
+            },
+        );
+        // Internal services: no redirect URIs, they only exchange tokens
+        for (id, name, secret) in [
+            ("orders-service", "Orders Service", "orders-secret"),
+            ("todo-service", "Todo Service", "todo-secret"),
+        ] {
+            store.data.write().unwrap().clients.insert(
+                id.to_string(),
+                Client {
+                    name: name.to_string(),
+                    secret: secret.to_string(),
+                    redirect_uris: Vec::new(),
+                    scopes: SCOPES.iter().map(|(s, _)| s.to_string()).collect(),
+                },
+            );
+        }
+        store
+    }
+
+    fn client(&self, client_id: &str) -> Option<Client> {
+        self.data.read().unwrap().clients.get(client_id).cloned()
+    }
+
+    /// A live token, or `None` for unknown, expired and revoked ones alike
+    pub fn lookup(&self, token: &str) -> Option<TokenInfo> {
+        let now = Utc::now().timestamp();
+        let data = self.data.read().unwrap();
+        let token = data.tokens.get(token).filter(|t| t.expires_at > now)?;
+        Some(TokenInfo {
+            subject: token.user_id.clone(),
+            scopes: token.scopes.clone(),
+            audience: token.audience.clone(),
+            actors: token.actors.clone(),
+        })
+    }
+
+    /// Client credentials check shared by `/token` and `/introspect`
+    fn authenticate_client(&self, client_id: &str, secret: &str) -> Result<Client, OAuthError> {
+        self.client(client_id)
+            .filter(|client| client.secret == secret)
+            .ok_or(OAuthError::InvalidClient)
+    }
+
+    fn random_string(&self, len: usize) -> String {
+        rng::alphanumeric(self.rng.as_ref(), len)
+    }
+}
+
+fn parse_scopes(scope: &str) -> BTreeSet<String> {
+    scope.split_whitespace().map(str::to_string).collect()
+}
+
+fn join_scopes(scopes: &BTreeSet<String>) -> String {
+    scopes.iter().cloned().collect::<Vec<_>>().join(" ")
+}
+
+/// RFC 8693 §4.1: `{"sub": "orders-service", "act": {"sub": "demo-app"}}`
+pub fn act_claim(actors: &[String]) -> Option<serde_json::Value> {
+    actors.iter().rev().fold(None, |inner, actor| {
+        let mut act = serde_json::json!({ "sub": actor });
+        if let Some(inner) = inner {
+            act["act"] = inner;
+        }
+        Some(act)
+    })
+}
+
+// ============================================================================
+// ERRORS
+// ============================================================================
+
+/// Token and introspection errors, in the RFC 6749 §5.2 JSON shape
+#[derive(Debug)]
+pub enum OAuthError {
+    InvalidRequest(&'static str),
+    InvalidClient,
+    InvalidGrant(&'static str),
+    InvalidScope(&'static str),
+    /// RFC 8693: the requested `audience` isn't one we issue tokens for
+    InvalidTarget(&'static str),
+    UnsupportedGrantType,
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let (status, error, description) = match self {
+            OAuthError::InvalidRequest(why) => (StatusCode::BAD_REQUEST, "invalid_request", why),
+            OAuthError::InvalidClient => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+                "Unknown client or wrong secret",
+            ),
+            OAuthError::InvalidGrant(why) => (StatusCode::BAD_REQUEST, "invalid_grant", why),
+            OAuthError::InvalidScope(why) => (StatusCode::BAD_REQUEST, "invalid_scope", why),
+            OAuthError::InvalidTarget(why) => (StatusCode::BAD_REQUEST, "invalid_target", why),
+            OAuthError::UnsupportedGrantType => (
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+                "Supported: authorization_code and token exchange",
+            ),
+        };
+        let body = Json(serde_json::json!({
+            "error": error,
+            "error_description": description,
+        }));
+        (status, [(header::CACHE_CONTROL, "no-store")], body).into_response()
+    }
+}
+
+// ============================================================================
+// CLIENT REGISTRATION
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct RegisterClient {
+    client_name: String,
+    redirect_uris: Vec<String>,
+    /// Space-separated, like every OAuth scope parameter
+    scope: String,
+}
+
+#[derive(Serialize)]
+pub struct RegisteredClient {
+    client_id: String,
+    client_secret: String,
+    client_name: String,
+    redirect_uris: Vec<String>,
+    scope: String,
+}
+
+/// POST /oauth/clients - dynamic registration, trimmed down from RFC 7591
+pub async fn register_client(
+    State(store): State<Arc<OAuthStore>>,
+    Json(input): Json<RegisterClient>,
+) -> Result<(StatusCode, Json<RegisteredClient>), OAuthError> {
+    if input.client_name.trim().is_empty() {
+        return Err(OAuthError::InvalidRequest("client_name is required"));
+    }
+    let valid_uri = |uri: &String| {
+        (uri.starts_with("https://") || uri.starts_with("http://localhost")) && !uri.contains('#')
+    };
+    if input.redirect_uris.is_empty() || !input.redirect_uris.iter().all(valid_uri) {
+        return Err(OAuthError::InvalidRequest(
+            "redirect_uris must be https (or http://localhost) URLs without a fragment",
+        ));
+    }
+    let scopes = parse_scopes(&input.scope);
+    if scopes.is_empty() || !scopes.iter().all(|s| SCOPES.iter().any(|(k, _)| k == s)) {
+        return Err(OAuthError::InvalidRequest(
+            "scope contains an unknown scope",
+        ));
+    }
+
+    let client_id = format!("client-{}", store.random_string(12));
+    let client = Client {
+        name: input.client_name,
+        secret: store.random_string(32),
+        redirect_uris: input.redirect_uris,
+        scopes,
+    };
+    let registered = RegisteredClient {
+        client_id: client_id.clone(),
+        client_secret: client.secret.clone(),
+        client_name: client.name.clone(),
+        redirect_uris: client.redirect_uris.clone(),
+        scope: join_scopes(&client.scopes),
+    };
+    store
+        .data
+        .write()
+        .unwrap()
+        .clients
+        .insert(client_id, client);
+    Ok((StatusCode::CREATED, Json(registered)))
+}
+
+// ============================================================================
+// AUTHORIZATION ENDPOINT + CONSENT SCREEN
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct AuthorizeQuery {
+    response_type: Option<String>,
+    client_id: String,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+}
+
+/// A request whose client and redirect URI have been verified - from here
+/// on, errors may be sent back to the client
+struct VerifiedRequest {
+    client_id: String,
+    client: Client,
+    redirect_uri: String,
+}
+
+/// Check the client and redirect URI. Failures are rendered, not redirected:
+/// redirecting to an unverified URI would make this an open redirector.
+fn verify_client(
+    store: &OAuthStore,
+    client_id: &str,
+    redirect_uri: Option<&str>,
+) -> Result<VerifiedRequest, &'static str> {
+    let client = store.client(client_id).ok_or("Unknown client_id")?;
+    let redirect_uri = match redirect_uri {
+        Some(uri) if client.redirect_uris.iter().any(|r| r == uri) => uri.to_string(),
+        Some(_) => return Err("redirect_uri is not registered for this client"),
+        // Optional only when there's exactly one to choose from
+        None if client.redirect_uris.len() == 1 => client.redirect_uris[0].clone(),
+        None => return Err("redirect_uri is required"),
+    };
+    Ok(VerifiedRequest {
+        client_id: client_id.to_string(),
+        client,
+        redirect_uri,
+    })
+}
+
+/// `redirect_uri?code=...&state=...` (or `?error=...`)
+fn redirect_with(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> Response {
+    let mut params = params.to_vec();
+    if let Some(state) = state {
+        params.push(("state", state));
+    }
+    let query = serde_urlencoded::to_string(&params).expect("strings always encode");
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    Redirect::to(&format!("{}{}{}", redirect_uri, separator, query)).into_response()
+}
+
+/// GET /oauth/authorize - show what the app wants and ask the user
+pub async fn authorize(
+    State(store): State<Arc<OAuthStore>>,
+    Query(query): Query<AuthorizeQuery>,
+) -> Response {
+    let request = match verify_client(&store, &query.client_id, query.redirect_uri.as_deref()) {
+        Ok(request) => request,
+        Err(why) => return error_page(why),
+    };
+    let state = query.state.as_deref();
+    if query.response_type.as_deref() != Some("code") {
+        return redirect_with(
+            &request.redirect_uri,
+            &[("error", "unsupported_response_type")],
+            state,
+        );
+    }
+    let requested = parse_scopes(query.scope.as_deref().unwrap_or_default());
+    if requested.is_empty() || !requested.is_subset(&request.client.scopes) {
+        return redirect_with(&request.redirect_uri, &[("error", "invalid_scope")], state);
+    }
+
+    consent_page(&request, &requested, state, None).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ConsentForm {
+    client_id: String,
+    redirect_uri: String,
+    /// What the client asked for, carried through the form
+    requested: String,
+    state: Option<String>,
+    email: String,
+    password: String,
+    /// `approve` or `deny`
+    decision: String,
+    /// One entry per ticked checkbox; `axum_extra::extract::Form` collects
+    /// repeated keys into a `Vec`
+    #[serde(default)]
+    scope: Vec<String>,
+}
+
+/// POST /oauth/authorize - the user's answer
+pub async fn decide(
+    State(store): State<Arc<OAuthStore>>,
+    State(users): State<Arc<dyn UserDirectory>>,
+    Form(form): Form<ConsentForm>,
+) -> Response {
+    // Hidden fields can be edited, so everything is checked again
+    let request = match verify_client(&store, &form.client_id, Some(&form.redirect_uri)) {
+        Ok(request) => request,
+        Err(why) => return error_page(why),
+    };
+    let state = form.state.as_deref().filter(|s| !s.is_empty());
+    let requested = parse_scopes(&form.requested);
+    if !requested.is_subset(&request.client.scopes) {
+        return redirect_with(&request.redirect_uri, &[("error", "invalid_scope")], state);
+    }
+
+    if form.decision != "approve" {
+        return redirect_with(&request.redirect_uri, &[("error", "access_denied")], state);
+    }
+    let Some((user_id, _role)) = users.authenticate(&form.email, &form.password) else {
+        let page = consent_page(&request, &requested, state, Some("Wrong email or password"));
+        return (StatusCode::UNAUTHORIZED, page).into_response();
+    };
+    // Only what was both requested and ticked
+    let approved: BTreeSet<String> = form
+        .scope
+        .into_iter()
+        .filter(|scope| requested.contains(scope))
+        .collect();
+    if approved.is_empty() {
+        return redirect_with(&request.redirect_uri, &[("error", "access_denied")], state);
+    }
+
+    let code = store.random_string(32);
+    store.data.write().unwrap().codes.insert(
+        code.clone(),
+        AuthCode {
+            client_id: request.client_id,
+            user_id,
+            redirect_uri: request.redirect_uri.clone(),
+            scopes: approved,
+            expires_at: Utc::now().timestamp() + CODE_TTL_SECS,
+            issued_token: None,
+        },
+    );
+    redirect_with(&request.redirect_uri, &[("code", &code)], state)
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn error_page(message: &str) -> Response {
+    let body = format!(
+        "<!doctype html><title>Authorization error</title>\
+         <h1>Authorization error</h1><p>{}</p>",
+        html_escape(message)
+    );
+    (StatusCode::BAD_REQUEST, Html(body)).into_response()
+}
+
+/// The consent screen: who is asking, for what, plus sign-in fields.
+/// (A real server would use its session cookie instead of asking for the
+/// password here.)
+fn consent_page(
+    request: &VerifiedRequest,
+    requested: &BTreeSet<String>,
+    state: Option<&str>,
+    error: Option<&str>,
+) -> Html<String> {
+    let scopes: String = SCOPES
+        .iter()
+        .filter(|(scope, _)| requested.contains(*scope))
+        .map(|(scope, description)| {
+            format!(
+                r#"<li><label><input type="checkbox" name="scope" value="{0}" checked> <b>{0}</b> - {1}</label></li>"#,
+                html_escape(scope),
+                html_escape(description)
+            )
+        })
+        .collect();
+    let error = error
+        .map(|e| format!(r#"<p style="color:#b00">{}</p>"#, html_escape(e)))
+        .unwrap_or_default();
+    let hidden = |name: &str, value: &str| {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            name,
+            html_escape(value)
+        )
+    };
+
+    Html(format!(
+        r#"<!doctype html>
+<html>
+<head><meta charset="utf-8"><title>Authorize {name}</title></head>
+<body style="font-family:sans-serif;max-width:32rem;margin:2rem auto">
+<h1>{name} wants access to your account</h1>
+{error}
+<form method="post" action="/oauth/authorize">
+  {client_id}{redirect_uri}{requested}{state}
+  <p>It will be able to:</p>
+  <ul style="list-style:none;padding:0">{scopes}</ul>
+  <p>Untick anything you don't want to allow.</p>
+  <p><input name="email" type="email" placeholder="Email" required></p>
+  <p><input name="password" type="password" placeholder="Password" required></p>
+  <button name="decision" value="approve">Allow</button>
+  <button name="decision" value="deny" formnovalidate>Deny</button>
+</form>
+<p><small>You'll be sent back to {redirect}</small></p>
+</body>
+</html>"#,
+        name = html_escape(&request.client.name),
+        error = error,
+        client_id = hidden("client_id", &request.client_id),
+        redirect_uri = hidden("redirect_uri", &request.redirect_uri),
+        requested = hidden("requested", &join_scopes(requested)),
+        state = hidden("state", state.unwrap_or_default()),
+        scopes = scopes,
+        redirect = html_escape(&request.redirect_uri),
+    ))
+}
+
+// ============================================================================
+// TOKEN ENDPOINT
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    /// `client_secret_post`; HTTP Basic works too
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    // Token exchange
+    subject_token: Option<String>,
+    subject_token_type: Option<String>,
+    requested_token_type: Option<String>,
+    audience: Option<String>,
+    scope: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    /// Token exchange responses only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    issued_token_type: Option<&'static str>,
+    token_type: &'static str,
+    expires_in: i64,
+    scope: String,
+}
+
+/// Client credentials from HTTP Basic or, failing that, the form body
+fn client_credentials(
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<(String, String), OAuthError> {
+    match basic {
+        Some(TypedHeader(Authorization(basic))) => {
+            Ok((basic.username().to_string(), basic.password().to_string()))
+        }
+        None => client_id
+            .zip(client_secret)
+            .ok_or(OAuthError::InvalidClient),
+    }
+}
+
+/// POST /oauth/token - exchange a code, or a token, for an access token
+pub async fn token(
+    State(store): State<Arc<OAuthStore>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(mut input): Form<TokenRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let (client_id, secret) =
+        client_credentials(basic, input.client_id.take(), input.client_secret.take())?;
+    store.authenticate_client(&client_id, &secret)?;
+    let response = match input.grant_type.as_str() {
+        "authorization_code" => redeem_code(&store, client_id, input)?,
+        TOKEN_EXCHANGE_GRANT => exchange_token(&store, client_id, input)?,
+        _ => return Err(OAuthError::UnsupportedGrantType),
+    };
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(response)))
+}
+
+fn redeem_code(
+    store: &OAuthStore,
+    client_id: String,
+    input: TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    let code = input
+        .code
+        .ok_or(OAuthError::InvalidRequest("code is required"))?;
+
+    let now = Utc::now().timestamp();
+    let mut data = store.data.write().unwrap();
+    let grant = data
+        .codes
+        .get_mut(&code)
+        .filter(|grant| grant.client_id == client_id)
+        .ok_or(OAuthError::InvalidGrant("Unknown code"))?;
+    if let Some(leaked) = grant.issued_token.take() {
+        // Second use: whoever has the code may also have the token
+        data.tokens.remove(&leaked);
+        data.codes.remove(&code);
+        return Err(OAuthError::InvalidGrant(
+            "Code already used; its token was revoked",
+        ));
+    }
+    if grant.expires_at < now {
+        data.codes.remove(&code);
+        return Err(OAuthError::InvalidGrant("Code expired"));
+    }
+    if input.redirect_uri.as_deref() != Some(grant.redirect_uri.as_str()) {
+        return Err(OAuthError::InvalidGrant("redirect_uri does not match"));
+    }
+
+    let access_token = store.random_string(40);
+    grant.issued_token = Some(access_token.clone());
+    let issued = AccessToken {
+        client_id,
+        user_id: grant.user_id.clone(),
+        scopes: grant.scopes.clone(),
+        issued_at: now,
+        expires_at: now + TOKEN_TTL_SECS,
+        audience: None,
+        actors: Vec::new(),
+    };
+    let response = TokenResponse {
+        access_token: access_token.clone(),
+        issued_token_type: None,
+        token_type: "Bearer",
+        expires_in: TOKEN_TTL_SECS,
+        scope: join_scopes(&issued.scopes),
+    };
+    data.tokens.insert(access_token, issued);
+
+    Ok(response)
+}
+
+// ============================================================================
+// TOKEN EXCHANGE (RFC 8693)
+// ============================================================================
+
+/// `client_id` trades `subject_token` for a token for `audience`
+fn exchange_token(
+    store: &OAuthStore,
+    client_id: String,
+    input: TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    let subject_token = input
+        .subject_token
+        .ok_or(OAuthError::InvalidRequest("subject_token is required"))?;
+    if input.subject_token_type.as_deref() != Some(ACCESS_TOKEN_TYPE) {
+        return Err(OAuthError::InvalidRequest(
+            "subject_token_type must be an access token",
+        ));
+    }
+    if input
+        .requested_token_type
+        .is_some_and(|requested| requested != ACCESS_TOKEN_TYPE)
+    {
+        return Err(OAuthError::InvalidRequest(
+            "Only access tokens can be issued",
+        ));
+    }
+    let audience = input
+        .audience
+        .ok_or(OAuthError::InvalidTarget("audience is required"))?;
+
+    let now = Utc::now().timestamp();
+    let mut data = store.data.write().unwrap();
+    if audience == client_id || !data.clients.contains_key(&audience) {
+        return Err(OAuthError::InvalidTarget(
+            "audience is not another known service",
+        ));
+    }
+    let subject = data
+        .tokens
+        .get(&subject_token)
+        .filter(|token| token.expires_at > now)
+        .ok_or(OAuthError::InvalidGrant("subject_token is not active"))?;
+    // Holding a token isn't enough: it must have been issued to or for you
+    let held_by_caller = match &subject.audience {
+        Some(audience) => *audience == client_id,
+        None => subject.client_id == client_id,
+    };
+    if !held_by_caller {
+        return Err(OAuthError::InvalidGrant(
+            "subject_token was not issued to this client",
+        ));
+    }
+    if subject.actors.len() >= MAX_DELEGATION_DEPTH {
+        return Err(OAuthError::InvalidGrant("Delegation chain too long"));
+    }
+    // Narrower or equal, never wider
+    let scopes = match input.scope.as_deref().map(parse_scopes) {
+        Some(requested) if requested.is_empty() || !requested.is_subset(&subject.scopes) => {
+            return Err(OAuthError::InvalidScope(
+                "scope must be a subset of the subject token's",
+            ))
+        }
+        Some(requested) => requested,
+        None => subject.scopes.clone(),
+    };
+
+    let expires_at = subject.expires_at.min(now + EXCHANGED_TOKEN_TTL_SECS);
+    let mut actors = vec![client_id.clone()];
+    actors.extend(subject.actors.iter().cloned());
+    let issued = AccessToken {
+        client_id,
+        user_id: subject.user_id.clone(),
+        scopes,
+        issued_at: now,
+        expires_at,
+        audience: Some(audience),
+        actors,
+    };
+    let access_token = store.random_string(40);
+    let response = TokenResponse {
+        access_token: access_token.clone(),
+        issued_token_type: Some(ACCESS_TOKEN_TYPE),
+        token_type: "Bearer",
+        expires_in: expires_at - now,
+        scope: join_scopes(&issued.scopes),
+    };
+    data.tokens.insert(access_token, issued);
+
+    Ok(response)
+}
+
+// ============================================================================
+// INTROSPECTION (RFC 7662)
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct IntrospectRequest {
+    token: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Introspection {
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+    /// Who is acting for `sub`, for exchanged tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    act: Option<serde_json::Value>,
+}
+
+/// POST /oauth/introspect - for resource servers, which authenticate as a
+/// registered client. Unknown, expired and revoked tokens all look the
+/// same: `{"active": false}`.
+pub async fn introspect(
+    State(store): State<Arc<OAuthStore>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Form(input): Form<IntrospectRequest>,
+) -> Result<Json<Introspection>, OAuthError> {
+    let (client_id, secret) = client_credentials(basic, input.client_id, input.client_secret)?;
+    store.authenticate_client(&client_id, &secret)?;
+
+    let now = Utc::now().timestamp();
+    let data = store.data.read().unwrap();
+    let introspection = match data.tokens.get(&input.token) {
+        Some(token) if token.expires_at > now => Introspection {
+            active: true,
+            scope: Some(join_scopes(&token.scopes)),
+            client_id: Some(token.client_id.clone()),
+            sub: Some(token.user_id.clone()),
+            token_type: Some("Bearer"),
+            iat: Some(token.issued_at),
+            exp: Some(token.expires_at),
+            aud: token.audience.clone(),
+            act: act_claim(&token.actors),
+        },
+        _ => Introspection::default(),
+    };
+    Ok(Json(introspection))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_app, rng::SequentialRng, AppState, AuthConfig, OrgStore, PasswordPolicy};
+    use axum::{body::Body, http::Request, Router};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    const REDIRECT: &str = "http://localhost:8080/callback";
+
+    struct OneUser;
+
+    impl UserDirectory for OneUser {
+        fn authenticate(&self, email: &str, password: &str) -> Option<(String, String)> {
+            (email == "eve@test.dev" && password == "pw").then(|| ("user-1".into(), "user".into()))
+        }
+    }
+
+    fn test_app() -> Router {
+        let rng: SharedRng = Arc::new(SequentialRng::default());
+        build_app(AppState {
+            config: Arc::new(AuthConfig {
+                jwt_secret: "oauth-tests".to_string(),
+                jwt_expiry_hours: 1,
+                password_policy: PasswordPolicy::default(),
+            }),
+            orgs: Arc::new(OrgStore::seeded()),
+            users: Arc::new(OneUser),
+            oauth: Arc::new(OAuthStore::seeded(rng.clone())),
+            rng,
+        })
+        .into_router()
+    }
+
+    async fn post_form(app: &Router, uri: &str, body: &str) -> Response {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    async fn json(response: Response) -> serde_json::Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Approve with only `ticked` of the requested scopes; returns the code
+    async fn approve(app: &Router, ticked: &[&str]) -> String {
+        let mut form = format!(
+            "client_id=demo-app&redirect_uri={}&requested=profile%3Aread+todos%3Aread+todos%3Awrite\
+             &state=xyz&email=eve%40test.dev&password=pw&decision=approve",
+            REDIRECT
+        );
+        for scope in ticked {
+            form.push_str(&format!("&scope={}", scope));
+        }
+        let response = post_form(app, "/oauth/authorize", &form).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let query = location.strip_prefix(&format!("{}?", REDIRECT)).unwrap();
+        let params: HashMap<String, String> = serde_urlencoded::from_str(query).unwrap();
+        assert_eq!(params["state"], "xyz");
+        params["code"].clone()
+    }
+
+    async fn exchange(app: &Router, code: &str) -> Response {
+        let form = format!(
+            "grant_type=authorization_code&code={}&redirect_uri={}\
+             &client_id=demo-app&client_secret=demo-secret",
+            code, REDIRECT
+        );
+        post_form(app, "/oauth/token", &form).await
+    }
+
+    async fn introspect(app: &Router, token: &str) -> serde_json::Value {
+        let form = format!(
+            "token={}&client_id=demo-app&client_secret=demo-secret",
+            token
+        );
+        json(post_form(app, "/oauth/introspect", &form).await).await
+    }
+
+    /// `client` trades `subject_token` for a token for `audience`
+    async fn token_exchange(
+        app: &Router,
+        (client, secret): (&str, &str),
+        subject_token: &str,
+        audience: &str,
+        scope: Option<&str>,
+    ) -> Response {
+        let mut form = format!(
+            "grant_type={}&subject_token={}&subject_token_type={}&audience={}\
+             &client_id={}&client_secret={}",
+            TOKEN_EXCHANGE_GRANT, subject_token, ACCESS_TOKEN_TYPE, audience, client, secret
+        );
+        if let Some(scope) = scope {
+            form.push_str(&format!("&scope={}", scope));
+        }
+        post_form(app, "/oauth/token", &form).await
+    }
+
+    async fn user_token(app: &Router) -> String {
+        let code = approve(app, &["profile:read", "todos:read", "todos:write"]).await;
+        json(exchange(app, &code).await).await["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_registered_client_gets_its_id_and_secret_from_the_rng() {
+        let app = test_app();
+        let request = Request::post("/oauth/clients")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"client_name":"CLI","redirect_uris":["http://localhost:9000/cb"],"scope":"todos:read"}"#,
+            ))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let client = json(response).await;
+
+        // Bytes 00.. from `SequentialRng`: 12 for the id, the next 32 for the secret
+        assert_eq!(client["client_id"], "client-0123456789AB");
+        assert_eq!(client["client_secret"], "CDEFGHIJKLMNOPQRSTUVWXYZabcdefgh");
+    }
+
+    #[tokio::test]
+    async fn test_consent_screen_lists_requested_scopes() {
+        let app = test_app();
+        let uri = format!(
+            "/oauth/authorize?response_type=code&client_id=demo-app&redirect_uri={}\
+             &scope=profile:read%20todos:read&state=xyz",
+            REDIRECT
+        );
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(page.to_vec()).unwrap();
+
+        assert!(page.contains("Demo Todo Dashboard"));
+        assert!(page.contains(r#"value="profile:read""#));
+        assert!(page.contains(r#"value="todos:read""#));
+        assert!(!page.contains(r#"value="todos:write""#));
+    }
+
+    #[tokio::test]
+    async fn test_token_carries_only_approved_scopes() {
+        let app = test_app();
+        // todos:write was requested but not ticked; orgs:read was never requested
+        let code = approve(&app, &["profile:read", "todos:read", "orgs:read"]).await;
+
+        let response = exchange(&app, &code).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = json(response).await;
+        assert_eq!(token["scope"], "profile:read todos:read");
+
+        let info = introspect(&app, token["access_token"].as_str().unwrap()).await;
+        assert_eq!(info["active"], true);
+        assert_eq!(info["scope"], "profile:read todos:read");
+        assert_eq!(info["sub"], "user-1");
+        assert_eq!(info["client_id"], "demo-app");
+    }
+
+    #[tokio::test]
+    async fn test_reused_code_revokes_its_token() {
+        let app = test_app();
+        let code = approve(&app, &["profile:read"]).await;
+        let token = json(exchange(&app, &code).await).await;
+        let access_token = token["access_token"].as_str().unwrap();
+
+        let replay = exchange(&app, &code).await;
+        assert_eq!(replay.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json(replay).await["error"], "invalid_grant");
+        assert_eq!(introspect(&app, access_token).await["active"], false);
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_redirect_uri_is_never_followed() {
+        let app = test_app();
+        let uri = "/oauth/authorize?response_type=code&client_id=demo-app\
+                   &redirect_uri=https://evil.example/steal&scope=profile:read";
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.headers().get(header::LOCATION).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_token_exchange_narrows_scopes_and_names_the_actors() {
+        let app = test_app();
+        let user_token = user_token(&app).await;
+
+        // demo-app -> orders-service, read-only
+        let response = token_exchange(
+            &app,
+            ("demo-app", "demo-secret"),
+            &user_token,
+            "orders-service",
+            Some("todos:read"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let orders = json(response).await;
+        assert_eq!(orders["issued_token_type"], ACCESS_TOKEN_TYPE);
+        assert_eq!(orders["scope"], "todos:read");
+        assert!(orders["expires_in"].as_i64().unwrap() <= EXCHANGED_TOKEN_TTL_SECS);
+        let orders_token = orders["access_token"].as_str().unwrap();
+
+        // orders-service -> todo-service, scopes inherited
+        let todo = json(
+            token_exchange(
+                &app,
+                ("orders-service", "orders-secret"),
+                orders_token,
+                "todo-service",
+                None,
+            )
+            .await,
+        )
+        .await;
+        let todo_token = todo["access_token"].as_str().unwrap();
+
+        let info = introspect(&app, todo_token).await;
+        assert_eq!(info["sub"], "user-1");
+        assert_eq!(info["aud"], "todo-service");
+        assert_eq!(
+            info["act"],
+            serde_json::json!({ "sub": "orders-service", "act": { "sub": "demo-app" } })
+        );
+
+        let request = Request::get("/internal/todo-service/caller")
+            .header(header::AUTHORIZATION, format!("Bearer {}", todo_token))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json(response).await["depth"], 2);
+
+        // The token for orders-service is no good at the todo service
+        let request = Request::get("/internal/todo-service/caller")
+            .header(header::AUTHORIZATION, format!("Bearer {}", orders_token))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_token_exchange_never_widens_or_deepens() {
+        let app = test_app();
+        let user_token = user_token(&app).await;
+        let demo = ("demo-app", "demo-secret");
+
+        let wider =
+            token_exchange(&app, demo, &user_token, "orders-service", Some("orgs:read")).await;
+        assert_eq!(json(wider).await["error"], "invalid_scope");
+
+        // Only the client holding the token may exchange it
+        let stolen = token_exchange(
+            &app,
+            ("orders-service", "orders-secret"),
+            &user_token,
+            "todo-service",
+            None,
+        )
+        .await;
+        assert_eq!(json(stolen).await["error"], "invalid_grant");
+
+        let orders = token_exchange(&app, demo, &user_token, "orders-service", None).await;
+        let orders = json(orders).await["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let todo = token_exchange(
+            &app,
+            ("orders-service", "orders-secret"),
+            &orders,
+            "todo-service",
+            None,
+        )
+        .await;
+        let todo = json(todo).await["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // A third hop is past MAX_DELEGATION_DEPTH
+        let too_deep = token_exchange(
+            &app,
+            ("todo-service", "todo-secret"),
+            &todo,
+            "orders-service",
+            None,
+        )
+        .await;
+        assert_eq!(too_deep.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json(too_deep).await["error_description"],
+            "Delegation chain too long"
+        );
+    }
+}
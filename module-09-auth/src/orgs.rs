@@ -0,0 +1,393 @@
+//! # Organizations, Teams & Membership-Based Access
+//!
+//! The JWT answers "who are you?". In a multi-tenant app that isn't enough:
+//! the same user can own one organization and only view another. So every
+//! org-scoped request names its organization in `X-Org-Id`, and access is
+//! decided by the caller's *membership* in that org:
+//!
+//! | Role   | Read todos | Create todos | Delete todos     | Manage members |
+//! |--------|------------|--------------|------------------|----------------|
+//! | owner  | ✅         | ✅           | ✅ any           | ✅             |
+//! | member | ✅         | ✅           | ✅ their own     | ❌             |
+//! | viewer | ✅         | ❌           | ❌               | ❌             |
+//!
+//! The global `admin` role from the token grants nothing here - membership
+//! is the only source of truth. Non-members get `404`, not `403`, so the
+//! API doesn't reveal which organizations exist.
+
+use axum::{
+    extract::{FromRef, FromRequestParts, Path, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::CurrentUser;
+
+pub const ORG_ID_HEADER: &str = "x-org-id";
+
+// ============================================================================
+// MODELS
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrgRole {
+    Owner,
+    Member,
+    Viewer,
+}
+
+/// What a request wants to do inside an organization
+#[derive(Debug, Clone, Copy)]
+pub enum OrgAction {
+    ReadTodos,
+    CreateTodo,
+    DeleteAnyTodo,
+    DeleteOwnTodo,
+    ManageMembers,
+}
+
+impl OrgRole {
+    pub fn allows(self, action: OrgAction) -> bool {
+        use OrgAction::*;
+        match self {
+            OrgRole::Owner => true,
+            OrgRole::Member => matches!(action, ReadTodos | CreateTodo | DeleteOwnTodo),
+            OrgRole::Viewer => matches!(action, ReadTodos),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Membership {
+    pub org_id: String,
+    pub user_id: String,
+    pub role: OrgRole,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrgTodo {
+    pub id: u64,
+    pub org_id: String,
+    pub title: String,
+    pub completed: bool,
+    pub created_by: String,
+}
+
+// ============================================================================
+// STORE
+// ============================================================================
+
+#[derive(Default)]
+struct OrgData {
+    orgs: HashMap<String, Organization>,
+    /// (org_id, user_id) -> role
+    memberships: HashMap<(String, String), OrgRole>,
+    todos: Vec<OrgTodo>,
+    next_todo_id: u64,
+}
+
+/// In-memory organizations, memberships and org-scoped todos
+#[derive(Default)]
+pub struct OrgStore {
+    data: RwLock<OrgData>,
+}
+
+impl OrgStore {
+    /// Two orgs with overlapping members, matching the demo logins:
+    /// `user-1` owns Acme and views Globex; `admin-1` owns Globex and is a
+    /// member of Acme
+    pub fn seeded() -> Self {
+        let store = Self::default();
+        {
+            let mut data = store.data.write().unwrap();
+            for (id, name) in [("acme", "Acme Corp"), ("globex", "Globex Inc")] {
+                data.orgs.insert(
+                    id.to_string(),
+                    Organization {
+                        id: id.to_string(),
+                        name: name.to_string(),
+                    },
+                );
+            }
+            for (org, user, role) in [
+                ("acme", "user-1", OrgRole::Owner),
+                ("acme", "admin-1", OrgRole::Member),
+                ("globex", "admin-1", OrgRole::Owner),
+                ("globex", "user-1", OrgRole::Viewer),
+            ] {
+                data.memberships
+                    .insert((org.to_string(), user.to_string()), role);
+            }
+        }
+        store
+    }
+
+    pub fn role_of(&self, org_id: &str, user_id: &str) -> Option<OrgRole> {
+        let data = self.data.read().unwrap();
+        data.memberships
+            .get(&(org_id.to_string(), user_id.to_string()))
+            .copied()
+    }
+}
+
+// ============================================================================
+// ORG CONTEXT EXTRACTOR
+// ============================================================================
+
+#[derive(Debug)]
+pub enum OrgError {
+    MissingOrgHeader,
+    /// Unknown org *or* not a member - deliberately indistinguishable
+    NotFound,
+    Forbidden { role: OrgRole, action: OrgAction },
+    Unauthenticated,
+    TodoNotFound,
+    LastOwner,
+}
+
+impl IntoResponse for OrgError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            OrgError::MissingOrgHeader => (
+                StatusCode::BAD_REQUEST,
+                "X-Org-Id header is required".to_string(),
+            ),
+            OrgError::NotFound => (StatusCode::NOT_FOUND, "Organization not found".to_string()),
+            OrgError::Forbidden { role, action } => (
+                StatusCode::FORBIDDEN,
+                format!("Role {:?} cannot {:?} in this organization", role, action),
+            ),
+            OrgError::Unauthenticated => {
+                (StatusCode::UNAUTHORIZED, "Authentication required".to_string())
+            }
+            OrgError::TodoNotFound => (StatusCode::NOT_FOUND, "Todo not found".to_string()),
+            OrgError::LastOwner => (
+                StatusCode::CONFLICT,
+                "An organization must keep at least one owner".to_string(),
+            ),
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+/// The organization named by `X-Org-Id` and the caller's role in it.
+/// Extracting it proves the caller is a member.
+#[derive(Debug, Clone)]
+pub struct OrgContext {
+    pub org_id: String,
+    pub user_id: String,
+    pub role: OrgRole,
+}
+
+impl OrgContext {
+    pub fn require(&self, action: OrgAction) -> Result<(), OrgError> {
+        if self.role.allows(action) {
+            Ok(())
+        } else {
+            Err(OrgError::Forbidden {
+                role: self.role,
+                action,
+            })
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for OrgContext
+where
+    Arc<OrgStore>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = OrgError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Set by `auth_middleware`
+        let user = parts
+            .extensions
+            .get::<CurrentUser>()
+            .ok_or(OrgError::Unauthenticated)?;
+        let org_id = parts
+            .headers
+            .get(ORG_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .ok_or(OrgError::MissingOrgHeader)?;
+
+        let store = Arc::<OrgStore>::from_ref(state);
+        let role = store
+            .role_of(org_id, &user.id)
+            .ok_or(OrgError::NotFound)?;
+
+        Ok(OrgContext {
+            org_id: org_id.to_string(),
+            user_id: user.id.clone(),
+            role,
+        })
+    }
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+#[derive(Serialize)]
+pub struct MyOrg {
+    #[serde(flatten)]
+    org: Organization,
+    role: OrgRole,
+}
+
+/// GET /protected/orgs - organizations the caller belongs to (no header needed)
+pub async fn list_my_orgs(
+    State(store): State<Arc<OrgStore>>,
+    axum::Extension(user): axum::Extension<CurrentUser>,
+) -> Json<Vec<MyOrg>> {
+    let data = store.data.read().unwrap();
+    let mut orgs: Vec<MyOrg> = data
+        .memberships
+        .iter()
+        .filter(|((_, user_id), _)| *user_id == user.id)
+        .filter_map(|((org_id, _), role)| {
+            data.orgs.get(org_id).map(|org| MyOrg {
+                org: org.clone(),
+                role: *role,
+            })
+        })
+        .collect();
+    orgs.sort_by(|a, b| a.org.id.cmp(&b.org.id));
+    Json(orgs)
+}
+
+/// GET /protected/org/todos
+pub async fn list_org_todos(
+    State(store): State<Arc<OrgStore>>,
+    org: OrgContext,
+) -> Result<Json<Vec<OrgTodo>>, OrgError> {
+    org.require(OrgAction::ReadTodos)?;
+    let data = store.data.read().unwrap();
+    let todos = data
+        .todos
+        .iter()
+        .filter(|t| t.org_id == org.org_id)
+        .cloned()
+        .collect();
+    Ok(Json(todos))
+}
+
+#[derive(Deserialize)]
+pub struct CreateOrgTodo {
+    title: String,
+}
+
+/// POST /protected/org/todos
+pub async fn create_org_todo(
+    State(store): State<Arc<OrgStore>>,
+    org: OrgContext,
+    Json(input): Json<CreateOrgTodo>,
+) -> Result<(StatusCode, Json<OrgTodo>), OrgError> {
+    org.require(OrgAction::CreateTodo)?;
+    let mut data = store.data.write().unwrap();
+    data.next_todo_id += 1;
+    let todo = OrgTodo {
+        id: data.next_todo_id,
+        org_id: org.org_id,
+        title: input.title,
+        completed: false,
+        created_by: org.user_id,
+    };
+    data.todos.push(todo.clone());
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+/// DELETE /protected/org/todos/{id} - owners delete anything, members their own
+pub async fn delete_org_todo(
+    State(store): State<Arc<OrgStore>>,
+    org: OrgContext,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, OrgError> {
+    let mut data = store.data.write().unwrap();
+    // Todos of other orgs are invisible, not forbidden
+    let index = data
+        .todos
+        .iter()
+        .position(|t| t.id == id && t.org_id == org.org_id)
+        .ok_or(OrgError::TodoNotFound)?;
+
+    if data.todos[index].created_by == org.user_id {
+        org.require(OrgAction::DeleteOwnTodo)?;
+    } else {
+        org.require(OrgAction::DeleteAnyTodo)?;
+    }
+    data.todos.remove(index);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /protected/org/members
+pub async fn list_members(
+    State(store): State<Arc<OrgStore>>,
+    org: OrgContext,
+) -> Json<Vec<Membership>> {
+    // Any member may see who else is in the org
+    let data = store.data.read().unwrap();
+    let mut members: Vec<Membership> = data
+        .memberships
+        .iter()
+        .filter(|((org_id, _), _)| *org_id == org.org_id)
+        .map(|((org_id, user_id), role)| Membership {
+            org_id: org_id.clone(),
+            user_id: user_id.clone(),
+            role: *role,
+        })
+        .collect();
+    members.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+    Json(members)
+}
+
+#[derive(Deserialize)]
+pub struct SetRole {
+    role: OrgRole,
+}
+
+/// PUT /protected/org/members/{user_id} - add a member or change their role
+pub async fn set_member_role(
+    State(store): State<Arc<OrgStore>>,
+    org: OrgContext,
+    Path(user_id): Path<String>,
+    Json(input): Json<SetRole>,
+) -> Result<Json<Membership>, OrgError> {
+    org.require(OrgAction::ManageMembers)?;
+    let mut data = store.data.write().unwrap();
+
+    // Never leave an organization without an owner
+    let demoting_last_owner = user_id == org.user_id
+        && input.role != OrgRole::Owner
+        && data
+            .memberships
+            .iter()
+            .filter(|((o, _), role)| *o == org.org_id && **role == OrgRole::Owner)
+            .count()
+            == 1;
+    if demoting_last_owner {
+        return Err(OrgError::LastOwner);
+    }
+
+    data.memberships
+        .insert((org.org_id.clone(), user_id.clone()), input.role);
+    Ok(Json(Membership {
+        org_id: org.org_id,
+        user_id,
+        role: input.role,
+    }))
+}
@@ -0,0 +1,315 @@
+//! # Password Policy Engine
+//!
+//! Evaluates a candidate password against a configurable set of rules and
+//! returns per-rule feedback plus a 0-4 strength score:
+//! - Minimum length
+//! - Character classes (lower, upper, digit, symbol)
+//! - Breached/common password list
+//! - Similarity to the user's email address
+//!
+//! The bundled `data/common-passwords.txt` only holds the top 130 or so of
+//! the well-known "top passwords" lists, enough for the demo. A deployment
+//! points `COMMON_PASSWORDS_FILE` at a full list instead, e.g. SecLists'
+//! `10k-most-common.txt`: one password per line, in any case.
+
+use serde::Serialize;
+use std::{collections::HashSet, sync::OnceLock};
+
+/// The fallback list, one password per line
+const BUNDLED_PASSWORDS: &str = include_str!("../data/common-passwords.txt");
+
+/// The list in use, lowercased: `COMMON_PASSWORDS_FILE` when it is set, the
+/// bundled one otherwise. `main` loads it before serving, so a missing file
+/// stops the server at startup rather than failing a request.
+pub fn common_passwords() -> &'static HashSet<String> {
+    static SET: OnceLock<HashSet<String>> = OnceLock::new();
+    SET.get_or_init(|| match std::env::var("COMMON_PASSWORDS_FILE") {
+        Ok(path) => {
+            let list = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("COMMON_PASSWORDS_FILE {}: {}", path, e));
+            parse_list(&list)
+        }
+        Err(_) => parse_list(BUNDLED_PASSWORDS),
+    })
+}
+
+fn parse_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+// ============================================================================
+// POLICY CONFIG
+// ============================================================================
+
+/// Password rules, stored in `AuthConfig`
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Reject passwords found in the common-password list
+    pub reject_breached: bool,
+    /// Reject passwords whose similarity to the email local part is at or
+    /// above this ratio (0.0 - 1.0)
+    pub max_email_similarity: f64,
+    /// Minimum strength score (0 - 4) required on top of the rules
+    pub min_score: u8,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 10,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: false,
+            reject_breached: true,
+            max_email_similarity: 0.7,
+            min_score: 2,
+        }
+    }
+}
+
+// ============================================================================
+// REPORT
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+pub struct RuleResult {
+    pub rule: &'static str,
+    pub passed: bool,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PolicyReport {
+    pub accepted: bool,
+    /// 0 (very weak) to 4 (very strong)
+    pub score: u8,
+    pub strength: &'static str,
+    pub rules: Vec<RuleResult>,
+}
+
+const STRENGTH_LABELS: [&str; 5] = ["very weak", "weak", "fair", "strong", "very strong"];
+
+// ============================================================================
+// EVALUATION
+// ============================================================================
+
+impl PasswordPolicy {
+    pub fn evaluate(&self, password: &str, email: &str) -> PolicyReport {
+        let mut rules = Vec::new();
+        let length = password.chars().count();
+
+        rules.push(RuleResult {
+            rule: "min_length",
+            passed: length >= self.min_length,
+            message: format!("At least {} characters (got {})", self.min_length, length),
+        });
+
+        let has_lower = password.chars().any(|c| c.is_lowercase());
+        let has_upper = password.chars().any(|c| c.is_uppercase());
+        let has_digit = password.chars().any(|c| c.is_ascii_digit());
+        let has_symbol = password.chars().any(|c| !c.is_alphanumeric());
+
+        let class_rules = [
+            (self.require_lowercase, has_lower, "lowercase", "a lowercase letter"),
+            (self.require_uppercase, has_upper, "uppercase", "an uppercase letter"),
+            (self.require_digit, has_digit, "digit", "a digit"),
+            (self.require_symbol, has_symbol, "symbol", "a symbol"),
+        ];
+        for (required, present, rule, what) in class_rules {
+            if required {
+                rules.push(RuleResult {
+                    rule,
+                    passed: present,
+                    message: format!("Contains {}", what),
+                });
+            }
+        }
+
+        let breached = common_passwords().contains(&password.to_lowercase());
+        if self.reject_breached {
+            rules.push(RuleResult {
+                rule: "not_breached",
+                passed: !breached,
+                message: "Not in the list of commonly used passwords".to_string(),
+            });
+        }
+
+        let similarity = email_similarity(password, email);
+        rules.push(RuleResult {
+            rule: "not_similar_to_email",
+            passed: similarity < self.max_email_similarity,
+            message: format!(
+                "Not too similar to your email (similarity {:.0}%)",
+                similarity * 100.0
+            ),
+        });
+
+        let mut score = entropy_score(password, has_lower, has_upper, has_digit, has_symbol);
+        if breached {
+            score = 0;
+        } else if similarity >= self.max_email_similarity {
+            score = score.min(1);
+        }
+
+        rules.push(RuleResult {
+            rule: "min_score",
+            passed: score >= self.min_score,
+            message: format!(
+                "Strength at least '{}'",
+                STRENGTH_LABELS[self.min_score.min(4) as usize]
+            ),
+        });
+
+        PolicyReport {
+            accepted: rules.iter().all(|r| r.passed),
+            score,
+            strength: STRENGTH_LABELS[score as usize],
+            rules,
+        }
+    }
+}
+
+/// Rough entropy estimate: length * log2(character pool size), bucketed 0-4
+fn entropy_score(password: &str, lower: bool, upper: bool, digit: bool, symbol: bool) -> u8 {
+    let pool = [(lower, 26), (upper, 26), (digit, 10), (symbol, 33)]
+        .iter()
+        .filter(|(present, _)| *present)
+        .map(|(_, size)| size)
+        .sum::<u32>()
+        .max(1);
+    let bits = password.chars().count() as f64 * f64::from(pool).log2();
+
+    match bits {
+        b if b < 28.0 => 0,
+        b if b < 36.0 => 1,
+        b if b < 60.0 => 2,
+        b if b < 80.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Similarity between the password and the email's local part (0.0 - 1.0)
+///
+/// Containment counts as a full match: "alice2024!" is as bad as "alice".
+fn email_similarity(password: &str, email: &str) -> f64 {
+    let local = email.split('@').next().unwrap_or("").to_lowercase();
+    let password = password.to_lowercase();
+    if local.len() < 3 {
+        return 0.0;
+    }
+    if password.contains(&local) {
+        return 1.0;
+    }
+
+    let distance = levenshtein(&password, &local);
+    let longest = password.chars().count().max(local.chars().count());
+    1.0 - distance as f64 / longest as f64
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            current[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        prev = current;
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(report: &PolicyReport) -> Vec<&'static str> {
+        report
+            .rules
+            .iter()
+            .filter(|rule| !rule.passed)
+            .map(|rule| rule.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_a_long_mixed_password_passes_every_rule() {
+        let report = PasswordPolicy::default().evaluate("Tr0ub4dor&3-staple", "karan@example.com");
+        assert!(report.accepted);
+        assert!(failed(&report).is_empty());
+        assert_eq!(report.score, 4);
+        assert_eq!(report.strength, "very strong");
+    }
+
+    #[test]
+    fn test_each_broken_rule_is_reported() {
+        let policy = PasswordPolicy::default();
+
+        let report = policy.evaluate("Ab1", "karan@example.com");
+        assert!(!report.accepted);
+        assert_eq!(failed(&report), ["min_length", "min_score"]);
+        assert_eq!(report.rules[0].message, "At least 10 characters (got 3)");
+
+        let report = policy.evaluate("alllowercase", "karan@example.com");
+        assert_eq!(failed(&report), ["uppercase", "digit"]);
+    }
+
+    #[test]
+    fn test_a_common_password_scores_zero_whatever_its_case() {
+        let report = PasswordPolicy::default().evaluate("Password123", "karan@example.com");
+        assert!(!report.accepted);
+        assert_eq!(failed(&report), ["not_breached", "min_score"]);
+        assert_eq!(report.score, 0);
+    }
+
+    #[test]
+    fn test_a_password_built_on_the_email_is_capped_at_weak() {
+        let report = PasswordPolicy::default().evaluate("Karan2024-Xyz", "karan@example.com");
+        assert!(!report.accepted);
+        assert_eq!(failed(&report), ["not_similar_to_email", "min_score"]);
+        assert_eq!(report.score, 1);
+    }
+
+    #[test]
+    fn test_optional_rules_only_appear_when_configured() {
+        let policy = PasswordPolicy {
+            require_symbol: true,
+            reject_breached: false,
+            ..PasswordPolicy::default()
+        };
+        let report = policy.evaluate("Tr0ub4dor3staple", "karan@example.com");
+        let rules: Vec<_> = report.rules.iter().map(|rule| rule.rule).collect();
+        assert_eq!(
+            rules,
+            [
+                "min_length",
+                "lowercase",
+                "uppercase",
+                "digit",
+                "symbol",
+                "not_similar_to_email",
+                "min_score"
+            ]
+        );
+        assert_eq!(failed(&report), ["symbol"]);
+    }
+
+    #[test]
+    fn test_a_list_file_is_read_case_insensitively() {
+        let list = parse_list("Dragon\n\n  MonKey  \nsunshine\n");
+        assert_eq!(list.len(), 3);
+        assert!(list.contains("monkey"));
+    }
+}
@@ -0,0 +1,86 @@
+//! # Injected Randomness
+//!
+//! Salts, client secrets, authorization codes and access tokens are all
+//! random, which makes responses impossible to compare in a test. They
+//! all come from one `Rng` instead:
+//! - `OsRandom` in the server: the operating system's CSPRNG, which is what
+//!   secrets need
+//! - `SequentialRng` in tests: bytes `00 01 02 ...`, so a test knows the
+//!   secret it will be given
+//!
+//! `AppState` holds it for password hashing, and the `OAuthStore` is built
+//! with the same one. Module 11 covers the pattern.
+
+use rand::RngCore;
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub trait Rng: Send + Sync {
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+/// How the source is kept in state
+pub type SharedRng = Arc<dyn Rng>;
+
+pub struct OsRandom;
+
+impl Rng for OsRandom {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        rand::rngs::OsRng.fill_bytes(dest);
+    }
+}
+
+const ALPHANUMERIC: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// `len` characters from `[0-9A-Za-z]`, each equally likely
+pub fn alphanumeric(rng: &dyn Rng, len: usize) -> String {
+    // 248 = 4 * 62: bytes above it are dropped, or the first characters
+    // would come up more often
+    let usable = (u8::MAX as usize / ALPHANUMERIC.len() * ALPHANUMERIC.len()) as u8;
+    let mut out = String::with_capacity(len);
+    let mut byte = [0u8];
+    while out.len() < len {
+        rng.fill_bytes(&mut byte);
+        if byte[0] < usable {
+            out.push(ALPHANUMERIC[byte[0] as usize % ALPHANUMERIC.len()] as char);
+        }
+    }
+    out
+}
+
+/// Bytes `00 01 02 ...` (wrapping at `ff`), continuing where the last call
+/// stopped
+#[cfg(test)]
+#[derive(Default)]
+pub struct SequentialRng(AtomicU64);
+
+#[cfg(test)]
+impl Rng for SequentialRng {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        let start = self.0.fetch_add(dest.len() as u64, Ordering::Relaxed);
+        for (offset, byte) in dest.iter_mut().enumerate() {
+            *byte = (start + offset as u64) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alphanumeric_is_exact_from_a_sequential_rng() {
+        let rng = SequentialRng::default();
+        assert_eq!(alphanumeric(&rng, 12), "0123456789AB");
+        assert_eq!(alphanumeric(&rng, 3), "CDE");
+    }
+
+    #[test]
+    fn test_alphanumeric_skips_bytes_that_would_bias_it() {
+        let rng = SequentialRng::default();
+        // Bytes 248..=255 are skipped, then 0 and 1 are used
+        let _ = alphanumeric(&rng, 248);
+        assert_eq!(alphanumeric(&rng, 2), "01");
+    }
+}
@@ -0,0 +1,182 @@
+//! # File Downloads with Range Support
+//!
+//! `ServeDir` does all of this for you; this handler spells it out so the
+//! headers involved are visible:
+//! - `Content-Type` guessed from the extension
+//! - `Content-Length` from the file metadata (the body is streamed, not buffered)
+//! - `Content-Disposition: attachment` so browsers save instead of display
+//! - `Accept-Ranges: bytes` plus `Range` -> `206 Partial Content`, which is
+//!   what makes resumable downloads and video seeking work
+//!
+//! Only single ranges are honoured. A multi-range request gets the whole file,
+//! which RFC 9110 allows.
+
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::{
+    io::SeekFrom,
+    path::{Component, PathBuf},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+/// Downloads are served from the same directory as `/static`
+const DOWNLOAD_ROOT: &str = "static";
+
+// ============================================================================
+// RANGE PARSING
+// ============================================================================
+
+#[derive(Debug)]
+enum RangeRequest {
+    /// No (usable) Range header: send everything
+    Full,
+    /// Inclusive byte range within the file
+    Partial { start: u64, end: u64 },
+    /// Syntactically valid but outside the file
+    Unsatisfiable,
+}
+
+/// Parse `bytes=0-99`, `bytes=100-` or `bytes=-100` against a file of `len` bytes
+fn parse_range(value: Option<&HeaderValue>, len: u64) -> RangeRequest {
+    let Some(spec) = value
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("bytes="))
+    else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        // Suffix range: the last N bytes
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(n) => (len.saturating_sub(n), len.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, len.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            _ => return RangeRequest::Full,
+        },
+    };
+
+    if len == 0 || start >= len {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial { start, end }
+    }
+}
+
+// ============================================================================
+// HANDLER
+// ============================================================================
+
+/// Only plain relative components - no `..`, no absolute paths
+fn resolve(path: &str) -> Option<PathBuf> {
+    let relative = PathBuf::from(path);
+    relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then(|| PathBuf::from(DOWNLOAD_ROOT).join(relative))
+}
+
+/// `attachment; filename="..."` plus an RFC 5987 `filename*` for non-ASCII names
+fn content_disposition(name: &str) -> HeaderValue {
+    let ascii: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() || c == ' ' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+
+    HeaderValue::from_str(&format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii, encoded
+    ))
+    .unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+/// GET /download/{*path}
+pub async fn download(Path(path): Path<String>, headers: HeaderMap) -> Response {
+    let Some(full_path) = resolve(&path) else {
+        return (StatusCode::BAD_REQUEST, "Invalid path").into_response();
+    };
+    let Ok(mut file) = File::open(&full_path).await else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+    let metadata = match file.metadata().await {
+        Ok(m) if m.is_file() => m,
+        _ => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+    };
+    let len = metadata.len();
+
+    let file_name = full_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "download".to_string());
+    let mime = mime_guess::from_path(&full_path).first_or_octet_stream();
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(mime.as_ref()).unwrap(),
+    );
+    response_headers.insert(header::CONTENT_DISPOSITION, content_disposition(&file_name));
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    match parse_range(headers.get(header::RANGE), len) {
+        RangeRequest::Full => {
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+            let body = Body::from_stream(ReaderStream::new(file));
+            (StatusCode::OK, response_headers, body).into_response()
+        }
+        RangeRequest::Partial { start, end } => {
+            if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+                return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+            }
+            let part_len = end - start + 1;
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(part_len));
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, len)).unwrap(),
+            );
+            let body = Body::from_stream(ReaderStream::new(file.take(part_len)));
+            (StatusCode::PARTIAL_CONTENT, response_headers, body).into_response()
+        }
+        RangeRequest::Unsatisfiable => {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len)).unwrap(),
+            );
+            (StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response()
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! # Chat History as an Event Log
+//!
+//! Broadcast channels forget: whoever wasn't connected when a message went
+//! out never sees it. `ChatHistory` keeps an append-only log of every chat
+//! message per room, and everything else is derived from it:
+//! - In memory, the last `HISTORY_CAPACITY` messages of each room (a ring)
+//! - On disk (optional), one JSON line per message in
+//!   `$CHAT_HISTORY_DIR/{room}.jsonl`; the first time a room is touched after
+//!   a restart, its ring is rebuilt by replaying that file
+//!
+//! Every message gets a per-room sequence number `id`, which is what makes
+//! the log usable:
+//! - `GET /rooms/{room}/messages?before=&limit=` pages backwards through the
+//!   ring for history backfill (`before` is an `id`, exclusive)
+//! - A newly joined WebSocket receives a `history` frame with the last
+//!   `REPLAY_ON_JOIN` messages, then live events. Clients dedupe by `id`.
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Messages kept in memory per room
+pub const HISTORY_CAPACITY: usize = 500;
+/// Messages sent to a client when it joins
+pub const REPLAY_ON_JOIN: usize = 20;
+const DEFAULT_PAGE: usize = 50;
+const MAX_PAGE: usize = 200;
+
+/// One event in a room's log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// Sequence number within the room, starting at 1
+    pub id: u64,
+    pub user: String,
+    pub text: String,
+    /// Unix milliseconds
+    pub sent_at: u64,
+}
+
+#[derive(Default)]
+struct RoomLog {
+    last_id: u64,
+    recent: VecDeque<ChatMessage>,
+}
+
+/// Every room's log; shared by the socket handlers and the history endpoint
+pub struct ChatHistory {
+    dir: Option<PathBuf>,
+    rooms: Mutex<HashMap<String, RoomLog>>,
+}
+
+impl ChatHistory {
+    /// Memory only, or also appended to `dir`
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir).expect("Failed to create chat history directory");
+        }
+        Self {
+            dir,
+            rooms: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Persist to `CHAT_HISTORY_DIR` when it's set
+    pub fn from_env() -> Self {
+        Self::new(std::env::var_os("CHAT_HISTORY_DIR").map(PathBuf::from))
+    }
+
+    pub fn dir(&self) -> Option<&PathBuf> {
+        self.dir.as_ref()
+    }
+
+    /// Room names become file names, so only plain ones get a file;
+    /// anything else stays in memory
+    fn file_for(&self, room: &str) -> Option<PathBuf> {
+        let plain = !room.is_empty()
+            && room.len() <= 64
+            && room
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        let dir = self.dir.as_ref()?;
+        plain.then(|| dir.join(format!("{}.jsonl", room)))
+    }
+
+    /// Rebuild a room's ring from its file. A torn last line (crash
+    /// mid-write) is skipped.
+    fn replay(&self, room: &str) -> RoomLog {
+        let mut log = RoomLog::default();
+        let Some(file) = self
+            .file_for(room)
+            .and_then(|path| fs::File::open(path).ok())
+        else {
+            return log;
+        };
+        for line in BufReader::new(file).lines().map_while(Result::ok) {
+            if let Ok(message) = serde_json::from_str::<ChatMessage>(&line) {
+                log.last_id = message.id;
+                log.push(message);
+            }
+        }
+        log
+    }
+
+    fn with_room<T>(&self, room: &str, f: impl FnOnce(&mut RoomLog) -> T) -> T {
+        let mut rooms = self.rooms.lock().unwrap();
+        let log = rooms
+            .entry(room.to_string())
+            .or_insert_with(|| self.replay(room));
+        f(log)
+    }
+
+    /// Append a message to the log and return it with its `id`
+    pub fn record(&self, room: &str, user: &str, text: &str) -> ChatMessage {
+        let sent_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.with_room(room, |log| {
+            log.last_id += 1;
+            let message = ChatMessage {
+                id: log.last_id,
+                user: user.to_string(),
+                text: text.to_string(),
+                sent_at,
+            };
+            // One short append per message; the in-memory ring is updated
+            // even if the disk is full, the message just won't survive a restart
+            if let Some(path) = self.file_for(room) {
+                let line = serde_json::to_string(&message).unwrap_or_default();
+                let appended = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| writeln!(file, "{}", line));
+                if let Err(e) = appended {
+                    eprintln!("⚠️  Failed to append to {}: {}", path.display(), e);
+                }
+            }
+            log.push(message.clone());
+            message
+        })
+    }
+
+    /// The last `n` messages, oldest first
+    pub fn recent(&self, room: &str, n: usize) -> Vec<ChatMessage> {
+        self.with_room(room, |log| {
+            let skip = log.recent.len().saturating_sub(n);
+            log.recent.iter().skip(skip).cloned().collect()
+        })
+    }
+
+    /// Up to `limit` messages older than `before` (all, if `None`), oldest first
+    pub fn page(&self, room: &str, before: Option<u64>, limit: usize) -> HistoryPage {
+        // Don't create a log for every room name someone asks about
+        let known = self.rooms.lock().unwrap().contains_key(room)
+            || self.file_for(room).is_some_and(|path| path.exists());
+        if !known {
+            return HistoryPage {
+                room: room.to_string(),
+                messages: Vec::new(),
+                next_before: None,
+            };
+        }
+        self.with_room(room, |log| {
+            let older: Vec<&ChatMessage> = log
+                .recent
+                .iter()
+                .filter(|m| before.is_none_or(|before| m.id < before))
+                .collect();
+            let skip = older.len().saturating_sub(limit);
+            let messages: Vec<ChatMessage> = older[skip..].iter().map(|m| (*m).clone()).collect();
+            HistoryPage {
+                room: room.to_string(),
+                // More to fetch only while there are older ones still in memory
+                next_before: (skip > 0).then(|| messages[0].id),
+                messages,
+            }
+        })
+    }
+}
+
+impl RoomLog {
+    fn push(&mut self, message: ChatMessage) {
+        if self.recent.len() == HISTORY_CAPACITY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(message);
+    }
+}
+
+// ============================================================================
+// HANDLER
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    before: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryPage {
+    room: String,
+    messages: Vec<ChatMessage>,
+    /// Pass as `before` to get the previous page; absent at the start
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_before: Option<u64>,
+}
+
+/// GET /rooms/{room}/messages?before=&limit=
+pub async fn room_messages(
+    Path(room): Path<String>,
+    Query(query): Query<HistoryQuery>,
+    State(history): State<Arc<ChatHistory>>,
+) -> Json<HistoryPage> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+    Json(history.page(&room, query.before, limit))
+}
@@ -0,0 +1,205 @@
+//! # Motion JPEG over `multipart/x-mixed-replace`
+//!
+//! Long before WebSockets and SSE, webcams streamed video to browsers with
+//! a single never-ending HTTP response. Its content type is
+//! `multipart/x-mixed-replace`: a multipart body whose parts are not
+//! attachments side by side, but versions of one thing - each new part
+//! *replaces* the last. With a JPEG in every part, that's Motion JPEG, and
+//! an `<img src="/camera.mjpg">` plays it with no JavaScript at all:
+//!
+//! ```text
+//! HTTP/1.1 200 OK
+//! Content-Type: multipart/x-mixed-replace; boundary=frame
+//!
+//! --frame
+//! Content-Type: image/jpeg
+//! Content-Length: 5123
+//!
+//! <JPEG bytes>
+//! --frame
+//! ...
+//! ```
+//!
+//! `GET /camera.mjpg?fps=10` plays a generated test pattern. Frames are
+//! paced by a `tokio::time::interval` that *skips* missed ticks: a client
+//! that reads slower than `fps` gets fewer frames, never a growing backlog
+//! of stale ones, which is what you want from a live feed. `frames=N` ends
+//! the stream after N frames (handy for `curl` and tests); without it the
+//! stream runs until the client goes away, which drops the body and stops
+//! the encoding.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Query,
+    http::header,
+    response::IntoResponse,
+};
+use futures::stream::{self, StreamExt};
+use jpeg_encoder::{ColorType, Encoder};
+use serde::Deserialize;
+use std::{convert::Infallible, time::Duration};
+use tokio::time::{interval, MissedTickBehavior};
+
+pub const BOUNDARY: &str = "frame";
+pub const WIDTH: u16 = 320;
+pub const HEIGHT: u16 = 240;
+
+const DEFAULT_FPS: u32 = 10;
+const MAX_FPS: u32 = 30;
+const QUALITY: u8 = 75;
+
+#[derive(Debug, Deserialize)]
+pub struct CameraQuery {
+    fps: Option<u32>,
+    frames: Option<u64>,
+}
+
+/// Frame `n` of the test pattern: a diagonal gradient that drifts, with a
+/// white bar sweeping left to right
+pub fn render_frame(n: u64) -> Vec<u8> {
+    let (width, height) = (WIDTH as usize, HEIGHT as usize);
+    let bar = (n as usize * 8) % width;
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            if x.abs_diff(bar) < 6 {
+                rgb.extend_from_slice(&[255, 255, 255]);
+            } else {
+                let shift = n as usize * 4;
+                rgb.extend_from_slice(&[
+                    ((x + shift) % 256) as u8,
+                    ((y + shift) % 256) as u8,
+                    ((x + y) / 2 % 256) as u8,
+                ]);
+            }
+        }
+    }
+    let mut jpeg = Vec::new();
+    Encoder::new(&mut jpeg, QUALITY)
+        .encode(&rgb, WIDTH, HEIGHT, ColorType::Rgb)
+        .expect("the frame buffer matches WIDTH x HEIGHT");
+    jpeg
+}
+
+/// One part of the multipart body: boundary, headers, the image
+fn part(jpeg: &[u8]) -> Bytes {
+    let mut part = format!(
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        jpeg.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(jpeg);
+    part.extend_from_slice(b"\r\n");
+    part.into()
+}
+
+/// GET /camera.mjpg?fps=&frames=
+pub async fn camera(Query(query): Query<CameraQuery>) -> impl IntoResponse {
+    let fps = query.fps.unwrap_or(DEFAULT_FPS).clamp(1, MAX_FPS);
+    let mut ticks = interval(Duration::from_secs(1) / fps);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let frames = stream::unfold((ticks, 0u64), |(mut ticks, n)| async move {
+        ticks.tick().await;
+        Some((Ok::<_, Infallible>(part(&render_frame(n))), (ticks, n + 1)))
+    })
+    .take(query.frames.map_or(usize::MAX, |n| n as usize))
+    // Only reached with `frames`: the close delimiter ends the multipart body
+    .chain(stream::once(async {
+        Ok(Bytes::from(format!("--{}--\r\n", BOUNDARY)))
+    }));
+
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={}", BOUNDARY),
+            ),
+            // Every frame is live; nothing along the way should keep one
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (
+                header::HeaderName::from_static("x-frame-rate"),
+                fps.to_string(),
+            ),
+        ],
+        Body::from_stream(frames),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Request, http::HeaderMap, routing::get, Router};
+    use tokio::time::Instant;
+    use tower::ServiceExt;
+
+    async fn get_camera(uri: &str) -> (HeaderMap, Bytes, Duration) {
+        let app = Router::new().route("/camera.mjpg", get(camera));
+        let started = Instant::now();
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (headers, body, started.elapsed())
+    }
+
+    /// The JPEGs in a multipart body, checked against their Content-Length
+    fn split_parts(mut body: &[u8]) -> Vec<Vec<u8>> {
+        let mut images = Vec::new();
+        let delimiter = format!("--{}\r\n", BOUNDARY);
+        let close = format!("--{}--\r\n", BOUNDARY);
+        while body != close.as_bytes() {
+            body = body.strip_prefix(delimiter.as_bytes()).expect("a boundary");
+            let end = body.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            let headers = std::str::from_utf8(&body[..end]).unwrap();
+            assert!(headers.contains("Content-Type: image/jpeg"), "{}", headers);
+            let length: usize = headers
+                .split("Content-Length: ")
+                .nth(1)
+                .unwrap()
+                .parse()
+                .unwrap();
+            let image = &body[end + 4..end + 4 + length];
+            images.push(image.to_vec());
+            body = body[end + 4 + length..]
+                .strip_prefix(b"\r\n")
+                .expect("CRLF after the image");
+        }
+        images
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_frames_arrive_as_replacing_jpeg_parts_at_the_requested_rate() {
+        let (headers, body, elapsed) = get_camera("/camera.mjpg?fps=5&frames=6").await;
+        assert_eq!(
+            headers[header::CONTENT_TYPE],
+            "multipart/x-mixed-replace; boundary=frame"
+        );
+        assert_eq!(headers["x-frame-rate"], "5");
+
+        let images = split_parts(&body);
+        assert_eq!(images.len(), 6);
+        for image in &images {
+            // JPEG start and end markers
+            assert_eq!(&image[..2], [0xff, 0xd8]);
+            assert_eq!(&image[image.len() - 2..], [0xff, 0xd9]);
+        }
+        assert_ne!(images[0], images[1], "the pattern moves");
+
+        // The first tick is immediate, then one every 200ms
+        assert_eq!(elapsed, Duration::from_millis(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_frame_rate_is_clamped() {
+        let (headers, _, _) = get_camera("/camera.mjpg?fps=1000&frames=1").await;
+        assert_eq!(headers["x-frame-rate"], MAX_FPS.to_string());
+        let (headers, _, _) = get_camera("/camera.mjpg?fps=0&frames=1").await;
+        assert_eq!(headers["x-frame-rate"], "1");
+    }
+}
@@ -0,0 +1,293 @@
+//! # Chat Rooms with Presence Tracking
+//!
+//! `WS /rooms/{room}/ws?user=alice` joins a chat room. Besides chat messages,
+//! every connection change is broadcast to the room as a presence event:
+//! - `join` when a connection opens
+//! - `leave` when it closes cleanly (or the socket errors)
+//! - `timeout` when nothing - not even a Pong - arrived for
+//!   `HEARTBEAT_TIMEOUT`; the server Pings every `HEARTBEAT_INTERVAL`, and
+//!   browsers answer Pings automatically
+//!
+//! A user with several tabs open has several connections; `connections` in
+//! each event is how many that user still has, so `0` means "went offline".
+//! `GET /rooms/{room}/presence` returns the current roster.
+//!
+//! Chat messages are also recorded in `ChatHistory` (see `history.rs`), and
+//! a new connection first gets a `history` frame with the latest of them.
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::broadcast,
+    time::{self, Instant},
+};
+use uuid::Uuid;
+
+use crate::history::{ChatHistory, ChatMessage, REPLAY_ON_JOIN};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Events buffered per room for slow receivers
+const ROOM_CAPACITY: usize = 64;
+
+// ============================================================================
+// EVENTS
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceKind {
+    Join,
+    Leave,
+    Timeout,
+}
+
+/// Everything sent to clients in a room
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    Presence {
+        event: PresenceKind,
+        user: String,
+        /// Connections this user still has in the room
+        connections: usize,
+    },
+    Message(ChatMessage),
+    /// Sent once, only to a connection that just joined
+    History {
+        messages: Vec<ChatMessage>,
+    },
+}
+
+// ============================================================================
+// PRESENCE REGISTRY
+// ============================================================================
+
+struct Connection {
+    user: String,
+    connected_at: SystemTime,
+}
+
+struct Room {
+    events: broadcast::Sender<RoomEvent>,
+    connections: HashMap<Uuid, Connection>,
+}
+
+impl Room {
+    fn connections_of(&self, user: &str) -> usize {
+        self.connections.values().filter(|c| c.user == user).count()
+    }
+}
+
+/// Who is connected to which room; rooms exist while someone is in them
+pub struct Presence {
+    rooms: Mutex<HashMap<String, Room>>,
+    history: Arc<ChatHistory>,
+}
+
+impl Presence {
+    pub fn new(history: Arc<ChatHistory>) -> Self {
+        Self {
+            rooms: Mutex::new(HashMap::new()),
+            history,
+        }
+    }
+
+    /// Register a connection and announce it; the returned receiver already
+    /// includes the caller's own `join`. Also returns the messages to replay.
+    ///
+    /// Messages are recorded and broadcast under the same lock (see `post`),
+    /// so each one is either in the replay or in the receiver - never both,
+    /// never neither.
+    fn join(
+        &self,
+        room_name: &str,
+        user: &str,
+    ) -> (Uuid, broadcast::Receiver<RoomEvent>, Vec<ChatMessage>) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let room = rooms.entry(room_name.to_string()).or_insert_with(|| Room {
+            events: broadcast::channel(ROOM_CAPACITY).0,
+            connections: HashMap::new(),
+        });
+
+        let id = Uuid::new_v4();
+        room.connections.insert(
+            id,
+            Connection {
+                user: user.to_string(),
+                connected_at: SystemTime::now(),
+            },
+        );
+        let receiver = room.events.subscribe();
+        let backlog = self.history.recent(room_name, REPLAY_ON_JOIN);
+        let _ = room.events.send(RoomEvent::Presence {
+            event: PresenceKind::Join,
+            user: user.to_string(),
+            connections: room.connections_of(user),
+        });
+        (id, receiver, backlog)
+    }
+
+    fn leave(&self, room_name: &str, id: Uuid, reason: PresenceKind) {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(room) = rooms.get_mut(room_name) else {
+            return;
+        };
+        let Some(connection) = room.connections.remove(&id) else {
+            return;
+        };
+        let _ = room.events.send(RoomEvent::Presence {
+            event: reason,
+            connections: room.connections_of(&connection.user),
+            user: connection.user,
+        });
+        if room.connections.is_empty() {
+            rooms.remove(room_name);
+        }
+    }
+
+    /// Record a chat message in the history, then broadcast it
+    fn post(&self, room_name: &str, user: &str, text: &str) {
+        let rooms = self.rooms.lock().unwrap();
+        let message = self.history.record(room_name, user, text);
+        if let Some(room) = rooms.get(room_name) {
+            let _ = room.events.send(RoomEvent::Message(message));
+        }
+    }
+
+    pub fn roster(&self, room_name: &str) -> Roster {
+        let rooms = self.rooms.lock().unwrap();
+        let mut users: BTreeMap<&str, RosterEntry> = BTreeMap::new();
+        if let Some(room) = rooms.get(room_name) {
+            for connection in room.connections.values() {
+                let since = connection
+                    .connected_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let entry = users.entry(&connection.user).or_insert(RosterEntry {
+                    user: connection.user.clone(),
+                    connections: 0,
+                    online_since: since,
+                });
+                entry.connections += 1;
+                entry.online_since = entry.online_since.min(since);
+            }
+        }
+        let users: Vec<RosterEntry> = users.into_values().collect();
+        Roster {
+            room: room_name.to_string(),
+            total_connections: users.iter().map(|u| u.connections).sum(),
+            users,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RosterEntry {
+    user: String,
+    connections: usize,
+    /// Unix seconds of the user's oldest open connection
+    online_since: u64,
+}
+
+#[derive(Serialize)]
+pub struct Roster {
+    room: String,
+    users: Vec<RosterEntry>,
+    total_connections: usize,
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+#[derive(Deserialize)]
+pub struct JoinParams {
+    user: String,
+}
+
+/// WS /rooms/{room}/ws?user=alice
+pub async fn room_ws(
+    ws: WebSocketUpgrade,
+    Path(room): Path<String>,
+    Query(params): Query<JoinParams>,
+    State(presence): State<Arc<Presence>>,
+) -> Response {
+    let user = params.user.trim().to_string();
+    if user.is_empty() || user.len() > 32 {
+        return (StatusCode::BAD_REQUEST, "user must be 1-32 characters").into_response();
+    }
+    ws.on_upgrade(move |socket| room_socket(socket, presence, room, user))
+}
+
+/// GET /rooms/{room}/presence
+pub async fn room_presence(
+    Path(room): Path<String>,
+    State(presence): State<Arc<Presence>>,
+) -> Json<Roster> {
+    Json(presence.roster(&room))
+}
+
+async fn room_socket(mut socket: WebSocket, presence: Arc<Presence>, room: String, user: String) {
+    let (id, mut events, backlog) = presence.join(&room, &user);
+    let history = RoomEvent::History { messages: backlog };
+    let json = serde_json::to_string(&history).unwrap_or_default();
+    if socket.send(Message::Text(json.into())).await.is_err() {
+        presence.leave(&room, id, PresenceKind::Leave);
+        return;
+    }
+    let mut ping = time::interval(HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+
+    let reason = loop {
+        tokio::select! {
+            received = time::timeout_at(last_seen + HEARTBEAT_TIMEOUT, socket.recv()) => {
+                let message = match received {
+                    Err(_) => break PresenceKind::Timeout,
+                    Ok(None) | Ok(Some(Err(_))) | Ok(Some(Ok(Message::Close(_)))) => {
+                        break PresenceKind::Leave
+                    }
+                    Ok(Some(Ok(message))) => message,
+                };
+                // Any frame - text, Ping or Pong - proves the client is alive
+                last_seen = Instant::now();
+                if let Message::Text(text) = message {
+                    presence.post(&room, &user, &text);
+                }
+            }
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    // Too slow to keep up: skip what was missed
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break PresenceKind::Leave,
+                };
+                let json = serde_json::to_string(&event).unwrap_or_default();
+                if socket.send(Message::Text(json.into())).await.is_err() {
+                    break PresenceKind::Leave;
+                }
+            }
+            _ = ping.tick() => {
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    break PresenceKind::Leave;
+                }
+            }
+        }
+    };
+
+    presence.leave(&room, id, reason);
+}
@@ -0,0 +1,258 @@
+//! # Bandwidth Throttling
+//!
+//! A body is a stream of chunks, so capping a transfer's rate is a stream
+//! wrapper: `Throttled` hands each chunk on only once the transfer's
+//! average would stay under `bytes_per_sec`. Large chunks (a 64 KB file
+//! read, a whole buffered upload) are cut into slices of about 1/10 s each,
+//! so the rate is smooth rather than bursty.
+//!
+//! The same wrapper works in both directions:
+//! - downloads: the response body is throttled, the client simply receives
+//!   slower
+//! - uploads: the request body is throttled, so the handler reads slower
+//!   and TCP flow control makes the client send slower
+//!
+//! `throttle_bandwidth` does both, with a rate from its `BandwidthLimit` -
+//! one per group of routes - picked by the caller's tier. Module 10 has no
+//! logins, so the tier comes from an `X-User-Tier: pro` header; a real app
+//! would read it from the authenticated user.
+//!
+//! A client that stops reading for a while doesn't earn a burst: pacing
+//! restarts from the moment it comes back.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use futures::Stream;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::time::{Instant, Sleep};
+
+pub const USER_TIER_HEADER: &str = "x-user-tier";
+/// The rate a response was served at, in bytes per second
+pub const BANDWIDTH_HEADER: &str = "x-bandwidth-limit";
+
+const KIB: u64 = 1024;
+
+/// Rates for one group of routes, in bytes per second
+#[derive(Debug, Clone, Copy)]
+pub struct BandwidthLimit {
+    pub free: u64,
+    pub pro: u64,
+}
+
+/// `/download/*`
+pub const DOWNLOAD_LIMIT: BandwidthLimit = BandwidthLimit {
+    free: 256 * KIB,
+    pro: 1024 * KIB,
+};
+
+/// `/upload` and `/upload/zip`
+pub const UPLOAD_LIMIT: BandwidthLimit = BandwidthLimit {
+    free: 128 * KIB,
+    pro: 512 * KIB,
+};
+
+impl BandwidthLimit {
+    fn for_request(&self, request: &Request) -> u64 {
+        let tier = request
+            .headers()
+            .get(USER_TIER_HEADER)
+            .and_then(|v| v.to_str().ok());
+        match tier {
+            Some(tier) if tier.eq_ignore_ascii_case("pro") => self.pro,
+            _ => self.free,
+        }
+    }
+}
+
+// ============================================================================
+// THE STREAM WRAPPER
+// ============================================================================
+
+pub struct Throttled<S> {
+    inner: S,
+    bytes_per_sec: u64,
+    /// When the transfer started, moved forward after a stall
+    started: Option<Instant>,
+    sent: u64,
+    /// What's left of the current inner chunk
+    pending: Bytes,
+    /// A slice waiting for its turn
+    ready: Option<(Bytes, Pin<Box<Sleep>>)>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, bytes_per_sec: u64) -> Self {
+        Self {
+            inner,
+            bytes_per_sec: bytes_per_sec.max(1),
+            started: None,
+            sent: 0,
+            pending: Bytes::new(),
+            ready: None,
+        }
+    }
+
+    /// About 1/10 s worth, between 1 and 64 KB
+    fn slice_len(&self) -> usize {
+        (self.bytes_per_sec / 10).clamp(KIB, 64 * KIB) as usize
+    }
+
+    /// Time for the first `bytes` of the transfer at the configured rate
+    fn time_for(&self, bytes: u64) -> Duration {
+        Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64)
+    }
+}
+
+impl<S, E> Stream for Throttled<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some((_, sleep)) = &mut this.ready {
+                ready!(sleep.as_mut().poll(cx));
+                let (slice, _) = this.ready.take().unwrap();
+                return Poll::Ready(Some(Ok(slice)));
+            }
+
+            if this.pending.is_empty() {
+                match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                    Some(Ok(chunk)) => this.pending = chunk,
+                    other => return Poll::Ready(other),
+                }
+                continue;
+            }
+
+            let slice = this
+                .pending
+                .split_to(this.pending.len().min(this.slice_len()));
+            let now = Instant::now();
+            let started = *this.started.get_or_insert(now);
+            this.sent += slice.len() as u64;
+            // The slice may go once the whole transfer so far fits the rate
+            let mut due = started + this.time_for(this.sent);
+            if due < now {
+                this.started = Some(now - this.time_for(this.sent));
+                due = now;
+            }
+            this.ready = Some((slice, Box::pin(tokio::time::sleep_until(due))));
+        }
+    }
+}
+
+/// `body`, delivered at no more than `bytes_per_sec`
+pub fn throttle_body(body: Body, bytes_per_sec: u64) -> Body {
+    Body::from_stream(Throttled::new(body.into_data_stream(), bytes_per_sec))
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// The layer: `middleware::from_fn_with_state(DOWNLOAD_LIMIT, throttle_bandwidth)`
+pub async fn throttle_bandwidth(
+    State(limit): State<BandwidthLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let rate = limit.for_request(&request);
+    let request = request.map(|body| throttle_body(body, rate));
+    let mut response = next
+        .run(request)
+        .await
+        .map(|body| throttle_body(body, rate));
+    response
+        .headers_mut()
+        .insert(BANDWIDTH_HEADER, HeaderValue::from(rate));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        middleware,
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    const MIB: usize = 1024 * 1024;
+    const LIMIT: BandwidthLimit = BandwidthLimit {
+        free: 256 * KIB,
+        pro: 1024 * KIB,
+    };
+
+    fn app() -> Router {
+        Router::new()
+            .route("/download", get(|| async { vec![0u8; MIB] }))
+            .route(
+                "/upload",
+                post(|body: Bytes| async move { body.len().to_string() }),
+            )
+            .layer(middleware::from_fn_with_state(LIMIT, throttle_bandwidth))
+    }
+
+    /// Send `request` and read the whole response; returns it and how long that took
+    async fn timed(request: Request) -> (Bytes, Duration) {
+        let started = Instant::now();
+        let response = app().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (body, started.elapsed())
+    }
+
+    /// Within 5%. The tests run on paused time, so the sleeps cost nothing
+    /// and the clock only moves as far as they ask
+    fn assert_about(elapsed: Duration, expected: Duration) {
+        let tolerance = expected / 20;
+        assert!(
+            elapsed >= expected - tolerance && elapsed <= expected + tolerance,
+            "took {:?}, expected about {:?}",
+            elapsed,
+            expected
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_mib_download_at_256_kib_per_second_takes_four_seconds() {
+        let (body, elapsed) = timed(Request::get("/download").body(Body::empty()).unwrap()).await;
+        assert_eq!(body.len(), MIB);
+        assert_about(elapsed, Duration::from_secs(4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_mib_upload_at_256_kib_per_second_takes_four_seconds() {
+        let request = Request::post("/upload")
+            .body(Body::from(vec![0u8; MIB]))
+            .unwrap();
+        let (body, elapsed) = timed(request).await;
+        assert_eq!(body, MIB.to_string());
+        assert_about(elapsed, Duration::from_secs(4));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_the_pro_tier_gets_its_own_rate() {
+        let request = Request::get("/download")
+            .header(USER_TIER_HEADER, "pro")
+            .body(Body::empty())
+            .unwrap();
+        let (body, elapsed) = timed(request).await;
+        assert_eq!(body.len(), MIB);
+        assert_about(elapsed, Duration::from_secs(1));
+    }
+}
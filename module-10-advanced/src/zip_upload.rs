@@ -0,0 +1,345 @@
+//! # Zip Upload with Server-Side Extraction
+//!
+//! Accepts a `.zip` file, extracts it into `static/uploads/{id}/` and returns
+//! a manifest of the extracted files, which are then served by the existing
+//! `/static` ServeDir.
+//!
+//! Archives are attacker-controlled input, so extraction is defensive:
+//! - Zip-slip: entry names like `../../etc/passwd` or `/abs/path` are rejected
+//! - Zip bombs: per-entry, total size and entry-count limits are enforced on
+//!   the bytes actually written, not on the sizes the archive claims
+//! - Any violation aborts the upload and removes the partial directory
+//!
+//! The upload itself is streamed to a temp file chunk by chunk, so a 10 MB
+//! archive never sits in memory; the zip reader then seeks around the file.
+//! A filesystem error is logged with its path and answered with a generic
+//! 500, so server paths never reach the client.
+
+use axum::{
+    extract::{multipart::MultipartError, Multipart},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+/// Extraction root, served under `/static/uploads`
+const UPLOADS_DIR: &str = "static/uploads";
+/// Largest single extracted file
+const MAX_ENTRY_BYTES: u64 = 5 * 1024 * 1024;
+/// Largest total extracted size (the compressed upload is capped by the body limit)
+const MAX_TOTAL_BYTES: u64 = 20 * 1024 * 1024;
+/// Most entries (files + directories) per archive
+const MAX_ENTRIES: usize = 500;
+
+// ============================================================================
+// ERRORS
+// ============================================================================
+
+#[derive(Debug)]
+pub enum ZipUploadError {
+    MissingFile,
+    Multipart(String),
+    InvalidArchive(String),
+    UnsafePath(String),
+    TooManyEntries,
+    EntryTooLarge(String),
+    ArchiveTooLarge,
+    /// For the log only: the client gets a generic message
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+}
+
+/// `map_err(io_at(&path))`: an I/O error, with the path it happened on
+fn io_at(path: &Path) -> impl FnOnce(io::Error) -> ZipUploadError + '_ {
+    move |error| ZipUploadError::Io {
+        path: path.to_path_buf(),
+        error,
+    }
+}
+
+impl IntoResponse for ZipUploadError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ZipUploadError::MissingFile => (
+                StatusCode::BAD_REQUEST,
+                "Expected a multipart field named 'file'".to_string(),
+            ),
+            ZipUploadError::Multipart(e) => (StatusCode::BAD_REQUEST, e),
+            ZipUploadError::InvalidArchive(e) => {
+                (StatusCode::BAD_REQUEST, format!("Invalid zip archive: {}", e))
+            }
+            ZipUploadError::UnsafePath(name) => (
+                StatusCode::BAD_REQUEST,
+                format!("Rejected unsafe entry path: {}", name),
+            ),
+            ZipUploadError::TooManyEntries => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Archive has more than {} entries", MAX_ENTRIES),
+            ),
+            ZipUploadError::EntryTooLarge(name) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Entry '{}' exceeds {} bytes", name, MAX_ENTRY_BYTES),
+            ),
+            ZipUploadError::ArchiveTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Archive expands to more than {} bytes", MAX_TOTAL_BYTES),
+            ),
+            ZipUploadError::Io { path, error } => {
+                eprintln!("⚠️  Zip upload failed at {}: {}", path.display(), error);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not store the upload".to_string(),
+                )
+            }
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+// ============================================================================
+// MANIFEST
+// ============================================================================
+
+#[derive(Serialize)]
+pub struct ExtractedFile {
+    path: String,
+    size: u64,
+    url: String,
+}
+
+#[derive(Serialize)]
+pub struct UploadManifest {
+    id: String,
+    base_url: String,
+    total_bytes: u64,
+    files: Vec<ExtractedFile>,
+}
+
+// ============================================================================
+// HANDLER
+// ============================================================================
+
+/// POST /upload/zip - multipart form with a `file` field containing a zip
+pub async fn upload_zip(multipart: Multipart) -> Result<Json<UploadManifest>, ZipUploadError> {
+    let id = Uuid::new_v4().to_string();
+    let upload = std::env::temp_dir().join(format!("module-10-upload-{}.zip", id));
+    let dest = Path::new(UPLOADS_DIR).join(&id);
+
+    let manifest = receive_and_extract(multipart, &upload, &dest, &id).await;
+    let _ = tokio::fs::remove_file(&upload).await;
+    if manifest.is_err() {
+        // Never leave half-extracted (possibly malicious) content behind
+        let _ = tokio::fs::remove_dir_all(&dest).await;
+    }
+    manifest.map(Json)
+}
+
+async fn receive_and_extract(
+    mut multipart: Multipart,
+    upload: &Path,
+    dest: &Path,
+    id: &str,
+) -> Result<UploadManifest, ZipUploadError> {
+    let multipart_error = |e: MultipartError| ZipUploadError::Multipart(e.to_string());
+    let mut field = loop {
+        match multipart.next_field().await.map_err(multipart_error)? {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => continue,
+            None => return Err(ZipUploadError::MissingFile),
+        }
+    };
+
+    let mut file = tokio::fs::File::create(upload)
+        .await
+        .map_err(io_at(upload))?;
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        file.write_all(&chunk).await.map_err(io_at(upload))?;
+    }
+    file.flush().await.map_err(io_at(upload))?;
+    drop(file);
+
+    // zip and std::fs are blocking - keep them off the async runtime
+    tokio::task::spawn_blocking({
+        let (upload, dest, id) = (upload.to_path_buf(), dest.to_path_buf(), id.to_string());
+        move || extract_archive(&upload, &dest, &id)
+    })
+    .await
+    .map_err(|e| ZipUploadError::Io {
+        path: dest.to_path_buf(),
+        error: io::Error::other(e),
+    })?
+}
+
+fn extract_archive(upload: &Path, dest: &Path, id: &str) -> Result<UploadManifest, ZipUploadError> {
+    let file = fs::File::open(upload).map_err(io_at(upload))?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|e| ZipUploadError::InvalidArchive(e.to_string()))?;
+
+    if archive.len() > MAX_ENTRIES {
+        return Err(ZipUploadError::TooManyEntries);
+    }
+
+    fs::create_dir_all(dest).map_err(io_at(dest))?;
+    let base_url = format!("/static/uploads/{}/", id);
+    let mut files = Vec::new();
+    let mut total_bytes = 0;
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| ZipUploadError::InvalidArchive(e.to_string()))?;
+
+        // `enclosed_name` returns None for absolute paths and `..` escapes
+        let relative: PathBuf = entry
+            .enclosed_name()
+            .filter(|p| p.components().next().is_some())
+            .ok_or_else(|| ZipUploadError::UnsafePath(entry.name().to_string()))?;
+        let target = dest.join(&relative);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&target).map_err(io_at(&target))?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(io_at(parent))?;
+        }
+
+        // Count what we actually write: the header's declared size can lie
+        let mut out = fs::File::create(&target).map_err(io_at(&target))?;
+        let written = io::copy(&mut entry.by_ref().take(MAX_ENTRY_BYTES + 1), &mut out)
+            .map_err(io_at(&target))?;
+        if written > MAX_ENTRY_BYTES {
+            return Err(ZipUploadError::EntryTooLarge(relative.display().to_string()));
+        }
+        total_bytes += written;
+        if total_bytes > MAX_TOTAL_BYTES {
+            return Err(ZipUploadError::ArchiveTooLarge);
+        }
+
+        let path = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push(ExtractedFile {
+            url: format!("{}{}", base_url, path),
+            path,
+            size: written,
+        });
+    }
+
+    Ok(UploadManifest {
+        id: id.to_string(),
+        base_url,
+        total_bytes,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, extract::Request, routing::post, Router};
+    use std::io::Write;
+    use tower::ServiceExt;
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    const BOUNDARY: &str = "zip-upload-test";
+
+    fn zip_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    async fn post_zip(archive: &[u8]) -> (StatusCode, serde_json::Value) {
+        let mut body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.zip\"\r\n\
+             Content-Type: application/zip\r\n\r\n",
+            b = BOUNDARY
+        )
+        .into_bytes();
+        body.extend_from_slice(archive);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        let app = Router::new().route("/upload/zip", post(upload_zip));
+        let response = app
+            .oneshot(
+                Request::post("/upload/zip")
+                    .header(
+                        "content-type",
+                        format!("multipart/form-data; boundary={}", BOUNDARY),
+                    )
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn temp_upload(id: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("module-10-upload-{}.zip", id))
+    }
+
+    #[tokio::test]
+    async fn extracts_a_streamed_upload_and_removes_the_temp_file() {
+        let archive = zip_of(&[("hello.txt", b"hello"), ("css/site.css", b"body {}")]);
+        let (status, manifest) = post_zip(&archive).await;
+        assert_eq!(status, StatusCode::OK, "{}", manifest);
+
+        let id = manifest["id"].as_str().unwrap().to_string();
+        let dest = Path::new(UPLOADS_DIR).join(&id);
+        let extracted = fs::read_to_string(dest.join("css/site.css"));
+        let _ = fs::remove_dir_all(&dest);
+
+        assert_eq!(extracted.unwrap(), "body {}");
+        assert_eq!(manifest["total_bytes"], 12);
+        assert_eq!(manifest["files"].as_array().unwrap().len(), 2);
+        assert!(!temp_upload(&id).exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_zip_slip_entries() {
+        let archive = zip_of(&[("../escape.txt", b"nope")]);
+        let (status, body) = post_zip(&archive).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("escape.txt"));
+    }
+
+    #[tokio::test]
+    async fn io_errors_do_not_reveal_server_paths() {
+        let error = ZipUploadError::Io {
+            path: PathBuf::from("/srv/app/static/uploads/secret"),
+            error: io::Error::other("disk full"),
+        };
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains("/srv/app"), "{}", body);
+        assert!(!body.contains("disk full"), "{}", body);
+    }
+}
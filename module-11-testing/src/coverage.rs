@@ -0,0 +1,98 @@
+//! # Handler Branch Coverage
+//!
+//! Line coverage tools tell you which lines ran; this asks a sharper
+//! question: did the test suite exercise every *outcome* of every handler?
+//!
+//! - Every interesting branch is declared once in `BRANCHES`, next to the
+//!   test that reaches it
+//! - Handlers mark the branch they took with `covered!("handler::branch")`
+//! - `GET /_coverage` shows the hit counts, and a test runs each branch's
+//!   test and fails if the branch wasn't hit
+//!
+//! Only compiled for tests or with `--features coverage`; in a normal build
+//! `covered!` expands to nothing.
+
+use axum::Json;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, OnceLock},
+};
+
+/// A handler outcome, and the test that reaches it
+pub struct Branch {
+    pub name: &'static str,
+    #[cfg(test)]
+    pub test: fn(),
+}
+
+/// `"handler::branch" => test_fn`; the test is only referenced when
+/// building the tests
+macro_rules! branches {
+    ($($name:literal => $test:ident),* $(,)?) => {
+        &[$(Branch {
+            name: $name,
+            #[cfg(test)]
+            test: crate::tests::$test,
+        }),*]
+    };
+}
+
+/// Every branch the test suite is expected to reach, and which test does
+pub const BRANCHES: &[Branch] = branches![
+    "health::ok" => test_health_check,
+    "list_users::ok" => test_list_users,
+    "get_user::found" => test_get_user_found,
+    "get_user::not_found" => test_get_user_not_found,
+    "create_user::created" => test_create_user,
+    "create_invite::created" => test_create_invite,
+];
+
+fn hits() -> &'static Mutex<HashMap<&'static str, u64>> {
+    static HITS: OnceLock<Mutex<HashMap<&'static str, u64>>> = OnceLock::new();
+    HITS.get_or_init(Default::default)
+}
+
+/// Called by `covered!`
+pub fn hit(branch: &'static str) {
+    *hits().lock().unwrap().entry(branch).or_default() += 1;
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoverageReport {
+    pub branches: BTreeMap<&'static str, u64>,
+    /// Declared but never hit
+    pub missed: Vec<&'static str>,
+    /// Hit but missing from `BRANCHES` - usually a typo in `covered!`
+    pub undeclared: Vec<&'static str>,
+}
+
+pub fn report() -> CoverageReport {
+    let hits = hits().lock().unwrap();
+    let branches: BTreeMap<_, _> = BRANCHES
+        .iter()
+        .map(|b| (b.name, hits.get(b.name).copied().unwrap_or(0)))
+        .collect();
+    let missed = branches
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(b, _)| *b)
+        .collect();
+    let mut undeclared: Vec<_> = hits
+        .keys()
+        .filter(|b| !BRANCHES.iter().any(|declared| declared.name == **b))
+        .copied()
+        .collect();
+    undeclared.sort_unstable();
+
+    CoverageReport {
+        branches,
+        missed,
+        undeclared,
+    }
+}
+
+/// GET /_coverage
+pub async fn coverage_report() -> Json<CoverageReport> {
+    Json(report())
+}
@@ -0,0 +1,117 @@
+//! # Deterministic Ids and Randomness for Tests
+//!
+//! A handler that calls `Uuid::new_v4()` or reads the OS's random source
+//! answers differently every run. Its tests can't compare the response to
+//! an expected one; they redact the random fields first, or only check
+//! that they "look like" a UUID - and miss a bug in exactly those fields.
+//!
+//! Randomness is a dependency like any other, so it comes in through state:
+//! - `IdProvider` hands out ids; `RandomIds` in production,
+//!   `SequentialIds` (`...0001`, `...0002`, ...) in tests
+//! - `Rng` fills bytes (salts, tokens, codes); `OsRandom` in production,
+//!   `SequentialRng` (`00 01 02 ...`) in tests
+//!
+//! Handlers use `state.ids.next_id()` and `state.rng.fill_bytes(..)` and
+//! never know which they got. A test builds its app with the sequential
+//! ones and asserts the whole body, ids and tokens included. Each app gets
+//! its own counters, so parallel tests don't disturb each other.
+
+use rand::RngCore;
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+use uuid::Uuid;
+
+pub trait IdProvider: Send + Sync {
+    fn next_id(&self) -> Uuid;
+}
+
+pub trait Rng: Send + Sync {
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+/// `len` random bytes, hex-encoded
+pub fn token(rng: &dyn Rng, len: usize) -> String {
+    let mut bytes = vec![0; len];
+    rng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// ============================================================================
+// PRODUCTION
+// ============================================================================
+
+pub struct RandomIds;
+
+impl IdProvider for RandomIds {
+    fn next_id(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// The operating system's CSPRNG - what secrets need
+pub struct OsRandom;
+
+impl Rng for OsRandom {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        rand::rngs::OsRng.fill_bytes(dest);
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+/// `00000000-0000-0000-0000-000000000001`, then `...0002`, ...
+#[cfg(test)]
+#[derive(Default)]
+pub struct SequentialIds(AtomicU64);
+
+#[cfg(test)]
+impl IdProvider for SequentialIds {
+    fn next_id(&self) -> Uuid {
+        let n = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+        Uuid::from_u128(n as u128)
+    }
+}
+
+/// Bytes `00 01 02 ...` (wrapping at `ff`), continuing where the last call
+/// stopped
+#[cfg(test)]
+#[derive(Default)]
+pub struct SequentialRng(AtomicU64);
+
+#[cfg(test)]
+impl Rng for SequentialRng {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        let start = self.0.fetch_add(dest.len() as u64, Ordering::Relaxed);
+        for (offset, byte) in dest.iter_mut().enumerate() {
+            *byte = (start + offset as u64) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_sources_repeat_exactly() {
+        let (ids, rng) = (SequentialIds::default(), SequentialRng::default());
+        assert_eq!(
+            ids.next_id().to_string(),
+            "00000000-0000-0000-0000-000000000001"
+        );
+        assert_eq!(
+            ids.next_id().to_string(),
+            "00000000-0000-0000-0000-000000000002"
+        );
+        assert_eq!(token(&rng, 4), "00010203");
+        assert_eq!(token(&rng, 2), "0405");
+    }
+
+    #[test]
+    fn test_production_sources_are_random() {
+        assert_ne!(RandomIds.next_id(), RandomIds.next_id());
+        assert_ne!(token(&OsRandom, 16), token(&OsRandom, 16));
+    }
+}
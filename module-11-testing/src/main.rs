@@ -4,23 +4,41 @@
 //! - Unit testing handlers
 //! - Integration testing with TestClient
 //! - Testing with mock state
+//! - Handler branch coverage with `covered!` (see `coverage.rs`)
+//! - Parallel-safe tests: one app per test, built from its own state
+//! - Injected ids and randomness for exact assertions (see `ids.rs`)
+
+#[cfg(any(test, feature = "coverage"))]
+mod coverage;
+mod ids;
 
 use axum::{
-    extract::{Path, State},
+    extract::{FromRef, Path, State},
     http::StatusCode,
-    routing::get,
-    Json, Router,
+    Json,
 };
+use course_routes::Routes;
+use ids::{IdProvider, Rng};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     sync::{Arc, RwLock},
 };
+use uuid::Uuid;
 
 // ============================================================================
 // APPLICATION CODE
 // ============================================================================
 
+/// Record that a handler branch ran; compiles to nothing outside of
+/// tests and `--features coverage`
+macro_rules! covered {
+    ($branch:literal) => {
+        #[cfg(any(test, feature = "coverage"))]
+        crate::coverage::hit($branch);
+    };
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 struct User {
     id: u64,
@@ -34,8 +52,46 @@ struct CreateUser {
 
 type UserStore = Arc<RwLock<HashMap<u64, User>>>;
 
+#[derive(Clone)]
+struct AppState {
+    users: UserStore,
+    ids: Arc<dyn IdProvider>,
+    rng: Arc<dyn Rng>,
+}
+
+impl AppState {
+    /// Real ids and real randomness
+    fn new(users: UserStore) -> Self {
+        Self {
+            users,
+            ids: Arc::new(ids::RandomIds),
+            rng: Arc::new(ids::OsRandom),
+        }
+    }
+}
+
+impl FromRef<AppState> for UserStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.users.clone()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Invite {
+    id: Uuid,
+    email: String,
+    /// What the invitee types in; 8 random bytes, hex-encoded
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct CreateInvite {
+    email: String,
+}
+
 async fn list_users(State(store): State<UserStore>) -> Json<Vec<User>> {
     let users = store.read().unwrap();
+    covered!("list_users::ok");
     Json(users.values().cloned().collect())
 }
 
@@ -44,11 +100,16 @@ async fn get_user(
     Path(id): Path<u64>,
 ) -> Result<Json<User>, StatusCode> {
     let users = store.read().unwrap();
-    users
-        .get(&id)
-        .cloned()
-        .map(Json)
-        .ok_or(StatusCode::NOT_FOUND)
+    match users.get(&id) {
+        Some(user) => {
+            covered!("get_user::found");
+            Ok(Json(user.clone()))
+        }
+        None => {
+            covered!("get_user::not_found");
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
 }
 
 async fn create_user(
@@ -62,19 +123,82 @@ async fn create_user(
         name: input.name,
     };
     users.insert(id, user.clone());
+    covered!("create_user::created");
     (StatusCode::CREATED, Json(user))
 }
 
+async fn create_invite(
+    State(state): State<AppState>,
+    Json(input): Json<CreateInvite>,
+) -> (StatusCode, Json<Invite>) {
+    let invite = Invite {
+        id: state.ids.next_id(),
+        email: input.email,
+        code: ids::token(state.rng.as_ref(), 8),
+    };
+    covered!("create_invite::created");
+    (StatusCode::CREATED, Json(invite))
+}
+
 async fn health() -> &'static str {
+    covered!("health::ok");
     "OK"
 }
 
-fn create_app(store: UserStore) -> Router {
-    Router::new()
-        .route("/health", get(health))
-        .route("/users", get(list_users).post(create_user))
-        .route("/users/{id}", get(get_user))
-        .with_state(store)
+#[cfg(test)]
+fn create_app(store: UserStore) -> axum::Router {
+    build_app(AppState::new(store))
+}
+
+/// The app over any state; tests hand it sequential ids and bytes
+#[cfg(test)]
+fn build_app(state: AppState) -> axum::Router {
+    routes(state).into_router()
+}
+
+/// The routes, with the descriptions the startup banner lists
+fn routes(state: AppState) -> Routes {
+    let routes = Routes::new()
+        .get("/health", health, "Health check")
+        .get("/users", list_users, "List users")
+        .post("/users", create_user, "Create user")
+        .get("/users/{id}", get_user, "Get user")
+        .post(
+            "/invites",
+            create_invite,
+            "Invite someone (random id and code)",
+        );
+
+    #[cfg(any(test, feature = "coverage"))]
+    let routes = routes.get(
+        "/_coverage",
+        coverage::coverage_report,
+        "Handler branch hit counts",
+    );
+
+    routes.with_state(state)
+}
+
+// ============================================================================
+// MAIN
+// ============================================================================
+
+#[tokio::main]
+async fn main() {
+    let store = Arc::new(RwLock::new(HashMap::new()));
+    let routes = routes(AppState::new(store));
+    let table = routes.to_string();
+    let app = routes.into_router();
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+
+    println!("🚀 Module 11: Testing");
+    println!("   Server: http://localhost:3000\n");
+    println!("📝 Endpoints:");
+    println!("{}", table);
+    println!("🧪 Run tests: cargo test");
+
+    axum::serve(listener, app).await.unwrap();
 }
 
 // ============================================================================
@@ -84,7 +208,8 @@ fn create_app(store: UserStore) -> Router {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use axum::{body::Body, http::Request};
+    use axum::{body::Body, http::Request, Router};
+    use course_routes::conformance::{self, Probes};
     use http_body_util::BodyExt;
     use tower::ServiceExt; // for `oneshot`
 
@@ -92,8 +217,17 @@ mod tests {
         Arc::new(RwLock::new(HashMap::new()))
     }
 
+    /// Same app, but every id and random byte is known in advance
+    fn deterministic_app() -> Router {
+        build_app(AppState {
+            users: test_store(),
+            ids: Arc::new(ids::SequentialIds::default()),
+            rng: Arc::new(ids::SequentialRng::default()),
+        })
+    }
+
     #[tokio::test]
-    async fn test_health_check() {
+    pub(crate) async fn test_health_check() {
         let app = create_app(test_store());
 
         let response = app
@@ -113,7 +247,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_create_user() {
+    pub(crate) async fn test_create_user() {
         let app = create_app(test_store());
 
         let response = app
@@ -136,7 +270,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_get_user_not_found() {
+    pub(crate) async fn test_get_user_not_found() {
         let app = create_app(test_store());
 
         let response = app
@@ -153,7 +287,37 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_list_users() {
+    pub(crate) async fn test_get_user_found() {
+        let store = test_store();
+        store.write().unwrap().insert(
+            1,
+            User {
+                id: 1,
+                name: "Carol".to_string(),
+            },
+        );
+
+        let app = create_app(store);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/users/1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let user: User = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user.name, "Carol");
+    }
+
+    #[tokio::test]
+    pub(crate) async fn test_list_users() {
         let store = test_store();
         store.write().unwrap().insert(
             1,
@@ -181,27 +345,129 @@ mod tests {
         let users: Vec<User> = serde_json::from_slice(&body).unwrap();
         assert_eq!(users.len(), 1);
     }
-}
 
-// ============================================================================
-// MAIN
-// ============================================================================
+    /// No redacting, no "looks like a UUID": the whole body is asserted,
+    /// and it's the same on every run
+    #[tokio::test]
+    pub(crate) async fn test_create_invite() {
+        let app = deterministic_app();
+
+        let mut bodies = Vec::new();
+        for email in ["ann@example.com", "bob@example.com"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/invites")
+                        .header("content-type", "application/json")
+                        .body(Body::from(format!(r#"{{"email":"{}"}}"#, email)))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            bodies.push(String::from_utf8(body.to_vec()).unwrap());
+        }
+
+        assert_eq!(
+            bodies,
+            [
+                r#"{"id":"00000000-0000-0000-0000-000000000001","email":"ann@example.com","code":"0001020304050607"}"#,
+                r#"{"id":"00000000-0000-0000-0000-000000000002","email":"bob@example.com","code":"08090a0b0c0d0e0f"}"#,
+            ]
+        );
+    }
 
-#[tokio::main]
-async fn main() {
-    let store = Arc::new(RwLock::new(HashMap::new()));
-    let app = create_app(store);
+    /// Many apps at once, each with its own store: ids restart at 1 in
+    /// every app, which could not happen if any state were shared. Only
+    /// holds if each test builds its own app: `create_app(test_store())`,
+    /// never a `static` store.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_parallel_apps_are_isolated() {
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                tokio::spawn(async {
+                    let app = create_app(test_store());
+                    let response = app
+                        .oneshot(
+                            Request::builder()
+                                .method("POST")
+                                .uri("/users")
+                                .header("content-type", "application/json")
+                                .body(Body::from(r#"{"name":"Dana"}"#))
+                                .unwrap(),
+                        )
+                        .await
+                        .unwrap();
+                    let body = response.into_body().collect().await.unwrap().to_bytes();
+                    serde_json::from_slice::<User>(&body).unwrap()
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert_eq!(task.await.unwrap().id, 1);
+        }
+    }
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    #[tokio::test]
+    async fn test_coverage_endpoint() {
+        let app = create_app(test_store());
 
-    println!("🚀 Module 11: Testing");
-    println!("   Server: http://localhost:3000\n");
-    println!("📝 Endpoints:");
-    println!("   GET  /health    - Health check");
-    println!("   GET  /users     - List users");
-    println!("   POST /users     - Create user");
-    println!("   GET  /users/:id - Get user\n");
-    println!("🧪 Run tests: cargo test");
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/_coverage")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
 
-    axum::serve(listener, app).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            report["branches"].as_object().unwrap().len(),
+            coverage::BRANCHES.len()
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "Axum's rejections are plain text and the handlers' errors bare statuses"]
+    async fn test_error_conformance() {
+        let probes = Probes {
+            get_route: "/health",
+            json_route: Some("/users"),
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        conformance::assert_conforms(create_app(test_store()), &probes).await;
+    }
+
+    /// Runs each branch's test and checks the branch was hit. A plain
+    /// `#[test]` so it can call the `#[tokio::test]` functions, which each
+    /// build their own runtime.
+    #[test]
+    fn test_all_declared_branches_are_covered() {
+        for branch in coverage::BRANCHES {
+            let before = coverage::report().branches[branch.name];
+            (branch.test)();
+            assert!(
+                coverage::report().branches[branch.name] > before,
+                "{}: its test never hit it",
+                branch.name
+            );
+        }
+
+        let report = coverage::report();
+        assert!(
+            report.undeclared.is_empty(),
+            "covered!() names missing from BRANCHES: {:?}",
+            report.undeclared
+        );
+    }
 }
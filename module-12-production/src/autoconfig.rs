@@ -0,0 +1,210 @@
+//! # Container-Aware Resource Autoconfiguration
+//!
+//! Inside a container, the host's CPU count and RAM are the wrong numbers:
+//! a pod limited to 2 CPUs on a 64-core node that starts 64 tokio workers
+//! and a 130-connection pool spends its quota on context switches.
+//!
+//! At startup we read the cgroup limits and derive:
+//! - tokio worker threads: one per available CPU (rounded up)
+//! - DB pool size: `2 * cpus + 1`, the classic starting point
+//! - max concurrent requests: what fits in ~75% of the memory limit at
+//!   `REQUEST_MEMORY_BUDGET` each, capped per CPU
+//!
+//! Each can be overridden with `WORKER_THREADS`, `DB_POOL_SIZE` and
+//! `MAX_CONCURRENCY`. Both cgroup v2 (`cpu.max`, `memory.max`) and v1
+//! (`cpu.cfs_quota_us`, `memory.limit_in_bytes`) are understood.
+
+use serde::Serialize;
+use std::{fs, path::Path};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Memory one in-flight request is assumed to need
+const REQUEST_MEMORY_BUDGET: u64 = 1024 * 1024;
+/// Upper bound on in-flight requests per CPU, memory permitting
+const REQUESTS_PER_CPU: usize = 256;
+const MAX_DB_POOL: u32 = 32;
+/// cgroup v1 reports "no limit" as a huge page-aligned number
+const V1_UNLIMITED: u64 = 1 << 62;
+
+// ============================================================================
+// DETECTION
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceLimits {
+    /// Fractional CPUs allowed, e.g. 1.5 for a 150ms/100ms quota
+    pub cpus: f64,
+    pub cpu_source: &'static str,
+    /// `None` = unlimited and host memory unknown
+    pub memory_bytes: Option<u64>,
+    pub memory_source: &'static str,
+}
+
+impl ResourceLimits {
+    pub fn detect() -> Self {
+        Self::detect_from(Path::new(CGROUP_ROOT))
+    }
+
+    fn detect_from(root: &Path) -> Self {
+        let host_cpus = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1) as f64;
+
+        let (cpus, cpu_source) = match cgroup_cpu_quota(root) {
+            // A quota above the host's CPU count can't be used anyway
+            Some((quota, source)) => (quota.min(host_cpus), source),
+            None => (host_cpus, "host"),
+        };
+        let (memory_bytes, memory_source) = match cgroup_memory_limit(root) {
+            Some((limit, source)) => (Some(limit), source),
+            None => (host_memory(), "host"),
+        };
+
+        Self {
+            cpus,
+            cpu_source,
+            memory_bytes,
+            memory_source,
+        }
+    }
+}
+
+fn read_trimmed(path: impl AsRef<Path>) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// CPU quota in CPUs, or `None` when unlimited / not in a cgroup
+fn cgroup_cpu_quota(root: &Path) -> Option<(f64, &'static str)> {
+    // v2: "<quota> <period>" or "max <period>"
+    if let Some(cpu_max) = read_trimmed(root.join("cpu.max")) {
+        let mut parts = cpu_max.split_whitespace();
+        let quota = parts.next()?.parse::<f64>().ok()?;
+        let period = parts.next()?.parse::<f64>().ok()?;
+        return (period > 0.0).then(|| (quota / period, "cgroup v2"));
+    }
+    // v1: quota is -1 when unlimited
+    let quota = read_trimmed(root.join("cpu/cpu.cfs_quota_us"))?
+        .parse::<f64>()
+        .ok()?;
+    let period = read_trimmed(root.join("cpu/cpu.cfs_period_us"))?
+        .parse::<f64>()
+        .ok()?;
+    (quota > 0.0 && period > 0.0).then(|| (quota / period, "cgroup v1"))
+}
+
+fn cgroup_memory_limit(root: &Path) -> Option<(u64, &'static str)> {
+    // v2: bytes or "max"
+    if let Some(memory_max) = read_trimmed(root.join("memory.max")) {
+        return memory_max.parse().ok().map(|limit| (limit, "cgroup v2"));
+    }
+    let limit: u64 = read_trimmed(root.join("memory/memory.limit_in_bytes"))?
+        .parse()
+        .ok()?;
+    (limit < V1_UNLIMITED).then_some((limit, "cgroup v1"))
+}
+
+/// `MemTotal` from /proc/meminfo
+fn host_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let kb: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+// ============================================================================
+// DERIVED SETTINGS
+// ============================================================================
+
+/// Where a setting came from, for the startup log
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    Derived,
+    Override,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeConfig {
+    pub worker_threads: usize,
+    pub worker_threads_origin: Origin,
+    /// Size to pass to `PgPoolOptions::max_connections` once a DB is attached
+    pub db_pool_size: u32,
+    pub db_pool_size_origin: Origin,
+    pub max_concurrency: usize,
+    pub max_concurrency_origin: Origin,
+    pub limits: ResourceLimits,
+}
+
+impl RuntimeConfig {
+    /// Detect limits and apply env overrides
+    pub fn from_environment() -> Self {
+        Self::derive(ResourceLimits::detect(), |name| std::env::var(name).ok())
+    }
+
+    fn derive(limits: ResourceLimits, env: impl Fn(&str) -> Option<String>) -> Self {
+        let cpus = (limits.cpus.ceil() as usize).max(1);
+
+        let worker_threads = cpus;
+        let db_pool_size = (2 * cpus as u32 + 1).min(MAX_DB_POOL);
+        let per_cpu_cap = cpus * REQUESTS_PER_CPU;
+        let max_concurrency = match limits.memory_bytes {
+            Some(bytes) => ((bytes / 4 * 3 / REQUEST_MEMORY_BUDGET) as usize).clamp(1, per_cpu_cap),
+            None => per_cpu_cap,
+        };
+
+        let (worker_threads, worker_threads_origin) =
+            with_override(&env, "WORKER_THREADS", worker_threads);
+        let (db_pool_size, db_pool_size_origin) =
+            with_override(&env, "DB_POOL_SIZE", db_pool_size);
+        let (max_concurrency, max_concurrency_origin) =
+            with_override(&env, "MAX_CONCURRENCY", max_concurrency);
+
+        Self {
+            worker_threads,
+            worker_threads_origin,
+            db_pool_size,
+            db_pool_size_origin,
+            max_concurrency,
+            max_concurrency_origin,
+            limits,
+        }
+    }
+
+    /// Called once tracing is initialized
+    pub fn log(&self) {
+        tracing::info!(
+            cpus = self.limits.cpus,
+            cpu_source = self.limits.cpu_source,
+            memory_mb = self.limits.memory_bytes.map(|b| b / 1024 / 1024),
+            memory_source = self.limits.memory_source,
+            "Detected resource limits"
+        );
+        tracing::info!(
+            worker_threads = self.worker_threads,
+            worker_threads_origin = ?self.worker_threads_origin,
+            db_pool_size = self.db_pool_size,
+            db_pool_size_origin = ?self.db_pool_size_origin,
+            max_concurrency = self.max_concurrency,
+            max_concurrency_origin = ?self.max_concurrency_origin,
+            "Runtime configuration"
+        );
+    }
+}
+
+/// A positive integer from the environment wins over the derived value
+fn with_override<T: std::str::FromStr + PartialOrd + Default>(
+    env: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    derived: T,
+) -> (T, Origin) {
+    match env(name).and_then(|v| v.trim().parse::<T>().ok()) {
+        Some(value) if value > T::default() => (value, Origin::Override),
+        _ => (derived, Origin::Derived),
+    }
+}
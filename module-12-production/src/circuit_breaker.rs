@@ -0,0 +1,646 @@
+//! # Circuit Breaker for Upstream Calls
+//!
+//! When an upstream fails, it usually fails slowly: every call waits for a
+//! timeout before it errors. Calling it anyway ties up our requests, our
+//! connections and the upstream's recovery. A circuit breaker notices the
+//! failures and stops calling for a while:
+//!
+//! ```text
+//! closed    --(failure_threshold failures in a row)-->  open
+//! open      --(open_for has passed)------------------>  half-open
+//! half-open --(the probe succeeds)------------------->  closed
+//! half-open --(the probe fails)---------------------->  open
+//! ```
+//!
+//! - **closed**: calls go through; failures are counted, a success resets
+//!   the count
+//! - **open**: calls fail at once with `CircuitError::Open`, without
+//!   touching the upstream, and say how long until it's tried again
+//! - **half-open**: exactly one call - the probe - goes through to test the
+//!   water; the others are still rejected. A probe that's cancelled (its
+//!   request went away) counts as a failure, so it can't wedge the breaker
+//!
+//! It's a tower `Layer`, so it wraps any `Service` - an HTTP client, a
+//! database pool, a gRPC channel:
+//!
+//! ```ignore
+//! let client = ServiceBuilder::new()
+//!     .layer(CircuitBreakerLayer::new(BreakerPolicy::default()))
+//!     .service(StockService::default());
+//! ```
+//!
+//! Only `Err` counts as a failure. For an HTTP client whose 5xx responses
+//! are `Ok`, turn them into errors in a layer beneath the breaker.
+//!
+//! `GET /items/{id}/stock` asks a simulated stock service through one;
+//! `POST /admin/upstreams/stock` makes it fail, and `/metrics` shows the
+//! circuit.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::time::Instant;
+use tower::{Layer, Service, ServiceExt};
+
+use crate::AppState;
+
+#[derive(Debug, Clone)]
+pub struct BreakerPolicy {
+    /// Consecutive failures that open the circuit
+    pub failure_threshold: u32,
+    /// How long it stays open before a probe is let through
+    pub open_for: Duration,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_for: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: Instant,
+}
+
+/// What the breaker lets a call do
+enum Admission {
+    Call,
+    Probe,
+    Reject { retry_in: Duration },
+}
+
+#[derive(Debug, Serialize)]
+pub struct BreakerStats {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// Times the circuit has opened
+    pub opened: u64,
+    /// Calls answered without reaching the upstream
+    pub rejected: u64,
+}
+
+/// The shared state behind every clone of a `CircuitBreaker`
+#[derive(Debug)]
+pub struct Breaker {
+    policy: BreakerPolicy,
+    circuit: Mutex<Circuit>,
+    opened: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Breaker {
+    pub fn new(policy: BreakerPolicy) -> Self {
+        Self {
+            policy,
+            circuit: Mutex::new(Circuit {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: Instant::now(),
+            }),
+            opened: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.circuit.lock().unwrap().state
+    }
+
+    fn admit(&self) -> Admission {
+        let mut circuit = self.circuit.lock().unwrap();
+        let admission = match circuit.state {
+            CircuitState::Closed => Admission::Call,
+            CircuitState::Open => {
+                let retry_at = circuit.opened_at + self.policy.open_for;
+                let now = Instant::now();
+                if now >= retry_at {
+                    circuit.state = CircuitState::HalfOpen;
+                    tracing::info!("Circuit half-open, probing the upstream");
+                    Admission::Probe
+                } else {
+                    Admission::Reject {
+                        retry_in: retry_at - now,
+                    }
+                }
+            }
+            // The probe is in flight; its outcome decides for everyone
+            CircuitState::HalfOpen => Admission::Reject {
+                retry_in: Duration::from_secs(1),
+            },
+        };
+        if matches!(admission, Admission::Reject { .. }) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+        admission
+    }
+
+    fn on_success(&self, probe: bool) {
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.consecutive_failures = 0;
+        if probe {
+            circuit.state = CircuitState::Closed;
+            tracing::info!("Circuit closed, the upstream has recovered");
+        }
+    }
+
+    fn on_failure(&self, probe: bool) {
+        let mut circuit = self.circuit.lock().unwrap();
+        circuit.consecutive_failures += 1;
+        let trips = probe
+            || (circuit.state == CircuitState::Closed
+                && circuit.consecutive_failures >= self.policy.failure_threshold);
+        if trips {
+            circuit.state = CircuitState::Open;
+            circuit.opened_at = Instant::now();
+            self.opened.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(
+                failures = circuit.consecutive_failures,
+                open_for_secs = self.policy.open_for.as_secs(),
+                "Circuit opened"
+            );
+        }
+    }
+
+    pub fn stats(&self) -> BreakerStats {
+        let circuit = self.circuit.lock().unwrap();
+        BreakerStats {
+            state: circuit.state,
+            consecutive_failures: circuit.consecutive_failures,
+            opened: self.opened.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reports one call's outcome; dropped without one, the call was cancelled
+struct Attempt {
+    breaker: Arc<Breaker>,
+    probe: bool,
+    finished: bool,
+}
+
+impl Attempt {
+    fn finish(mut self, success: bool) {
+        self.finished = true;
+        if success {
+            self.breaker.on_success(self.probe);
+        } else {
+            self.breaker.on_failure(self.probe);
+        }
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        // An ordinary call that's cancelled says nothing about the upstream,
+        // but the probe must settle the half-open state one way or the other
+        if !self.finished && self.probe {
+            self.breaker.on_failure(true);
+        }
+    }
+}
+
+// ============================================================================
+// THE LAYER AND ITS SERVICE
+// ============================================================================
+
+#[derive(Debug)]
+pub enum CircuitError<E> {
+    /// Rejected without calling the upstream
+    Open { retry_in: Duration },
+    /// The upstream was called and failed
+    Upstream(E),
+}
+
+impl<E: fmt::Display> fmt::Display for CircuitError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CircuitError::Open { retry_in } => {
+                write!(f, "circuit open, retry in {:?}", retry_in)
+            }
+            CircuitError::Upstream(e) => write!(f, "upstream failed: {}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for CircuitError<E> {}
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerLayer {
+    breaker: Arc<Breaker>,
+}
+
+impl CircuitBreakerLayer {
+    pub fn new(policy: BreakerPolicy) -> Self {
+        Self {
+            breaker: Arc::new(Breaker::new(policy)),
+        }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer {
+    type Service = CircuitBreaker<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CircuitBreaker {
+            inner,
+            breaker: self.breaker.clone(),
+        }
+    }
+}
+
+/// Clones share one circuit
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker<S> {
+    inner: S,
+    breaker: Arc<Breaker>,
+}
+
+impl<S> CircuitBreaker<S> {
+    pub fn breaker(&self) -> &Breaker {
+        &self.breaker
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, Request> Service<Request> for CircuitBreaker<S>
+where
+    S: Service<Request>,
+    S::Response: Send + 'static,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = CircuitError<S::Error>;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(CircuitError::Upstream)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let probe = match self.breaker.admit() {
+            Admission::Call => false,
+            Admission::Probe => true,
+            Admission::Reject { retry_in } => {
+                return Box::pin(std::future::ready(Err(CircuitError::Open { retry_in })));
+            }
+        };
+        let attempt = Attempt {
+            breaker: self.breaker.clone(),
+            probe,
+            finished: false,
+        };
+        let call = self.inner.call(request);
+        Box::pin(async move {
+            let result = call.await;
+            attempt.finish(result.is_ok());
+            result.map_err(CircuitError::Upstream)
+        })
+    }
+}
+
+// ============================================================================
+// A SIMULATED UPSTREAM
+// ============================================================================
+
+/// A stock service that fails `failure_rate` of its calls - slowly, the
+/// way a struggling service times out
+#[derive(Debug, Clone, Default)]
+pub struct StockService {
+    /// A fraction 0.0-1.0, stored as its bits
+    failure_rate: Arc<AtomicU64>,
+}
+
+impl StockService {
+    pub fn set_failure_rate(&self, rate: f64) {
+        self.failure_rate
+            .store(rate.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        f64::from_bits(self.failure_rate.load(Ordering::Relaxed))
+    }
+}
+
+impl Service<u64> for StockService {
+    type Response = u64;
+    type Error = &'static str;
+    type Future = Pin<Box<dyn Future<Output = Result<u64, &'static str>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, item_id: u64) -> Self::Future {
+        let fails = rand::thread_rng().gen_bool(self.failure_rate());
+        Box::pin(async move {
+            if fails {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                Err("stock service timed out")
+            } else {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(item_id * 7 % 40)
+            }
+        })
+    }
+}
+
+pub type StockClient = CircuitBreaker<StockService>;
+
+#[derive(Serialize)]
+pub struct Stock {
+    item_id: u64,
+    in_stock: u64,
+}
+
+/// GET /items/{id}/stock
+pub async fn item_stock(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    if !state.items.read().unwrap().iter().any(|item| item.id == id) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match state.stock.clone().oneshot(id).await {
+        Ok(in_stock) => Json(Stock {
+            item_id: id,
+            in_stock,
+        })
+        .into_response(),
+        Err(CircuitError::Open { retry_in }) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, retry_in.as_secs().max(1).to_string())],
+            Json(serde_json::json!({
+                "error": "The stock service is unavailable, not calling it for now",
+                "circuit": state.stock.breaker().state(),
+            })),
+        )
+            .into_response(),
+        Err(CircuitError::Upstream(e)) => (
+            StatusCode::BAD_GATEWAY,
+            Json(serde_json::json!({ "error": e })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetFailureRate {
+    failure_rate: f64,
+}
+
+/// POST /admin/upstreams/stock - `{"failure_rate": 1.0}` takes it down
+pub async fn set_stock_failure_rate(
+    State(state): State<AppState>,
+    Json(input): Json<SetFailureRate>,
+) -> Json<serde_json::Value> {
+    let upstream = state.stock.get_ref();
+    upstream.set_failure_rate(input.failure_rate);
+    Json(serde_json::json!({
+        "failure_rate": upstream.failure_rate(),
+        "circuit": state.stock.breaker().stats(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use tower::ServiceBuilder;
+
+    /// An upstream that fails while `failing` is set, counting its calls
+    #[derive(Clone, Default)]
+    struct Switchable {
+        failing: Arc<AtomicBool>,
+        calls: Arc<AtomicU64>,
+    }
+
+    impl Service<()> for Switchable {
+        type Response = ();
+        type Error = &'static str;
+        type Future = Pin<Box<dyn Future<Output = Result<(), &'static str>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let failing = self.failing.load(Ordering::SeqCst);
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if failing {
+                    Err("down")
+                } else {
+                    Ok(())
+                }
+            })
+        }
+    }
+
+    fn breaker_over(upstream: &Switchable) -> CircuitBreaker<Switchable> {
+        ServiceBuilder::new()
+            .layer(CircuitBreakerLayer::new(BreakerPolicy {
+                failure_threshold: 3,
+                open_for: Duration::from_secs(10),
+            }))
+            .service(upstream.clone())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_opens_after_the_threshold_and_fails_fast() {
+        let upstream = Switchable::default();
+        upstream.failing.store(true, Ordering::SeqCst);
+        let client = breaker_over(&upstream);
+
+        for _ in 0..3 {
+            let result = client.clone().oneshot(()).await;
+            assert!(matches!(result, Err(CircuitError::Upstream("down"))));
+        }
+        assert_eq!(client.breaker().state(), CircuitState::Open);
+
+        let started = Instant::now();
+        let result = client.clone().oneshot(()).await;
+        assert!(
+            matches!(result, Err(CircuitError::Open { retry_in }) if retry_in <= Duration::from_secs(10))
+        );
+        assert_eq!(
+            started.elapsed(),
+            Duration::ZERO,
+            "no waiting on the upstream"
+        );
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 3);
+        assert_eq!(client.breaker().stats().rejected, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_half_open_probe_decides_recovery() {
+        let upstream = Switchable::default();
+        upstream.failing.store(true, Ordering::SeqCst);
+        let client = breaker_over(&upstream);
+        for _ in 0..3 {
+            let _ = client.clone().oneshot(()).await;
+        }
+
+        // Still down when the probe goes: open again, for another 10s
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(client.clone().oneshot(()).await.is_err());
+        assert_eq!(client.breaker().state(), CircuitState::Open);
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 4);
+
+        // Recovered: one probe, and the circuit closes
+        upstream.failing.store(false, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let probe = tokio::spawn(client.clone().oneshot(()));
+        tokio::task::yield_now().await;
+        // While the probe is out, everyone else is still turned away
+        assert!(matches!(
+            client.clone().oneshot(()).await,
+            Err(CircuitError::Open { .. })
+        ));
+        assert!(probe.await.unwrap().is_ok());
+        assert_eq!(client.breaker().state(), CircuitState::Closed);
+        assert!(client.clone().oneshot(()).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_cancelled_probe_does_not_wedge_the_breaker() {
+        let upstream = Switchable::default();
+        upstream.failing.store(true, Ordering::SeqCst);
+        let client = breaker_over(&upstream);
+        for _ in 0..3 {
+            let _ = client.clone().oneshot(()).await;
+        }
+        tokio::time::advance(Duration::from_secs(10)).await;
+
+        // The probe's request goes away before the upstream answers
+        let probe = client.clone().oneshot(());
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), probe).await;
+        assert!(cancelled.is_err());
+        assert_eq!(client.breaker().state(), CircuitState::Open);
+
+        upstream.failing.store(false, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(client.clone().oneshot(()).await.is_ok());
+        assert_eq!(client.breaker().state(), CircuitState::Closed);
+    }
+
+    fn retry_in(result: Result<(), CircuitError<&'static str>>) -> Duration {
+        match result {
+            Err(CircuitError::Open { retry_in }) => retry_in,
+            other => panic!("expected the circuit to be open, got {:?}", other),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_in_counts_down_while_open() {
+        let upstream = Switchable::default();
+        upstream.failing.store(true, Ordering::SeqCst);
+        let client = breaker_over(&upstream);
+        for _ in 0..3 {
+            let _ = client.clone().oneshot(()).await;
+        }
+
+        assert_eq!(
+            retry_in(client.clone().oneshot(()).await),
+            Duration::from_secs(10)
+        );
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert_eq!(
+            retry_in(client.clone().oneshot(()).await),
+            Duration::from_secs(6)
+        );
+        tokio::time::advance(Duration::from_millis(5_500)).await;
+        assert_eq!(
+            retry_in(client.clone().oneshot(()).await),
+            Duration::from_millis(500)
+        );
+        // None of them reached the upstream
+        assert_eq!(upstream.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stats_follow_every_transition() {
+        let upstream = Switchable::default();
+        let client = breaker_over(&upstream);
+        let stats = || serde_json::to_value(client.breaker().stats()).unwrap();
+
+        // A success in between resets the run of failures
+        upstream.failing.store(true, Ordering::SeqCst);
+        for _ in 0..2 {
+            let _ = client.clone().oneshot(()).await;
+        }
+        assert_eq!(stats()["consecutive_failures"], 2);
+        upstream.failing.store(false, Ordering::SeqCst);
+        assert!(client.clone().oneshot(()).await.is_ok());
+        assert_eq!(stats()["consecutive_failures"], 0);
+
+        upstream.failing.store(true, Ordering::SeqCst);
+        for _ in 0..3 {
+            let _ = client.clone().oneshot(()).await;
+        }
+        let _ = client.clone().oneshot(()).await;
+        assert_eq!(
+            stats(),
+            serde_json::json!({
+                "state": "open",
+                "consecutive_failures": 3,
+                "opened": 1,
+                "rejected": 1,
+            })
+        );
+
+        // A failed probe opens it again
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let _ = client.clone().oneshot(()).await;
+        assert_eq!(stats()["opened"], 2);
+        assert_eq!(stats()["consecutive_failures"], 4);
+
+        upstream.failing.store(false, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let probe = tokio::spawn(client.clone().oneshot(()));
+        tokio::task::yield_now().await;
+        assert_eq!(stats()["state"], "half_open");
+        assert!(probe.await.unwrap().is_ok());
+        assert_eq!(
+            stats(),
+            serde_json::json!({
+                "state": "closed",
+                "consecutive_failures": 0,
+                "opened": 2,
+                "rejected": 1,
+            })
+        );
+    }
+}
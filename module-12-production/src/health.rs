@@ -0,0 +1,396 @@
+//! # Health State Machine
+//!
+//! Service health is modelled as `Healthy -> Degraded -> Unhealthy`, driven by:
+//! - Dependency checks (critical and non-critical dependencies)
+//! - The 5xx error rate over a sliding window of recent responses
+//!
+//! Moving to a worse state happens immediately; recovering requires the
+//! better state to be observed for several consecutive evaluations
+//! (hysteresis), so a flapping dependency doesn't flap the whole service.
+//!
+//! While not healthy, `disable_when_degraded` turns expensive endpoints
+//! (search, exports) into fast 503s so core CRUD keeps its capacity. Those
+//! 503s are ours, not failures: they stay out of the error window, or
+//! shedding load would keep the error rate up and the service would never
+//! recover.
+
+use axum::{
+    extract::{Path, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::AppState;
+
+/// Responses remembered for the error-rate calculation
+const ERROR_WINDOW: usize = 100;
+/// Below this many samples the error rate is considered unknown (treated as 0)
+const MIN_SAMPLES: usize = 20;
+/// Consecutive better evaluations needed before recovering
+const RECOVERY_CHECKS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// Thresholds driving the state machine
+#[derive(Debug, Clone)]
+pub struct HealthThresholds {
+    /// Error rate (0.0 - 1.0) at which we become degraded
+    pub degraded_error_rate: f64,
+    /// Error rate at which we become unhealthy
+    pub unhealthy_error_rate: f64,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            degraded_error_rate: 0.10,
+            unhealthy_error_rate: 0.50,
+        }
+    }
+}
+
+/// A dependency we check; in a real service `check()` would ping a DB,
+/// cache or upstream. Here its status can be flipped via the admin API.
+pub struct Dependency {
+    name: &'static str,
+    /// Critical dependencies make us unhealthy, others only degraded
+    critical: bool,
+    up: AtomicBool,
+}
+
+impl Dependency {
+    pub fn new(name: &'static str, critical: bool) -> Self {
+        Self {
+            name,
+            critical,
+            up: AtomicBool::new(true),
+        }
+    }
+
+    fn check(&self) -> bool {
+        self.up.load(Ordering::SeqCst)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Transition {
+    from: HealthState,
+    to: HealthState,
+    reason: String,
+    at_unix: u64,
+}
+
+struct MachineState {
+    current: HealthState,
+    /// Candidate better state and how many times in a row we've seen it
+    recovering_to: Option<(HealthState, u32)>,
+    last_reason: String,
+    transitions: VecDeque<Transition>,
+}
+
+pub struct HealthMonitor {
+    thresholds: HealthThresholds,
+    dependencies: Vec<Dependency>,
+    /// `true` = the response was a 5xx
+    outcomes: Mutex<VecDeque<bool>>,
+    machine: RwLock<MachineState>,
+}
+
+impl HealthMonitor {
+    pub fn new(thresholds: HealthThresholds, dependencies: Vec<Dependency>) -> Self {
+        Self {
+            thresholds,
+            dependencies,
+            outcomes: Mutex::new(VecDeque::with_capacity(ERROR_WINDOW)),
+            machine: RwLock::new(MachineState {
+                current: HealthState::Healthy,
+                recovering_to: None,
+                last_reason: "startup".to_string(),
+                transitions: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn state(&self) -> HealthState {
+        self.machine.read().unwrap().current
+    }
+
+    pub fn record_outcome(&self, is_server_error: bool) {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        if outcomes.len() == ERROR_WINDOW {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(is_server_error);
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        let outcomes = self.outcomes.lock().unwrap();
+        if outcomes.len() < MIN_SAMPLES {
+            return 0.0;
+        }
+        outcomes.iter().filter(|e| **e).count() as f64 / outcomes.len() as f64
+    }
+
+    /// What the inputs say right now, with a human-readable reason
+    fn observe(&self) -> (HealthState, String) {
+        let error_rate = self.error_rate();
+        let down: Vec<&Dependency> = self.dependencies.iter().filter(|d| !d.check()).collect();
+
+        if let Some(dep) = down.iter().find(|d| d.critical) {
+            return (HealthState::Unhealthy, format!("critical dependency '{}' down", dep.name));
+        }
+        if error_rate >= self.thresholds.unhealthy_error_rate {
+            return (HealthState::Unhealthy, format!("error rate {:.0}%", error_rate * 100.0));
+        }
+        if let Some(dep) = down.first() {
+            return (HealthState::Degraded, format!("dependency '{}' down", dep.name));
+        }
+        if error_rate >= self.thresholds.degraded_error_rate {
+            return (HealthState::Degraded, format!("error rate {:.0}%", error_rate * 100.0));
+        }
+        (HealthState::Healthy, "all checks passing".to_string())
+    }
+
+    /// Run one step of the state machine
+    pub fn evaluate(&self) {
+        let (observed, reason) = self.observe();
+        let mut machine = self.machine.write().unwrap();
+        let current = machine.current;
+
+        let next = if observed > current {
+            // Getting worse: act immediately
+            machine.recovering_to = None;
+            Some(observed)
+        } else if observed < current {
+            // Getting better: require RECOVERY_CHECKS consecutive observations
+            let streak = match machine.recovering_to {
+                Some((state, n)) if state == observed => n + 1,
+                _ => 1,
+            };
+            machine.recovering_to = Some((observed, streak));
+            (streak >= RECOVERY_CHECKS).then_some(observed)
+        } else {
+            machine.recovering_to = None;
+            None
+        };
+
+        if let Some(to) = next {
+            tracing::warn!(from = ?current, to = ?to, reason = %reason, "Health state changed");
+            machine.current = to;
+            machine.recovering_to = None;
+            if machine.transitions.len() == 20 {
+                machine.transitions.pop_front();
+            }
+            machine.transitions.push_back(Transition {
+                from: current,
+                to,
+                reason: reason.clone(),
+                at_unix: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            });
+        }
+        machine.last_reason = reason;
+    }
+}
+
+/// Background task re-evaluating health on a fixed interval
+pub async fn run_health_checks(monitor: Arc<HealthMonitor>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        monitor.evaluate();
+    }
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// Marks a response `disable_when_degraded` turned away
+#[derive(Debug, Clone, Copy)]
+struct LoadShed;
+
+/// Global: feed every response status into the error-rate window, except
+/// the ones we shed on purpose
+pub async fn track_outcomes(
+    State(health): State<Arc<HealthMonitor>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    if response.extensions().get::<LoadShed>().is_none() {
+        health.record_outcome(response.status().is_server_error());
+    }
+    response
+}
+
+/// Route layer for expensive endpoints: only served while fully healthy
+pub async fn disable_when_degraded(
+    State(monitor): State<Arc<HealthMonitor>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let health = monitor.state();
+    if health == HealthState::Healthy {
+        return next.run(request).await;
+    }
+
+    let mut response = (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [("Retry-After", "30")],
+        Json(serde_json::json!({
+            "error": "This feature is temporarily disabled while the service is degraded",
+            "health": health
+        })),
+    )
+        .into_response();
+    response.extensions_mut().insert(LoadShed);
+    response
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+#[derive(Serialize)]
+struct DependencyStatus {
+    name: &'static str,
+    critical: bool,
+    up: bool,
+}
+
+/// GET /health/details
+pub async fn health_details(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let monitor = &state.health;
+    let machine = monitor.machine.read().unwrap();
+    let dependencies: Vec<DependencyStatus> = monitor
+        .dependencies
+        .iter()
+        .map(|d| DependencyStatus {
+            name: d.name,
+            critical: d.critical,
+            up: d.check(),
+        })
+        .collect();
+
+    Json(serde_json::json!({
+        "state": machine.current,
+        "reason": machine.last_reason,
+        "error_rate": monitor.error_rate(),
+        "dependencies": dependencies,
+        "transitions": machine.transitions,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct SetDependency {
+    up: bool,
+}
+
+/// POST /admin/dependencies/{name} - simulate a dependency outage/recovery
+pub async fn set_dependency(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(input): Json<SetDependency>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let dependency = state
+        .health
+        .dependencies
+        .iter()
+        .find(|d| d.name == name)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    dependency.up.store(input.up, Ordering::SeqCst);
+
+    // Evaluate right away so the effect is visible without waiting
+    state.health.evaluate();
+    Ok(Json(serde_json::json!({
+        "dependency": dependency.name,
+        "up": input.up,
+        "state": state.health.state()
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    /// `/items` fails while `failing` is set; `/search` is shed when not healthy
+    fn app(monitor: Arc<HealthMonitor>, failing: Arc<AtomicBool>) -> Router {
+        let items = move || async move {
+            if failing.load(Ordering::SeqCst) {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::OK
+            }
+        };
+        Router::new()
+            .route("/items", get(items))
+            .route(
+                "/search",
+                get(|| async { "results" }).route_layer(middleware::from_fn_with_state(
+                    monitor.clone(),
+                    disable_when_degraded,
+                )),
+            )
+            .layer(middleware::from_fn_with_state(monitor, track_outcomes))
+    }
+
+    async fn status(app: &Router, uri: &str) -> StatusCode {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_shed_requests_do_not_hold_back_recovery() {
+        let monitor = Arc::new(HealthMonitor::new(HealthThresholds::default(), Vec::new()));
+        let failing = Arc::new(AtomicBool::new(true));
+        let app = app(monitor.clone(), failing.clone());
+
+        for _ in 0..MIN_SAMPLES {
+            assert_eq!(
+                status(&app, "/items").await,
+                StatusCode::INTERNAL_SERVER_ERROR
+            );
+        }
+        monitor.evaluate();
+        assert_eq!(monitor.state(), HealthState::Unhealthy);
+
+        // Fixed, but half the traffic is still turned away while unhealthy
+        failing.store(false, Ordering::SeqCst);
+        for _ in 0..ERROR_WINDOW {
+            assert_eq!(
+                status(&app, "/search").await,
+                StatusCode::SERVICE_UNAVAILABLE
+            );
+            assert_eq!(status(&app, "/items").await, StatusCode::OK);
+        }
+        assert_eq!(monitor.error_rate(), 0.0);
+
+        for _ in 0..RECOVERY_CHECKS {
+            monitor.evaluate();
+        }
+        assert_eq!(monitor.state(), HealthState::Healthy);
+        assert_eq!(status(&app, "/search").await, StatusCode::OK);
+    }
+}
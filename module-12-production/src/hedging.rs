@@ -0,0 +1,403 @@
+//! # Request Hedging for Idempotent Upstream Calls
+//!
+//! Most upstream calls are fast, but a few land on a GC pause, a cold cache
+//! or a busy replica and take ten times longer - and those few dominate p99.
+//! Hedging races the tail instead of waiting it out:
+//! 1. Send the request
+//! 2. If it hasn't answered by the upstream's usual p95 latency, send the
+//!    same request again
+//! 3. Take whichever answers successfully first; drop (cancel) the other
+//!
+//! Only 5% of calls get a second attempt, yet a slow first attempt no longer
+//! decides the response time. The rules that keep it safe:
+//! - **Idempotent calls only.** Both attempts may complete upstream, so a
+//!   hedged `POST /charge` would charge twice
+//! - **A hedge budget.** If the upstream is slow for *everyone*, hedging
+//!   every call doubles its load exactly when it can least take it, so at
+//!   most `max_hedge_ratio` of calls are hedged
+//! - **The delay adapts.** It's the chosen percentile of recently observed
+//!   attempt latencies, not a constant that goes stale
+//!
+//! `GET /items/{id}/price` asks a simulated pricing service through a
+//! `Hedger`; `/metrics` shows hedges fired and won.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use rand::Rng;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::AppState;
+
+/// Attempt latencies kept for the percentile
+const LATENCY_WINDOW: usize = 200;
+/// Below this many samples, `default_delay` is used
+const MIN_SAMPLES: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct HedgePolicy {
+    /// Hedge once an attempt is slower than this share of recent attempts
+    pub percentile: f64,
+    /// Never hedge sooner than this, however fast the upstream has been
+    pub min_delay: Duration,
+    /// Used until enough latencies have been observed
+    pub default_delay: Duration,
+    /// At most this share of calls may be hedged
+    pub max_hedge_ratio: f64,
+}
+
+impl Default for HedgePolicy {
+    fn default() -> Self {
+        Self {
+            percentile: 0.95,
+            min_delay: Duration::from_millis(5),
+            default_delay: Duration::from_millis(100),
+            max_hedge_ratio: 0.10,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct HedgeStats {
+    pub calls: u64,
+    /// Second attempts sent
+    pub hedges_fired: u64,
+    /// Second attempts that answered first
+    pub hedges_won: u64,
+    /// Would have hedged, but the budget was spent
+    pub hedges_skipped: u64,
+    pub delay_ms: u64,
+}
+
+/// Wraps calls to one upstream; shared by every request that uses it
+pub struct Hedger {
+    policy: HedgePolicy,
+    latencies: Mutex<VecDeque<Duration>>,
+    calls: AtomicU64,
+    fired: AtomicU64,
+    won: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl Hedger {
+    pub fn new(policy: HedgePolicy) -> Self {
+        Self {
+            policy,
+            latencies: Mutex::new(VecDeque::with_capacity(LATENCY_WINDOW)),
+            calls: AtomicU64::new(0),
+            fired: AtomicU64::new(0),
+            won: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// How long to wait before hedging
+    pub fn delay(&self) -> Duration {
+        let latencies = self.latencies.lock().unwrap();
+        if latencies.len() < MIN_SAMPLES {
+            return self.policy.default_delay;
+        }
+        let mut sorted: Vec<Duration> = latencies.iter().copied().collect();
+        sorted.sort();
+        let rank = ((sorted.len() - 1) as f64 * self.policy.percentile).round() as usize;
+        sorted[rank].max(self.policy.min_delay)
+    }
+
+    fn record(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == LATENCY_WINDOW {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    fn within_budget(&self) -> bool {
+        let calls = self.calls.load(Ordering::Relaxed).max(1);
+        let fired = self.fired.load(Ordering::Relaxed);
+        (fired as f64) < self.policy.max_hedge_ratio * calls as f64
+    }
+
+    /// Run `attempt`, and run it again if the first is slow. `attempt` must
+    /// be safe to execute twice - both may reach the upstream.
+    pub async fn call_idempotent<T, E, F, Fut>(&self, attempt: F) -> Result<T, E>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let started = Instant::now();
+        let primary = attempt();
+        tokio::pin!(primary);
+
+        tokio::select! {
+            result = &mut primary => {
+                self.record(started.elapsed());
+                return result;
+            }
+            _ = tokio::time::sleep(self.delay()) => {}
+        }
+
+        if !self.within_budget() {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+            let result = primary.await;
+            self.record(started.elapsed());
+            return result;
+        }
+        self.fired.fetch_add(1, Ordering::Relaxed);
+        let hedge_started = Instant::now();
+        let hedge = attempt();
+        tokio::pin!(hedge);
+
+        // First *successful* answer wins; an error just waits for the other.
+        // Returning drops the loser, which cancels it.
+        tokio::select! {
+            result = &mut primary => {
+                self.record(started.elapsed());
+                match result {
+                    Ok(value) => Ok(value),
+                    Err(_) => self.finish_hedge(hedge, hedge_started).await,
+                }
+            }
+            result = &mut hedge => {
+                // The primary is at least this slow; keep the tail in the window
+                self.record(started.elapsed());
+                self.record(hedge_started.elapsed());
+                match result {
+                    Ok(value) => {
+                        self.won.fetch_add(1, Ordering::Relaxed);
+                        Ok(value)
+                    }
+                    Err(_) => primary.await,
+                }
+            }
+        }
+    }
+
+    async fn finish_hedge<T, E>(
+        &self,
+        hedge: impl Future<Output = Result<T, E>>,
+        started: Instant,
+    ) -> Result<T, E> {
+        let result = hedge.await;
+        self.record(started.elapsed());
+        if result.is_ok() {
+            self.won.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    pub fn stats(&self) -> HedgeStats {
+        HedgeStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            hedges_fired: self.fired.load(Ordering::Relaxed),
+            hedges_won: self.won.load(Ordering::Relaxed),
+            hedges_skipped: self.skipped.load(Ordering::Relaxed),
+            delay_ms: self.delay().as_millis() as u64,
+        }
+    }
+}
+
+// ============================================================================
+// A SIMULATED UPSTREAM
+// ============================================================================
+
+/// A pricing service with a long tail: 96% of requests take 10-30ms, the
+/// rest 300-800ms. Looking up a price is a read, so it's safe to hedge.
+pub async fn fetch_price(item_id: u64) -> Result<u64, &'static str> {
+    let latency = {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(0.96) {
+            rng.gen_range(10..30)
+        } else {
+            rng.gen_range(300..800)
+        }
+    };
+    tokio::time::sleep(Duration::from_millis(latency)).await;
+    Ok(999 + item_id * 250)
+}
+
+#[derive(Serialize)]
+pub struct Price {
+    item_id: u64,
+    price_cents: u64,
+    latency_ms: u64,
+}
+
+/// GET /items/{id}/price
+pub async fn item_price(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<Price>, StatusCode> {
+    if !state.items.read().unwrap().iter().any(|item| item.id == id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let started = Instant::now();
+    let price_cents = state
+        .pricing
+        .call_idempotent(|| fetch_price(id))
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+    Ok(Json(Price {
+        item_id: id,
+        price_cents,
+        latency_ms: started.elapsed().as_millis() as u64,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    /// An upstream whose n-th attempt takes `script[n]`'s milliseconds and
+    /// returns its result; attempts past the end repeat the last entry
+    struct Scripted {
+        script: Vec<(u64, Result<u32, &'static str>)>,
+        attempts: AtomicUsize,
+    }
+
+    impl Scripted {
+        fn new(script: &[(u64, Result<u32, &'static str>)]) -> Self {
+            Self {
+                script: script.to_vec(),
+                attempts: AtomicUsize::new(0),
+            }
+        }
+
+        fn attempt(&self) -> impl Future<Output = Result<u32, &'static str>> {
+            let n = self.attempts.fetch_add(1, Ordering::SeqCst);
+            let (ms, result) = self.script[n.min(self.script.len() - 1)];
+            async move {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                result
+            }
+        }
+    }
+
+    /// Hedges after 100ms until 20 latencies are in
+    fn hedger(max_hedge_ratio: f64) -> Hedger {
+        Hedger::new(HedgePolicy {
+            max_hedge_ratio,
+            ..HedgePolicy::default()
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_slow_primary_loses_to_the_hedge() {
+        let hedger = hedger(1.0);
+        let upstream = Scripted::new(&[(1000, Ok(1)), (20, Ok(2))]);
+
+        let started = Instant::now();
+        let result = hedger.call_idempotent(|| upstream.attempt()).await;
+
+        assert_eq!(result, Ok(2));
+        // 100ms hedge delay plus the hedge's own 20ms
+        assert_eq!(started.elapsed(), Duration::from_millis(120));
+        let stats = hedger.stats();
+        assert_eq!(
+            (stats.calls, stats.hedges_fired, stats.hedges_won),
+            (1, 1, 1)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_fast_primary_never_hedges() {
+        let hedger = hedger(1.0);
+        let upstream = Scripted::new(&[(30, Ok(1))]);
+
+        assert_eq!(hedger.call_idempotent(|| upstream.attempt()).await, Ok(1));
+        assert_eq!(upstream.attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(hedger.stats().hedges_fired, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_failing_hedge_falls_back_to_the_primary() {
+        let hedger = hedger(1.0);
+        let upstream = Scripted::new(&[(300, Ok(1)), (10, Err("boom"))]);
+
+        let started = Instant::now();
+        let result = hedger.call_idempotent(|| upstream.attempt()).await;
+
+        // The first *successful* answer wins, not the first answer
+        assert_eq!(result, Ok(1));
+        assert_eq!(started.elapsed(), Duration::from_millis(300));
+        let stats = hedger.stats();
+        assert_eq!((stats.hedges_fired, stats.hedges_won), (1, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_failing_primary_falls_back_to_the_hedge() {
+        let hedger = hedger(1.0);
+        let upstream = Scripted::new(&[(150, Err("boom")), (100, Ok(2))]);
+
+        let started = Instant::now();
+        let result = hedger.call_idempotent(|| upstream.attempt()).await;
+
+        assert_eq!(result, Ok(2));
+        assert_eq!(started.elapsed(), Duration::from_millis(200));
+        assert_eq!(hedger.stats().hedges_won, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_spent_budget_waits_for_the_primary() {
+        // One hedge per ten calls
+        let hedger = hedger(0.1);
+        let upstream = Scripted::new(&[(1000, Ok(1)), (20, Ok(2)), (1000, Ok(3))]);
+        assert_eq!(hedger.call_idempotent(|| upstream.attempt()).await, Ok(2));
+
+        // Two calls, one hedge: already over 10%
+        let started = Instant::now();
+        let result = hedger.call_idempotent(|| upstream.attempt()).await;
+
+        assert_eq!(result, Ok(3));
+        assert_eq!(started.elapsed(), Duration::from_millis(1000));
+        assert_eq!(upstream.attempts.load(Ordering::SeqCst), 3);
+        let stats = hedger.stats();
+        assert_eq!(
+            (stats.calls, stats.hedges_fired, stats.hedges_skipped),
+            (2, 1, 1)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_the_delay_is_a_percentile_once_there_are_enough_samples() {
+        let hedger = hedger(1.0);
+        let upstream = Scripted::new(&[(10, Ok(1))]);
+        for _ in 0..MIN_SAMPLES - 1 {
+            hedger.call_idempotent(|| upstream.attempt()).await.unwrap();
+        }
+        assert_eq!(hedger.delay(), Duration::from_millis(100));
+
+        hedger.call_idempotent(|| upstream.attempt()).await.unwrap();
+        assert_eq!(hedger.delay(), Duration::from_millis(10));
+
+        // 5% of slow calls put the p95 on them
+        let slow = Scripted::new(&[(60, Ok(1))]);
+        for _ in 0..2 {
+            hedger.call_idempotent(|| slow.attempt()).await.unwrap();
+        }
+        assert_eq!(hedger.delay(), Duration::from_millis(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_the_delay_never_drops_below_the_minimum() {
+        let hedger = hedger(1.0);
+        let upstream = Scripted::new(&[(1, Ok(1))]);
+        for _ in 0..MIN_SAMPLES {
+            hedger.call_idempotent(|| upstream.attempt()).await.unwrap();
+        }
+        assert_eq!(hedger.delay(), Duration::from_millis(5));
+    }
+}
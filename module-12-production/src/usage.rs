@@ -0,0 +1,450 @@
+//! # Cost Accounting and Usage Reports
+//!
+//! Counting requests treats a health probe and a full export as equals.
+//! For billing or quotas, each route has a *cost* instead: the weight of
+//! the work it does. The cost is declared where the route is registered,
+//! as a layer on its handler, so a route and its price can't drift apart:
+//!
+//! ```ignore
+//! .route("/search", get(search_items.layer(cost(10))))
+//! ```
+//!
+//! Routes registered without one cost `DEFAULT_COST`.
+//!
+//! `account_usage` charges every response to the caller's `X-API-Key`
+//! ("anonymous" without one), by the route's `MatchedPath`. Keys must be
+//! ones we issued (`API_KEYS`); any other key gets `401`, so a client can't
+//! grow the ledger by inventing keys. Only answers below 400 are billable;
+//! errors are counted, but cost nothing. Usage is rolled up per key and
+//! per UTC day, and days older than the longest report are dropped, so
+//! memory is bounded by keys times days, not by requests.
+//!
+//! `GET /usage?from=&to=` reports the caller's own usage between two dates
+//! (inclusive, default: this month so far): totals, a per-route breakdown
+//! and one row per day. `GET /admin/usage` has the same range, for every
+//! key. The ledger lives in memory; a billing system would persist the
+//! daily rollups (compare `persisted_metrics.rs`).
+
+use axum::{
+    extract::{MatchedPath, Query, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+use tower::util::MapResponseLayer;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const ANONYMOUS: &str = "anonymous";
+
+/// Longest range one report may cover, and how long daily rollups are kept
+const MAX_REPORT_DAYS: i64 = 366;
+
+/// Keys accepted when `API_KEYS` isn't set
+const DEMO_KEYS: &str = "acme,globex";
+
+/// Cost units per request to a route registered without a `cost`
+pub const DEFAULT_COST: u64 = 1;
+
+/// What one request to a route costs; carried on its response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cost(pub u64);
+
+/// Layer for a handler: every response it gives is charged `units`
+pub fn cost(
+    units: u64,
+) -> MapResponseLayer<impl Fn(Response) -> Response + Clone + Send + Sync + 'static> {
+    MapResponseLayer::new(move |mut response: Response| {
+        response.extensions_mut().insert(Cost(units));
+        response
+    })
+}
+
+/// One key's usage on one day, or summed over a range
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Usage {
+    pub requests: u64,
+    /// Of `requests`, the ones answered with a 4xx/5xx
+    pub errors: u64,
+    pub cost: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.cost += other.cost;
+    }
+}
+
+#[derive(Debug, Default)]
+struct DayRollup {
+    total: Usage,
+    /// Keyed "GET /items/{id}/price"
+    routes: BTreeMap<String, Usage>,
+}
+
+#[derive(Debug)]
+pub struct UsageLedger {
+    /// The keys we issued; any other `X-API-Key` is refused
+    keys: BTreeSet<String>,
+    days: Mutex<BTreeMap<(String, NaiveDate), DayRollup>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DayUsage {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub key: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub total: Usage,
+    pub routes: BTreeMap<String, Usage>,
+    /// Only days with traffic
+    pub days: Vec<DayUsage>,
+}
+
+impl UsageLedger {
+    pub fn new<K: Into<String>>(keys: impl IntoIterator<Item = K>) -> Self {
+        Self {
+            keys: keys.into_iter().map(Into::into).collect(),
+            days: Mutex::default(),
+        }
+    }
+
+    /// `API_KEYS=acme,globex` - comma-separated, and those two by default
+    pub fn from_env() -> Self {
+        let keys = std::env::var("API_KEYS").unwrap_or_else(|_| DEMO_KEYS.to_string());
+        Self::new(
+            keys.split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty() && *key != ANONYMOUS),
+        )
+    }
+
+    fn knows(&self, key: &str) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Charge one response; `cost` is what the route costs when it succeeds
+    pub fn record(
+        &self,
+        key: &str,
+        method: &str,
+        route: &str,
+        status: StatusCode,
+        cost: u64,
+        date: NaiveDate,
+    ) {
+        let is_error = status.is_client_error() || status.is_server_error();
+        let usage = Usage {
+            requests: 1,
+            errors: is_error as u64,
+            cost: if is_error { 0 } else { cost },
+        };
+        let mut days = self.days.lock().unwrap();
+        let id = (key.to_string(), date);
+        if !days.contains_key(&id) {
+            // A new day: drop the ones no report can reach any more
+            days.retain(|(_, day), _| (date - *day).num_days() < MAX_REPORT_DAYS);
+        }
+        let day = days.entry(id).or_default();
+        day.total.add(&usage);
+        day.routes
+            .entry(format!("{} {}", method, route))
+            .or_default()
+            .add(&usage);
+    }
+
+    /// `key`'s usage from `from` to `to`, both included
+    pub fn report(&self, key: &str, from: NaiveDate, to: NaiveDate) -> UsageReport {
+        let days = self.days.lock().unwrap();
+        let mut report = UsageReport {
+            key: key.to_string(),
+            from,
+            to,
+            total: Usage::default(),
+            routes: BTreeMap::new(),
+            days: Vec::new(),
+        };
+        for ((_, date), day) in days.range((key.to_string(), from)..=(key.to_string(), to)) {
+            report.total.add(&day.total);
+            for (route, usage) in &day.routes {
+                report.routes.entry(route.clone()).or_default().add(usage);
+            }
+            report.days.push(DayUsage {
+                date: *date,
+                usage: day.total.clone(),
+            });
+        }
+        report
+    }
+
+    /// Every key's totals from `from` to `to`
+    pub fn totals(&self, from: NaiveDate, to: NaiveDate) -> BTreeMap<String, Usage> {
+        let days = self.days.lock().unwrap();
+        let mut totals: BTreeMap<String, Usage> = BTreeMap::new();
+        for ((key, date), day) in days.iter() {
+            if (from..=to).contains(date) {
+                totals.entry(key.clone()).or_default().add(&day.total);
+            }
+        }
+        totals
+    }
+}
+
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|key| !key.is_empty())
+}
+
+// ============================================================================
+// MIDDLEWARE
+// ============================================================================
+
+/// Global: charge each routed response to the caller's key, refusing keys
+/// we never issued. Requests that match no route aren't charged.
+pub async fn account_usage(
+    State(ledger): State<Arc<UsageLedger>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(route) = request.extensions().get::<MatchedPath>().cloned() else {
+        return next.run(request).await;
+    };
+    let key = match api_key(request.headers()) {
+        None => ANONYMOUS.to_string(),
+        Some(key) if ledger.knows(key) => key.to_string(),
+        Some(_) => return error(StatusCode::UNAUTHORIZED, "Unknown X-API-Key".to_string()),
+    };
+    let method = request.method().clone();
+
+    let mut response = next.run(request).await;
+    let Cost(cost) = response
+        .extensions_mut()
+        .remove::<Cost>()
+        .unwrap_or(Cost(DEFAULT_COST));
+    ledger.record(
+        &key,
+        method.as_str(),
+        route.as_str(),
+        response.status(),
+        cost,
+        Utc::now().date_naive(),
+    );
+    response
+}
+
+// ============================================================================
+// HANDLERS
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct ReportRange {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+impl ReportRange {
+    /// Defaults to this month so far
+    fn resolve(&self, today: NaiveDate) -> Result<(NaiveDate, NaiveDate), String> {
+        let to = self.to.unwrap_or(today);
+        let from = self.from.unwrap_or_else(|| to.with_day(1).unwrap());
+        if from > to {
+            return Err(format!("from ({}) is after to ({})", from, to));
+        }
+        if (to - from).num_days() >= MAX_REPORT_DAYS {
+            return Err(format!("A report covers at most {} days", MAX_REPORT_DAYS));
+        }
+        Ok((from, to))
+    }
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+/// GET /usage?from=&to= - the caller's own usage (dates as YYYY-MM-DD)
+pub async fn usage_report(
+    State(ledger): State<Arc<UsageLedger>>,
+    headers: HeaderMap,
+    Query(range): Query<ReportRange>,
+) -> Result<Json<UsageReport>, Response> {
+    let key = api_key(&headers).ok_or_else(|| {
+        let message = "Send your X-API-Key to see its usage".to_string();
+        error(StatusCode::UNAUTHORIZED, message)
+    })?;
+    let (from, to) = range
+        .resolve(Utc::now().date_naive())
+        .map_err(|message| error(StatusCode::BAD_REQUEST, message))?;
+    Ok(Json(ledger.report(key, from, to)))
+}
+
+/// GET /admin/usage?from=&to= - totals for every key
+pub async fn all_usage(
+    State(ledger): State<Arc<UsageLedger>>,
+    Query(range): Query<ReportRange>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let (from, to) = range
+        .resolve(Utc::now().date_naive())
+        .map_err(|message| error(StatusCode::BAD_REQUEST, message))?;
+    Ok(Json(serde_json::json!({
+        "from": from,
+        "to": to,
+        "keys": ledger.totals(from, to),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, handler::Handler, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_costs_roll_up_per_key_and_day() {
+        let ledger = UsageLedger::new(["k1", "k2"]);
+        let (day1, day2) = (date("2026-03-01"), date("2026-03-02"));
+        ledger.record("k1", "GET", "/search", StatusCode::OK, 10, day1);
+        ledger.record("k1", "GET", "/items", StatusCode::OK, 1, day1);
+        ledger.record("k1", "GET", "/export", StatusCode::OK, 25, day2);
+        // Errors are counted, not billed
+        ledger.record(
+            "k1",
+            "GET",
+            "/export",
+            StatusCode::SERVICE_UNAVAILABLE,
+            25,
+            day2,
+        );
+        ledger.record("k1", "GET", "/health", StatusCode::OK, 0, day2);
+        // Another key, and a day outside the range
+        ledger.record("k2", "GET", "/search", StatusCode::OK, 10, day1);
+        ledger.record(
+            "k1",
+            "GET",
+            "/search",
+            StatusCode::OK,
+            10,
+            date("2026-04-01"),
+        );
+
+        let report = ledger.report("k1", day1, date("2026-03-31"));
+        assert_eq!(
+            report.total,
+            Usage {
+                requests: 5,
+                errors: 1,
+                cost: 10 + 1 + 25
+            }
+        );
+        assert_eq!(report.days.len(), 2);
+        assert_eq!(report.days[1].usage.cost, 25);
+        assert_eq!(
+            report.routes["GET /export"],
+            Usage {
+                requests: 2,
+                errors: 1,
+                cost: 25
+            }
+        );
+
+        let totals = ledger.totals(day1, day1);
+        assert_eq!(totals["k1"].cost, 11);
+        assert_eq!(totals["k2"].cost, 10);
+    }
+
+    #[test]
+    fn test_report_range_defaults_to_this_month_and_is_bounded() {
+        let today = date("2026-03-17");
+        let range = |from: Option<&str>, to: Option<&str>| ReportRange {
+            from: from.map(date),
+            to: to.map(date),
+        };
+        assert_eq!(
+            range(None, None).resolve(today).unwrap(),
+            (date("2026-03-01"), today)
+        );
+        assert!(range(Some("2026-03-10"), Some("2026-03-01"))
+            .resolve(today)
+            .is_err());
+        assert!(range(Some("2024-01-01"), None).resolve(today).is_err());
+    }
+
+    #[test]
+    fn test_days_past_the_longest_report_are_dropped() {
+        let ledger = UsageLedger::new(["k1"]);
+        let first = date("2026-01-01");
+        ledger.record("k1", "GET", "/items", StatusCode::OK, 1, first);
+        let last_kept = first + chrono::Duration::days(MAX_REPORT_DAYS - 1);
+        ledger.record("k1", "GET", "/items", StatusCode::OK, 1, last_kept);
+        assert_eq!(ledger.report("k1", first, first).total.requests, 1);
+
+        ledger.record(
+            "k1",
+            "GET",
+            "/items",
+            StatusCode::OK,
+            1,
+            last_kept.succ_opt().unwrap(),
+        );
+        assert_eq!(ledger.report("k1", first, first).total.requests, 0);
+        assert_eq!(ledger.days.lock().unwrap().len(), 2);
+    }
+
+    async fn call(app: &Router, uri: &str, key: Option<&str>) -> StatusCode {
+        let mut request = axum::http::Request::get(uri);
+        if let Some(key) = key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_costs_come_from_the_routes_and_unknown_keys_are_refused() {
+        let ledger = Arc::new(UsageLedger::new(["acme"]));
+        let app = Router::new()
+            .route("/search", get((|| async { "hits" }).layer(cost(10))))
+            .route("/health", get((|| async { "OK" }).layer(cost(0))))
+            .route("/plain", get(|| async { "no cost declared" }))
+            .layer(middleware::from_fn_with_state(
+                ledger.clone(),
+                account_usage,
+            ));
+
+        for uri in ["/search", "/health", "/plain"] {
+            assert_eq!(call(&app, uri, Some("acme")).await, StatusCode::OK);
+        }
+        assert_eq!(call(&app, "/search", None).await, StatusCode::OK);
+        assert_eq!(
+            call(&app, "/search", Some("made-up")).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let today = Utc::now().date_naive();
+        let acme = ledger.report("acme", today, today);
+        assert_eq!(acme.total.cost, 10 + DEFAULT_COST);
+        assert_eq!(acme.routes["GET /health"].requests, 1);
+        let totals = ledger.totals(today, today);
+        assert_eq!(totals[ANONYMOUS].cost, 10);
+        assert!(!totals.contains_key("made-up"));
+    }
+}